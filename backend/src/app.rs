@@ -1,16 +1,21 @@
 use std::{collections::HashMap, sync::Arc};
 
 use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use libp2p::{Multiaddr, PeerId};
 use tokio::sync::RwLock;
 
 use crate::{
+    connection::Connection,
+    events::{EventBus, PortaEvent},
+    invite::Invite,
     models::{
-        CommunityAddRequest, CommunitySummary, DiscoveredService, PublishRequest, PublishedService,
-        SecureConnectRequest, SecureRoute, ServiceRegistryItem, SessionInfo, SubscribeRequest,
-        SubscribedService,
+        normalize_tags, CommunityAddRequest, CommunityInvite, CommunitySummary, ConnectionState,
+        DiscoveredService, InviteCreateRequest, NodeBanRequest, PublishRequest, PublishedService,
+        SecureConnectRequest, SecureRoute, ServiceChange, ServicePage, ServiceQuery,
+        ServiceRegistryItem, SessionInfo, SubscribeRequest, SubscribedService,
     },
-    p2p::{P2pRequest, P2pResponse},
+    p2p::{address_book::without_peer, protocol::IdentityHandover, P2pRequest, P2pResponse},
     state::Store,
     tunnel,
 };
@@ -20,26 +25,178 @@ pub struct AppService {
     store: Arc<dyn Store>,
     p2p: crate::p2p::NodeHandle,
     peer_cache: Arc<RwLock<HashMap<String, PeerId>>>,
+    events: EventBus,
+    /// Connection state of communities, loaded from the store on first use
+    connections: Arc<RwLock<HashMap<String, Connection>>>,
+    /// Identity handovers not yet accepted by a community, oldest first
+    pending_handovers: Arc<RwLock<HashMap<String, Vec<IdentityHandover>>>>,
+    /// Invite tokens to present in the next Hello to a community
+    pending_invites: Arc<RwLock<HashMap<String, String>>>,
+    role: String,
 }
 
 impl AppService {
-    pub fn new(store: Arc<dyn Store>, p2p: crate::p2p::NodeHandle) -> Self {
+    pub fn new(
+        store: Arc<dyn Store>,
+        p2p: crate::p2p::NodeHandle,
+        events: EventBus,
+        role: &str,
+    ) -> Self {
         Self {
             store,
             p2p,
             peer_cache: Arc::new(RwLock::new(HashMap::new())),
+            events,
+            connections: Arc::new(RwLock::new(HashMap::new())),
+            pending_handovers: Arc::new(RwLock::new(HashMap::new())),
+            pending_invites: Arc::new(RwLock::new(HashMap::new())),
+            role: role.to_string(),
         }
     }
 
+    /// Record the outcome of a connection attempt: a success resets the
+    /// backoff, a failure schedules the next attempt. The backoff is persisted
+    /// and an event is emitted when the community becomes reachable or stops
+    /// being so.
+    async fn set_community_status(&self, id: &str, connected: bool, error: Option<String>) {
+        let known = self.connection(id).await;
+        let was_connected = known.state == ConnectionState::Connected;
+        let connection = {
+            let mut connections = self.connections.write().await;
+            let connection = connections.entry(id.to_string()).or_insert(known);
+            if connected {
+                connection.succeed();
+            } else {
+                connection.fail(
+                    error.clone().unwrap_or_else(|| "连接失败".to_string()),
+                    chrono::Utc::now().timestamp(),
+                );
+            }
+            connection.clone()
+        };
+        if let Some(at) = connection.next_retry_at {
+            tracing::info!(
+                "[社区连接] 社区 {} 连续失败 {} 次, {} 后重试",
+                id,
+                connection.failures,
+                chrono::DateTime::from_timestamp(at, 0)
+                    .map(|at| at.to_rfc3339())
+                    .unwrap_or_default()
+            );
+        }
+        if let Err(err) = self
+            .store
+            .set_community_connection(
+                id,
+                connection.failures,
+                connection.last_error.as_deref(),
+                connection.next_retry_at,
+            )
+            .await
+        {
+            tracing::warn!("[社区连接] 保存连接状态失败: id={}, {}", id, err);
+        }
+        if connected != was_connected && (connected || connection.failures == 1) {
+            self.events.publish(PortaEvent::CommunityStatus {
+                community_id: id.to_string(),
+                connected,
+                error,
+            });
+        }
+    }
+
+    /// The connection state of a community, picking up a persisted backoff
+    /// the first time the community is seen.
+    async fn connection(&self, id: &str) -> Connection {
+        if let Some(connection) = self.connections.read().await.get(id) {
+            return connection.clone();
+        }
+        let restored = match self.store.community_by_id(id).await {
+            Ok(Some(community)) => Connection::restore(&community),
+            _ => Connection::default(),
+        };
+        self.connections
+            .write()
+            .await
+            .entry(id.to_string())
+            .or_insert(restored)
+            .clone()
+    }
+
+    /// Move a community to `state` if it is currently in one of `from`.
+    async fn advance_connection(&self, id: &str, from: &[ConnectionState], state: ConnectionState) {
+        let known = self.connection(id).await;
+        let mut connections = self.connections.write().await;
+        let connection = connections.entry(id.to_string()).or_insert(known);
+        if from.contains(&connection.state) {
+            connection.state = state;
+        }
+    }
+
+    /// Communities with their current connection state.
+    pub async fn communities(&self) -> Result<Vec<CommunitySummary>> {
+        let mut communities = self.store.communities().await?;
+        let connections = self.connections.read().await;
+        for community in communities.iter_mut() {
+            match connections.get(&community.id) {
+                Some(connection) => connection.apply(community),
+                None => Connection::restore(community).apply(community),
+            }
+        }
+        Ok(communities)
+    }
+
     pub async fn add_community(&self, mut req: CommunityAddRequest) -> Result<CommunitySummary> {
+        let invite = match req.invite.as_deref().filter(|t| !t.trim().is_empty()) {
+            Some(token) => {
+                let invite = Invite::decode(token)?;
+                if invite.is_expired(chrono::Utc::now().timestamp()) {
+                    return Err(anyhow!("邀请已过期"));
+                }
+                if req.name.trim().is_empty() {
+                    req.name = invite.name.clone();
+                }
+                if req.description.trim().is_empty() {
+                    req.description = invite.description.clone();
+                }
+                if req.multiaddr.is_none() {
+                    req.multiaddr = invite.multiaddrs.first().cloned();
+                }
+                if req.multiaddrs.is_empty() {
+                    req.multiaddrs = invite.multiaddrs.clone();
+                }
+                Some((invite, token.trim().to_string()))
+            }
+            None => None,
+        };
         if req.name.trim().is_empty() {
             return Err(anyhow!("社区名称不能为空"));
         }
         if req.description.trim().is_empty() {
             return Err(anyhow!("社区描述不能为空"));
         }
-        if req.multiaddr.as_ref().is_none() {
-            return Err(anyhow!("缺少 multiaddr"));
+        if req.multiaddr.is_none() && !req.multiaddrs.is_empty() {
+            req.multiaddr = Some(req.multiaddrs.remove(0));
+        }
+        if req.multiaddr.is_none() {
+            let Some(peer_id) = req
+                .peer_id
+                .as_deref()
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+            else {
+                return Err(anyhow!("缺少 multiaddr 或 peer ID"));
+            };
+            let peer: PeerId = peer_id
+                .parse()
+                .map_err(|_| anyhow!("peer ID 无效: {}", peer_id))?;
+            let addrs = self
+                .resolve_peer(&peer)
+                .await
+                .map_err(|err| anyhow!("缺少 multiaddr，且{}", err))?;
+            tracing::info!("通过 peer ID 解析到社区地址: {} -> {:?}", peer, addrs);
+            req.multiaddr = Some(addrs[0].to_string());
+            req.multiaddrs = addrs[1..].iter().map(|addr| addr.to_string()).collect();
         }
         let existing = self.store.communities().await?;
         if existing.iter().any(|item| item.name == req.name) {
@@ -50,8 +207,8 @@ impl AppService {
                 return Err(anyhow!("社区 ID 已存在"));
             }
         }
-        if let Some(addr) = req.multiaddr.as_ref() {
-            let addr: Multiaddr = addr.parse()?;
+        if let Some(primary) = req.multiaddr.clone() {
+            let addr: Multiaddr = primary.parse()?;
             let peer_id =
                 extract_peer_id(&addr).ok_or_else(|| anyhow!("multiaddr 缺少 /p2p/peerId"))?;
             if self
@@ -61,16 +218,217 @@ impl AppService {
             {
                 return Err(anyhow!("该社区已存在"));
             }
+            if let Some((invite, _)) = invite.as_ref() {
+                if invite.peer_id != peer_id.to_string() {
+                    return Err(anyhow!("multiaddr 与邀请的社区 peer 不符"));
+                }
+            }
+            req.multiaddrs = fallback_addrs(&primary, &req.multiaddrs, &peer_id)?;
             req.peer_id = Some(peer_id.to_string());
         }
         let saved = self.store.add_community(req.clone()).await?;
         tracing::info!("新增社区: {} ({})", saved.name, saved.id);
+        if let Some((_, token)) = invite {
+            self.pending_invites
+                .write()
+                .await
+                .insert(saved.id.clone(), token);
+        }
         Ok(saved)
     }
 
+    /// Issue a signed invite to this community node.
+    pub async fn create_invite(&self, req: InviteCreateRequest) -> Result<CommunityInvite> {
+        if self.role != "community" {
+            return Err(anyhow!("只有社区节点可以生成邀请"));
+        }
+        let keypair = self.p2p.keypair();
+        let peer_id = PeerId::from(keypair.public());
+        let node = self.store.node_info().await?;
+        let multiaddrs = if req.multiaddrs.is_empty() {
+            let listeners = self.p2p.listeners().await?;
+            crate::invite::invite_addrs(&listeners, &node.external_addr, &peer_id)
+        } else {
+            let mut multiaddrs = Vec::new();
+            for addr in &req.multiaddrs {
+                let mut addr: Multiaddr = addr
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow!("multiaddr 无效: {}", addr))?;
+                match extract_peer_id(&addr) {
+                    Some(peer) if peer != peer_id => {
+                        return Err(anyhow!("multiaddr 指向其他节点: {}", addr));
+                    }
+                    Some(_) => {}
+                    None => addr.push(libp2p::multiaddr::Protocol::P2p(peer_id)),
+                }
+                multiaddrs.push(addr.to_string());
+            }
+            multiaddrs
+        };
+        if multiaddrs.is_empty() {
+            return Err(anyhow!("节点没有可用的监听地址"));
+        }
+        let name = req
+            .name
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| node.name.clone());
+        let description = req
+            .description
+            .map(|description| description.trim().to_string())
+            .filter(|description| !description.is_empty())
+            .unwrap_or_else(|| name.clone());
+        let invite = Invite {
+            id: uuid::Uuid::new_v4().to_string(),
+            name,
+            description,
+            multiaddrs,
+            peer_id: peer_id.to_string(),
+            public_key: BASE64.encode(keypair.public().encode_protobuf()),
+            expires_at: req
+                .expires_in_secs
+                .map(|secs| chrono::Utc::now().timestamp() + secs as i64),
+            one_time: req.one_time,
+        };
+        let token = invite.encode(&keypair)?;
+        tracing::info!(
+            "生成社区邀请: id={}, one_time={}, expires_at={:?}",
+            invite.id,
+            invite.one_time,
+            invite.expires_at
+        );
+        Ok(CommunityInvite { token, invite })
+    }
+
+    /// Let a queued edge into this community. It becomes a member right
+    /// away and is accepted the next time it says Hello.
+    pub async fn approve_join_request(&self, peer_id: &str) -> Result<()> {
+        let request = self
+            .store
+            .join_requests()
+            .await?
+            .into_iter()
+            .find(|request| request.peer_id == peer_id)
+            .ok_or_else(|| anyhow!("未找到加入申请"))?;
+        self.store
+            .upsert_peer(
+                &request.peer_id,
+                &request.node_id,
+                "",
+                &request.name,
+                "edge",
+                "offline",
+            )
+            .await?;
+        self.store.remove_join_request(peer_id).await?;
+        tracing::info!("批准加入申请: peer={}", peer_id);
+        Ok(())
+    }
+
+    /// Turn a queued edge away; its later Hellos are refused until approved.
+    pub async fn reject_join_request(&self, peer_id: &str) -> Result<()> {
+        if !self
+            .store
+            .set_join_request_status(peer_id, "rejected")
+            .await?
+        {
+            return Err(anyhow!("未找到加入申请"));
+        }
+        tracing::info!("拒绝加入申请: peer={}", peer_id);
+        Ok(())
+    }
+
+    /// Ban a community node, by peer ID or by node UUID (every peer the node
+    /// has used). Its connections are closed, it cannot reconnect until the
+    /// ban lifts, and the services it registered are dropped. Returns the
+    /// banned peers.
+    pub async fn ban_node(&self, req: NodeBanRequest) -> Result<Vec<String>> {
+        let peers = self.ban_targets(&req).await?;
+        let reason = req
+            .reason
+            .as_deref()
+            .map(str::trim)
+            .filter(|reason| !reason.is_empty());
+        let until = req
+            .expires_in_secs
+            .map(|secs| chrono::Utc::now().timestamp() + secs as i64);
+        for peer in &peers {
+            if !self.store.set_node_ban(peer, true, reason, until).await? {
+                return Err(anyhow!("未找到节点"));
+            }
+            let removed = self.store.remove_provider_services(peer).await?;
+            tracing::info!(
+                "封禁节点: peer={}, reason={:?}, until={:?}, 移除服务 {} 个",
+                peer,
+                reason,
+                until,
+                removed.len()
+            );
+            for uuid in &removed {
+                self.p2p
+                    .notify_subscribers(uuid, ServiceChange::Removed)
+                    .await;
+            }
+            if !removed.is_empty() {
+                self.p2p.notify_registry_changed().await;
+            }
+        }
+        self.sync_bans().await?;
+        Ok(peers)
+    }
+
+    /// Lift a ban set by [`Self::ban_node`]. Returns the unbanned peers.
+    pub async fn unban_node(&self, req: NodeBanRequest) -> Result<Vec<String>> {
+        let peers = self.ban_targets(&req).await?;
+        for peer in &peers {
+            if !self.store.set_node_ban(peer, false, None, None).await? {
+                return Err(anyhow!("未找到节点"));
+            }
+            tracing::info!("解封节点: peer={}", peer);
+        }
+        self.sync_bans().await?;
+        Ok(peers)
+    }
+
+    async fn ban_targets(&self, req: &NodeBanRequest) -> Result<Vec<String>> {
+        let peers = match (req.id.as_deref(), req.uuid.as_deref()) {
+            (Some(id), _) if !id.trim().is_empty() => vec![id.trim().to_string()],
+            (_, Some(uuid)) if !uuid.trim().is_empty() => {
+                self.store.peers_by_node_uuid(uuid.trim()).await?
+            }
+            _ => return Err(anyhow!("需要提供节点 peer ID 或 UUID")),
+        };
+        if peers.is_empty() {
+            return Err(anyhow!("未找到节点"));
+        }
+        Ok(peers)
+    }
+
+    /// Hand the currently banned peers to the swarm, so expired bans stop
+    /// blocking connections.
+    pub async fn sync_bans(&self) -> Result<()> {
+        let peers = self
+            .store
+            .banned_peers()
+            .await?
+            .iter()
+            .filter_map(|peer| peer.parse().ok())
+            .collect();
+        self.p2p.block_peers(peers).await;
+        Ok(())
+    }
+
     pub async fn remove_community(&self, id: &str) -> Result<()> {
+        if let Some(community) = self.store.community_by_id(id).await? {
+            if community.managed {
+                return Err(anyhow!("该社区由配置文件管理，无法通过接口移除"));
+            }
+        }
         let removed = self.store.remove_community(id).await?;
         if removed {
+            self.pending_invites.write().await.remove(id);
+            self.connections.write().await.remove(id);
             Ok(())
         } else {
             Err(anyhow!("未找到社区"))
@@ -79,6 +437,28 @@ impl AppService {
 
     pub async fn connect_community(&self, id: &str) -> Result<()> {
         tracing::info!("[社区连接] 开始连接社区: id={}", id);
+        self.advance_connection(
+            id,
+            &[
+                ConnectionState::Disconnected,
+                ConnectionState::Backoff,
+                ConnectionState::Connected,
+            ],
+            ConnectionState::Connecting,
+        )
+        .await;
+        let result = self.try_connect_community(id).await;
+        match &result {
+            Ok(()) => self.set_community_status(id, true, None).await,
+            Err(e) => {
+                self.set_community_status(id, false, Some(e.to_string()))
+                    .await
+            }
+        }
+        result
+    }
+
+    async fn try_connect_community(&self, id: &str) -> Result<()> {
         let peer_id = match self.ensure_community_peer(id).await {
             Ok(peer) => {
                 tracing::info!("[社区连接] 已获取社区 peer: id={}, peer={}", id, peer);
@@ -92,6 +472,9 @@ impl AppService {
         match self.store.connect_community(id).await {
             Ok(_) => {
                 tracing::info!("[社区连接] 社区连接成功: id={}, peer={}", id, peer_id);
+                if let Err(err) = self.watch_registry(id, peer_id).await {
+                    tracing::warn!("[社区连接] 订阅注册表推送失败: id={}, error={}", id, err);
+                }
                 Ok(())
             }
             Err(e) => {
@@ -104,49 +487,213 @@ impl AppService {
     pub async fn discover_services(
         &self,
         community_id: Option<String>,
-    ) -> Result<Vec<DiscoveredService>> {
+        query: ServiceQuery,
+    ) -> Result<ServicePage<DiscoveredService>> {
         let Some(community_id) = community_id else {
-            return self.store.discovered_services(None).await;
+            return self.cached_services(None, &query).await;
         };
         tracing::info!("发现服务: 社区 {}", community_id);
         let peer_id = self.ensure_community_peer(&community_id).await?;
+        if !query.has_filters() {
+            // Plain listings are served from the local cache after pulling only the deltas
+            if let Err(err) = self.sync_services(&community_id, peer_id).await {
+                tracing::warn!("社区 {} 增量同步失败，改为全量发现: {}", community_id, err);
+                self.fetch_services(&community_id, peer_id, ServiceQuery::default())
+                    .await?;
+            }
+            return self.cached_services(Some(community_id), &query).await;
+        }
+        let registry = self.fetch_services(&community_id, peer_id, query).await?;
+        let subscribed = self.store.subscribed_services().await?;
+        let items = registry
+            .items
+            .into_iter()
+            .map(|item| {
+                let hit = subscribed
+                    .iter()
+                    .any(|sub| sub.service_uuid.as_deref() == Some(item.uuid.as_str()));
+                DiscoveredService {
+                    uuid: item.uuid,
+                    name: item.name,
+                    r#type: item.r#type,
+                    remote_port: item.port,
+                    provider: item.provider_peer,
+                    description: item.description,
+                    subscribed: hit.then_some(true),
+                    community_id: Some(community_id.clone()),
+                    provider_addr: Some(item.provider_addr),
+                    online: item.online,
+                    tags: item.tags,
+                }
+            })
+            .collect();
+        Ok(ServicePage {
+            items,
+            total: registry.total,
+            next_cursor: registry.next_cursor,
+        })
+    }
+
+    /// Run a remote search and cache the results. Without an explicit limit
+    /// every page is fetched by following the community's cursor.
+    async fn fetch_services(
+        &self,
+        community_id: &str,
+        peer_id: PeerId,
+        mut query: ServiceQuery,
+    ) -> Result<ServicePage<ServiceRegistryItem>> {
+        let follow_cursor = query.limit.is_none();
+        let mut registry = Vec::new();
+        let mut total = None;
+        let next_cursor = loop {
+            let response = self
+                .p2p
+                .request(
+                    peer_id,
+                    P2pRequest::DiscoverServices {
+                        community_id: community_id.to_string(),
+                        query: query.clone(),
+                    },
+                )
+                .await?;
+            let (services, next_cursor) = match response {
+                P2pResponse::ServiceList {
+                    services,
+                    total: reported,
+                    next_cursor,
+                } => {
+                    total = total.or(reported);
+                    (services, next_cursor)
+                }
+                P2pResponse::Error { message } => {
+                    tracing::error!("服务发现失败: {}", message);
+                    return Err(anyhow!(message));
+                }
+                _ => (Vec::new(), None),
+            };
+            let page: Vec<ServiceRegistryItem> = services.into_iter().map(Into::into).collect();
+            self.store
+                .upsert_discovered_services(community_id, page.clone())
+                .await?;
+            registry.extend(page);
+            match next_cursor {
+                Some(cursor) if follow_cursor => query.cursor = Some(cursor),
+                next_cursor => break next_cursor,
+            }
+        };
+        tracing::info!("从社区 {} 发现 {} 个服务", community_id, registry.len());
+        Ok(ServicePage {
+            total: total.unwrap_or(registry.len() as u64),
+            items: registry,
+            next_cursor,
+        })
+    }
+
+    /// Bring the local cache of a community's registry up to date by
+    /// applying the changes made since the last synced revision.
+    async fn sync_services(&self, community_id: &str, peer_id: PeerId) -> Result<()> {
+        let mut since = self.store.community_sync_revision(community_id).await?;
+        loop {
+            let response = self
+                .p2p
+                .request(
+                    peer_id,
+                    P2pRequest::SyncServices {
+                        community_id: community_id.to_string(),
+                        since_revision: since,
+                    },
+                )
+                .await?;
+            let P2pResponse::ServiceChanges {
+                revision,
+                upserted,
+                removed,
+                more,
+                reset,
+            } = response
+            else {
+                if let P2pResponse::Error { message } = response {
+                    return Err(anyhow!(message));
+                }
+                return Err(anyhow!("无效的同步响应"));
+            };
+            if reset {
+                tracing::info!("社区 {} 注册表已重建，清空本地缓存", community_id);
+                self.store.clear_discovered_services(community_id).await?;
+            }
+            tracing::debug!(
+                "社区 {} 同步: 修订 {} -> {}, 更新 {}, 移除 {}",
+                community_id,
+                since,
+                revision,
+                upserted.len(),
+                removed.len()
+            );
+            self.store
+                .upsert_discovered_services(
+                    community_id,
+                    upserted.into_iter().map(Into::into).collect(),
+                )
+                .await?;
+            self.store
+                .remove_discovered_services(community_id, &removed)
+                .await?;
+            self.store
+                .set_community_sync_revision(community_id, revision)
+                .await?;
+            since = revision;
+            if !more {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Ask the community to push registry changes made after our last synced
+    /// revision. Joined communities renew this on every keep-alive.
+    async fn watch_registry(&self, community_id: &str, peer_id: PeerId) -> Result<()> {
+        let since_revision = self.store.community_sync_revision(community_id).await?;
         let response = self
             .p2p
             .request(
                 peer_id,
-                P2pRequest::DiscoverServices {
-                    community_id: community_id.clone(),
+                P2pRequest::WatchRegistry {
+                    community_id: community_id.to_string(),
+                    since_revision,
                 },
             )
             .await?;
-        let services = match response {
-            P2pResponse::ServiceList { services } => services,
-            P2pResponse::Error { message } => {
-                tracing::error!("服务发现失败: {}", message);
-                return Err(anyhow!(message));
-            }
-            _ => Vec::new(),
-        };
-        tracing::info!("从社区 {} 发现 {} 个服务", community_id, services.len());
-        let registry = services
+        match response {
+            P2pResponse::Ack => Ok(()),
+            P2pResponse::Error { message } => Err(anyhow!(message)),
+            _ => Err(anyhow!("无效的订阅响应")),
+        }
+    }
+
+    async fn cached_services(
+        &self,
+        community_id: Option<String>,
+        query: &ServiceQuery,
+    ) -> Result<ServicePage<DiscoveredService>> {
+        let mut list: Vec<DiscoveredService> = self
+            .store
+            .discovered_services(community_id)
+            .await?
             .into_iter()
-            .map(|svc| ServiceRegistryItem {
-                uuid: svc.uuid,
-                name: svc.name,
-                r#type: svc.r#type,
-                port: svc.port,
-                description: svc.description,
-                provider_peer: svc.provider_peer,
-                provider_addr: svc.provider_addr,
-                online: true,
+            .filter(|item| {
+                (item.online || !query.online_only)
+                    && query.matches(
+                        &item.name,
+                        &item.description,
+                        &item.r#type,
+                        &item.provider,
+                        &item.tags,
+                    )
             })
-            .collect::<Vec<_>>();
-        self.store
-            .upsert_discovered_services(&community_id, registry)
-            .await?;
-        let mut list = self.store.discovered_services(Some(community_id)).await?;
+            .collect();
+        list.sort_by(|a, b| (&a.name, &a.uuid).cmp(&(&b.name, &b.uuid)));
         let subscribed = self.store.subscribed_services().await?;
-        for item in &mut list {
+        let mut page = query.paginate(list, |item| (item.name.as_str(), item.uuid.as_str()));
+        for item in &mut page.items {
             let hit = subscribed
                 .iter()
                 .any(|sub| sub.service_uuid.as_deref() == Some(item.uuid.as_str()));
@@ -154,7 +701,7 @@ impl AppService {
                 item.subscribed = Some(true);
             }
         }
-        Ok(list)
+        Ok(page)
     }
 
     pub async fn subscribe_service(&self, req: SubscribeRequest) -> Result<SubscribedService> {
@@ -194,17 +741,71 @@ impl AppService {
         let Some(service_uuid) = subscription.service_uuid.clone() else {
             return Err(anyhow!("订阅缺少 service_uuid"));
         };
-        let Some(community_id) = self.find_community_for_service(&service_uuid).await? else {
+        let (peer_id, remote_addr) = self.request_connect(&service_uuid).await?;
+        let updated = self
+            .store
+            .update_subscription_endpoint(id, &remote_addr, "畅通")
+            .await?;
+        if !updated {
+            return Err(anyhow!("更新订阅失败"));
+        }
+        let session = SessionInfo {
+            session_id: format!("sess-{}", id),
+            service_id: id.to_string(),
+            local_port,
+            remote_peer: remote_addr.clone(),
+            state: "connected".into(),
+            created_at: None,
+            last_active: None,
+        };
+        self.store.upsert_session(session).await?;
+        tunnel::ensure_stream_mapping(local_port, peer_id, service_uuid, self.p2p.clone()).await?;
+        self.events.publish(PortaEvent::SessionOpened {
+            session_id: format!("sess-{}", id),
+            service_id: id.to_string(),
+            local_port,
+        });
+        tracing::info!("服务 {} 连接成功，本地端口: {}", id, local_port);
+        Ok(())
+    }
+
+    /// Open a single stream to a discovered service without a subscription or
+    /// local port, e.g. to bridge it to stdin/stdout.
+    pub async fn open_service_stream(&self, service_uuid: &str) -> Result<libp2p::Stream> {
+        let (peer_id, _) = self.request_connect(service_uuid).await?;
+        self.p2p.open_stream(peer_id, service_uuid).await
+    }
+
+    /// Forward connections accepted on `listener` to a discovered service,
+    /// without a subscription; the caller binds the listener.
+    pub async fn forward_service(
+        &self,
+        service_uuid: &str,
+        listener: tokio::net::TcpListener,
+    ) -> Result<()> {
+        let (peer_id, _) = self.request_connect(service_uuid).await?;
+        tunnel::serve_stream_mapping(
+            listener,
+            peer_id,
+            service_uuid.to_string(),
+            self.p2p.clone(),
+        );
+        Ok(())
+    }
+
+    /// Ask the service's community for its provider; returns the provider peer
+    /// and the remote address shown on the subscription.
+    async fn request_connect(&self, service_uuid: &str) -> Result<(PeerId, String)> {
+        let Some(community_id) = self.find_community_for_service(service_uuid).await? else {
             return Err(anyhow!("未找到社区"));
         };
         let peer_id = self.ensure_community_peer(&community_id).await?;
-        let service_for_stream = service_uuid.clone();
         let response = self
             .p2p
             .request(
                 peer_id,
                 P2pRequest::ConnectService {
-                    service_uuid: service_uuid.clone(),
+                    service_uuid: service_uuid.to_string(),
                     subscriber_peer: self.p2p.peer_id(),
                 },
             )
@@ -222,29 +823,8 @@ impl AppService {
             }
             _ => return Err(anyhow!("连接失败")),
         };
-        let remote_addr = compose_remote_addr(&provider_addr, port);
-        let updated = self
-            .store
-            .update_subscription_endpoint(id, &remote_addr, "畅通")
-            .await?;
-        if !updated {
-            return Err(anyhow!("更新订阅失败"));
-        }
-        let session = SessionInfo {
-            session_id: format!("sess-{}", id),
-            service_id: id.to_string(),
-            local_port,
-            remote_peer: remote_addr.clone(),
-            state: "connected".into(),
-            created_at: None,
-            last_active: None,
-        };
-        self.store.upsert_session(session).await?;
-        let peer_id: PeerId = provider_peer.parse()?;
-        tunnel::ensure_stream_mapping(local_port, peer_id, service_for_stream, self.p2p.clone())
-            .await?;
-        tracing::info!("服务 {} 连接成功，本地端口: {}", id, local_port);
-        Ok(())
+        let provider_peer: PeerId = provider_peer.parse()?;
+        Ok((provider_peer, compose_remote_addr(&provider_addr, port)))
     }
 
     pub async fn disconnect_service(&self, id: &str) -> Result<()> {
@@ -260,13 +840,27 @@ impl AppService {
             last_active: None,
         };
         self.store.upsert_session(session).await?;
+        self.events.publish(PortaEvent::SessionClosed {
+            session_id: format!("sess-{}", id),
+            service_id: id.to_string(),
+        });
         tracing::info!("服务 {} 已断开", id);
         Ok(())
     }
 
-    pub async fn publish_service(&self, req: PublishRequest) -> Result<PublishedService> {
+    pub async fn publish_service(&self, mut req: PublishRequest) -> Result<PublishedService> {
+        if let Some(id) = req.id.as_deref() {
+            self.ensure_published_editable(id).await?;
+        }
         tracing::info!("发布服务: {} ({}:{})", req.name, req.r#type, req.port);
-        let published = self.store.publish_service(req.clone()).await?;
+        req.tags = normalize_tags(req.tags);
+        let published = self.store.publish_service(req).await?;
+        self.announce_service(&published).await?;
+        Ok(published)
+    }
+
+    /// Announce a published service to every joined community.
+    pub(crate) async fn announce_service(&self, published: &PublishedService) -> Result<()> {
         let node = self.store.node_info().await?;
         let provider_addr = node
             .external_addr
@@ -277,7 +871,7 @@ impl AppService {
         let mut publish_count = 0;
         for community in communities.into_iter().filter(|c| c.joined) {
             if let Ok(peer_id) = self.ensure_community_peer(&community.id).await {
-                match self
+                let result = match self
                     .p2p
                     .request(
                         peer_id,
@@ -290,17 +884,34 @@ impl AppService {
                                 provider_peer: self.p2p.peer_id(),
                                 provider_addr: provider_addr.clone(),
                                 description: published.summary.clone(),
+                                online: true,
+                                tags: published.tags.clone(),
                             },
                         },
                     )
                     .await
                 {
-                    Ok(_) => {
+                    Ok(P2pResponse::Error { message }) => Err(anyhow!(message)),
+                    Ok(_) => Ok(()),
+                    Err(err) => Err(err),
+                };
+                let message = match result {
+                    Ok(()) => {
                         publish_count += 1;
                         tracing::debug!("服务已发布到社区: {}", community.id);
+                        None
                     }
-                    Err(err) => tracing::warn!("向社区 {} 发布失败: {}", community.id, err),
-                }
+                    Err(err) => {
+                        tracing::warn!("向社区 {} 发布失败: {}", community.id, err);
+                        Some(err.to_string())
+                    }
+                };
+                self.events.publish(PortaEvent::PublishResult {
+                    service_id: published.id.clone(),
+                    community_id: community.id.clone(),
+                    ok: message.is_none(),
+                    message,
+                });
             }
         }
         tracing::info!(
@@ -308,14 +919,34 @@ impl AppService {
             published.name,
             publish_count
         );
-        Ok(published)
+        Ok(())
     }
 
     pub async fn unpublish_service(&self, id: &str) -> Result<()> {
+        self.ensure_published_editable(id).await?;
         let updated = self.store.unpublish_service(id).await?;
         if !updated {
             return Err(anyhow!("未找到发布服务"));
         }
+        self.withdraw_service(id).await
+    }
+
+    pub async fn remove_published(&self, id: &str) -> Result<bool> {
+        self.ensure_published_editable(id).await?;
+        self.store.remove_published(id).await
+    }
+
+    async fn ensure_published_editable(&self, id: &str) -> Result<()> {
+        match self.store.published_service_by_id(id).await? {
+            Some(service) if service.managed => {
+                Err(anyhow!("该服务由配置文件管理，无法通过接口修改"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Tell every joined community that a service is no longer published.
+    pub(crate) async fn withdraw_service(&self, id: &str) -> Result<()> {
         let communities = self.store.communities().await?;
         for community in communities.into_iter().filter(|c| c.joined) {
             if let Ok(peer_id) = self.ensure_community_peer(&community.id).await {
@@ -341,25 +972,29 @@ impl AppService {
             .and_then(|item| item.community_id))
     }
 
-    async fn build_hello(&self) -> Result<crate::p2p::protocol::NodeHello> {
+    async fn build_hello(&self, community_id: &str) -> Result<crate::p2p::protocol::NodeHello> {
         let info = self.store.node_info().await?;
-        let role = current_role();
         Ok(crate::p2p::protocol::NodeHello {
             node_id: info.node_id,
-            role,
+            uuid: info.uuid,
+            name: info.name,
+            role: self.role.clone(),
+            invite: self.pending_invites.read().await.get(community_id).cloned(),
         })
     }
 
     async fn ensure_community_peer(&self, community_id: &str) -> Result<PeerId> {
-        {
-            let cache = self.peer_cache.read().await;
-            if let Some(cached_peer) = cache.get(community_id) {
+        let cached = self.peer_cache.read().await.get(community_id).copied();
+        if let Some(cached_peer) = cached {
+            // A dropped connection (e.g. the community banned us) needs a
+            // fresh dial and Hello
+            if self.p2p.is_connected(&cached_peer).await {
                 tracing::debug!(
                     "[社区连接] 使用缓存的 peer: community={}, peer={}",
                     community_id,
                     cached_peer
                 );
-                return Ok(*cached_peer);
+                return Ok(cached_peer);
             }
         }
 
@@ -369,55 +1004,53 @@ impl AppService {
             return Err(anyhow!("未找到社区: {}", community_id));
         };
 
-        let Some(addr_str) = community.multiaddr.clone() else {
-            tracing::error!(
-                "[社区连接] 社区缺少 multiaddr: id={}, name={}",
-                community_id,
-                community.name
-            );
-            return Err(anyhow!(
-                "社区 '{}' 缺少 multiaddr，请检查社区配置",
-                community.name
-            ));
+        let (mut expected_peer, mut addrs) = match community_dial_addrs(&community) {
+            Some(found) => found,
+            None => match community
+                .peer_id
+                .as_deref()
+                .and_then(|peer| peer.parse::<PeerId>().ok())
+            {
+                Some(peer) => {
+                    let addrs = self.resolve_peer(&peer).await?;
+                    self.store
+                        .update_community_multiaddr(
+                            community_id,
+                            &addrs[0].to_string(),
+                            &peer.to_string(),
+                        )
+                        .await?;
+                    (peer, addrs)
+                }
+                None => {
+                    tracing::error!(
+                        "[社区连接] 社区缺少 multiaddr: id={}, name={}",
+                        community_id,
+                        community.name
+                    );
+                    return Err(anyhow!(
+                        "社区 '{}' 缺少 multiaddr，请检查社区配置",
+                        community.name
+                    ));
+                }
+            },
         };
 
         tracing::info!(
-            "[社区连接] 解析 multiaddr: id={}, addr={}",
-            community_id,
-            addr_str
-        );
-        let mut addr: Multiaddr = addr_str.parse().map_err(|e| {
-            tracing::error!(
-                "[社区连接] multiaddr 解析失败: id={}, addr={}, error={}",
-                community_id,
-                addr_str,
-                e
-            );
-            anyhow!("无效的 multiaddr 格式: {}", e)
-        })?;
-
-        let mut expected_peer = extract_peer_id(&addr).ok_or_else(|| {
-            tracing::error!(
-                "[社区连接] multiaddr 缺少 peerId: id={}, addr={}",
-                community_id,
-                addr
-            );
-            anyhow!("multiaddr 缺少 /p2p/peerId")
-        })?;
-
-        tracing::info!(
-            "[社区连接] 开始拨号: id={}, peer={}, addr={}",
+            "[社区连接] 开始拨号: id={}, peer={}, addrs={:?}",
             community_id,
             expected_peer,
-            addr
+            addrs
         );
-        let addr_for_error = addr.clone();
 
-        // Retry dial with exponential backoff
+        // Retry dial with exponential backoff; every address is tried at once
         let mut peer_id = None;
         let max_retries = 3;
         for attempt in 1..=max_retries {
-            match self.p2p.dial(addr.clone()).await {
+            match self
+                .dial_community(&community, expected_peer, addrs.clone())
+                .await
+            {
                 Ok(peer) => {
                     tracing::info!(
                         "[社区连接] 拨号成功: id={}, peer={}, 尝试次数={}",
@@ -443,17 +1076,17 @@ impl AppService {
                                     tracing::warn!("[社区连接] 检测到 WrongPeerId 错误: id={}, 期望={}, 实际={}", 
                                         community_id, expected_peer, actual_peer_id);
 
-                                    // Update multiaddr with actual peer_id
-                                    let mut new_addr = addr.clone();
-                                    // Remove old /p2p/peer_id part
-                                    new_addr = new_addr
-                                        .iter()
-                                        .filter(|p| {
-                                            !matches!(p, libp2p::multiaddr::Protocol::P2p(_))
-                                        })
-                                        .collect::<Multiaddr>();
-                                    // Add correct /p2p/peer_id
-                                    new_addr.push(libp2p::multiaddr::Protocol::P2p(actual_peer_id));
+                                    // Point every address at the actual peer_id
+                                    let with_actual = |addr: &Multiaddr| {
+                                        without_peer(addr)
+                                            .with(libp2p::multiaddr::Protocol::P2p(actual_peer_id))
+                                    };
+                                    let new_addr = community
+                                        .multiaddr
+                                        .as_deref()
+                                        .and_then(|addr| addr.parse::<Multiaddr>().ok())
+                                        .map(|addr| with_actual(&addr))
+                                        .unwrap_or_else(|| with_actual(&addrs[0]));
 
                                     let new_addr_str = new_addr.to_string();
                                     if self
@@ -471,8 +1104,8 @@ impl AppService {
                                             community_id,
                                             new_addr_str
                                         );
-                                        // Update addr for next retry
-                                        addr = new_addr.clone();
+                                        // Update addrs for next retry
+                                        addrs = addrs.iter().map(with_actual).collect();
                                         expected_peer = actual_peer_id;
                                         // Continue to retry with corrected address
                                         let backoff = std::time::Duration::from_millis(500);
@@ -507,14 +1140,24 @@ impl AppService {
                         tokio::time::sleep(backoff).await;
                     } else {
                         tracing::error!(
-                            "[社区连接] 拨号失败 (已重试{}次): id={}, peer={}, addr={}, error={}",
+                            "[社区连接] 拨号失败 (已重试{}次): id={}, peer={}, addrs={:?}, error={}",
                             max_retries,
                             community_id,
                             expected_peer,
-                            addr_for_error,
+                            addrs,
                             e
                         );
-                        return Err(anyhow!("无法连接到社区节点 ({}): {}", addr_for_error, e));
+                        // The community may have moved; look its peer ID up again
+                        if let Some(peer) = self
+                            .relocate_community(&community, expected_peer, &addrs)
+                            .await
+                        {
+                            peer_id = Some(peer);
+                            break;
+                        }
+                        let tried: Vec<String> =
+                            addrs.iter().map(|addr| addr.to_string()).collect();
+                        return Err(anyhow!("无法连接到社区节点 ({}): {}", tried.join(", "), e));
                     }
                 }
             }
@@ -545,6 +1188,13 @@ impl AppService {
             return Err(anyhow!("对端 peer 已被封禁"));
         }
 
+        self.advance_connection(
+            community_id,
+            &[ConnectionState::Connecting],
+            ConnectionState::Handshaking,
+        )
+        .await;
+
         // The dial() already waits for Identify protocol to complete
         // Send Hello request immediately to keep connection alive
         tracing::info!(
@@ -557,7 +1207,7 @@ impl AppService {
             .request(
                 peer_id,
                 P2pRequest::Hello {
-                    hello: self.build_hello().await?,
+                    hello: self.build_hello(community_id).await?,
                 },
             )
             .await
@@ -581,13 +1231,26 @@ impl AppService {
             }
         };
         match response {
-            P2pResponse::HelloAck { hello } => {
+            P2pResponse::HelloAck { hello, members } => {
                 if hello.role != "community" {
                     return Err(anyhow!("对端角色不匹配"));
                 }
                 self.store
-                    .upsert_peer(&peer_id.to_string(), &hello.node_id, &hello.role, "online")
+                    .upsert_peer(
+                        &peer_id.to_string(),
+                        &hello.node_id,
+                        &hello.uuid,
+                        &hello.name,
+                        &hello.role,
+                        "online",
+                    )
                     .await?;
+                if let Some(members) = members {
+                    self.store
+                        .set_community_peers(community_id, members)
+                        .await?;
+                }
+                self.pending_invites.write().await.remove(community_id);
             }
             P2pResponse::Error { message } => return Err(anyhow!(message)),
             _ => return Err(anyhow!("握手失败")),
@@ -600,14 +1263,97 @@ impl AppService {
         Ok(peer_id)
     }
 
-    pub async fn secure_connect_service(&self, req: SecureConnectRequest) -> Result<SecureRoute> {
-        if req.relay_peers.len() < 2 {
-            return Err(anyhow!("至少需要两个中继节点"));
+    /// Re-join every joined community after the swarm restarted under a new
+    /// identity and hand what `old` registered and subscribed to over to the
+    /// new peer ID. Communities that cannot be reached get the handover when
+    /// they are reconnected; returns how many accepted it now.
+    pub async fn hand_over_identity(&self, old: &libp2p::identity::Keypair) -> Result<usize> {
+        let new_peer: PeerId = self.p2p.peer_id().parse()?;
+        let handover = IdentityHandover::sign(old, &new_peer)?;
+        self.peer_cache.write().await.clear();
+        let communities = self.store.communities().await?;
+        let mut accepted = 0;
+        for community in communities.into_iter().filter(|c| c.joined) {
+            self.pending_handovers
+                .write()
+                .await
+                .entry(community.id.clone())
+                .or_default()
+                .push(handover.clone());
+            match self.send_pending_handovers(&community.id).await {
+                Ok(()) => accepted += 1,
+                Err(err) => {
+                    tracing::warn!("社区 {} 身份交接失败，重连后重试: {}", community.id, err);
+                    self.set_community_status(&community.id, false, Some(err.to_string()))
+                        .await;
+                }
+            }
         }
-        tracing::info!(
-            "建立安全连接: 订阅 {} 经由 {} 个中继",
-            req.subscription_id,
-            req.relay_peers.len()
+        Ok(accepted)
+    }
+
+    /// Deliver queued identity handovers to a community in order, then watch
+    /// its registry again under the current peer ID.
+    async fn send_pending_handovers(&self, community_id: &str) -> Result<()> {
+        let peer_id = self.ensure_community_peer(community_id).await?;
+        loop {
+            let Some(handover) = self
+                .pending_handovers
+                .read()
+                .await
+                .get(community_id)
+                .and_then(|queue| queue.first().cloned())
+            else {
+                break;
+            };
+            match self
+                .p2p
+                .request(
+                    peer_id,
+                    P2pRequest::HandOverIdentity {
+                        handover: handover.clone(),
+                    },
+                )
+                .await?
+            {
+                P2pResponse::Ack => {
+                    tracing::info!(
+                        "社区 {} 已接受身份交接: {} -> {}",
+                        community_id,
+                        handover.old_peer,
+                        handover.new_peer
+                    );
+                    let mut pending = self.pending_handovers.write().await;
+                    if let Some(queue) = pending.get_mut(community_id) {
+                        queue.remove(0);
+                        if queue.is_empty() {
+                            pending.remove(community_id);
+                        }
+                    }
+                }
+                P2pResponse::Error { message } => return Err(anyhow!(message)),
+                _ => return Err(anyhow!("身份交接失败")),
+            }
+        }
+        self.set_community_status(community_id, true, None).await;
+        if let Err(err) = self.watch_registry(community_id, peer_id).await {
+            tracing::warn!(
+                "[社区连接] 订阅注册表推送失败: id={}, error={}",
+                community_id,
+                err
+            );
+        }
+        Ok(())
+    }
+
+    pub async fn secure_connect_service(&self, req: SecureConnectRequest) -> Result<SecureRoute> {
+        if req.relay_peers.len() < 2 {
+            return Err(anyhow!("至少需要两个中继节点"));
+        }
+        tracing::info!(
+            "建立安全连接: 订阅 {} 经由 {} 个中继",
+            req.subscription_id,
+            req.relay_peers.len()
         );
         let Some(subscription) = self.store.find_subscription(&req.subscription_id).await? else {
             return Err(anyhow!("未找到订阅"));
@@ -667,6 +1413,10 @@ impl AppService {
             self.p2p.clone(),
         )
         .await?;
+        self.events.publish(PortaEvent::RouteStatus {
+            route_id: route_id.clone(),
+            status: route.status.clone(),
+        });
         tracing::info!("安全路由 {} 建立成功，本地端口: {}", route_id, local_port);
         Ok(route)
     }
@@ -676,6 +1426,10 @@ impl AppService {
         if !updated {
             return Err(anyhow!("未找到安全路由"));
         }
+        self.events.publish(PortaEvent::RouteStatus {
+            route_id: id.to_string(),
+            status: "断开".into(),
+        });
         Ok(())
     }
 
@@ -687,6 +1441,7 @@ impl AppService {
             r#type: "omega".into(),
             port: proxy_status.listen_port,
             summary: "HTTP/HTTPS/SOCKS5 代理服务".into(),
+            tags: vec!["proxy".into()],
         };
         let _ = self.publish_service(req).await?;
         Ok(())
@@ -697,50 +1452,275 @@ impl AppService {
         Ok(())
     }
 
+    /// Find addresses for a peer ID. Rendezvous points are asked first since
+    /// they hear from a moved node soonest, then come the addresses this node
+    /// learned itself. Every address ends in `/p2p/<peer>`.
+    pub async fn resolve_peer(&self, peer: &PeerId) -> Result<Vec<Multiaddr>> {
+        let mut found: Vec<Multiaddr> = Vec::new();
+        for point in self.p2p.rendezvous_points() {
+            match self.query_rendezvous(point, peer).await {
+                Ok(addrs) => {
+                    for addr in addrs {
+                        if !found.contains(&addr) {
+                            found.push(addr);
+                        }
+                    }
+                }
+                Err(err) => {
+                    tracing::warn!("向 rendezvous {} 查询 peer {} 失败: {}", point, peer, err)
+                }
+            }
+        }
+        for addr in self.p2p.known_addrs(peer) {
+            if !found.contains(&addr) {
+                found.push(addr);
+            }
+        }
+        if found.is_empty() {
+            return Err(anyhow!("无法解析 peer {} 的地址", peer));
+        }
+        Ok(found)
+    }
+
+    async fn query_rendezvous(&self, point: &Multiaddr, peer: &PeerId) -> Result<Vec<Multiaddr>> {
+        let point_peer = self.hello_rendezvous(point).await?;
+        let response = self
+            .p2p
+            .request(
+                point_peer,
+                P2pRequest::ResolvePeer {
+                    peer_id: peer.to_string(),
+                },
+            )
+            .await?;
+        match response {
+            P2pResponse::PeerAddrs { addrs } => Ok(addrs
+                .iter()
+                .filter_map(|addr| addr.parse::<Multiaddr>().ok())
+                .filter(|addr| extract_peer_id(addr) == Some(*peer))
+                .collect()),
+            P2pResponse::Error { message } => Err(anyhow!(message)),
+            _ => Err(anyhow!("rendezvous 响应无效")),
+        }
+    }
+
+    /// Dial a rendezvous point and say hello so it accepts further requests.
+    async fn hello_rendezvous(&self, point: &Multiaddr) -> Result<PeerId> {
+        let point_peer = self.p2p.dial(point.clone()).await?;
+        match self
+            .p2p
+            .request(
+                point_peer,
+                P2pRequest::Hello {
+                    hello: self.build_hello("").await?,
+                },
+            )
+            .await?
+        {
+            P2pResponse::HelloAck { .. } => Ok(point_peer),
+            P2pResponse::Error { message } => Err(anyhow!(message)),
+            _ => Err(anyhow!("握手失败")),
+        }
+    }
+
+    /// Tell every rendezvous point where this community node can be reached
+    /// now, so edges can find it again after its address changes.
+    pub async fn register_with_rendezvous(&self) -> Result<()> {
+        if self.role != "community" || self.p2p.rendezvous_points().is_empty() {
+            return Ok(());
+        }
+        let node = self.store.node_info().await?;
+        let peer_id: PeerId = self.p2p.peer_id().parse()?;
+        let listeners = self.p2p.listeners().await?;
+        let addrs = crate::invite::invite_addrs(&listeners, &node.external_addr, &peer_id);
+        for point in self.p2p.rendezvous_points() {
+            let result = match self.hello_rendezvous(point).await {
+                Ok(point_peer) => self
+                    .p2p
+                    .request(
+                        point_peer,
+                        P2pRequest::RegisterAddrs {
+                            addrs: addrs.clone(),
+                        },
+                    )
+                    .await
+                    .and_then(|response| match response {
+                        P2pResponse::Error { message } => Err(anyhow!(message)),
+                        _ => Ok(()),
+                    }),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                tracing::warn!("向 rendezvous {} 登记地址失败: {}", point, err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Dial a community at all of `addrs` at once and remember the address
+    /// that answered, so the next dial tries it first.
+    async fn dial_community(
+        &self,
+        community: &CommunitySummary,
+        peer: PeerId,
+        addrs: Vec<Multiaddr>,
+    ) -> Result<PeerId> {
+        let via = self.p2p.dial_any(peer, addrs).await?;
+        if let Some(via) = via.map(|addr| addr.to_string()) {
+            if community.last_multiaddr.as_deref() != Some(via.as_str()) {
+                tracing::info!("[社区连接] 记录可用地址: id={}, addr={}", community.id, via);
+                if let Err(err) = self
+                    .store
+                    .set_community_last_multiaddr(&community.id, &via)
+                    .await
+                {
+                    tracing::warn!("[社区连接] 记录可用地址失败: id={}, {}", community.id, err);
+                }
+            }
+        }
+        Ok(peer)
+    }
+
+    /// Look a community up by peer ID again after none of its stored
+    /// addresses answered. If it is reachable somewhere else the new address
+    /// replaces the stored one.
+    async fn relocate_community(
+        &self,
+        community: &CommunitySummary,
+        peer: PeerId,
+        stale: &[Multiaddr],
+    ) -> Option<PeerId> {
+        let addrs: Vec<Multiaddr> = match self.resolve_peer(&peer).await {
+            Ok(addrs) => addrs
+                .into_iter()
+                .filter(|addr| !stale.contains(addr))
+                .collect(),
+            Err(err) => {
+                tracing::debug!(
+                    "[社区连接] 重新解析社区地址失败: id={}, {}",
+                    community.id,
+                    err
+                );
+                return None;
+            }
+        };
+        if addrs.is_empty() {
+            return None;
+        }
+        let via = match self.p2p.dial_any(peer, addrs.clone()).await {
+            Ok(via) => via.unwrap_or_else(|| addrs[0].clone()),
+            Err(err) => {
+                tracing::debug!("[社区连接] 候选地址不可用: {:?}: {}", addrs, err);
+                return None;
+            }
+        };
+        let addr = via.to_string();
+        if let Err(err) = self
+            .store
+            .update_community_multiaddr(&community.id, &addr, &peer.to_string())
+            .await
+        {
+            tracing::warn!(
+                "[社区连接] 更新 multiaddr 失败: id={}, {}",
+                community.id,
+                err
+            );
+        }
+        let _ = self
+            .store
+            .set_community_last_multiaddr(&community.id, &addr)
+            .await;
+        tracing::info!(
+            "[社区连接] 社区地址已变更: id={}, {:?} -> {}",
+            community.id,
+            community.multiaddr,
+            addr
+        );
+        Some(peer)
+    }
+
+    /// Start reconnecting every joined community that needs it: one that
+    /// was never connected or whose connection dropped, or one in backoff
+    /// whose retry time has come. Healthy connections and attempts already
+    /// under way are left alone; attempts run in the background.
     pub async fn reconnect_communities(&self) -> Result<()> {
+        let now = chrono::Utc::now().timestamp();
         let communities = self.store.communities().await?;
         for community in communities.into_iter().filter(|c| c.joined) {
-            if let Some(addr) = community.multiaddr.clone() {
-                let addr: Multiaddr = match addr.parse() {
-                    Ok(a) => a,
-                    Err(_) => {
-                        tracing::warn!("无效的社区 multiaddr: {}", community.id);
-                        continue;
-                    }
-                };
-                let expected_peer = match extract_peer_id(&addr) {
-                    Some(p) => p,
-                    None => {
-                        tracing::warn!("社区缺少 peerId: {}", community.id);
-                        continue;
-                    }
-                };
-                match self.p2p.dial(addr.clone()).await {
-                    Ok(peer_id) if peer_id == expected_peer => {
-                        tracing::debug!("社区 {} 连接保活成功", community.id);
-                    }
-                    Ok(peer_id) => {
-                        tracing::warn!(
-                            "社区 {} peerId 不匹配: 期望 {}, 实际 {}",
-                            community.id,
-                            expected_peer,
-                            peer_id
-                        );
-                    }
-                    Err(err) => {
-                        tracing::warn!("社区 {} 连接失败: {}", community.id, err);
-                    }
+            let connection = self.connection(&community.id).await;
+            if connection.state == ConnectionState::Connected {
+                if self.community_link_alive(&community.id).await {
+                    continue;
                 }
+                tracing::info!("社区 {} 连接已断开", community.id);
+                self.peer_cache.write().await.remove(&community.id);
+                self.advance_connection(
+                    &community.id,
+                    &[ConnectionState::Connected],
+                    ConnectionState::Disconnected,
+                )
+                .await;
+                self.events.publish(PortaEvent::CommunityStatus {
+                    community_id: community.id.clone(),
+                    connected: false,
+                    error: Some("连接已断开".to_string()),
+                });
+            } else if !connection.is_due(now) {
+                continue;
             }
+            let app = self.clone();
+            tokio::spawn(async move { app.reconnect_community(&community.id).await });
         }
         Ok(())
     }
 
+    /// Whether the peer a community was reached at is still connected.
+    async fn community_link_alive(&self, community_id: &str) -> bool {
+        let peer = self.peer_cache.read().await.get(community_id).copied();
+        match peer {
+            Some(peer) => self.p2p.is_connected(&peer).await,
+            None => false,
+        }
+    }
+
+    async fn reconnect_community(&self, community_id: &str) {
+        let has_handovers = self
+            .pending_handovers
+            .read()
+            .await
+            .contains_key(community_id);
+        let result = if has_handovers {
+            self.advance_connection(
+                community_id,
+                &[ConnectionState::Disconnected, ConnectionState::Backoff],
+                ConnectionState::Connecting,
+            )
+            .await;
+            let result = self.send_pending_handovers(community_id).await;
+            if let Err(err) = &result {
+                self.set_community_status(community_id, false, Some(err.to_string()))
+                    .await;
+            }
+            result
+        } else {
+            self.connect_community(community_id).await
+        };
+        if let Err(err) = result {
+            tracing::warn!("社区 {} 重连失败: {}", community_id, err);
+        }
+    }
+
     pub async fn cleanup_expired_sessions(&self) -> Result<()> {
         let timeout_minutes = 30;
         let removed = self.store.cleanup_expired_sessions(timeout_minutes).await?;
-        if removed > 0 {
-            tracing::info!("清理过期会话 {} 个", removed);
+        if !removed.is_empty() {
+            tracing::info!("清理过期会话 {} 个", removed.len());
+        }
+        for session in removed {
+            self.events.publish(PortaEvent::SessionClosed {
+                session_id: session.session_id,
+                service_id: session.service_id,
+            });
         }
         Ok(())
     }
@@ -754,11 +1734,7 @@ fn parse_local_port(mapping: &str) -> Result<u16> {
     Ok(port_str.parse::<u16>()?)
 }
 
-fn current_role() -> String {
-    std::env::var("PORTA_ROLE").unwrap_or_else(|_| "edge".into())
-}
-
-fn extract_peer_id(addr: &Multiaddr) -> Option<PeerId> {
+pub(crate) fn extract_peer_id(addr: &Multiaddr) -> Option<PeerId> {
     addr.iter().find_map(|protocol| {
         if let libp2p::multiaddr::Protocol::P2p(peer_id) = protocol {
             Some(peer_id)
@@ -768,7 +1744,82 @@ fn extract_peer_id(addr: &Multiaddr) -> Option<PeerId> {
     })
 }
 
-fn compose_remote_addr(provider_addr: &str, port: u16) -> String {
+/// Normalise a community's fallback addresses: each is given `/p2p/<peer>`
+/// if it has none, addresses of any other peer are refused, and duplicates
+/// of `primary` or of each other are dropped.
+pub(crate) fn fallback_addrs(
+    primary: &str,
+    others: &[String],
+    peer: &PeerId,
+) -> Result<Vec<String>> {
+    let mut addrs: Vec<String> = Vec::new();
+    for raw in others
+        .iter()
+        .map(|addr| addr.trim())
+        .filter(|addr| !addr.is_empty())
+    {
+        let mut addr: Multiaddr = raw
+            .parse()
+            .map_err(|_| anyhow!("multiaddr 无效: {}", raw))?;
+        match extract_peer_id(&addr) {
+            Some(other) if other != *peer => {
+                return Err(anyhow!("multiaddr 指向其他节点: {}", raw));
+            }
+            Some(_) => {}
+            None => addr.push(libp2p::multiaddr::Protocol::P2p(*peer)),
+        }
+        let addr = addr.to_string();
+        if addr != primary && !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+    Ok(addrs)
+}
+
+/// The peer a community is expected to be and every address to dial it at:
+/// the one the last successful dial went through first, then `multiaddr`
+/// and the fallbacks in order. `None` when there is no address to dial.
+fn community_dial_addrs(community: &CommunitySummary) -> Option<(PeerId, Vec<Multiaddr>)> {
+    let parsed: Vec<Multiaddr> = community
+        .multiaddr
+        .iter()
+        .chain(community.multiaddrs.iter())
+        .filter_map(|addr| match addr.parse::<Multiaddr>() {
+            Ok(addr) => Some(addr),
+            Err(err) => {
+                tracing::warn!("无效的社区 multiaddr: {}: {} ({})", community.id, addr, err);
+                None
+            }
+        })
+        .collect();
+    let peer = community
+        .multiaddr
+        .as_deref()
+        .and_then(|addr| addr.parse::<Multiaddr>().ok())
+        .and_then(|addr| extract_peer_id(&addr))
+        .or_else(|| community.peer_id.as_deref()?.parse().ok())?;
+    let mut addrs: Vec<Multiaddr> = Vec::new();
+    for addr in parsed {
+        let addr = without_peer(&addr).with(libp2p::multiaddr::Protocol::P2p(peer));
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+    let last = community
+        .last_multiaddr
+        .as_deref()
+        .and_then(|addr| addr.parse::<Multiaddr>().ok());
+    if let Some(index) = last.and_then(|last| addrs.iter().position(|addr| *addr == last)) {
+        let last = addrs.remove(index);
+        addrs.insert(0, last);
+    }
+    if addrs.is_empty() {
+        return None;
+    }
+    Some((peer, addrs))
+}
+
+pub(crate) fn compose_remote_addr(provider_addr: &str, port: u16) -> String {
     if provider_addr.contains(':') {
         provider_addr.to_string()
     } else {
@@ -778,7 +1829,37 @@ fn compose_remote_addr(provider_addr: &str, port: u16) -> String {
 
 #[cfg(test)]
 mod tests {
-    use super::compose_remote_addr;
+    use super::{community_dial_addrs, compose_remote_addr, fallback_addrs};
+    use crate::{
+        config::BackendConfig,
+        models::{
+            AdmissionMode, CommunityAddRequest, CommunitySummary, ConnectionState, NodeBanRequest,
+            PeerLimits, PublishRequest, ServiceChange, ServiceQuery, ServiceRegistryItem,
+            SubscribeRequest,
+        },
+        p2p::{protocol::ServiceAnnouncement, P2pRequest, P2pResponse},
+        state::AppState,
+    };
+
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    async fn node(role: &str, port: u16, rendezvous_points: Vec<String>) -> AppState {
+        AppState::with_config(BackendConfig {
+            role: role.into(),
+            db_path: ":memory:".into(),
+            listen_addrs: vec![format!("/ip4/127.0.0.1/tcp/{}", port)],
+            rendezvous_points,
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+    }
 
     #[test]
     fn should_compose_remote_addr() {
@@ -788,4 +1869,780 @@ mod tests {
             "127.0.0.1:9000"
         );
     }
+
+    #[test]
+    fn should_dial_last_working_address_first() {
+        let peer = libp2p::PeerId::random();
+        let primary = format!("/ip4/10.0.0.1/tcp/4001/p2p/{}", peer);
+        let fallbacks = fallback_addrs(
+            &primary,
+            &[
+                "/ip6/2001:db8::1/tcp/4001".into(),
+                primary.clone(),
+                format!("/dns/lab.example.com/tcp/4001/p2p/{}", peer),
+            ],
+            &peer,
+        )
+        .unwrap();
+        assert_eq!(
+            fallbacks,
+            vec![
+                format!("/ip6/2001:db8::1/tcp/4001/p2p/{}", peer),
+                format!("/dns/lab.example.com/tcp/4001/p2p/{}", peer),
+            ]
+        );
+        let other = format!("/ip4/10.0.0.2/tcp/4001/p2p/{}", libp2p::PeerId::random());
+        assert!(fallback_addrs(&primary, &[other], &peer).is_err());
+
+        let mut community = CommunitySummary {
+            id: "lab".into(),
+            name: "Lab".into(),
+            description: "Lab".into(),
+            peers: 0,
+            joined: true,
+            multiaddr: Some(primary.clone()),
+            multiaddrs: fallbacks.clone(),
+            last_multiaddr: Some(fallbacks[1].clone()),
+            peer_id: Some(peer.to_string()),
+            managed: false,
+            state: Default::default(),
+            connect_failures: 0,
+            last_error: None,
+            next_retry_at: None,
+        };
+        let (expected, addrs) = community_dial_addrs(&community).unwrap();
+        assert_eq!(expected, peer);
+        let addrs: Vec<String> = addrs.iter().map(|addr| addr.to_string()).collect();
+        assert_eq!(
+            addrs,
+            vec![fallbacks[1].clone(), primary, fallbacks[0].clone()]
+        );
+
+        community.multiaddr = None;
+        community.multiaddrs.clear();
+        assert!(community_dial_addrs(&community).is_none());
+    }
+
+    #[tokio::test]
+    async fn should_fall_back_to_next_address() {
+        let port = free_port();
+        let community = node("community", port, Vec::new()).await;
+        let edge = node("edge", free_port(), Vec::new()).await;
+        let peer = community.p2p.peer_id();
+        let live = format!("/ip4/127.0.0.1/tcp/{}/p2p/{}", port, peer);
+        let joined = edge
+            .app
+            .add_community(CommunityAddRequest {
+                id: Some("lab".into()),
+                name: "Lab".into(),
+                description: "test community".into(),
+                multiaddr: Some(format!("/ip4/127.0.0.1/tcp/{}/p2p/{}", free_port(), peer)),
+                multiaddrs: vec![format!("/ip4/127.0.0.1/tcp/{}", port)],
+                peer_id: None,
+                invite: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(joined.multiaddrs, vec![live.clone()]);
+
+        edge.app.connect_community("lab").await.unwrap();
+        let lab = edge.store.community_by_id("lab").await.unwrap().unwrap();
+        assert_eq!(lab.last_multiaddr.as_deref(), Some(live.as_str()));
+
+        // A healthy connection is not dialed again
+        edge.app.reconnect_communities().await.unwrap();
+        let lab = edge.app.communities().await.unwrap().remove(0);
+        assert_eq!(lab.state, ConnectionState::Connected);
+        assert_eq!(lab.connect_failures, 0);
+    }
+
+    #[tokio::test]
+    async fn should_register_edge_as_community_member() {
+        let port = free_port();
+        let community = node("community", port, Vec::new()).await;
+        let edge = node("edge", free_port(), Vec::new()).await;
+        edge.app
+            .add_community(CommunityAddRequest {
+                id: Some("lab".into()),
+                name: "Lab".into(),
+                description: "test community".into(),
+                multiaddr: Some(format!(
+                    "/ip4/127.0.0.1/tcp/{}/p2p/{}",
+                    port,
+                    community.p2p.peer_id()
+                )),
+                multiaddrs: Vec::new(),
+                peer_id: None,
+                invite: None,
+            })
+            .await
+            .unwrap();
+        edge.app.connect_community("lab").await.unwrap();
+
+        let members = community.store.community_nodes().await.unwrap();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].id, edge.p2p.peer_id());
+        assert_eq!(members[0].name, edge.store.node_info().await.unwrap().name);
+        assert!(members[0].online);
+        assert!(members[0].joined_at.is_some());
+        let lab = edge.store.community_by_id("lab").await.unwrap().unwrap();
+        assert_eq!(lab.peers, 1);
+    }
+
+    #[tokio::test]
+    async fn should_only_take_service_changes_from_the_subscribed_community() {
+        let port = free_port();
+        let community = node("community", port, Vec::new()).await;
+        let edge_port = free_port();
+        let edge = node("edge", edge_port, Vec::new()).await;
+        let edge_peer: libp2p::PeerId = edge.p2p.peer_id().parse().unwrap();
+        edge.app
+            .add_community(CommunityAddRequest {
+                id: Some("lab".into()),
+                name: "Lab".into(),
+                description: "test community".into(),
+                multiaddr: Some(format!(
+                    "/ip4/127.0.0.1/tcp/{}/p2p/{}",
+                    port,
+                    community.p2p.peer_id()
+                )),
+                multiaddrs: Vec::new(),
+                peer_id: None,
+                invite: None,
+            })
+            .await
+            .unwrap();
+        edge.app.connect_community("lab").await.unwrap();
+        let sub = edge
+            .store
+            .subscribe_service(SubscribeRequest {
+                id: None,
+                service_uuid: Some("svc-1".into()),
+                name: "SSH".into(),
+                r#type: "TCP".into(),
+                community: "Lab".into(),
+                remote_addr: "10.0.0.1:22".into(),
+                local_mapping: "127.0.0.1:2222".into(),
+            })
+            .await
+            .unwrap();
+
+        // Any node can claim the community role in its Hello
+        let rogue = node("community", free_port(), Vec::new()).await;
+        rogue
+            .app
+            .add_community(CommunityAddRequest {
+                id: Some("victim".into()),
+                name: "Victim".into(),
+                description: "edge under attack".into(),
+                multiaddr: Some(format!(
+                    "/ip4/127.0.0.1/tcp/{}/p2p/{}",
+                    edge_port, edge_peer
+                )),
+                multiaddrs: Vec::new(),
+                peer_id: None,
+                invite: None,
+            })
+            .await
+            .unwrap();
+        // The rogue rejects the edge's reply, but the edge has recorded its claim by then
+        let _ = rogue.app.connect_community("victim").await;
+        for change in [
+            ServiceChange::Updated {
+                provider_addr: "203.0.113.7".into(),
+                port: 22,
+            },
+            ServiceChange::Removed,
+        ] {
+            let reply = rogue
+                .p2p
+                .request(
+                    edge_peer,
+                    P2pRequest::ServiceStatusChanged {
+                        service_uuid: "svc-1".into(),
+                        change,
+                    },
+                )
+                .await;
+            assert!(
+                matches!(reply, Ok(P2pResponse::Error { .. })),
+                "{:?}",
+                reply
+            );
+        }
+        let kept = edge
+            .store
+            .find_subscription(&sub.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(kept.remote_addr, "10.0.0.1:22");
+        assert_eq!(kept.status, sub.status);
+
+        let reply = community
+            .p2p
+            .request(
+                edge_peer,
+                P2pRequest::ServiceStatusChanged {
+                    service_uuid: "svc-1".into(),
+                    change: ServiceChange::Offline,
+                },
+            )
+            .await;
+        assert!(matches!(reply, Ok(P2pResponse::Ack)), "{:?}", reply);
+        let offline = edge
+            .store
+            .find_subscription(&sub.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(offline.status, "离线");
+    }
+
+    #[tokio::test]
+    async fn should_track_provider_online_state() {
+        let port = free_port();
+        let community = node("community", port, Vec::new()).await;
+        let provider = node("edge", free_port(), Vec::new()).await;
+        let subscriber = node("edge", free_port(), Vec::new()).await;
+        for edge in [&provider, &subscriber] {
+            edge.app
+                .add_community(CommunityAddRequest {
+                    id: Some("lab".into()),
+                    name: "Lab".into(),
+                    description: "test community".into(),
+                    multiaddr: Some(format!(
+                        "/ip4/127.0.0.1/tcp/{}/p2p/{}",
+                        port,
+                        community.p2p.peer_id()
+                    )),
+                    multiaddrs: Vec::new(),
+                    peer_id: None,
+                    invite: None,
+                })
+                .await
+                .unwrap();
+            edge.app.connect_community("lab").await.unwrap();
+        }
+        let published = provider
+            .app
+            .publish_service(PublishRequest {
+                id: None,
+                name: "SSH".into(),
+                r#type: "TCP".into(),
+                port: 22,
+                summary: String::new(),
+                tags: Vec::new(),
+            })
+            .await
+            .unwrap();
+        subscriber
+            .app
+            .discover_services(Some("lab".into()), ServiceQuery::default())
+            .await
+            .unwrap();
+        let sub = subscriber
+            .app
+            .subscribe_service(SubscribeRequest {
+                id: None,
+                service_uuid: Some(published.id.clone()),
+                name: "SSH".into(),
+                r#type: "TCP".into(),
+                community: "Lab".into(),
+                remote_addr: String::new(),
+                local_mapping: "127.0.0.1:2222".into(),
+            })
+            .await
+            .unwrap();
+
+        // Restarting the swarm drops the provider's connection
+        provider.p2p.restart(provider.p2p.keypair()).await.unwrap();
+        let mut status = String::new();
+        for _ in 0..50 {
+            status = subscriber
+                .store
+                .find_subscription(&sub.id)
+                .await
+                .unwrap()
+                .unwrap()
+                .status;
+            if status == "离线" {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert_eq!(status, "离线");
+        let entry = community
+            .store
+            .resolve_service_registry(&published.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!entry.online);
+        let listed = subscriber
+            .app
+            .discover_services(Some("lab".into()), ServiceQuery::default())
+            .await
+            .unwrap();
+        assert!(listed.items.is_empty());
+
+        // Reconnecting brings the services back: the watching edge gets them
+        // pushed and the subscription can be reconnected
+        provider.app.connect_community("lab").await.unwrap();
+        let mut cached = Vec::new();
+        for _ in 0..50 {
+            cached = subscriber
+                .store
+                .discovered_services(Some("lab".into()))
+                .await
+                .unwrap();
+            status = subscriber
+                .store
+                .find_subscription(&sub.id)
+                .await
+                .unwrap()
+                .unwrap()
+                .status;
+            if !cached.is_empty() && status != "离线" {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert_eq!(cached.len(), 1);
+        assert!(cached[0].online);
+        assert_eq!(status, "断开");
+        assert!(
+            community
+                .store
+                .resolve_service_registry(&published.id)
+                .await
+                .unwrap()
+                .unwrap()
+                .online
+        );
+    }
+
+    #[tokio::test]
+    async fn should_hold_edges_until_approved() {
+        let port = free_port();
+        let community = node("community", port, Vec::new()).await;
+        community
+            .store
+            .set_admission_mode(AdmissionMode::Approval)
+            .await
+            .unwrap();
+        let lab = |id: &str| CommunityAddRequest {
+            id: Some(id.into()),
+            name: "Lab".into(),
+            description: "test community".into(),
+            multiaddr: Some(format!(
+                "/ip4/127.0.0.1/tcp/{}/p2p/{}",
+                port,
+                community.p2p.peer_id()
+            )),
+            multiaddrs: Vec::new(),
+            peer_id: None,
+            invite: None,
+        };
+        let edge = node("edge", free_port(), Vec::new()).await;
+        edge.app.add_community(lab("lab")).await.unwrap();
+
+        let err = edge.app.connect_community("lab").await.unwrap_err();
+        assert!(err.to_string().contains("等待社区管理员审批"), "{}", err);
+        assert!(community.store.community_nodes().await.unwrap().is_empty());
+        let requests = community.store.join_requests().await.unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].peer_id, edge.p2p.peer_id());
+        assert_eq!(requests[0].status, "pending");
+
+        community
+            .app
+            .approve_join_request(&edge.p2p.peer_id())
+            .await
+            .unwrap();
+        assert!(community.store.join_requests().await.unwrap().is_empty());
+        edge.app.connect_community("lab").await.unwrap();
+        assert!(community.store.community_nodes().await.unwrap()[0].online);
+
+        // Invite-only communities turn away edges without an invite
+        community
+            .store
+            .set_admission_mode(AdmissionMode::Invite)
+            .await
+            .unwrap();
+        let stranger = node("edge", free_port(), Vec::new()).await;
+        stranger.app.add_community(lab("lab")).await.unwrap();
+        let err = stranger.app.connect_community("lab").await.unwrap_err();
+        assert!(err.to_string().contains("社区仅接受邀请加入"), "{}", err);
+        assert!(community.store.join_requests().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_refuse_unadmitted_peers_whatever_role_they_claim() {
+        let port = free_port();
+        let community = node("community", port, Vec::new()).await;
+        community
+            .store
+            .set_admission_mode(AdmissionMode::Invite)
+            .await
+            .unwrap();
+        community
+            .store
+            .upsert_service_registry(ServiceRegistryItem {
+                uuid: "svc-ssh".into(),
+                name: "ssh".into(),
+                r#type: "TCP".into(),
+                port: 22,
+                description: String::new(),
+                provider_peer: community.p2p.peer_id(),
+                provider_addr: String::new(),
+                online: true,
+                tags: Vec::new(),
+            })
+            .await
+            .unwrap();
+        let community_peer: libp2p::PeerId = community.p2p.peer_id().parse().unwrap();
+
+        // A peer that says Hello as a community carries no invite and must
+        // not get further than an edge without one
+        let rogue = node("community", free_port(), Vec::new()).await;
+        rogue
+            .app
+            .add_community(CommunityAddRequest {
+                id: Some("lab".into()),
+                name: "Lab".into(),
+                description: "test community".into(),
+                multiaddr: Some(format!(
+                    "/ip4/127.0.0.1/tcp/{}/p2p/{}",
+                    port, community_peer
+                )),
+                multiaddrs: Vec::new(),
+                peer_id: None,
+                invite: None,
+            })
+            .await
+            .unwrap();
+        let err = rogue.app.connect_community("lab").await.unwrap_err();
+        assert!(err.to_string().contains("社区仅接受邀请加入"), "{}", err);
+        assert_eq!(
+            community
+                .store
+                .peer_role(&rogue.p2p.peer_id())
+                .await
+                .unwrap(),
+            None
+        );
+
+        let response = rogue
+            .p2p
+            .request(
+                community_peer,
+                P2pRequest::BuildRelayRoute {
+                    service_uuid: "svc-ssh".into(),
+                    relay_chain: Vec::new(),
+                    initiator_peer: rogue.p2p.peer_id(),
+                },
+            )
+            .await
+            .unwrap();
+        assert!(
+            matches!(response, P2pResponse::Error { .. }),
+            "{:?}",
+            response
+        );
+    }
+
+    #[tokio::test]
+    async fn should_disconnect_and_refuse_banned_edges() {
+        let port = free_port();
+        let community = node("community", port, Vec::new()).await;
+        let lab = CommunityAddRequest {
+            id: Some("lab".into()),
+            name: "Lab".into(),
+            description: "test community".into(),
+            multiaddr: Some(format!(
+                "/ip4/127.0.0.1/tcp/{}/p2p/{}",
+                port,
+                community.p2p.peer_id()
+            )),
+            multiaddrs: Vec::new(),
+            peer_id: None,
+            invite: None,
+        };
+        let edge = node("edge", free_port(), Vec::new()).await;
+        edge.app.add_community(lab.clone()).await.unwrap();
+        edge.app.connect_community("lab").await.unwrap();
+        let edge_peer: libp2p::PeerId = edge.p2p.peer_id().parse().unwrap();
+        community
+            .store
+            .upsert_service_registry(ServiceRegistryItem {
+                uuid: "svc-ssh".into(),
+                name: "ssh".into(),
+                r#type: "TCP".into(),
+                port: 22,
+                description: String::new(),
+                provider_peer: edge_peer.to_string(),
+                provider_addr: String::new(),
+                online: true,
+                tags: Vec::new(),
+            })
+            .await
+            .unwrap();
+
+        let uuid = edge.store.node_info().await.unwrap().uuid;
+        let banned = community
+            .app
+            .ban_node(NodeBanRequest {
+                uuid: Some(uuid.clone()),
+                reason: Some("abuse".into()),
+                expires_in_secs: Some(3600),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(banned, vec![edge_peer.to_string()]);
+        assert!(community
+            .store
+            .resolve_service_registry("svc-ssh")
+            .await
+            .unwrap()
+            .is_none());
+        let member = community.store.community_nodes().await.unwrap().remove(0);
+        assert!(member.banned);
+        assert_eq!(member.ban_reason.as_deref(), Some("abuse"));
+        for _ in 0..50 {
+            if !community.p2p.is_connected(&edge_peer).await {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert!(!community.p2p.is_connected(&edge_peer).await);
+        assert!(edge.app.connect_community("lab").await.is_err());
+
+        // A node returning under a new peer ID keeps its ban
+        let rotated = node("edge", free_port(), Vec::new()).await;
+        let rotated_uuid = rotated.store.node_info().await.unwrap().uuid;
+        community
+            .store
+            .upsert_peer("peer-old", "node-old", &rotated_uuid, "", "edge", "offline")
+            .await
+            .unwrap();
+        community
+            .store
+            .set_node_ban("peer-old", true, Some("abuse"), None)
+            .await
+            .unwrap();
+        rotated.app.add_community(lab).await.unwrap();
+        let err = rotated.app.connect_community("lab").await.unwrap_err();
+        assert!(err.to_string().contains("已被封禁"), "{}", err);
+        let rotated_peer: libp2p::PeerId = rotated.p2p.peer_id().parse().unwrap();
+        assert!(community
+            .store
+            .peer_is_banned(&rotated_peer.to_string())
+            .await
+            .unwrap());
+        community.app.sync_bans().await.unwrap();
+        for _ in 0..50 {
+            if !community.p2p.is_connected(&rotated_peer).await {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert!(!community.p2p.is_connected(&rotated_peer).await);
+
+        community
+            .app
+            .unban_node(NodeBanRequest {
+                id: Some(edge_peer.to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        edge.app.connect_community("lab").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_throttle_and_auto_ban_noisy_edges() {
+        let port = free_port();
+        let community = AppState::with_config(BackendConfig {
+            role: "community".into(),
+            db_path: ":memory:".into(),
+            listen_addrs: vec![format!("/ip4/127.0.0.1/tcp/{}", port)],
+            limits: PeerLimits {
+                requests_per_sec: Some(10),
+                services_per_provider: Some(1),
+                auto_ban_after: Some(3),
+                auto_ban_secs: Some(600),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let community_peer: libp2p::PeerId = community.p2p.peer_id().parse().unwrap();
+        let edge = node("edge", free_port(), Vec::new()).await;
+        edge.app
+            .add_community(CommunityAddRequest {
+                id: Some("lab".into()),
+                name: "Lab".into(),
+                description: "test community".into(),
+                multiaddr: Some(format!(
+                    "/ip4/127.0.0.1/tcp/{}/p2p/{}",
+                    port, community_peer
+                )),
+                multiaddrs: Vec::new(),
+                peer_id: None,
+                invite: None,
+            })
+            .await
+            .unwrap();
+        edge.app.connect_community("lab").await.unwrap();
+        // Let the handshake's requests fall out of the rate window
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+        let publish = |uuid: &str| P2pRequest::PublishService {
+            service: ServiceAnnouncement {
+                uuid: uuid.into(),
+                name: uuid.into(),
+                r#type: "TCP".into(),
+                port: 22,
+                provider_peer: edge.p2p.peer_id(),
+                provider_addr: String::new(),
+                description: String::new(),
+                online: true,
+                tags: Vec::new(),
+            },
+        };
+        let first = edge.p2p.request(community_peer, publish("svc-1")).await;
+        assert!(matches!(first, Ok(P2pResponse::Ack)), "{:?}", first);
+        // Re-publishing a known entry is not a new service
+        let again = edge.p2p.request(community_peer, publish("svc-1")).await;
+        assert!(matches!(again, Ok(P2pResponse::Ack)), "{:?}", again);
+        match edge.p2p.request(community_peer, publish("svc-2")).await {
+            Ok(P2pResponse::Error { message }) => {
+                assert!(message.contains("服务数量"), "{}", message)
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+
+        let mut throttled = false;
+        for _ in 0..20 {
+            match edge.p2p.request(community_peer, publish("svc-1")).await {
+                Ok(P2pResponse::Error { message }) if message.contains("请求过于频繁") => {
+                    throttled = true
+                }
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        assert!(throttled);
+        let edge_peer = edge.p2p.peer_id();
+        let ban = community.store.peer_ban(&edge_peer).await.unwrap().unwrap();
+        assert!(ban.reason.unwrap().starts_with("自动封禁"));
+        assert!(ban.until.is_some());
+        assert!(community
+            .store
+            .resolve_service_registry("svc-1")
+            .await
+            .unwrap()
+            .is_none());
+        let edge_peer: libp2p::PeerId = edge_peer.parse().unwrap();
+        for _ in 0..50 {
+            if !community.p2p.is_connected(&edge_peer).await {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert!(!community.p2p.is_connected(&edge_peer).await);
+    }
+
+    #[tokio::test]
+    async fn should_back_off_from_unreachable_community() {
+        let edge = node("edge", free_port(), Vec::new()).await;
+        let peer = libp2p::PeerId::random();
+        edge.app
+            .add_community(CommunityAddRequest {
+                id: Some("lab".into()),
+                name: "Lab".into(),
+                description: "test community".into(),
+                multiaddr: Some(format!("/ip4/127.0.0.1/tcp/{}/p2p/{}", free_port(), peer)),
+                multiaddrs: Vec::new(),
+                peer_id: None,
+                invite: None,
+            })
+            .await
+            .unwrap();
+        edge.store.connect_community("lab").await.unwrap();
+        let before = chrono::Utc::now().timestamp();
+        assert!(edge.app.connect_community("lab").await.is_err());
+
+        let lab = edge.app.communities().await.unwrap().remove(0);
+        assert_eq!(lab.state, ConnectionState::Backoff);
+        assert_eq!(lab.connect_failures, 1);
+        assert!(lab.last_error.is_some());
+        let retry_at = lab.next_retry_at.unwrap();
+        assert!(retry_at > before);
+
+        // Not due yet, so the maintenance pass leaves it alone
+        edge.app.reconnect_communities().await.unwrap();
+        let lab = edge.app.communities().await.unwrap().remove(0);
+        assert_eq!(lab.state, ConnectionState::Backoff);
+        assert_eq!(lab.next_retry_at, Some(retry_at));
+
+        let stored = edge.store.community_by_id("lab").await.unwrap().unwrap();
+        assert_eq!(stored.connect_failures, 1);
+        assert_eq!(stored.next_retry_at, Some(retry_at));
+    }
+
+    #[tokio::test]
+    async fn should_find_community_by_peer_id_after_it_moves() {
+        let rendezvous_port = free_port();
+        let rendezvous = node("community", rendezvous_port, Vec::new()).await;
+        let point = vec![format!(
+            "/ip4/127.0.0.1/tcp/{}/p2p/{}",
+            rendezvous_port,
+            rendezvous.p2p.peer_id()
+        )];
+        let community_port = free_port();
+        let community = node("community", community_port, point.clone()).await;
+        community.app.register_with_rendezvous().await.unwrap();
+
+        let edge = node("edge", 0, point).await;
+        let joined = edge
+            .app
+            .add_community(CommunityAddRequest {
+                id: Some("lab".into()),
+                name: "Lab".into(),
+                description: "test community".into(),
+                multiaddr: None,
+                multiaddrs: Vec::new(),
+                peer_id: Some(community.p2p.peer_id()),
+                invite: None,
+            })
+            .await
+            .unwrap();
+        let expected = format!(
+            "/ip4/127.0.0.1/tcp/{}/p2p/{}",
+            community_port,
+            community.p2p.peer_id()
+        );
+        assert_eq!(joined.multiaddr.as_deref(), Some(expected.as_str()));
+
+        // A stored address nobody answers on any more is replaced by the
+        // one the rendezvous point knows
+        let stale = format!(
+            "/ip4/127.0.0.1/tcp/{}/p2p/{}",
+            free_port(),
+            community.p2p.peer_id()
+        );
+        edge.store
+            .update_community_multiaddr("lab", &stale, &community.p2p.peer_id())
+            .await
+            .unwrap();
+        edge.app.connect_community("lab").await.unwrap();
+        assert_eq!(
+            edge.store.community_multiaddr("lab").await.unwrap(),
+            Some(expected)
+        );
+    }
 }
@@ -0,0 +1,510 @@
+//! Backup and restore of the whole node state.
+//!
+//! An export is a single JSON document holding the node settings, identity
+//! key, communities, subscriptions, published services, secure routes and
+//! proxy status. Since it carries the private key, the state is always sealed
+//! with ChaCha20-Poly1305 under an Argon2id-derived key from a passphrase.
+//! Imports write through the `Store` trait only, so any store can be restored
+//! from any other.
+
+use std::collections::HashSet;
+
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use libp2p::{identity::Keypair, PeerId};
+
+use crate::{
+    crypto::{self, Sealed, KDF},
+    models::{
+        BackupArchive, BackupData, BackupImportReport, BackupImportRequest, CommunityAddRequest,
+        ConflictPolicy, EncryptedBackup, NodeConfigUpdate, PublishRequest, SubscribeRequest,
+    },
+    state::AppState,
+};
+
+pub const BACKUP_FORMAT: &str = "porta-backup";
+pub const BACKUP_VERSION: u32 = 1;
+
+/// Snapshot the node into an archive encrypted with `passphrase`. Exporting
+/// without one is refused, as the archive would hold the identity key in plain.
+pub async fn export(state: &AppState, passphrase: Option<&str>) -> Result<BackupArchive> {
+    let passphrase = passphrase
+        .filter(|p| !p.is_empty())
+        .ok_or_else(|| anyhow!("备份包含节点私钥, 必须提供加密口令"))?;
+    let data = BackupData {
+        node: state.store.node_info().await?,
+        identity: BASE64.encode(state.p2p.keypair_bytes()?),
+        communities: state.store.communities().await?,
+        subscriptions: state.store.subscribed_services().await?,
+        published: state.store.published_services().await?,
+        secure_routes: state.store.secure_routes().await?,
+        proxy: state.store.proxy_status().await?,
+    };
+    Ok(BackupArchive {
+        format: BACKUP_FORMAT.into(),
+        version: BACKUP_VERSION,
+        created_at: chrono::Utc::now().to_rfc3339(),
+        data: None,
+        encrypted: Some(seal(&data, passphrase)?),
+    })
+}
+
+/// Restore an archive into the running node. Entries whose ID already exists
+/// follow `on_conflict`; entries managed by the config file are never
+/// overwritten. A restored identity replaces the running one right away, so
+/// communities are joined and services announced under it.
+pub async fn import(state: &AppState, req: BackupImportRequest) -> Result<BackupImportReport> {
+    let data = open_archive(req.archive, req.passphrase.as_deref())?;
+    let policy = req.on_conflict;
+    let kept = plan(state, &data, policy).await?;
+    let mut report = BackupImportReport::default();
+
+    let mut to_join = Vec::new();
+    for community in &data.communities {
+        if !mark(&mut report, &kept, format!("community/{}", community.id)) {
+            continue;
+        }
+        state
+            .store
+            .upsert_community(CommunityAddRequest {
+                id: Some(community.id.clone()),
+                name: community.name.clone(),
+                description: community.description.clone(),
+                multiaddr: community.multiaddr.clone(),
+                multiaddrs: community.multiaddrs.clone(),
+                peer_id: community.peer_id.clone(),
+                invite: None,
+            })
+            .await?;
+        if community.joined {
+            state.store.connect_community(&community.id).await?;
+            to_join.push(community.id.clone());
+        }
+    }
+
+    for subscription in &data.subscriptions {
+        if !mark(
+            &mut report,
+            &kept,
+            format!("subscription/{}", subscription.id),
+        ) {
+            continue;
+        }
+        state
+            .store
+            .subscribe_service(SubscribeRequest {
+                id: Some(subscription.id.clone()),
+                service_uuid: subscription.service_uuid.clone(),
+                name: subscription.name.clone(),
+                r#type: subscription.r#type.clone(),
+                community: subscription.community.clone(),
+                remote_addr: subscription.remote_addr.clone(),
+                local_mapping: subscription.local_mapping.clone(),
+            })
+            .await?;
+        // No mapping is running for it yet
+        state
+            .store
+            .update_subscription_status(&subscription.id, "断开")
+            .await?;
+    }
+
+    let mut announce = Vec::new();
+    for service in &data.published {
+        if !mark(&mut report, &kept, format!("publish/{}", service.id)) {
+            continue;
+        }
+        let saved = state
+            .store
+            .publish_service(PublishRequest {
+                id: Some(service.id.clone()),
+                name: service.name.clone(),
+                r#type: service.r#type.clone(),
+                port: service.port,
+                summary: service.summary.clone(),
+                tags: service.tags.clone(),
+            })
+            .await?;
+        if service.status == "已下架" {
+            state.store.unpublish_service(&service.id).await?;
+        } else {
+            announce.push(saved);
+        }
+    }
+
+    for route in &data.secure_routes {
+        if !mark(&mut report, &kept, format!("route/{}", route.id)) {
+            continue;
+        }
+        state.store.remove_secure_route(&route.id).await?;
+        let mut route = route.clone();
+        route.status = "断开".into();
+        state.store.add_secure_route(route).await?;
+    }
+
+    let current_proxy = state.store.proxy_status().await?;
+    let mut proxy_enabled = None;
+    if mark(&mut report, &kept, "proxy".into()) {
+        if data.proxy.listen_port != current_proxy.listen_port {
+            // The listener is bound once at startup
+            state
+                .store
+                .set_proxy_listen_port(data.proxy.listen_port)
+                .await?;
+            report.restart_required.push("proxy.listen_port".into());
+        }
+        if data.proxy.enabled != current_proxy.enabled {
+            if data.proxy.enabled {
+                state.proxy_server.start().await?;
+            } else {
+                state.proxy_server.stop().await?;
+            }
+            state.store.set_proxy_enabled(data.proxy.enabled).await?;
+            proxy_enabled = Some(data.proxy.enabled);
+        }
+    }
+
+    let current_node = state.store.node_info().await?;
+    let node = &data.node;
+    state
+        .store
+        .update_node_config(NodeConfigUpdate {
+            name: Some(node.name.clone()),
+            tcp_listen_enable: Some(node.tcp_listen_enable),
+            tcp_listen_port: Some(node.tcp_listen_port),
+            quci_listen_enable: Some(node.quci_listen_enable),
+            quci_listen_port: Some(node.quci_listen_port),
+            external_addr: Some(node.external_addr.clone()),
+            mdns_enable: Some(node.mdns_enable),
+            dht_enable: Some(node.dht_enable),
+        })
+        .await?;
+    report.imported.push("node".into());
+    let listeners_changed = node.tcp_listen_enable != current_node.tcp_listen_enable
+        || node.tcp_listen_port != current_node.tcp_listen_port
+        || node.quci_listen_enable != current_node.quci_listen_enable
+        || node.quci_listen_port != current_node.quci_listen_port
+        || node.mdns_enable != current_node.mdns_enable
+        || node.dht_enable != current_node.dht_enable;
+    if listeners_changed {
+        report.restart_required.push("node".into());
+    }
+
+    if !req.keep_identity {
+        restore_identity(state, &data.identity, &mut report).await?;
+    }
+    tracing::info!(
+        "备份已导入: 导入 {:?}, 跳过 {:?}, 需重启 {:?}",
+        report.imported,
+        report.skipped,
+        report.restart_required
+    );
+
+    let app = state.app.clone();
+    tokio::spawn(async move {
+        for id in to_join {
+            if let Err(err) = app.connect_community(&id).await {
+                tracing::warn!("加入导入的社区 {} 失败, 将在保活时重试: {}", id, err);
+            }
+        }
+        for service in announce {
+            if let Err(err) = app.announce_service(&service).await {
+                tracing::warn!("发布导入的服务 {} 失败: {}", service.id, err);
+            }
+        }
+        let result = match proxy_enabled {
+            Some(true) => app.publish_proxy_service().await,
+            Some(false) => app.unpublish_proxy_service().await,
+            None => Ok(()),
+        };
+        if let Err(err) = result {
+            tracing::warn!("同步代理服务发布状态失败: {}", err);
+        }
+    });
+    Ok(report)
+}
+
+/// Keys of archived entries that must not be written. Fails up front under
+/// `ConflictPolicy::Fail` so that nothing is imported partially.
+async fn plan(
+    state: &AppState,
+    data: &BackupData,
+    policy: ConflictPolicy,
+) -> Result<HashSet<String>> {
+    // (key, managed by the config file) for every archived entry that already exists
+    let mut existing: Vec<(String, bool)> = Vec::new();
+    let communities = state.store.communities().await?;
+    for item in &data.communities {
+        if let Some(current) = communities.iter().find(|c| c.id == item.id) {
+            existing.push((format!("community/{}", item.id), current.managed));
+        }
+    }
+    let subscriptions = state.store.subscribed_services().await?;
+    for item in &data.subscriptions {
+        if subscriptions.iter().any(|s| s.id == item.id) {
+            existing.push((format!("subscription/{}", item.id), false));
+        }
+    }
+    let published = state.store.published_services().await?;
+    for item in &data.published {
+        if let Some(current) = published.iter().find(|p| p.id == item.id) {
+            existing.push((format!("publish/{}", item.id), current.managed));
+        }
+    }
+    let routes = state.store.secure_routes().await?;
+    for item in &data.secure_routes {
+        if routes.iter().any(|r| r.id == item.id) {
+            existing.push((format!("route/{}", item.id), false));
+        }
+    }
+    if state.store.proxy_status().await?.managed {
+        existing.push(("proxy".into(), true));
+    }
+
+    if policy == ConflictPolicy::Fail && !existing.is_empty() {
+        let keys: Vec<&str> = existing.iter().map(|(key, _)| key.as_str()).collect();
+        bail!("备份与现有数据冲突: {}", keys.join(", "));
+    }
+    Ok(existing
+        .into_iter()
+        .filter(|(_, managed)| *managed || policy == ConflictPolicy::Skip)
+        .map(|(key, _)| key)
+        .collect())
+}
+
+/// Record the entry as imported, or as skipped when it is in `kept`.
+fn mark(report: &mut BackupImportReport, kept: &HashSet<String>, key: String) -> bool {
+    if kept.contains(&key) {
+        report.skipped.push(key);
+        false
+    } else {
+        report.imported.push(key);
+        true
+    }
+}
+
+async fn restore_identity(
+    state: &AppState,
+    identity: &str,
+    report: &mut BackupImportReport,
+) -> Result<()> {
+    let bytes = BASE64
+        .decode(identity)
+        .map_err(|err| anyhow!("备份中的密钥无效: {}", err))?;
+    let keypair = Keypair::from_protobuf_encoding(&bytes)
+        .map_err(|err| anyhow!("备份中的密钥无效: {}", err))?;
+    if PeerId::from(keypair.public()).to_string() == state.p2p.peer_id() {
+        return Ok(());
+    }
+    let config = state.config().await;
+    // In-memory nodes have no key file to restore into
+    if crate::p2p::node::resolve_key_path(&state.store, &config)
+        .await?
+        .is_none()
+    {
+        report.skipped.push("identity".into());
+        return Ok(());
+    }
+    // Same swap as a key import: the swarm restarts under the restored key
+    let info = crate::identity::rotate(state, keypair).await?;
+    tracing::info!("已恢复节点身份 {}", info.node_id);
+    report.imported.push("identity".into());
+    Ok(())
+}
+
+/// Check the format and version, decrypting the state if needed.
+fn open_archive(archive: BackupArchive, passphrase: Option<&str>) -> Result<BackupData> {
+    if archive.format != BACKUP_FORMAT {
+        bail!("不是 Porta 备份文件");
+    }
+    if archive.version > BACKUP_VERSION {
+        bail!(
+            "备份版本 {} 高于当前支持的版本 {}, 请升级 Porta",
+            archive.version,
+            BACKUP_VERSION
+        );
+    }
+    match (archive.data, archive.encrypted) {
+        (Some(data), _) => Ok(data),
+        (None, Some(encrypted)) => {
+            let passphrase = passphrase
+                .filter(|p| !p.is_empty())
+                .ok_or_else(|| anyhow!("备份已加密, 需要提供口令"))?;
+            unseal(&encrypted, passphrase)
+        }
+        (None, None) => bail!("备份内容为空"),
+    }
+}
+
+fn seal(data: &BackupData, passphrase: &str) -> Result<EncryptedBackup> {
+    let sealed = crypto::seal(&serde_json::to_vec(data)?, passphrase)
+        .map_err(|err| anyhow!("备份加密失败: {}", err))?;
+    Ok(EncryptedBackup {
+        kdf: KDF.into(),
+        salt: BASE64.encode(sealed.salt),
+        nonce: BASE64.encode(sealed.nonce),
+        ciphertext: BASE64.encode(sealed.ciphertext),
+    })
+}
+
+fn unseal(encrypted: &EncryptedBackup, passphrase: &str) -> Result<BackupData> {
+    if encrypted.kdf != KDF {
+        bail!("不支持的密钥派生算法: {}", encrypted.kdf);
+    }
+    let decode = |value: &str| {
+        BASE64
+            .decode(value)
+            .map_err(|_| anyhow!("备份加密数据已损坏"))
+    };
+    let sealed = Sealed {
+        salt: decode(&encrypted.salt)?,
+        nonce: decode(&encrypted.nonce)?,
+        ciphertext: decode(&encrypted.ciphertext)?,
+    };
+    let plaintext = crypto::open(&sealed, passphrase)
+        .map_err(|_| anyhow!("备份解密失败: 口令错误或文件已损坏"))?;
+    Ok(serde_json::from_slice(&plaintext)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::BackendConfig, models::SecureRoute};
+
+    async fn node() -> AppState {
+        AppState::with_config(BackendConfig {
+            db_path: ":memory:".into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+    }
+
+    fn request(archive: BackupArchive, on_conflict: ConflictPolicy) -> BackupImportRequest {
+        BackupImportRequest {
+            archive,
+            passphrase: Some("secret".into()),
+            on_conflict,
+            keep_identity: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn should_restore_encrypted_backup_into_another_node() {
+        let source = node().await;
+        source
+            .store
+            .publish_service(PublishRequest {
+                id: Some("pub-web".into()),
+                name: "Web".into(),
+                r#type: "HTTP".into(),
+                port: 8080,
+                summary: "站点".into(),
+                tags: vec!["web".into()],
+            })
+            .await
+            .unwrap();
+        source
+            .store
+            .add_secure_route(SecureRoute {
+                id: "route-1".into(),
+                subscription_id: "sub-1".into(),
+                relay_peers: vec!["relay".into()],
+                local_port: 2222,
+                status: "connected".into(),
+            })
+            .await
+            .unwrap();
+        let archive = export(&source, Some("secret")).await.unwrap();
+        assert!(archive.data.is_none());
+        let text = serde_json::to_string(&archive).unwrap();
+        assert!(!text.contains("pub-web"));
+
+        let target = node().await;
+        let mut wrong = request(archive.clone(), ConflictPolicy::Skip);
+        wrong.passphrase = Some("guess".into());
+        assert!(import(&target, wrong).await.is_err());
+
+        let report = import(&target, request(archive.clone(), ConflictPolicy::Skip))
+            .await
+            .unwrap();
+        assert!(report.imported.contains(&"publish/pub-web".to_string()));
+        assert!(report.imported.contains(&"route/route-1".to_string()));
+        // In-memory nodes cannot persist an identity
+        assert_eq!(report.skipped, vec!["identity".to_string()]);
+        let published = target.store.published_services().await.unwrap();
+        assert!(published
+            .iter()
+            .any(|p| p.id == "pub-web" && p.tags == ["web"]));
+        let route = target.store.find_secure_route("route-1").await.unwrap();
+        assert_eq!(route.unwrap().status, "断开");
+
+        let again = import(&target, request(archive.clone(), ConflictPolicy::Skip))
+            .await
+            .unwrap();
+        assert!(again.skipped.contains(&"publish/pub-web".to_string()));
+        let replaced = import(&target, request(archive.clone(), ConflictPolicy::Replace))
+            .await
+            .unwrap();
+        assert!(replaced.imported.contains(&"route/route-1".to_string()));
+        let err = import(&target, request(archive, ConflictPolicy::Fail))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("publish/pub-web"));
+    }
+
+    #[tokio::test]
+    async fn should_switch_to_the_restored_identity_right_away() {
+        let source = node().await;
+        let archive = export(&source, Some("secret")).await.unwrap();
+        let dir = std::env::temp_dir().join(format!("porta-backup-{}", uuid::Uuid::new_v4()));
+        let key_path = dir.join("node.key").to_string_lossy().into_owned();
+        let target = AppState::with_config(BackendConfig {
+            db_path: ":memory:".into(),
+            key_path: Some(key_path.clone()),
+            listen_addrs: vec!["/ip4/127.0.0.1/tcp/0".into()],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let report = import(&target, request(archive, ConflictPolicy::Skip))
+            .await
+            .unwrap();
+        assert!(report.imported.contains(&"identity".to_string()));
+        assert!(!report.restart_required.contains(&"identity".to_string()));
+        // The swarm, the node ID and the key file all agree on the restored peer
+        let restored = source.p2p.peer_id();
+        assert_eq!(target.p2p.peer_id(), restored);
+        assert_eq!(target.store.node_info().await.unwrap().node_id, restored);
+        let saved = crate::keystore::read(&key_path, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(PeerId::from(saved.keypair.public()).to_string(), restored);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn should_refuse_to_export_without_passphrase() {
+        let state = node().await;
+        for passphrase in [None, Some("")] {
+            let err = export(&state, passphrase).await.unwrap_err();
+            assert!(err.to_string().contains("口令"));
+        }
+    }
+
+    #[test]
+    fn should_reject_unknown_format_and_newer_versions() {
+        let archive = |format: &str, version| BackupArchive {
+            format: format.into(),
+            version,
+            created_at: String::new(),
+            data: None,
+            encrypted: None,
+        };
+        assert!(open_archive(archive("other", 1), None).is_err());
+        let err = open_archive(archive(BACKUP_FORMAT, BACKUP_VERSION + 1), None).unwrap_err();
+        assert!(err.to_string().contains("升级"));
+    }
+}
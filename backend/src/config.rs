@@ -0,0 +1,129 @@
+use crate::models::{AdmissionMode, PeerLimits, Provisioning};
+
+/// Startup settings for an embedded backend. `create_app_with` and
+/// `AppState::with_config` take this directly, so several nodes can run in
+/// one process; `from_env` keeps the `PORTA_*` variables working for `create_app`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackendConfig {
+    /// "edge" or "community"
+    pub role: String,
+    /// SQLite database path, or ":memory:" to keep all state in process
+    /// memory without sqlite
+    pub db_path: String,
+    /// PostgreSQL connection URL; when set the store lives there and
+    /// `db_path` only locates the default key file
+    pub database_url: Option<String>,
+    /// P2P identity key file; derived from `db_path` when unset
+    pub key_path: Option<String>,
+    /// Passphrase the key file is encrypted with; the key is stored in
+    /// plaintext when unset
+    pub key_passphrase: Option<String>,
+    /// P2P listen multiaddrs
+    pub listen_addrs: Vec<String>,
+    /// Node display name; the stored name is kept when unset
+    pub node_name: Option<String>,
+    /// Stored node flags and addresses are overwritten only when set
+    pub quic_enable: Option<bool>,
+    pub quic_port: Option<u16>,
+    pub mdns_enable: Option<bool>,
+    pub dht_enable: Option<bool>,
+    pub external_addrs: Option<Vec<String>>,
+    /// Who a community node lets join; the stored mode is kept when unset
+    pub admission: Option<AdmissionMode>,
+    /// Rate limits and quotas enforced on the peers this node serves
+    pub limits: PeerLimits,
+    /// Multiaddrs (with `/p2p/<peer_id>`) of nodes that community nodes
+    /// register their addresses with and that resolve peer IDs for edges
+    pub rendezvous_points: Vec<String>,
+    /// Seed demo communities into an empty database
+    pub seed_communities: bool,
+    /// Published services, communities and proxy settings kept in sync
+    /// with the store
+    pub provisioning: Provisioning,
+}
+
+impl Default for BackendConfig {
+    fn default() -> Self {
+        Self {
+            role: "edge".into(),
+            db_path: "data/porta.db".into(),
+            database_url: None,
+            key_path: None,
+            key_passphrase: None,
+            listen_addrs: vec![tcp_listen_addr(0)],
+            node_name: None,
+            quic_enable: None,
+            quic_port: None,
+            mdns_enable: None,
+            dht_enable: None,
+            external_addrs: None,
+            admission: None,
+            limits: PeerLimits::default(),
+            rendezvous_points: Vec::new(),
+            seed_communities: false,
+            provisioning: Provisioning::default(),
+        }
+    }
+}
+
+impl BackendConfig {
+    /// Build a config from `PORTA_ROLE`, `PORTA_DB`, `PORTA_DATABASE_URL`,
+    /// `PORTA_KEY_PATH`, `PORTA_KEY_PASSPHRASE`, `PORTA_P2P_TCP_PORT`,
+    /// `PORTA_RENDEZVOUS` (comma-separated), `PORTA_NODE_NAME`,
+    /// `PORTA_ADMISSION` and `PORTA_SEED_COMMUNITIES`.
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Ok(role) = std::env::var("PORTA_ROLE") {
+            config.role = role;
+        }
+        if let Ok(db_path) = std::env::var("PORTA_DB") {
+            config.db_path = db_path;
+        }
+        config.database_url = std::env::var("PORTA_DATABASE_URL").ok();
+        config.key_path = std::env::var("PORTA_KEY_PATH").ok();
+        config.key_passphrase = std::env::var("PORTA_KEY_PASSPHRASE").ok();
+        if let Some(port) = std::env::var("PORTA_P2P_TCP_PORT")
+            .ok()
+            .and_then(|s| s.parse::<u16>().ok())
+        {
+            config.listen_addrs = vec![tcp_listen_addr(port)];
+        }
+        if let Ok(points) = std::env::var("PORTA_RENDEZVOUS") {
+            config.rendezvous_points = points
+                .split(',')
+                .map(|point| point.trim().to_string())
+                .filter(|point| !point.is_empty())
+                .collect();
+        }
+        config.node_name = std::env::var("PORTA_NODE_NAME").ok();
+        config.admission = std::env::var("PORTA_ADMISSION")
+            .ok()
+            .and_then(|mode| mode.parse().ok());
+        config.seed_communities =
+            std::env::var("PORTA_SEED_COMMUNITIES").ok().as_deref() == Some("1");
+        config
+    }
+
+    pub fn is_in_memory(&self) -> bool {
+        self.db_path == ":memory:"
+    }
+}
+
+/// TCP listen multiaddr on all interfaces; port 0 picks a free port.
+pub fn tcp_listen_addr(port: u16) -> String {
+    format!("/ip4/0.0.0.0/tcp/{}", port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_default_to_edge_with_random_port() {
+        let config = BackendConfig::default();
+        assert_eq!(config.role, "edge");
+        assert_eq!(config.listen_addrs, vec!["/ip4/0.0.0.0/tcp/0".to_string()]);
+        assert!(config.node_name.is_none());
+        assert!(!config.is_in_memory());
+    }
+}
@@ -0,0 +1,123 @@
+//! Connection state of joined communities.
+//!
+//! A community moves from disconnected through connecting and handshaking to
+//! connected. A failed attempt puts it in backoff until a retry time that
+//! doubles with every failure in a row, up to [`BACKOFF_MAX_SECS`], with
+//! jitter so edges that lost the same community node do not all come back at
+//! the same moment. The failure count, last error and retry time are kept in
+//! the store so a restarted edge keeps backing off.
+
+use rand::Rng;
+
+use crate::models::{CommunitySummary, ConnectionState};
+
+/// Delay after the first failure.
+pub const BACKOFF_BASE_SECS: i64 = 5;
+/// Longest delay between two attempts.
+pub const BACKOFF_MAX_SECS: i64 = 600;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Connection {
+    pub state: ConnectionState,
+    /// Failed attempts in a row
+    pub failures: u32,
+    pub last_error: Option<String>,
+    /// Unix seconds of the next attempt while in backoff
+    pub next_retry_at: Option<i64>,
+}
+
+impl Connection {
+    /// Pick up the backoff a community was left in, e.g. before a restart.
+    pub fn restore(community: &CommunitySummary) -> Self {
+        let state = if community.next_retry_at.is_some() {
+            ConnectionState::Backoff
+        } else {
+            ConnectionState::Disconnected
+        };
+        Self {
+            state,
+            failures: community.connect_failures,
+            last_error: community.last_error.clone(),
+            next_retry_at: community.next_retry_at,
+        }
+    }
+
+    /// Whether the maintenance loop should dial now.
+    pub fn is_due(&self, now: i64) -> bool {
+        match self.state {
+            ConnectionState::Disconnected => true,
+            ConnectionState::Backoff => !matches!(self.next_retry_at, Some(at) if at > now),
+            _ => false,
+        }
+    }
+
+    pub fn succeed(&mut self) {
+        self.state = ConnectionState::Connected;
+        self.failures = 0;
+        self.next_retry_at = None;
+    }
+
+    /// Record a failed attempt and schedule the next one.
+    pub fn fail(&mut self, error: String, now: i64) {
+        self.failures = self.failures.saturating_add(1);
+        self.state = ConnectionState::Backoff;
+        self.last_error = Some(error);
+        self.next_retry_at = Some(now + backoff_delay(self.failures));
+    }
+
+    /// Copy the state onto a community listed from the store.
+    pub fn apply(&self, community: &mut CommunitySummary) {
+        community.state = self.state;
+        community.connect_failures = self.failures;
+        community.last_error = self.last_error.clone();
+        community.next_retry_at = self.next_retry_at;
+    }
+}
+
+/// Seconds to wait after `failures` failed attempts in a row: the
+/// exponential delay with its upper half randomised.
+pub fn backoff_delay(failures: u32) -> i64 {
+    let exponent = failures.saturating_sub(1).min(16);
+    let delay = (BACKOFF_BASE_SECS << exponent).min(BACKOFF_MAX_SECS);
+    let half = delay / 2;
+    delay - half + rand::thread_rng().gen_range(0..=half)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_back_off_exponentially_with_jitter() {
+        for _ in 0..100 {
+            assert!((3..=5).contains(&backoff_delay(1)));
+            assert!((5..=10).contains(&backoff_delay(2)));
+            assert!((40..=80).contains(&backoff_delay(5)));
+            assert!((300..=BACKOFF_MAX_SECS).contains(&backoff_delay(40)));
+        }
+    }
+
+    #[test]
+    fn should_only_retry_after_backoff_expires() {
+        let mut connection = Connection::default();
+        assert!(connection.is_due(0));
+        connection.state = ConnectionState::Connecting;
+        assert!(!connection.is_due(0));
+
+        connection.fail("连接超时".into(), 1_000);
+        connection.fail("连接超时".into(), 1_000);
+        assert_eq!(connection.state, ConnectionState::Backoff);
+        assert_eq!(connection.failures, 2);
+        let at = connection.next_retry_at.unwrap();
+        assert!((1_005..=1_010).contains(&at));
+        assert!(!connection.is_due(at - 1));
+        assert!(connection.is_due(at));
+
+        connection.succeed();
+        assert_eq!(connection.state, ConnectionState::Connected);
+        assert_eq!(connection.failures, 0);
+        assert_eq!(connection.next_retry_at, None);
+        assert_eq!(connection.last_error.as_deref(), Some("连接超时"));
+        assert!(!connection.is_due(at));
+    }
+}
@@ -0,0 +1,55 @@
+//! Passphrase sealing shared by backups and key files: ChaCha20-Poly1305
+//! under an Argon2id-derived key, with a random salt and nonce per seal.
+
+use anyhow::{anyhow, bail, Result};
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use rand::RngCore;
+
+/// Key derivation recorded next to sealed data.
+pub const KDF: &str = "argon2id";
+
+pub struct Sealed {
+    pub salt: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+pub fn seal(plaintext: &[u8], passphrase: &str) -> Result<Sealed> {
+    let mut salt = [0u8; 16];
+    let mut nonce = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = cipher(passphrase, &salt)?
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .map_err(|_| anyhow!("加密失败"))?;
+    Ok(Sealed {
+        salt: salt.to_vec(),
+        nonce: nonce.to_vec(),
+        ciphertext,
+    })
+}
+
+/// Decrypt sealed data; a wrong passphrase and tampered data fail alike.
+pub fn open(sealed: &Sealed, passphrase: &str) -> Result<Vec<u8>> {
+    if sealed.nonce.len() != 12 {
+        bail!("加密数据已损坏");
+    }
+    cipher(passphrase, &sealed.salt)?
+        .decrypt(
+            Nonce::from_slice(&sealed.nonce),
+            sealed.ciphertext.as_slice(),
+        )
+        .map_err(|_| anyhow!("解密失败"))
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<ChaCha20Poly1305> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|err| anyhow!("口令密钥派生失败: {}", err))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
@@ -0,0 +1,130 @@
+//! Event bus for background changes.
+//!
+//! Peers coming and going, community status, registry pushes, sessions and
+//! publish results are published here and streamed to the management UI as
+//! server-sent events by `GET /porta/events`. Each subscriber has a bounded
+//! buffer; one that falls behind is sent a `lagged` event with the number of
+//! events it missed and should refetch the state it shows.
+
+use serde::Serialize;
+
+use crate::models::ServiceChange;
+use tokio::sync::broadcast;
+
+const EVENT_BUFFER: usize = 256;
+
+/// Background changes surfaced to the management UI.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PortaEvent {
+    /// A community pushed registry changes that were applied to the local cache.
+    ServicesChanged {
+        community_id: String,
+        upserted: Vec<String>,
+        removed: Vec<String>,
+    },
+    /// A service this node subscribed to changed on its provider.
+    SubscriptionChanged {
+        service_uuid: String,
+        change: ServiceChange,
+    },
+    PeerConnected {
+        peer_id: String,
+    },
+    PeerDisconnected {
+        peer_id: String,
+    },
+    /// Emitted when a community becomes reachable or is lost, not on every keep-alive.
+    CommunityStatus {
+        community_id: String,
+        connected: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    SessionOpened {
+        session_id: String,
+        service_id: String,
+        local_port: u16,
+    },
+    SessionClosed {
+        session_id: String,
+        service_id: String,
+    },
+    RouteStatus {
+        route_id: String,
+        status: String,
+    },
+    /// Outcome of announcing a published service to one community.
+    PublishResult {
+        service_id: String,
+        community_id: String,
+        ok: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+    },
+}
+
+impl PortaEvent {
+    /// Event name, identical to the serialized `type` tag.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PortaEvent::ServicesChanged { .. } => "services_changed",
+            PortaEvent::SubscriptionChanged { .. } => "subscription_changed",
+            PortaEvent::PeerConnected { .. } => "peer_connected",
+            PortaEvent::PeerDisconnected { .. } => "peer_disconnected",
+            PortaEvent::CommunityStatus { .. } => "community_status",
+            PortaEvent::SessionOpened { .. } => "session_opened",
+            PortaEvent::SessionClosed { .. } => "session_closed",
+            PortaEvent::RouteStatus { .. } => "route_status",
+            PortaEvent::PublishResult { .. } => "publish_result",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<PortaEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUFFER);
+        Self { sender }
+    }
+
+    /// Deliver an event to every current subscriber; events with no
+    /// subscriber are dropped.
+    pub fn publish(&self, event: PortaEvent) {
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<PortaEvent> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn should_deliver_events_to_subscribers() {
+        let bus = EventBus::new();
+        let mut rx = bus.subscribe();
+        bus.publish(PortaEvent::ServicesChanged {
+            community_id: "dev".into(),
+            upserted: vec!["svc-1".into()],
+            removed: Vec::new(),
+        });
+        let event = rx.recv().await.unwrap();
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["type"], event.kind());
+        assert_eq!(json["community_id"], "dev");
+    }
+}
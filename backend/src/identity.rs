@@ -0,0 +1,395 @@
+//! Live rotation of the node identity and its key file passphrase.
+//!
+//! Generating, importing or restoring a key restarts the libp2p swarm under
+//! the new peer ID and records it as the node ID. Every joined community then
+//! gets a handover signed by the old key, so the services and subscriptions
+//! held under the old peer ID move to the new one instead of being orphaned.
+
+use anyhow::{anyhow, Result};
+use libp2p::{identity::Keypair, PeerId};
+
+use crate::{
+    keystore::{self, KeyLocked},
+    models::{KeyFileStatus, KeyImportRequest, KeyPassphraseRequest, NodeInfo},
+    p2p::node::{resolve_key_path, write_keypair},
+    state::AppState,
+};
+
+/// Switch to a freshly generated key, stored under a new key file.
+pub async fn generate(state: &AppState) -> Result<NodeInfo> {
+    rotate(state, Keypair::generate_ed25519()).await
+}
+
+/// Switch to the key stored in `req.key_path`. The key is copied into a key
+/// file of the node's own, so the file it came from is never written to.
+pub async fn import(state: &AppState, req: KeyImportRequest) -> Result<NodeInfo> {
+    let passphrase = state.config().await.key_passphrase;
+    let keypair = keystore::read(&req.key_path, passphrase.as_deref())
+        .await?
+        .ok_or_else(|| anyhow!("密钥文件 {} 不存在", req.key_path))?
+        .keypair;
+    rotate(state, keypair).await
+}
+
+/// Where the key file lives and whether it is encrypted.
+pub async fn key_status(state: &AppState) -> Result<KeyFileStatus> {
+    let config = state.config().await;
+    let key_path = resolve_key_path(&state.store, &config).await?;
+    let encrypted = match &key_path {
+        Some(path) => keystore::is_encrypted(path).await,
+        None => false,
+    };
+    Ok(KeyFileStatus {
+        key_path,
+        encrypted,
+    })
+}
+
+/// Re-write the key file under a new passphrase, or in plaintext when none
+/// is given. The passphrase used to unlock the node at startup (environment,
+/// passphrase file or prompt) has to be changed to match.
+pub async fn change_passphrase(
+    state: &AppState,
+    req: KeyPassphraseRequest,
+) -> Result<KeyFileStatus> {
+    let mut config = state.config.write().await;
+    let key_path = resolve_key_path(&state.store, &config)
+        .await?
+        .ok_or_else(|| anyhow!("内存节点没有密钥文件"))?;
+    let key = match keystore::read(&key_path, req.current_passphrase.as_deref()).await {
+        Ok(Some(key)) => key,
+        Ok(None) => return Err(anyhow!("密钥文件 {} 不存在", key_path)),
+        Err(err) if err.is::<KeyLocked>() => return Err(anyhow!("当前口令错误")),
+        Err(err) => return Err(err),
+    };
+    let new_passphrase = req.new_passphrase.filter(|p| !p.is_empty());
+    keystore::write(&key_path, &key.keypair, new_passphrase.as_deref()).await?;
+    tracing::info!(
+        "密钥文件 {} 已{}",
+        key_path,
+        if new_passphrase.is_some() {
+            "使用新口令加密"
+        } else {
+            "改为明文存储"
+        }
+    );
+    config.key_passphrase = new_passphrase;
+    Ok(KeyFileStatus {
+        encrypted: config.key_passphrase.is_some(),
+        key_path: Some(key_path),
+    })
+}
+
+/// Persist `keypair` under a new key file, restart the swarm with it and hand
+/// over to the joined communities. If any of that fails before the swarm runs
+/// the new identity, the key path is put back and the new key file removed
+/// (or, for a key path fixed in the config, the previous key written back), so
+/// disk still matches the running identity.
+pub(crate) async fn rotate(state: &AppState, keypair: Keypair) -> Result<NodeInfo> {
+    let new_peer = PeerId::from(keypair.public());
+    let old = state.p2p.keypair();
+    if PeerId::from(old.public()) == new_peer {
+        return state.store.node_info().await;
+    }
+    let config = state.config().await;
+    let previous_path = state.store.node_info().await?.key_path;
+    let previous_file = resolve_key_path(&state.store, &config).await?;
+    state.store.generate_key().await?;
+    let new_file = resolve_key_path(&state.store, &config).await?;
+    let swapped = async {
+        write_keypair(&state.store, &config, &keypair.to_protobuf_encoding()?).await?;
+        state.p2p.restart(keypair).await
+    };
+    if let Err(err) = swapped.await {
+        state
+            .store
+            .import_key(KeyImportRequest {
+                key_path: previous_path,
+            })
+            .await?;
+        match new_file {
+            Some(path) if Some(&path) == previous_file.as_ref() => {
+                write_keypair(&state.store, &config, &old.to_protobuf_encoding()?).await?;
+            }
+            Some(path) => {
+                if let Err(err) = tokio::fs::remove_file(&path).await {
+                    if err.kind() != std::io::ErrorKind::NotFound {
+                        tracing::warn!("删除密钥文件 {} 失败: {}", path, err);
+                    }
+                }
+            }
+            None => {}
+        }
+        return Err(err);
+    }
+    state.store.set_node_id(&new_peer.to_string()).await?;
+    tracing::info!(
+        "节点身份已轮换: {} -> {}",
+        PeerId::from(old.public()),
+        new_peer
+    );
+    let accepted = state.app.hand_over_identity(&old).await?;
+    tracing::info!("{} 个社区已接受身份交接", accepted);
+    state.store.node_info().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::BackendConfig,
+        models::{CommunityAddRequest, PublishRequest},
+    };
+
+    async fn node(role: &str, listen_addr: String) -> AppState {
+        AppState::with_config(BackendConfig {
+            role: role.into(),
+            db_path: ":memory:".into(),
+            listen_addrs: vec![listen_addr],
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+    }
+
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    #[tokio::test]
+    async fn should_hand_over_services_and_subscriptions_to_rotated_identity() {
+        let port = free_port();
+        let community = node("community", format!("/ip4/127.0.0.1/tcp/{}", port)).await;
+        let edge = node("edge", "/ip4/127.0.0.1/tcp/0".into()).await;
+        let joined = edge
+            .app
+            .add_community(CommunityAddRequest {
+                id: Some("lab".into()),
+                name: "Lab".into(),
+                description: "test community".into(),
+                multiaddr: Some(format!(
+                    "/ip4/127.0.0.1/tcp/{}/p2p/{}",
+                    port,
+                    community.p2p.peer_id()
+                )),
+                multiaddrs: Vec::new(),
+                peer_id: None,
+                invite: None,
+            })
+            .await
+            .unwrap();
+        edge.app.connect_community(&joined.id).await.unwrap();
+        let published = edge
+            .app
+            .publish_service(PublishRequest {
+                id: None,
+                name: "ssh".into(),
+                r#type: "TCP".into(),
+                port: 22,
+                summary: "shell".into(),
+                tags: Vec::new(),
+            })
+            .await
+            .unwrap();
+        let old_peer = edge.p2p.peer_id();
+        community
+            .store
+            .record_subscription("svc-remote", &old_peer)
+            .await
+            .unwrap();
+
+        let info = generate(&edge).await.unwrap();
+        let new_peer = edge.p2p.peer_id();
+        assert_ne!(new_peer, old_peer);
+        assert_eq!(info.node_id, new_peer);
+
+        let service = community
+            .store
+            .resolve_service_registry(&published.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(service.provider_peer, new_peer);
+        // The old peer's connection drops, but the services stay online under the new one
+        let mut online = service.online;
+        for _ in 0..20 {
+            if online {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            online = community
+                .store
+                .resolve_service_registry(&published.id)
+                .await
+                .unwrap()
+                .unwrap()
+                .online;
+        }
+        assert!(online);
+        assert_eq!(
+            community
+                .store
+                .service_subscribers("svc-remote")
+                .await
+                .unwrap(),
+            vec![new_peer.clone()]
+        );
+        assert_eq!(community.store.peer_role(&old_peer).await.unwrap(), None);
+
+        // The restarted swarm keeps talking to the community
+        let second = edge
+            .app
+            .publish_service(PublishRequest {
+                id: None,
+                name: "web".into(),
+                r#type: "HTTP".into(),
+                port: 80,
+                summary: "site".into(),
+                tags: Vec::new(),
+            })
+            .await
+            .unwrap();
+        let service = community
+            .store
+            .resolve_service_registry(&second.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(service.provider_peer, new_peer);
+
+        // A key that cannot be read leaves the running identity alone
+        let missing = KeyImportRequest {
+            key_path: "missing.key".into(),
+        };
+        assert!(import(&edge, missing).await.is_err());
+        assert_eq!(edge.p2p.peer_id(), new_peer);
+    }
+
+    #[tokio::test]
+    async fn should_reject_handover_from_banned_peer() {
+        let port = free_port();
+        let community = node("community", format!("/ip4/127.0.0.1/tcp/{}", port)).await;
+        let edge = node("edge", "/ip4/127.0.0.1/tcp/0".into()).await;
+        edge.app
+            .add_community(CommunityAddRequest {
+                id: Some("lab".into()),
+                name: "Lab".into(),
+                description: "test community".into(),
+                multiaddr: Some(format!(
+                    "/ip4/127.0.0.1/tcp/{}/p2p/{}",
+                    port,
+                    community.p2p.peer_id()
+                )),
+                multiaddrs: Vec::new(),
+                peer_id: None,
+                invite: None,
+            })
+            .await
+            .unwrap();
+        edge.app.connect_community("lab").await.unwrap();
+        let old_peer = edge.p2p.peer_id();
+        community
+            .store
+            .set_node_ban(&old_peer, true, None, None)
+            .await
+            .unwrap();
+
+        generate(&edge).await.unwrap();
+        assert!(community.store.peer_is_banned(&old_peer).await.unwrap());
+        assert!(community
+            .store
+            .peer_role(&edge.p2p.peer_id())
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn should_change_key_passphrase_and_require_it_at_startup() {
+        let dir = std::env::temp_dir().join(format!("porta-identity-{}", uuid::Uuid::new_v4()));
+        let key_path = dir.join("node.key").to_string_lossy().into_owned();
+        let config = BackendConfig {
+            db_path: ":memory:".into(),
+            key_path: Some(key_path.clone()),
+            listen_addrs: vec!["/ip4/127.0.0.1/tcp/0".into()],
+            ..Default::default()
+        };
+        let state = AppState::with_config(config.clone()).await.unwrap();
+        assert!(!key_status(&state).await.unwrap().encrypted);
+
+        let set = KeyPassphraseRequest {
+            current_passphrase: None,
+            new_passphrase: Some("first".into()),
+        };
+        assert!(change_passphrase(&state, set).await.unwrap().encrypted);
+        let wrong = KeyPassphraseRequest {
+            current_passphrase: Some("nope".into()),
+            new_passphrase: Some("second".into()),
+        };
+        assert!(change_passphrase(&state, wrong).await.is_err());
+        let change = KeyPassphraseRequest {
+            current_passphrase: Some("first".into()),
+            new_passphrase: Some("second".into()),
+        };
+        change_passphrase(&state, change).await.unwrap();
+
+        // Rotating keeps the key file encrypted under the current passphrase
+        generate(&state).await.unwrap();
+        let err = AppState::with_config(config.clone()).await.err().unwrap();
+        assert!(err.downcast_ref::<KeyLocked>().is_some());
+        let unlocked = AppState::with_config(BackendConfig {
+            key_passphrase: Some("second".into()),
+            ..config
+        })
+        .await
+        .unwrap();
+        assert_eq!(unlocked.p2p.peer_id(), state.p2p.peer_id());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn should_copy_an_imported_key_into_the_node_key_file() {
+        let dir = std::env::temp_dir().join(format!("porta-identity-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("member.key").to_string_lossy().into_owned();
+        let member = Keypair::generate_ed25519();
+        keystore::write(&source, &member, None).await.unwrap();
+        let original = std::fs::read(&source).unwrap();
+        // Without a configured key path the key file is named after the database
+        let db_name = format!("porta-import-{}", uuid::Uuid::new_v4());
+        let db_path = dir.join(format!("{}.db", db_name));
+        std::fs::File::create(&db_path).unwrap();
+        let state = AppState::with_config(BackendConfig {
+            db_path: db_path.to_string_lossy().into_owned(),
+            listen_addrs: vec!["/ip4/127.0.0.1/tcp/0".into()],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let info = import(
+            &state,
+            KeyImportRequest {
+                key_path: source.clone(),
+            },
+        )
+        .await
+        .unwrap();
+        let member_peer = PeerId::from(member.public()).to_string();
+        assert_eq!(info.node_id, member_peer);
+        assert_ne!(info.key_path, source);
+        assert_eq!(std::fs::read(&source).unwrap(), original);
+        let saved = keystore::read(&info.key_path, None).await.unwrap().unwrap();
+        assert_eq!(
+            PeerId::from(saved.keypair.public()).to_string(),
+            member_peer
+        );
+        let _ = std::fs::remove_file(&info.key_path);
+        let _ = std::fs::remove_file(format!("{}.key", db_name));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
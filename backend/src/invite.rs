@@ -0,0 +1,387 @@
+//! Community invites.
+//!
+//! An invite token is `porta-invite:<payload>.<signature>`, both parts
+//! base64url. The payload names the community, says where to reach it and
+//! which peer ID to expect, and may expire or be good for a single join. It
+//! is signed with the community node key, so an edge can check the token
+//! really comes from the peer it points to and the community can tell its own
+//! invites from anybody else's.
+
+use anyhow::{anyhow, bail, Result};
+use base64::{
+    engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD as BASE64_URL},
+    Engine,
+};
+use libp2p::{identity, multiaddr::Protocol, Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+
+use crate::{p2p::address_book::dialable_addrs, state::Store};
+
+pub const INVITE_PREFIX: &str = "porta-invite:";
+
+/// What an invite token carries.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Invite {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    /// Full multiaddrs ending in `/p2p/<peer_id>`, best first
+    pub multiaddrs: Vec<String>,
+    pub peer_id: String,
+    /// Protobuf-encoded public key of `peer_id`, base64
+    pub public_key: String,
+    /// Unix seconds after which the invite is refused
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    /// Whether only the first peer to join with it is accepted
+    #[serde(default)]
+    pub one_time: bool,
+}
+
+impl Invite {
+    /// Sign the invite with the community key it was issued for.
+    pub fn encode(&self, keypair: &identity::Keypair) -> Result<String> {
+        if PeerId::from(keypair.public()).to_string() != self.peer_id {
+            bail!("邀请的 peer ID 与节点密钥不符");
+        }
+        let payload = BASE64_URL.encode(serde_json::to_vec(self)?);
+        let signature = keypair
+            .sign(signed_message(&payload).as_bytes())
+            .map_err(|err| anyhow!("签名邀请失败: {}", err))?;
+        Ok(format!(
+            "{}{}.{}",
+            INVITE_PREFIX,
+            payload,
+            BASE64_URL.encode(signature)
+        ))
+    }
+
+    /// Parse a token and check its signature against the key it carries.
+    /// Expiry is left to [`Invite::is_expired`].
+    pub fn decode(token: &str) -> Result<Self> {
+        let token = token.trim();
+        let body = token
+            .strip_prefix(INVITE_PREFIX)
+            .ok_or_else(|| anyhow!("不是有效的邀请码"))?;
+        let (payload, signature) = body
+            .split_once('.')
+            .ok_or_else(|| anyhow!("邀请码格式无效"))?;
+        let invite: Invite = BASE64_URL
+            .decode(payload)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| anyhow!("邀请码内容无效"))?;
+        let signature = BASE64_URL
+            .decode(signature)
+            .map_err(|_| anyhow!("邀请码签名无效"))?;
+        let public_key = BASE64
+            .decode(&invite.public_key)
+            .ok()
+            .and_then(|bytes| identity::PublicKey::try_decode_protobuf(&bytes).ok())
+            .ok_or_else(|| anyhow!("邀请码公钥无效"))?;
+        if PeerId::from(public_key.clone()).to_string() != invite.peer_id {
+            bail!("邀请码公钥与 peer ID 不符");
+        }
+        if !public_key.verify(signed_message(payload).as_bytes(), &signature) {
+            bail!("邀请码签名校验失败");
+        }
+        Ok(invite)
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.map(|at| now >= at).unwrap_or(false)
+    }
+}
+
+/// Check an invite presented by `peer` when it says hello to the community
+/// node `local_peer`. Nothing is recorded; a one-time invite is only used up
+/// by [`redeem`] once the peer has been admitted.
+pub async fn verify(
+    store: &dyn Store,
+    local_peer: &PeerId,
+    peer: &PeerId,
+    token: &str,
+) -> Result<Invite> {
+    let invite = Invite::decode(token)?;
+    if invite.peer_id != local_peer.to_string() {
+        bail!("邀请不是本社区签发的");
+    }
+    if invite.is_expired(chrono::Utc::now().timestamp()) {
+        bail!("邀请已过期");
+    }
+    if invite.one_time {
+        if let Some(redeemed_by) = store.invite_redeemed_by(&invite.id).await? {
+            if redeemed_by != peer.to_string() {
+                bail!("邀请已被使用");
+            }
+        }
+    }
+    Ok(invite)
+}
+
+/// Use up a verified one-time invite for `peer`.
+pub async fn redeem(store: &dyn Store, peer: &PeerId, invite: &Invite) -> Result<()> {
+    if invite.one_time && !store.redeem_invite(&invite.id, &peer.to_string()).await? {
+        bail!("邀请已被使用");
+    }
+    Ok(())
+}
+
+fn signed_message(payload: &str) -> String {
+    format!("{}{}", INVITE_PREFIX, payload)
+}
+
+/// Addresses to put in an invite for a node listening on `listeners`: each
+/// configured external host with every listening transport first, then the
+/// listen addresses themselves with loopback ones last.
+pub fn invite_addrs(
+    listeners: &[Multiaddr],
+    external_hosts: &[String],
+    peer: &PeerId,
+) -> Vec<String> {
+    dialable_addrs(listeners, external_hosts)
+        .into_iter()
+        .map(|addr| addr.with(Protocol::P2p(*peer)).to_string())
+        .collect()
+}
+
+/// Render a token as a QR code, `svg` or `png`.
+pub fn render_qr(token: &str, format: &str) -> Result<(&'static str, Vec<u8>)> {
+    let code =
+        qrcode::QrCode::new(token.as_bytes()).map_err(|err| anyhow!("生成二维码失败: {}", err))?;
+    match format {
+        "svg" => {
+            let svg = code
+                .render::<qrcode::render::svg::Color>()
+                .min_dimensions(256, 256)
+                .build();
+            Ok(("image/svg+xml", svg.into_bytes()))
+        }
+        "png" => {
+            let image = code
+                .render::<image::Luma<u8>>()
+                .min_dimensions(256, 256)
+                .build();
+            let mut bytes = Vec::new();
+            image
+                .write_to(
+                    &mut std::io::Cursor::new(&mut bytes),
+                    image::ImageFormat::Png,
+                )
+                .map_err(|err| anyhow!("生成二维码失败: {}", err))?;
+            Ok(("image/png", bytes))
+        }
+        other => bail!("不支持的二维码格式: {}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::BackendConfig,
+        models::{CommunityAddRequest, InviteCreateRequest},
+        state::AppState,
+    };
+
+    fn invite(keypair: &identity::Keypair) -> Invite {
+        let peer = PeerId::from(keypair.public());
+        Invite {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: "测试社区".into(),
+            description: "社区描述".into(),
+            multiaddrs: vec![format!("/ip4/127.0.0.1/tcp/4001/p2p/{}", peer)],
+            peer_id: peer.to_string(),
+            public_key: BASE64.encode(keypair.public().encode_protobuf()),
+            expires_at: Some(1_000),
+            one_time: true,
+        }
+    }
+
+    #[test]
+    fn should_round_trip_signed_invite() {
+        let keypair = identity::Keypair::generate_ed25519();
+        let invite = invite(&keypair);
+        let token = invite.encode(&keypair).unwrap();
+        assert!(token.starts_with(INVITE_PREFIX));
+        assert_eq!(Invite::decode(&format!(" {}\n", token)).unwrap(), invite);
+        assert!(!invite.is_expired(999));
+        assert!(invite.is_expired(1_000));
+    }
+
+    #[test]
+    fn should_reject_tampered_or_foreign_invites() {
+        let keypair = identity::Keypair::generate_ed25519();
+        let mut invite = invite(&keypair);
+        let token = invite.encode(&keypair).unwrap();
+        let (payload, signature) = token
+            .trim_start_matches(INVITE_PREFIX)
+            .split_once('.')
+            .unwrap();
+
+        invite.name = "别的社区".into();
+        let forged = BASE64_URL.encode(serde_json::to_vec(&invite).unwrap());
+        assert!(Invite::decode(&format!("{}{}.{}", INVITE_PREFIX, forged, signature)).is_err());
+        assert!(Invite::decode(payload).is_err());
+
+        // Signing someone else's peer ID is refused outright
+        let other = identity::Keypair::generate_ed25519();
+        assert!(invite.encode(&other).is_err());
+    }
+
+    #[test]
+    fn should_list_external_hosts_before_loopback() {
+        let peer = PeerId::random();
+        let listeners: Vec<Multiaddr> = vec![
+            "/ip4/127.0.0.1/tcp/4001".parse().unwrap(),
+            "/ip4/192.168.1.5/tcp/4001".parse().unwrap(),
+        ];
+        let addrs = invite_addrs(&listeners, &["porta.example.com".into()], &peer);
+        assert_eq!(
+            addrs,
+            vec![
+                format!("/dns/porta.example.com/tcp/4001/p2p/{}", peer),
+                format!("/ip4/192.168.1.5/tcp/4001/p2p/{}", peer),
+                format!("/ip4/127.0.0.1/tcp/4001/p2p/{}", peer),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_render_qr_codes() {
+        let (mime, svg) = render_qr("porta-invite:abc.def", "svg").unwrap();
+        assert_eq!(mime, "image/svg+xml");
+        assert!(String::from_utf8(svg).unwrap().contains("<svg"));
+        let (mime, png) = render_qr("porta-invite:abc.def", "png").unwrap();
+        assert_eq!(mime, "image/png");
+        assert_eq!(&png[1..4], b"PNG");
+        assert!(render_qr("porta-invite:abc.def", "gif").is_err());
+    }
+
+    async fn node(role: &str, listen_addr: String) -> AppState {
+        AppState::with_config(BackendConfig {
+            role: role.into(),
+            db_path: ":memory:".into(),
+            listen_addrs: vec![listen_addr],
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+    }
+
+    fn join_with(token: &str) -> CommunityAddRequest {
+        CommunityAddRequest {
+            id: None,
+            name: String::new(),
+            description: String::new(),
+            multiaddr: None,
+            multiaddrs: Vec::new(),
+            peer_id: None,
+            invite: Some(token.into()),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_join_with_one_time_invite_once() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let community = node("community", format!("/ip4/127.0.0.1/tcp/{}", port)).await;
+        let issued = community
+            .app
+            .create_invite(InviteCreateRequest {
+                name: Some("Lab".into()),
+                multiaddrs: vec![format!("/ip4/127.0.0.1/tcp/{}", port)],
+                expires_in_secs: Some(3600),
+                one_time: true,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(issued.invite.peer_id, community.p2p.peer_id());
+        assert_eq!(issued.invite.description, "Lab");
+
+        let edge = node("edge", "/ip4/127.0.0.1/tcp/0".into()).await;
+        let joined = edge
+            .app
+            .add_community(join_with(&issued.token))
+            .await
+            .unwrap();
+        assert_eq!(joined.name, "Lab");
+        assert_eq!(
+            joined.peer_id.as_deref(),
+            Some(issued.invite.peer_id.as_str())
+        );
+        edge.app.connect_community(&joined.id).await.unwrap();
+
+        let other = node("edge", "/ip4/127.0.0.1/tcp/0".into()).await;
+        let second = other
+            .app
+            .add_community(join_with(&issued.token))
+            .await
+            .unwrap();
+        let err = other.app.connect_community(&second.id).await.unwrap_err();
+        assert!(err.to_string().contains("邀请已被使用"), "{}", err);
+
+        // Only community nodes sign invites
+        assert!(edge
+            .app
+            .create_invite(InviteCreateRequest::default())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn should_not_use_up_invite_without_admitting_the_peer() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let community = node("community", format!("/ip4/127.0.0.1/tcp/{}", port)).await;
+        let issued = community
+            .app
+            .create_invite(InviteCreateRequest {
+                multiaddrs: vec![format!("/ip4/127.0.0.1/tcp/{}", port)],
+                one_time: true,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        // Another community node has no use for the invite and must not burn it
+        let peer = node("community", "/ip4/127.0.0.1/tcp/0".into()).await;
+        let added = peer
+            .app
+            .add_community(join_with(&issued.token))
+            .await
+            .unwrap();
+        let _ = peer.app.connect_community(&added.id).await;
+        assert_eq!(
+            community
+                .store
+                .invite_redeemed_by(&issued.invite.id)
+                .await
+                .unwrap(),
+            None
+        );
+
+        let edge = node("edge", "/ip4/127.0.0.1/tcp/0".into()).await;
+        let joined = edge
+            .app
+            .add_community(join_with(&issued.token))
+            .await
+            .unwrap();
+        edge.app.connect_community(&joined.id).await.unwrap();
+        assert_eq!(
+            community
+                .store
+                .invite_redeemed_by(&issued.invite.id)
+                .await
+                .unwrap(),
+            Some(edge.p2p.peer_id())
+        );
+    }
+}
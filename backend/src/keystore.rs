@@ -0,0 +1,239 @@
+//! Node identity key files.
+//!
+//! A key file holds the protobuf-encoded libp2p keypair, either as is or,
+//! when the node has a key passphrase, sealed in a small JSON envelope (see
+//! [`crate::crypto`]). Key files are written owner-only (0600) on Unix, and
+//! looser permissions on an existing file are tightened when it is read.
+
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use libp2p::identity::Keypair;
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{self, Sealed, KDF};
+
+pub const KEY_FILE_FORMAT: &str = "porta-key";
+pub const KEY_FILE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct EncryptedKeyFile {
+    format: String,
+    version: u32,
+    kdf: String,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// A loaded key file.
+pub struct KeyFile {
+    pub keypair: Keypair,
+    /// Whether the file on disk is passphrase-encrypted
+    pub encrypted: bool,
+}
+
+/// The key file is encrypted and no passphrase, or a wrong one, was given.
+/// Callers that can ask for a passphrase look for this with `downcast_ref`.
+#[derive(Debug)]
+pub struct KeyLocked {
+    pub path: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for KeyLocked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "密钥文件 {} 已加密, {}", self.path, self.reason)
+    }
+}
+
+impl std::error::Error for KeyLocked {}
+
+/// Load the key at `path`, or `None` if there is no file.
+pub async fn read(path: &str, passphrase: Option<&str>) -> Result<Option<KeyFile>> {
+    let bytes = match tokio::fs::read(path).await {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(anyhow!("读取密钥文件 {} 失败: {}", path, err)),
+    };
+    restrict_permissions(path).await;
+    let Some(envelope) = parse_envelope(&bytes) else {
+        let keypair = Keypair::from_protobuf_encoding(&bytes)
+            .map_err(|err| anyhow!("密钥格式无效: {}", err))?;
+        return Ok(Some(KeyFile {
+            keypair,
+            encrypted: false,
+        }));
+    };
+    let locked = |reason: &str| KeyLocked {
+        path: path.to_string(),
+        reason: reason.to_string(),
+    };
+    if envelope.version > KEY_FILE_VERSION {
+        bail!(
+            "密钥文件版本 {} 高于当前支持的版本 {}, 请升级 Porta",
+            envelope.version,
+            KEY_FILE_VERSION
+        );
+    }
+    if envelope.kdf != KDF {
+        bail!("不支持的密钥派生算法: {}", envelope.kdf);
+    }
+    let Some(passphrase) = passphrase.filter(|p| !p.is_empty()) else {
+        return Err(locked("需要提供口令").into());
+    };
+    let decode = |value: &str| BASE64.decode(value).map_err(|_| anyhow!("密钥文件已损坏"));
+    let sealed = Sealed {
+        salt: decode(&envelope.salt)?,
+        nonce: decode(&envelope.nonce)?,
+        ciphertext: decode(&envelope.ciphertext)?,
+    };
+    let bytes = crypto::open(&sealed, passphrase).map_err(|_| locked("口令错误或文件已损坏"))?;
+    let keypair =
+        Keypair::from_protobuf_encoding(&bytes).map_err(|err| anyhow!("密钥格式无效: {}", err))?;
+    Ok(Some(KeyFile {
+        keypair,
+        encrypted: true,
+    }))
+}
+
+/// Write `keypair` to `path` owner-only, sealed when a passphrase is given.
+/// The file is replaced atomically so a crash never leaves a truncated key.
+pub async fn write(path: &str, keypair: &Keypair, passphrase: Option<&str>) -> Result<()> {
+    let encoded = keypair.to_protobuf_encoding()?;
+    let bytes = match passphrase.filter(|p| !p.is_empty()) {
+        Some(passphrase) => {
+            let sealed = crypto::seal(&encoded, passphrase)?;
+            serde_json::to_vec_pretty(&EncryptedKeyFile {
+                format: KEY_FILE_FORMAT.into(),
+                version: KEY_FILE_VERSION,
+                kdf: KDF.into(),
+                salt: BASE64.encode(sealed.salt),
+                nonce: BASE64.encode(sealed.nonce),
+                ciphertext: BASE64.encode(sealed.ciphertext),
+            })?
+        }
+        None => encoded,
+    };
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        if !parent.as_os_str().is_empty() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+    }
+    let tmp = format!("{}.tmp", path);
+    let _ = tokio::fs::remove_file(&tmp).await;
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options
+        .open(&tmp)
+        .await
+        .map_err(|err| anyhow!("写入密钥文件 {} 失败: {}", path, err))?;
+    tokio::io::AsyncWriteExt::write_all(&mut file, &bytes).await?;
+    file.sync_all().await?;
+    drop(file);
+    tokio::fs::rename(&tmp, path)
+        .await
+        .map_err(|err| anyhow!("写入密钥文件 {} 失败: {}", path, err))?;
+    Ok(())
+}
+
+/// Whether the file at `path` is a passphrase-encrypted key file.
+pub async fn is_encrypted(path: &str) -> bool {
+    match tokio::fs::read(path).await {
+        Ok(bytes) => parse_envelope(&bytes).is_some(),
+        Err(_) => false,
+    }
+}
+
+fn parse_envelope(bytes: &[u8]) -> Option<EncryptedKeyFile> {
+    serde_json::from_slice::<EncryptedKeyFile>(bytes)
+        .ok()
+        .filter(|envelope| envelope.format == KEY_FILE_FORMAT)
+}
+
+#[cfg(unix)]
+async fn restrict_permissions(path: &str) {
+    use std::os::unix::fs::PermissionsExt;
+
+    let Ok(metadata) = tokio::fs::metadata(path).await else {
+        return;
+    };
+    let mode = metadata.permissions().mode() & 0o777;
+    if mode & 0o077 == 0 {
+        return;
+    }
+    match tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).await {
+        Ok(()) => tracing::warn!("密钥文件 {} 权限为 {:o}, 已收紧为 600", path, mode),
+        Err(err) => tracing::warn!("密钥文件 {} 权限为 {:o}, 收紧失败: {}", path, mode, err),
+    }
+}
+
+#[cfg(not(unix))]
+async fn restrict_permissions(_path: &str) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("porta-keystore-{}-{}", uuid::Uuid::new_v4(), name))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[tokio::test]
+    async fn should_require_passphrase_for_encrypted_key() {
+        let path = temp_path("node.key");
+        let keypair = Keypair::generate_ed25519();
+        write(&path, &keypair, Some("secret")).await.unwrap();
+        assert!(is_encrypted(&path).await);
+
+        let err = read(&path, None).await.err().unwrap();
+        assert!(err.downcast_ref::<KeyLocked>().is_some());
+        let err = read(&path, Some("wrong")).await.err().unwrap();
+        assert!(err.downcast_ref::<KeyLocked>().is_some());
+
+        let loaded = read(&path, Some("secret")).await.unwrap().unwrap();
+        assert!(loaded.encrypted);
+        assert_eq!(loaded.keypair.public(), keypair.public());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn should_keep_reading_plaintext_keys() {
+        let path = temp_path("node.key");
+        let keypair = Keypair::generate_ed25519();
+        std::fs::write(&path, keypair.to_protobuf_encoding().unwrap()).unwrap();
+        assert!(!is_encrypted(&path).await);
+
+        // A passphrase is not needed for, and does not break, a plaintext key
+        let loaded = read(&path, Some("secret")).await.unwrap().unwrap();
+        assert!(!loaded.encrypted);
+        assert_eq!(loaded.keypair.public(), keypair.public());
+        assert!(read(&temp_path("missing.key"), None)
+            .await
+            .unwrap()
+            .is_none());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn should_keep_key_files_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = temp_path("node.key");
+        write(&path, &Keypair::generate_ed25519(), None)
+            .await
+            .unwrap();
+        let mode = |path: &str| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&path), 0o600);
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        read(&path, None).await.unwrap().unwrap();
+        assert_eq!(mode(&path), 0o600);
+        let _ = std::fs::remove_file(&path);
+    }
+}
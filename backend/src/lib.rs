@@ -1,6 +1,18 @@
 pub mod app;
+pub mod backup;
+pub mod config;
+pub mod connection;
+pub mod crypto;
+pub mod events;
+pub mod identity;
+pub mod invite;
+pub mod keystore;
+pub mod memory;
+pub mod migrations;
 pub mod models;
 pub mod p2p;
+pub mod postgres;
+pub mod provision;
 pub mod proxy;
 pub mod resp;
 pub mod response;
@@ -12,11 +24,25 @@ use axum::Router;
 use state::AppState;
 use tower_http::cors::{Any, CorsLayer};
 
+pub use config::BackendConfig;
+
+/// Build the API from the `PORTA_*` environment variables.
 pub async fn create_app() -> Router {
     let state = AppState::new().await.expect("init state");
+    router(state)
+}
+
+/// Build the API for an explicit configuration.
+pub async fn create_app_with(config: BackendConfig) -> anyhow::Result<Router> {
+    Ok(router(AppState::with_config(config).await?))
+}
+
+pub fn router(state: AppState) -> Router {
     Router::new()
         .merge(routes::node::router(state.clone()))
+        .merge(routes::backup::router(state.clone()))
         .merge(routes::community::router(state.clone()))
+        .merge(routes::events::router(state.clone()))
         .merge(routes::service::router(state.clone()))
         .merge(routes::proxy::router(state))
         .layer(CorsLayer::new().allow_origin(Any).allow_methods(Any))
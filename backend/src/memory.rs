@@ -0,0 +1,1239 @@
+//! Pure-Rust `Store` that keeps everything in process memory.
+//!
+//! Used for `:memory:` nodes (tests, `porta connect`, embedded harnesses) so
+//! they run without sqlite. It mirrors `SqliteStore` behavior exactly; the
+//! conformance suite in `tests/store_conformance.rs` runs against both.
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use std::{collections::BTreeMap, sync::Arc};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{
+    models::{
+        AdmissionMode, CommunityAddRequest, CommunityNode, CommunityService, CommunitySummary,
+        DiscoveredService, JoinRequest, KeyImportRequest, NodeConfigUpdate, NodeInfo, PeerBan,
+        ProxyStatus, PublishRequest, PublishedService, RegistryChanges, SecureRoute, ServicePage,
+        ServiceQuery, ServiceRegistryItem, SessionInfo, SubscribeRequest, SubscribedService,
+    },
+    state::{Store, StoreResult, DEMO_COMMUNITIES},
+};
+
+struct CommunityEntry {
+    summary: CommunitySummary,
+    sync_revision: u64,
+}
+
+struct SessionEntry {
+    info: SessionInfo,
+    created_at: DateTime<Utc>,
+    last_active: DateTime<Utc>,
+}
+
+struct PeerEntry {
+    node_id: String,
+    node_uuid: String,
+    name: String,
+    role: String,
+    status: String,
+    banned: bool,
+    ban_reason: Option<String>,
+    banned_until: Option<i64>,
+    joined_at: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+}
+
+struct RegistryEntry {
+    item: ServiceRegistryItem,
+    announced: bool,
+}
+
+struct Inner {
+    node: NodeInfo,
+    proxy: ProxyStatus,
+    communities: BTreeMap<String, CommunityEntry>,
+    discovered: BTreeMap<String, DiscoveredService>,
+    subscriptions: BTreeMap<String, SubscribedService>,
+    published: BTreeMap<String, PublishedService>,
+    sessions: BTreeMap<String, SessionEntry>,
+    peers: BTreeMap<String, PeerEntry>,
+    registry: BTreeMap<String, RegistryEntry>,
+    /// Latest revision per registry uuid, kept as a tombstone after removal
+    revisions: BTreeMap<String, u64>,
+    /// (service uuid, subscriber peer) in the order they were recorded
+    service_subscriptions: Vec<(String, String)>,
+    routes: BTreeMap<String, SecureRoute>,
+    /// One-time invite id -> the peer that redeemed it
+    invite_redemptions: BTreeMap<String, String>,
+    admission: AdmissionMode,
+    join_requests: BTreeMap<String, JoinRequest>,
+}
+
+impl PeerEntry {
+    /// The peer's ban, unless it has expired.
+    fn active_ban(&self) -> Option<PeerBan> {
+        let expired = matches!(self.banned_until, Some(until) if until <= Utc::now().timestamp());
+        (self.banned && !expired).then(|| PeerBan {
+            reason: self.ban_reason.clone(),
+            until: self.banned_until,
+        })
+    }
+}
+
+impl Inner {
+    fn bump_registry_revision(&mut self, uuid: &str) {
+        let next = self.revisions.values().max().copied().unwrap_or(0) + 1;
+        self.revisions.insert(uuid.to_string(), next);
+    }
+}
+
+pub struct MemoryStore {
+    inner: Mutex<Inner>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Arc<Self> {
+        let node = NodeInfo {
+            name: "我的节点".into(),
+            node_id: String::new(),
+            uuid: Uuid::new_v4().to_string(),
+            key_path: "porta.node.key".into(),
+            tcp_listen_enable: true,
+            tcp_listen_port: 0,
+            quci_listen_enable: true,
+            quci_listen_port: 0,
+            external_addr: Vec::new(),
+            mdns_enable: true,
+            dht_enable: true,
+        };
+        let proxy = ProxyStatus {
+            enabled: true,
+            listen_port: 1080,
+            managed: false,
+        };
+        Arc::new(Self {
+            inner: Mutex::new(Inner {
+                node,
+                proxy,
+                communities: BTreeMap::new(),
+                discovered: BTreeMap::new(),
+                subscriptions: BTreeMap::new(),
+                published: BTreeMap::new(),
+                sessions: BTreeMap::new(),
+                peers: BTreeMap::new(),
+                registry: BTreeMap::new(),
+                revisions: BTreeMap::new(),
+                service_subscriptions: Vec::new(),
+                routes: BTreeMap::new(),
+                invite_redemptions: BTreeMap::new(),
+                admission: AdmissionMode::default(),
+                join_requests: BTreeMap::new(),
+            }),
+        })
+    }
+
+    /// Seed mock communities when there are none, like
+    /// `SqliteStore::seed_demo_communities`.
+    pub async fn seed_demo_communities(&self) -> StoreResult<()> {
+        let mut inner = self.inner.lock().await;
+        if inner.communities.is_empty() {
+            for (id, name, description, peers, multiaddr) in DEMO_COMMUNITIES {
+                inner.communities.insert(
+                    id.to_string(),
+                    CommunityEntry {
+                        summary: CommunitySummary {
+                            id: id.to_string(),
+                            name: name.to_string(),
+                            description: description.to_string(),
+                            peers: *peers,
+                            joined: false,
+                            multiaddr: Some(multiaddr.to_string()),
+                            multiaddrs: Vec::new(),
+                            last_multiaddr: None,
+                            peer_id: None,
+                            managed: false,
+                            state: Default::default(),
+                            connect_failures: 0,
+                            last_error: None,
+                            next_retry_at: None,
+                        },
+                        sync_revision: 0,
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+fn timestamp(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+fn session_info(entry: &SessionEntry) -> SessionInfo {
+    SessionInfo {
+        created_at: Some(timestamp(entry.created_at)),
+        last_active: Some(timestamp(entry.last_active)),
+        ..entry.info.clone()
+    }
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn node_info(&self) -> StoreResult<NodeInfo> {
+        Ok(self.inner.lock().await.node.clone())
+    }
+
+    async fn update_node_config(&self, req: NodeConfigUpdate) -> StoreResult<NodeInfo> {
+        let mut inner = self.inner.lock().await;
+        let info = &mut inner.node;
+        if let Some(name) = req.name {
+            info.name = name;
+        }
+        if let Some(tcp_listen_enable) = req.tcp_listen_enable {
+            info.tcp_listen_enable = tcp_listen_enable;
+        }
+        if let Some(tcp_listen_port) = req.tcp_listen_port {
+            info.tcp_listen_port = tcp_listen_port;
+        }
+        if let Some(quci_listen_enable) = req.quci_listen_enable {
+            info.quci_listen_enable = quci_listen_enable;
+        }
+        if let Some(quci_listen_port) = req.quci_listen_port {
+            info.quci_listen_port = quci_listen_port;
+        }
+        if let Some(external_addr) = req.external_addr {
+            info.external_addr = external_addr;
+        }
+        if let Some(mdns_enable) = req.mdns_enable {
+            info.mdns_enable = mdns_enable;
+        }
+        if let Some(dht_enable) = req.dht_enable {
+            info.dht_enable = dht_enable;
+        }
+        Ok(info.clone())
+    }
+
+    async fn import_key(&self, req: KeyImportRequest) -> StoreResult<NodeInfo> {
+        let mut inner = self.inner.lock().await;
+        inner.node.key_path = req.key_path;
+        Ok(inner.node.clone())
+    }
+
+    async fn generate_key(&self) -> StoreResult<NodeInfo> {
+        let mut inner = self.inner.lock().await;
+        inner.node.key_path = format!("porta-{}.key", Uuid::new_v4());
+        Ok(inner.node.clone())
+    }
+
+    async fn ensure_node_identity(&self, peer_id: &str) -> StoreResult<()> {
+        let mut inner = self.inner.lock().await;
+        if inner.node.node_id.is_empty() {
+            inner.node.node_id = peer_id.to_string();
+        }
+        Ok(())
+    }
+
+    async fn set_node_id(&self, peer_id: &str) -> StoreResult<()> {
+        self.inner.lock().await.node.node_id = peer_id.to_string();
+        Ok(())
+    }
+
+    async fn communities(&self) -> StoreResult<Vec<CommunitySummary>> {
+        let inner = self.inner.lock().await;
+        Ok(inner
+            .communities
+            .values()
+            .map(|entry| entry.summary.clone())
+            .collect())
+    }
+
+    async fn add_community(&self, req: CommunityAddRequest) -> StoreResult<CommunitySummary> {
+        let id = req
+            .id
+            .unwrap_or_else(|| format!("community-{}", Uuid::new_v4()));
+        let mut inner = self.inner.lock().await;
+        if inner.communities.contains_key(&id) {
+            return Err(anyhow!("社区已存在: {}", id));
+        }
+        let summary = CommunitySummary {
+            id: id.clone(),
+            name: req.name,
+            description: req.description,
+            peers: 0,
+            joined: false,
+            multiaddr: req.multiaddr,
+            multiaddrs: req.multiaddrs,
+            last_multiaddr: None,
+            peer_id: req.peer_id,
+            managed: false,
+            state: Default::default(),
+            connect_failures: 0,
+            last_error: None,
+            next_retry_at: None,
+        };
+        inner.communities.insert(
+            id,
+            CommunityEntry {
+                summary: summary.clone(),
+                sync_revision: 0,
+            },
+        );
+        Ok(summary)
+    }
+
+    async fn upsert_community(&self, req: CommunityAddRequest) -> StoreResult<()> {
+        let id = req
+            .id
+            .unwrap_or_else(|| format!("community-{}", Uuid::new_v4()));
+        let mut inner = self.inner.lock().await;
+        let entry = inner
+            .communities
+            .entry(id.clone())
+            .or_insert_with(|| CommunityEntry {
+                summary: CommunitySummary {
+                    id,
+                    name: String::new(),
+                    description: String::new(),
+                    peers: 0,
+                    joined: false,
+                    multiaddr: None,
+                    multiaddrs: Vec::new(),
+                    last_multiaddr: None,
+                    peer_id: None,
+                    managed: false,
+                    state: Default::default(),
+                    connect_failures: 0,
+                    last_error: None,
+                    next_retry_at: None,
+                },
+                sync_revision: 0,
+            });
+        entry.summary.name = req.name;
+        entry.summary.description = req.description;
+        entry.summary.multiaddr = req.multiaddr;
+        entry.summary.multiaddrs = req.multiaddrs;
+        entry.summary.peer_id = req.peer_id;
+        Ok(())
+    }
+
+    async fn set_community_managed(&self, id: &str, managed: bool) -> StoreResult<bool> {
+        let mut inner = self.inner.lock().await;
+        Ok(inner
+            .communities
+            .get_mut(id)
+            .map(|entry| entry.summary.managed = managed)
+            .is_some())
+    }
+
+    async fn remove_community(&self, id: &str) -> StoreResult<bool> {
+        Ok(self.inner.lock().await.communities.remove(id).is_some())
+    }
+
+    async fn connect_community(&self, id: &str) -> StoreResult<bool> {
+        let mut inner = self.inner.lock().await;
+        Ok(inner
+            .communities
+            .get_mut(id)
+            .map(|entry| entry.summary.joined = true)
+            .is_some())
+    }
+
+    async fn community_multiaddr(&self, id: &str) -> StoreResult<Option<String>> {
+        let inner = self.inner.lock().await;
+        Ok(inner
+            .communities
+            .get(id)
+            .and_then(|entry| entry.summary.multiaddr.clone()))
+    }
+
+    async fn community_by_id(&self, id: &str) -> StoreResult<Option<CommunitySummary>> {
+        let inner = self.inner.lock().await;
+        Ok(inner.communities.get(id).map(|entry| entry.summary.clone()))
+    }
+
+    async fn community_exists_by_peer(&self, peer_id: &str) -> StoreResult<bool> {
+        let inner = self.inner.lock().await;
+        Ok(inner
+            .communities
+            .values()
+            .any(|entry| entry.summary.peer_id.as_deref() == Some(peer_id)))
+    }
+
+    async fn update_community_multiaddr(
+        &self,
+        id: &str,
+        multiaddr: &str,
+        peer_id: &str,
+    ) -> StoreResult<bool> {
+        let mut inner = self.inner.lock().await;
+        Ok(inner
+            .communities
+            .get_mut(id)
+            .map(|entry| {
+                entry.summary.multiaddr = Some(multiaddr.to_string());
+                entry.summary.peer_id = Some(peer_id.to_string());
+            })
+            .is_some())
+    }
+
+    async fn set_community_last_multiaddr(&self, id: &str, multiaddr: &str) -> StoreResult<bool> {
+        let mut inner = self.inner.lock().await;
+        Ok(inner
+            .communities
+            .get_mut(id)
+            .map(|entry| entry.summary.last_multiaddr = Some(multiaddr.to_string()))
+            .is_some())
+    }
+
+    async fn set_community_connection(
+        &self,
+        id: &str,
+        failures: u32,
+        last_error: Option<&str>,
+        next_retry_at: Option<i64>,
+    ) -> StoreResult<bool> {
+        let mut inner = self.inner.lock().await;
+        Ok(inner
+            .communities
+            .get_mut(id)
+            .map(|entry| {
+                entry.summary.connect_failures = failures;
+                entry.summary.last_error = last_error.map(str::to_string);
+                entry.summary.next_retry_at = next_retry_at;
+            })
+            .is_some())
+    }
+
+    async fn set_community_peers(&self, id: &str, peers: u32) -> StoreResult<bool> {
+        let mut inner = self.inner.lock().await;
+        Ok(inner
+            .communities
+            .get_mut(id)
+            .map(|entry| entry.summary.peers = peers)
+            .is_some())
+    }
+
+    async fn community_nodes(&self) -> StoreResult<Vec<CommunityNode>> {
+        let inner = self.inner.lock().await;
+        Ok(inner
+            .peers
+            .iter()
+            .filter(|(_, peer)| peer.role == "edge")
+            .map(|(peer_id, peer)| {
+                let ban = peer.active_ban();
+                CommunityNode {
+                    id: peer_id.clone(),
+                    uuid: if peer.node_uuid.is_empty() {
+                        peer.node_id.clone()
+                    } else {
+                        peer.node_uuid.clone()
+                    },
+                    name: peer.name.clone(),
+                    status: peer.status.clone(),
+                    online: peer.status == "online",
+                    banned: ban.is_some(),
+                    ban_reason: ban.as_ref().and_then(|ban| ban.reason.clone()),
+                    banned_until: ban.as_ref().and_then(|ban| ban.until),
+                    joined_at: Some(timestamp(peer.joined_at)),
+                    last_seen: Some(timestamp(peer.last_seen)),
+                    services: inner
+                        .registry
+                        .values()
+                        .filter(|entry| &entry.item.provider_peer == peer_id)
+                        .count() as u32,
+                }
+            })
+            .collect())
+    }
+
+    async fn community_services(&self) -> StoreResult<Vec<CommunityService>> {
+        let inner = self.inner.lock().await;
+        Ok(inner
+            .registry
+            .values()
+            .map(|entry| CommunityService {
+                id: entry.item.uuid.clone(),
+                name: entry.item.name.clone(),
+                uuid: entry.item.uuid.clone(),
+                protocol: entry.item.r#type.clone(),
+                port: entry.item.port,
+                online: entry.item.online,
+                announced: entry.announced,
+            })
+            .collect())
+    }
+
+    async fn discovered_services(
+        &self,
+        community_id: Option<String>,
+    ) -> StoreResult<Vec<DiscoveredService>> {
+        let inner = self.inner.lock().await;
+        Ok(inner
+            .discovered
+            .values()
+            .filter(|svc| community_id.is_none() || svc.community_id == community_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn upsert_discovered_services(
+        &self,
+        community_id: &str,
+        services: Vec<ServiceRegistryItem>,
+    ) -> StoreResult<()> {
+        let mut inner = self.inner.lock().await;
+        for svc in services {
+            inner.discovered.insert(
+                svc.uuid.clone(),
+                DiscoveredService {
+                    uuid: svc.uuid,
+                    name: svc.name,
+                    r#type: svc.r#type,
+                    remote_port: svc.port,
+                    provider: svc.provider_peer,
+                    description: svc.description,
+                    subscribed: None,
+                    community_id: Some(community_id.to_string()),
+                    provider_addr: Some(svc.provider_addr),
+                    online: svc.online,
+                    tags: svc.tags,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    async fn remove_discovered_services(
+        &self,
+        community_id: &str,
+        uuids: &[String],
+    ) -> StoreResult<u64> {
+        let mut inner = self.inner.lock().await;
+        let mut removed = 0;
+        for uuid in uuids {
+            let matches = inner
+                .discovered
+                .get(uuid)
+                .is_some_and(|svc| svc.community_id.as_deref() == Some(community_id));
+            if matches {
+                inner.discovered.remove(uuid);
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    async fn clear_discovered_services(&self, community_id: &str) -> StoreResult<u64> {
+        let mut inner = self.inner.lock().await;
+        let before = inner.discovered.len();
+        inner
+            .discovered
+            .retain(|_, svc| svc.community_id.as_deref() != Some(community_id));
+        Ok((before - inner.discovered.len()) as u64)
+    }
+
+    async fn community_sync_revision(&self, id: &str) -> StoreResult<u64> {
+        let inner = self.inner.lock().await;
+        Ok(inner
+            .communities
+            .get(id)
+            .map(|entry| entry.sync_revision)
+            .unwrap_or(0))
+    }
+
+    async fn set_community_sync_revision(&self, id: &str, revision: u64) -> StoreResult<bool> {
+        let mut inner = self.inner.lock().await;
+        Ok(inner
+            .communities
+            .get_mut(id)
+            .map(|entry| entry.sync_revision = revision)
+            .is_some())
+    }
+
+    async fn subscribed_services(&self) -> StoreResult<Vec<SubscribedService>> {
+        let inner = self.inner.lock().await;
+        Ok(inner.subscriptions.values().cloned().collect())
+    }
+
+    async fn find_subscription(&self, id: &str) -> StoreResult<Option<SubscribedService>> {
+        Ok(self.inner.lock().await.subscriptions.get(id).cloned())
+    }
+
+    async fn published_services(&self) -> StoreResult<Vec<PublishedService>> {
+        let inner = self.inner.lock().await;
+        Ok(inner.published.values().cloned().collect())
+    }
+
+    async fn published_service_by_id(&self, id: &str) -> StoreResult<Option<PublishedService>> {
+        Ok(self.inner.lock().await.published.get(id).cloned())
+    }
+
+    async fn proxy_status(&self) -> StoreResult<ProxyStatus> {
+        Ok(self.inner.lock().await.proxy.clone())
+    }
+
+    async fn sessions(&self) -> StoreResult<Vec<SessionInfo>> {
+        let inner = self.inner.lock().await;
+        Ok(inner.sessions.values().map(session_info).collect())
+    }
+
+    async fn upsert_session(&self, session: SessionInfo) -> StoreResult<()> {
+        let now = Utc::now();
+        let mut inner = self.inner.lock().await;
+        let created_at = inner
+            .sessions
+            .get(&session.session_id)
+            .map(|entry| entry.created_at)
+            .unwrap_or(now);
+        inner.sessions.insert(
+            session.session_id.clone(),
+            SessionEntry {
+                info: session,
+                created_at,
+                last_active: now,
+            },
+        );
+        Ok(())
+    }
+
+    async fn cleanup_expired_sessions(
+        &self,
+        timeout_minutes: i64,
+    ) -> StoreResult<Vec<SessionInfo>> {
+        let cutoff = Utc::now() - Duration::minutes(timeout_minutes);
+        let mut inner = self.inner.lock().await;
+        let expired: Vec<String> = inner
+            .sessions
+            .iter()
+            .filter(|(_, entry)| entry.info.state == "connected" && entry.last_active < cutoff)
+            .map(|(id, _)| id.clone())
+            .collect();
+        Ok(expired
+            .iter()
+            .filter_map(|id| inner.sessions.remove(id))
+            .map(|entry| session_info(&entry))
+            .collect())
+    }
+
+    async fn update_subscription_endpoint(
+        &self,
+        id: &str,
+        remote_addr: &str,
+        status: &str,
+    ) -> StoreResult<bool> {
+        let mut inner = self.inner.lock().await;
+        Ok(inner
+            .subscriptions
+            .get_mut(id)
+            .map(|sub| {
+                sub.remote_addr = remote_addr.to_string();
+                sub.status = status.to_string();
+            })
+            .is_some())
+    }
+
+    async fn subscribe_service(&self, req: SubscribeRequest) -> StoreResult<SubscribedService> {
+        let id = req.id.unwrap_or_else(|| format!("sub-{}", Uuid::new_v4()));
+        let sub = SubscribedService {
+            id: id.clone(),
+            name: req.name,
+            r#type: req.r#type,
+            community: req.community,
+            remote_addr: req.remote_addr,
+            local_mapping: req.local_mapping,
+            status: "畅通".into(),
+            service_uuid: req.service_uuid,
+        };
+        self.inner
+            .lock()
+            .await
+            .subscriptions
+            .insert(id, sub.clone());
+        Ok(sub)
+    }
+
+    async fn update_subscription_status(&self, id: &str, status: &str) -> StoreResult<bool> {
+        let mut inner = self.inner.lock().await;
+        Ok(inner
+            .subscriptions
+            .get_mut(id)
+            .map(|sub| sub.status = status.to_string())
+            .is_some())
+    }
+
+    async fn update_subscriptions_by_service(
+        &self,
+        service_uuid: &str,
+        communities: &[String],
+        status: &str,
+    ) -> StoreResult<u64> {
+        let mut inner = self.inner.lock().await;
+        let mut changed = 0;
+        for sub in inner.subscriptions.values_mut().filter(|sub| {
+            sub.service_uuid.as_deref() == Some(service_uuid)
+                && communities.contains(&sub.community)
+        }) {
+            sub.status = status.to_string();
+            changed += 1;
+        }
+        Ok(changed)
+    }
+
+    async fn update_subscription_endpoints_by_service(
+        &self,
+        service_uuid: &str,
+        communities: &[String],
+        remote_addr: &str,
+    ) -> StoreResult<u64> {
+        let mut inner = self.inner.lock().await;
+        let mut changed = 0;
+        for sub in inner.subscriptions.values_mut().filter(|sub| {
+            sub.service_uuid.as_deref() == Some(service_uuid)
+                && communities.contains(&sub.community)
+        }) {
+            sub.remote_addr = remote_addr.to_string();
+            if sub.status == "离线" || sub.status == "已下架" {
+                sub.status = "断开".into();
+            }
+            changed += 1;
+        }
+        Ok(changed)
+    }
+
+    async fn publish_service(&self, req: PublishRequest) -> StoreResult<PublishedService> {
+        let id = req.id.unwrap_or_else(|| format!("pub-{}", Uuid::new_v4()));
+        let mut inner = self.inner.lock().await;
+        let existing = inner.published.get(&id);
+        let publish_date = existing
+            .map(|svc| svc.publish_date.clone())
+            .unwrap_or_else(|| Utc::now().format("%Y-%m-%d").to_string());
+        let subscriptions = existing.map(|svc| svc.subscriptions).unwrap_or(0);
+        let managed = existing.is_some_and(|svc| svc.managed);
+        let published = PublishedService {
+            id: id.clone(),
+            name: req.name,
+            r#type: req.r#type,
+            port: req.port,
+            summary: req.summary,
+            subscriptions,
+            status: "在线".into(),
+            publish_date,
+            tags: req.tags,
+            managed,
+        };
+        inner.published.insert(id, published.clone());
+        Ok(PublishedService {
+            subscriptions: 0,
+            ..published
+        })
+    }
+
+    async fn unpublish_service(&self, id: &str) -> StoreResult<bool> {
+        let mut inner = self.inner.lock().await;
+        Ok(inner
+            .published
+            .get_mut(id)
+            .map(|svc| svc.status = "已下架".into())
+            .is_some())
+    }
+
+    async fn remove_published(&self, id: &str) -> StoreResult<bool> {
+        Ok(self.inner.lock().await.published.remove(id).is_some())
+    }
+
+    async fn set_published_managed(&self, id: &str, managed: bool) -> StoreResult<bool> {
+        let mut inner = self.inner.lock().await;
+        Ok(inner
+            .published
+            .get_mut(id)
+            .map(|svc| svc.managed = managed)
+            .is_some())
+    }
+
+    async fn set_service_announced(&self, id: &str, announced: bool) -> StoreResult<bool> {
+        let mut inner = self.inner.lock().await;
+        let Some(entry) = inner.registry.get_mut(id) else {
+            return Ok(false);
+        };
+        entry.announced = announced;
+        inner.bump_registry_revision(id);
+        Ok(true)
+    }
+
+    async fn set_node_ban(
+        &self,
+        id: &str,
+        banned: bool,
+        reason: Option<&str>,
+        until: Option<i64>,
+    ) -> StoreResult<bool> {
+        let mut inner = self.inner.lock().await;
+        Ok(inner
+            .peers
+            .get_mut(id)
+            .map(|peer| {
+                peer.banned = banned;
+                peer.ban_reason = reason.filter(|_| banned).map(str::to_string);
+                peer.banned_until = until.filter(|_| banned);
+            })
+            .is_some())
+    }
+
+    async fn set_proxy_enabled(&self, enabled: bool) -> StoreResult<()> {
+        self.inner.lock().await.proxy.enabled = enabled;
+        Ok(())
+    }
+
+    async fn set_proxy_listen_port(&self, port: u16) -> StoreResult<()> {
+        self.inner.lock().await.proxy.listen_port = port;
+        Ok(())
+    }
+
+    async fn set_proxy_managed(&self, managed: bool) -> StoreResult<()> {
+        self.inner.lock().await.proxy.managed = managed;
+        Ok(())
+    }
+
+    async fn upsert_peer(
+        &self,
+        peer_id: &str,
+        node_id: &str,
+        node_uuid: &str,
+        name: &str,
+        role: &str,
+        status: &str,
+    ) -> StoreResult<()> {
+        let mut inner = self.inner.lock().await;
+        let now = Utc::now();
+        let entry = inner
+            .peers
+            .entry(peer_id.to_string())
+            .or_insert_with(|| PeerEntry {
+                node_id: String::new(),
+                node_uuid: String::new(),
+                name: String::new(),
+                role: String::new(),
+                status: String::new(),
+                banned: false,
+                ban_reason: None,
+                banned_until: None,
+                joined_at: now,
+                last_seen: now,
+            });
+        entry.node_id = node_id.to_string();
+        if !node_uuid.is_empty() {
+            entry.node_uuid = node_uuid.to_string();
+        }
+        entry.name = name.to_string();
+        entry.role = role.to_string();
+        entry.status = status.to_string();
+        entry.last_seen = now;
+        Ok(())
+    }
+
+    async fn set_peer_status(&self, peer_id: &str, status: &str) -> StoreResult<bool> {
+        let mut inner = self.inner.lock().await;
+        Ok(inner
+            .peers
+            .get_mut(peer_id)
+            .map(|peer| {
+                peer.status = status.to_string();
+                peer.last_seen = Utc::now();
+            })
+            .is_some())
+    }
+
+    async fn admission_mode(&self) -> StoreResult<AdmissionMode> {
+        Ok(self.inner.lock().await.admission)
+    }
+
+    async fn set_admission_mode(&self, mode: AdmissionMode) -> StoreResult<()> {
+        self.inner.lock().await.admission = mode;
+        Ok(())
+    }
+
+    async fn record_join_request(
+        &self,
+        peer_id: &str,
+        node_id: &str,
+        name: &str,
+    ) -> StoreResult<JoinRequest> {
+        let mut inner = self.inner.lock().await;
+        let request = inner
+            .join_requests
+            .entry(peer_id.to_string())
+            .or_insert_with(|| JoinRequest {
+                peer_id: peer_id.to_string(),
+                node_id: String::new(),
+                name: String::new(),
+                status: "pending".into(),
+                requested_at: Some(timestamp(Utc::now())),
+            });
+        request.node_id = node_id.to_string();
+        request.name = name.to_string();
+        Ok(request.clone())
+    }
+
+    async fn join_requests(&self) -> StoreResult<Vec<JoinRequest>> {
+        let inner = self.inner.lock().await;
+        let mut requests: Vec<JoinRequest> = inner.join_requests.values().cloned().collect();
+        requests.sort_by(|a, b| a.requested_at.cmp(&b.requested_at));
+        Ok(requests)
+    }
+
+    async fn set_join_request_status(&self, peer_id: &str, status: &str) -> StoreResult<bool> {
+        let mut inner = self.inner.lock().await;
+        Ok(inner
+            .join_requests
+            .get_mut(peer_id)
+            .map(|request| request.status = status.to_string())
+            .is_some())
+    }
+
+    async fn remove_join_request(&self, peer_id: &str) -> StoreResult<bool> {
+        let mut inner = self.inner.lock().await;
+        Ok(inner.join_requests.remove(peer_id).is_some())
+    }
+
+    async fn peer_role(&self, peer_id: &str) -> StoreResult<Option<String>> {
+        let inner = self.inner.lock().await;
+        Ok(inner.peers.get(peer_id).map(|peer| peer.role.clone()))
+    }
+
+    async fn peer_is_banned(&self, peer_id: &str) -> StoreResult<bool> {
+        Ok(self.peer_ban(peer_id).await?.is_some())
+    }
+
+    async fn peer_ban(&self, peer_id: &str) -> StoreResult<Option<PeerBan>> {
+        let inner = self.inner.lock().await;
+        Ok(inner.peers.get(peer_id).and_then(PeerEntry::active_ban))
+    }
+
+    async fn banned_peers(&self) -> StoreResult<Vec<String>> {
+        let inner = self.inner.lock().await;
+        Ok(inner
+            .peers
+            .iter()
+            .filter(|(_, peer)| peer.active_ban().is_some())
+            .map(|(peer_id, _)| peer_id.clone())
+            .collect())
+    }
+
+    async fn peers_by_node_uuid(&self, node_uuid: &str) -> StoreResult<Vec<String>> {
+        let inner = self.inner.lock().await;
+        Ok(inner
+            .peers
+            .iter()
+            .filter(|(_, peer)| peer.node_uuid == node_uuid)
+            .map(|(peer_id, _)| peer_id.clone())
+            .collect())
+    }
+
+    async fn upsert_service_registry(&self, service: ServiceRegistryItem) -> StoreResult<()> {
+        let mut inner = self.inner.lock().await;
+        let uuid = service.uuid.clone();
+        // A re-registered service keeps its announcement flag
+        let announced = match inner.registry.get(&uuid) {
+            Some(entry) => entry.announced,
+            None => true,
+        };
+        inner.registry.insert(
+            uuid.clone(),
+            RegistryEntry {
+                item: service,
+                announced,
+            },
+        );
+        inner.bump_registry_revision(&uuid);
+        Ok(())
+    }
+
+    async fn remove_service_registry(&self, uuid: &str) -> StoreResult<bool> {
+        let mut inner = self.inner.lock().await;
+        if inner.registry.remove(uuid).is_none() {
+            return Ok(false);
+        }
+        inner.bump_registry_revision(uuid);
+        Ok(true)
+    }
+
+    async fn remove_provider_services(&self, provider_peer: &str) -> StoreResult<Vec<String>> {
+        let mut inner = self.inner.lock().await;
+        let uuids: Vec<String> = inner
+            .registry
+            .values()
+            .filter(|entry| entry.item.provider_peer == provider_peer)
+            .map(|entry| entry.item.uuid.clone())
+            .collect();
+        for uuid in &uuids {
+            inner.registry.remove(uuid);
+            inner.bump_registry_revision(uuid);
+        }
+        Ok(uuids)
+    }
+
+    async fn set_provider_online(
+        &self,
+        provider_peer: &str,
+        online: bool,
+    ) -> StoreResult<Vec<String>> {
+        let mut inner = self.inner.lock().await;
+        let mut uuids = Vec::new();
+        for entry in inner.registry.values_mut().filter(|entry| {
+            entry.item.provider_peer == provider_peer && entry.item.online != online
+        }) {
+            entry.item.online = online;
+            uuids.push(entry.item.uuid.clone());
+        }
+        for uuid in &uuids {
+            inner.bump_registry_revision(uuid);
+        }
+        Ok(uuids)
+    }
+
+    async fn provider_service_count(&self, provider_peer: &str) -> StoreResult<u64> {
+        let inner = self.inner.lock().await;
+        Ok(inner
+            .registry
+            .values()
+            .filter(|entry| entry.item.provider_peer == provider_peer)
+            .count() as u64)
+    }
+
+    async fn list_service_registry(&self) -> StoreResult<Vec<ServiceRegistryItem>> {
+        let inner = self.inner.lock().await;
+        Ok(inner
+            .registry
+            .values()
+            .filter(|entry| entry.announced && entry.item.online)
+            .map(|entry| entry.item.clone())
+            .collect())
+    }
+
+    async fn search_service_registry(
+        &self,
+        query: &ServiceQuery,
+    ) -> StoreResult<ServicePage<ServiceRegistryItem>> {
+        let inner = self.inner.lock().await;
+        let mut matched: Vec<ServiceRegistryItem> = inner
+            .registry
+            .values()
+            .filter(|entry| entry.announced && (entry.item.online || !query.online_only))
+            .map(|entry| entry.item.clone())
+            .filter(|item| {
+                query.matches(
+                    &item.name,
+                    &item.description,
+                    &item.r#type,
+                    &item.provider_peer,
+                    &item.tags,
+                )
+            })
+            .collect();
+        matched.sort_by(|a, b| (&a.name, &a.uuid).cmp(&(&b.name, &b.uuid)));
+        Ok(query.paginate(matched, |item| (item.name.as_str(), item.uuid.as_str())))
+    }
+
+    async fn registry_changes_since(
+        &self,
+        since_revision: u64,
+        limit: u32,
+    ) -> StoreResult<RegistryChanges> {
+        let inner = self.inner.lock().await;
+        let current = inner.revisions.values().max().copied().unwrap_or(0);
+        let reset = since_revision > current;
+        let since = if reset { 0 } else { since_revision };
+
+        let mut pending: Vec<(&String, u64)> = inner
+            .revisions
+            .iter()
+            .filter(|(_, revision)| **revision > since)
+            .map(|(uuid, revision)| (uuid, *revision))
+            .collect();
+        pending.sort_by_key(|(_, revision)| *revision);
+
+        let more = pending.len() > limit as usize;
+        let mut changes = RegistryChanges {
+            revision: since,
+            reset,
+            more,
+            ..Default::default()
+        };
+        for (uuid, revision) in pending.into_iter().take(limit as usize) {
+            changes.revision = revision;
+            match inner.registry.get(uuid) {
+                Some(entry) if entry.announced && entry.item.online => {
+                    changes.upserted.push(entry.item.clone())
+                }
+                _ => changes.removed.push(uuid.clone()),
+            }
+        }
+        if !more {
+            changes.revision = changes.revision.max(current);
+        }
+        Ok(changes)
+    }
+
+    async fn resolve_service_registry(
+        &self,
+        uuid: &str,
+    ) -> StoreResult<Option<ServiceRegistryItem>> {
+        let inner = self.inner.lock().await;
+        Ok(inner.registry.get(uuid).map(|entry| entry.item.clone()))
+    }
+
+    async fn record_subscription(
+        &self,
+        service_uuid: &str,
+        subscriber_peer: &str,
+    ) -> StoreResult<()> {
+        let mut inner = self.inner.lock().await;
+        let exists = inner
+            .service_subscriptions
+            .iter()
+            .any(|(service, peer)| service == service_uuid && peer == subscriber_peer);
+        if !exists {
+            inner
+                .service_subscriptions
+                .push((service_uuid.to_string(), subscriber_peer.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn service_subscribers(&self, service_uuid: &str) -> StoreResult<Vec<String>> {
+        let inner = self.inner.lock().await;
+        Ok(inner
+            .service_subscriptions
+            .iter()
+            .filter(|(service, _)| service == service_uuid)
+            .map(|(_, peer)| peer.clone())
+            .collect())
+    }
+
+    async fn hand_over_peer(&self, old_peer: &str, new_peer: &str) -> StoreResult<u64> {
+        let mut inner = self.inner.lock().await;
+        let uuids: Vec<String> = inner
+            .registry
+            .iter_mut()
+            .filter(|(_, entry)| entry.item.provider_peer == old_peer)
+            .map(|(uuid, entry)| {
+                entry.item.provider_peer = new_peer.to_string();
+                uuid.clone()
+            })
+            .collect();
+        for uuid in &uuids {
+            inner.bump_registry_revision(uuid);
+        }
+        // Keep each subscription in place so subscriber order is unchanged
+        let mut moved = Vec::new();
+        for (service, peer) in inner.service_subscriptions.iter_mut() {
+            if peer == old_peer {
+                *peer = new_peer.to_string();
+                moved.push(service.clone());
+            }
+        }
+        let mut seen = std::collections::HashSet::new();
+        inner.service_subscriptions.retain(|(service, peer)| {
+            peer != new_peer || !moved.contains(service) || seen.insert(service.clone())
+        });
+        inner.peers.remove(old_peer);
+        Ok(uuids.len() as u64)
+    }
+
+    async fn redeem_invite(&self, invite_id: &str, peer_id: &str) -> StoreResult<bool> {
+        let mut inner = self.inner.lock().await;
+        let redeemed_by = inner
+            .invite_redemptions
+            .entry(invite_id.to_string())
+            .or_insert_with(|| peer_id.to_string());
+        Ok(redeemed_by == peer_id)
+    }
+
+    async fn invite_redeemed_by(&self, invite_id: &str) -> StoreResult<Option<String>> {
+        Ok(self
+            .inner
+            .lock()
+            .await
+            .invite_redemptions
+            .get(invite_id)
+            .cloned())
+    }
+
+    async fn secure_routes(&self) -> StoreResult<Vec<SecureRoute>> {
+        Ok(self.inner.lock().await.routes.values().cloned().collect())
+    }
+
+    async fn add_secure_route(&self, route: SecureRoute) -> StoreResult<()> {
+        let mut inner = self.inner.lock().await;
+        if inner.routes.contains_key(&route.id) {
+            return Err(anyhow!("安全路由已存在: {}", route.id));
+        }
+        inner.routes.insert(route.id.clone(), route);
+        Ok(())
+    }
+
+    async fn remove_secure_route(&self, id: &str) -> StoreResult<bool> {
+        Ok(self.inner.lock().await.routes.remove(id).is_some())
+    }
+
+    async fn update_secure_route_status(&self, id: &str, status: &str) -> StoreResult<bool> {
+        let mut inner = self.inner.lock().await;
+        Ok(inner
+            .routes
+            .get_mut(id)
+            .map(|route| route.status = status.to_string())
+            .is_some())
+    }
+
+    async fn find_secure_route(&self, id: &str) -> StoreResult<Option<SecureRoute>> {
+        Ok(self.inner.lock().await.routes.get(id).cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn should_expire_only_idle_connected_sessions() {
+        let store = MemoryStore::new();
+        for (id, state) in [
+            ("s-1", "connected"),
+            ("s-2", "closed"),
+            ("s-3", "connected"),
+        ] {
+            store
+                .upsert_session(SessionInfo {
+                    session_id: id.into(),
+                    service_id: "svc-a".into(),
+                    local_port: 2222,
+                    remote_peer: "peer-a".into(),
+                    state: state.into(),
+                    created_at: None,
+                    last_active: None,
+                })
+                .await
+                .unwrap();
+        }
+        {
+            let mut inner = store.inner.lock().await;
+            for id in ["s-1", "s-2"] {
+                inner.sessions.get_mut(id).unwrap().last_active -= Duration::hours(2);
+            }
+        }
+        let expired = store.cleanup_expired_sessions(30).await.unwrap();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].session_id, "s-1");
+        let remaining: Vec<String> = store
+            .sessions()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| s.session_id)
+            .collect();
+        assert_eq!(remaining, vec!["s-2", "s-3"]);
+    }
+}
@@ -0,0 +1,566 @@
+//! Versioned schema migrations for `SqliteStore`.
+//!
+//! Each migration runs once, in order, inside its own transaction and is
+//! recorded in `schema_version`. Databases created before versioning have no
+//! `schema_version` table; every step is written so it can run against any of
+//! them (tables are created only if missing, columns added only if absent), so
+//! they are brought up to date the same way as a fresh database. A file-backed
+//! database with pending migrations is copied aside before anything changes.
+
+use anyhow::{anyhow, Result};
+use sqlx::{Row, SqliteConnection, SqlitePool};
+
+enum Step {
+    Sql(&'static str),
+    AddColumn {
+        table: &'static str,
+        column: &'static str,
+        ddl: &'static str,
+    },
+}
+
+struct Migration {
+    version: i64,
+    name: &'static str,
+    steps: &'static [Step],
+}
+
+/// Schema version written by this build.
+pub const LATEST_VERSION: i64 = 11;
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "baseline schema of 0.1.0",
+        steps: &[
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS node_config (
+                    id INTEGER PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    node_id TEXT NOT NULL,
+                    uuid TEXT NOT NULL,
+                    key_path TEXT NOT NULL,
+                    tcp_listen_enable INTEGER NOT NULL,
+                    tcp_listen_port INTEGER NOT NULL,
+                    quci_listen_enable INTEGER NOT NULL,
+                    quci_listen_port INTEGER NOT NULL,
+                    external_addr TEXT NOT NULL,
+                    mdns_enable INTEGER NOT NULL,
+                    dht_enable INTEGER NOT NULL
+                )
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS communities (
+                    id TEXT PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    description TEXT NOT NULL,
+                    peers INTEGER NOT NULL,
+                    joined INTEGER NOT NULL,
+                    multiaddr TEXT,
+                    peer_id TEXT
+                )
+                "#,
+            ),
+            Step::AddColumn {
+                table: "communities",
+                column: "peer_id",
+                ddl: "ALTER TABLE communities ADD COLUMN peer_id TEXT",
+            },
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS community_nodes (
+                    id TEXT PRIMARY KEY,
+                    uuid TEXT NOT NULL,
+                    status TEXT NOT NULL,
+                    banned INTEGER NOT NULL
+                )
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS community_services (
+                    id TEXT PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    uuid TEXT NOT NULL,
+                    protocol TEXT NOT NULL,
+                    port INTEGER NOT NULL,
+                    online INTEGER NOT NULL,
+                    announced INTEGER NOT NULL
+                )
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS discovered_services (
+                    uuid TEXT PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    type TEXT NOT NULL,
+                    remote_port INTEGER NOT NULL,
+                    provider TEXT NOT NULL,
+                    description TEXT NOT NULL,
+                    community_id TEXT NOT NULL,
+                    provider_addr TEXT
+                )
+                "#,
+            ),
+            Step::AddColumn {
+                table: "discovered_services",
+                column: "provider_addr",
+                ddl: "ALTER TABLE discovered_services ADD COLUMN provider_addr TEXT",
+            },
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS subscribed_services (
+                    id TEXT PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    type TEXT NOT NULL,
+                    community TEXT NOT NULL,
+                    remote_addr TEXT NOT NULL,
+                    local_mapping TEXT NOT NULL,
+                    status TEXT NOT NULL,
+                    service_uuid TEXT
+                )
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS published_services (
+                    id TEXT PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    type TEXT NOT NULL,
+                    port INTEGER NOT NULL,
+                    summary TEXT NOT NULL,
+                    subscriptions INTEGER NOT NULL,
+                    status TEXT NOT NULL,
+                    publish_date TEXT NOT NULL
+                )
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS proxy_status (
+                    id INTEGER PRIMARY KEY,
+                    enabled INTEGER NOT NULL,
+                    listen_port INTEGER NOT NULL
+                )
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS sessions (
+                    session_id TEXT PRIMARY KEY,
+                    service_id TEXT NOT NULL,
+                    local_port INTEGER NOT NULL,
+                    remote_peer TEXT NOT NULL,
+                    state TEXT NOT NULL,
+                    created_at TEXT NOT NULL,
+                    last_active TEXT NOT NULL
+                )
+                "#,
+            ),
+            Step::AddColumn {
+                table: "sessions",
+                column: "created_at",
+                ddl: "ALTER TABLE sessions ADD COLUMN created_at TEXT NOT NULL DEFAULT (datetime('now'))",
+            },
+            Step::AddColumn {
+                table: "sessions",
+                column: "last_active",
+                ddl: "ALTER TABLE sessions ADD COLUMN last_active TEXT NOT NULL DEFAULT (datetime('now'))",
+            },
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS peers (
+                    peer_id TEXT PRIMARY KEY,
+                    node_id TEXT NOT NULL,
+                    role TEXT NOT NULL,
+                    status TEXT NOT NULL,
+                    banned INTEGER NOT NULL,
+                    last_seen TEXT NOT NULL
+                )
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS service_registry (
+                    uuid TEXT PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    type TEXT NOT NULL,
+                    port INTEGER NOT NULL,
+                    description TEXT NOT NULL,
+                    provider_peer TEXT NOT NULL,
+                    provider_addr TEXT NOT NULL,
+                    online INTEGER NOT NULL,
+                    announced INTEGER NOT NULL,
+                    updated_at TEXT NOT NULL
+                )
+                "#,
+            ),
+            Step::AddColumn {
+                table: "service_registry",
+                column: "announced",
+                ddl: "ALTER TABLE service_registry ADD COLUMN announced INTEGER NOT NULL DEFAULT 1",
+            },
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS service_subscriptions (
+                    service_uuid TEXT NOT NULL,
+                    subscriber_peer TEXT NOT NULL,
+                    created_at TEXT NOT NULL,
+                    PRIMARY KEY (service_uuid, subscriber_peer)
+                )
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS secure_routes (
+                    id TEXT PRIMARY KEY,
+                    subscription_id TEXT NOT NULL,
+                    relay_peers TEXT NOT NULL,
+                    local_port INTEGER NOT NULL,
+                    status TEXT NOT NULL,
+                    created_at TEXT NOT NULL
+                )
+                "#,
+            ),
+        ],
+    },
+    Migration {
+        version: 2,
+        name: "service tags",
+        steps: &[
+            Step::AddColumn {
+                table: "discovered_services",
+                column: "tags",
+                ddl: "ALTER TABLE discovered_services ADD COLUMN tags TEXT NOT NULL DEFAULT '[]'",
+            },
+            Step::AddColumn {
+                table: "published_services",
+                column: "tags",
+                ddl: "ALTER TABLE published_services ADD COLUMN tags TEXT NOT NULL DEFAULT '[]'",
+            },
+            Step::AddColumn {
+                table: "service_registry",
+                column: "tags",
+                ddl: "ALTER TABLE service_registry ADD COLUMN tags TEXT NOT NULL DEFAULT '[]'",
+            },
+        ],
+    },
+    Migration {
+        version: 3,
+        name: "registry revisions",
+        steps: &[
+            Step::AddColumn {
+                table: "communities",
+                column: "sync_revision",
+                ddl: "ALTER TABLE communities ADD COLUMN sync_revision INTEGER NOT NULL DEFAULT 0",
+            },
+            // One row per registry uuid ever seen; removed entries stay as tombstones
+            // so edges syncing by revision learn about deletions.
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS service_registry_revisions (
+                    uuid TEXT PRIMARY KEY,
+                    revision INTEGER NOT NULL
+                )
+                "#,
+            ),
+        ],
+    },
+    Migration {
+        version: 4,
+        name: "config-managed entries",
+        steps: &[
+            Step::AddColumn {
+                table: "communities",
+                column: "managed",
+                ddl: "ALTER TABLE communities ADD COLUMN managed INTEGER NOT NULL DEFAULT 0",
+            },
+            Step::AddColumn {
+                table: "published_services",
+                column: "managed",
+                ddl: "ALTER TABLE published_services ADD COLUMN managed INTEGER NOT NULL DEFAULT 0",
+            },
+            Step::AddColumn {
+                table: "proxy_status",
+                column: "managed",
+                ddl: "ALTER TABLE proxy_status ADD COLUMN managed INTEGER NOT NULL DEFAULT 0",
+            },
+        ],
+    },
+    Migration {
+        version: 5,
+        name: "invite redemptions",
+        steps: &[Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS invite_redemptions (
+                invite_id TEXT PRIMARY KEY,
+                peer_id TEXT NOT NULL,
+                redeemed_at TEXT NOT NULL
+            )
+            "#,
+        )],
+    },
+    Migration {
+        version: 6,
+        name: "community fallback addresses",
+        steps: &[
+            Step::AddColumn {
+                table: "communities",
+                column: "multiaddrs",
+                ddl: "ALTER TABLE communities ADD COLUMN multiaddrs TEXT NOT NULL DEFAULT '[]'",
+            },
+            Step::AddColumn {
+                table: "communities",
+                column: "last_multiaddr",
+                ddl: "ALTER TABLE communities ADD COLUMN last_multiaddr TEXT",
+            },
+        ],
+    },
+    Migration {
+        version: 7,
+        name: "community reconnect backoff",
+        steps: &[
+            Step::AddColumn {
+                table: "communities",
+                column: "connect_failures",
+                ddl: "ALTER TABLE communities ADD COLUMN connect_failures INTEGER NOT NULL DEFAULT 0",
+            },
+            Step::AddColumn {
+                table: "communities",
+                column: "last_error",
+                ddl: "ALTER TABLE communities ADD COLUMN last_error TEXT",
+            },
+            Step::AddColumn {
+                table: "communities",
+                column: "next_retry_at",
+                ddl: "ALTER TABLE communities ADD COLUMN next_retry_at INTEGER",
+            },
+        ],
+    },
+    Migration {
+        version: 8,
+        name: "community membership",
+        steps: &[
+            Step::AddColumn {
+                table: "peers",
+                column: "name",
+                ddl: "ALTER TABLE peers ADD COLUMN name TEXT NOT NULL DEFAULT ''",
+            },
+            Step::AddColumn {
+                table: "peers",
+                column: "joined_at",
+                ddl: "ALTER TABLE peers ADD COLUMN joined_at TEXT",
+            },
+        ],
+    },
+    Migration {
+        version: 9,
+        name: "community admission",
+        steps: &[
+            Step::AddColumn {
+                table: "node_config",
+                column: "admission",
+                ddl: "ALTER TABLE node_config ADD COLUMN admission TEXT NOT NULL DEFAULT 'open'",
+            },
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS join_requests (
+                    peer_id TEXT PRIMARY KEY,
+                    node_id TEXT NOT NULL,
+                    name TEXT NOT NULL,
+                    status TEXT NOT NULL,
+                    requested_at TEXT NOT NULL
+                )
+                "#,
+            ),
+        ],
+    },
+    Migration {
+        version: 10,
+        name: "peer bans",
+        steps: &[
+            Step::AddColumn {
+                table: "peers",
+                column: "node_uuid",
+                ddl: "ALTER TABLE peers ADD COLUMN node_uuid TEXT NOT NULL DEFAULT ''",
+            },
+            Step::AddColumn {
+                table: "peers",
+                column: "ban_reason",
+                ddl: "ALTER TABLE peers ADD COLUMN ban_reason TEXT",
+            },
+            Step::AddColumn {
+                table: "peers",
+                column: "banned_until",
+                ddl: "ALTER TABLE peers ADD COLUMN banned_until INTEGER",
+            },
+        ],
+    },
+    Migration {
+        version: 11,
+        name: "discovered service online state",
+        steps: &[Step::AddColumn {
+            table: "discovered_services",
+            column: "online",
+            ddl: "ALTER TABLE discovered_services ADD COLUMN online INTEGER NOT NULL DEFAULT 1",
+        }],
+    },
+];
+
+/// Highest migration applied to the database; 0 for an unversioned one.
+pub async fn schema_version(pool: &SqlitePool) -> Result<i64> {
+    let mut conn = pool.acquire().await?;
+    current_version(&mut conn).await
+}
+
+/// Bring the database up to `LATEST_VERSION`. `db_path` is the file to back
+/// up before migrating an existing database; `None` for in-memory stores.
+pub(crate) async fn run(pool: &SqlitePool, db_path: Option<&str>) -> Result<()> {
+    let mut conn = pool.acquire().await?;
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at TEXT NOT NULL
+        )
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+    let current = current_version(&mut conn).await?;
+    if current > LATEST_VERSION {
+        return Err(anyhow!(
+            "数据库结构版本 {} 高于当前程序支持的版本 {}, 请升级 Porta",
+            current,
+            LATEST_VERSION
+        ));
+    }
+    if current == LATEST_VERSION {
+        return Ok(());
+    }
+    if let Some(path) = db_path {
+        if table_exists(&mut conn, "node_config").await? {
+            backup(&mut conn, path, current).await?;
+        }
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        let mut tx = sqlx::Connection::begin(&mut *conn).await?;
+        for step in migration.steps {
+            match step {
+                Step::Sql(sql) => {
+                    sqlx::query(sql).execute(&mut *tx).await?;
+                }
+                Step::AddColumn { table, column, ddl } => {
+                    if !column_exists(&mut tx, table, column).await? {
+                        sqlx::query(ddl).execute(&mut *tx).await?;
+                    }
+                }
+            }
+        }
+        sqlx::query(
+            "INSERT INTO schema_version (version, name, applied_at) VALUES (?, ?, datetime('now'))",
+        )
+        .bind(migration.version)
+        .bind(migration.name)
+        .execute(&mut *tx)
+        .await
+        .map_err(|err| anyhow!("数据库迁移 {} 失败: {}", migration.version, err))?;
+        tx.commit().await?;
+        tracing::info!(
+            "数据库已迁移到版本 {}: {}",
+            migration.version,
+            migration.name
+        );
+    }
+    Ok(())
+}
+
+async fn current_version(conn: &mut SqliteConnection) -> Result<i64> {
+    if !table_exists(conn, "schema_version").await? {
+        return Ok(0);
+    }
+    let row = sqlx::query("SELECT COALESCE(MAX(version), 0) AS version FROM schema_version")
+        .fetch_one(&mut *conn)
+        .await?;
+    Ok(row.get("version"))
+}
+
+/// Copy the database next to itself as `<db>.v<version>-<timestamp>.bak`.
+async fn backup(conn: &mut SqliteConnection, db_path: &str, version: i64) -> Result<()> {
+    let target = format!(
+        "{}.v{}-{}.bak",
+        db_path,
+        version,
+        chrono::Utc::now().format("%Y%m%d%H%M%S")
+    );
+    sqlx::query("VACUUM INTO ?")
+        .bind(&target)
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("迁移前备份数据库失败: {}", err))?;
+    tracing::info!("迁移前已备份数据库到 {}", target);
+    Ok(())
+}
+
+async fn table_exists(conn: &mut SqliteConnection, table: &str) -> Result<bool> {
+    let row = sqlx::query(
+        "SELECT COUNT(*) AS count FROM sqlite_master WHERE type = 'table' AND name = ?",
+    )
+    .bind(table)
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(row.get::<i64, _>("count") > 0)
+}
+
+async fn column_exists(conn: &mut SqliteConnection, table: &str, column: &str) -> Result<bool> {
+    let pragma = format!("PRAGMA table_info({})", table);
+    let rows = sqlx::query(&pragma).fetch_all(&mut *conn).await?;
+    Ok(rows
+        .iter()
+        .any(|row| row.get::<String, _>("name") == column))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_number_migrations_in_order() {
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, index as i64 + 1);
+        }
+        assert_eq!(MIGRATIONS.last().unwrap().version, LATEST_VERSION);
+    }
+
+    #[tokio::test]
+    async fn should_migrate_fresh_database_once() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        run(&pool, None).await.unwrap();
+        assert_eq!(schema_version(&pool).await.unwrap(), LATEST_VERSION);
+        run(&pool, None).await.unwrap();
+        let row = sqlx::query("SELECT COUNT(*) AS count FROM schema_version")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(row.get::<i64, _>("count"), LATEST_VERSION);
+    }
+
+    #[tokio::test]
+    async fn should_refuse_newer_schema() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        run(&pool, None).await.unwrap();
+        sqlx::query("INSERT INTO schema_version VALUES (?, 'future', datetime('now'))")
+            .bind(LATEST_VERSION + 1)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let err = run(&pool, None).await.unwrap_err();
+        assert!(err.to_string().contains("升级"));
+    }
+}
@@ -24,8 +24,39 @@ pub struct CommunitySummary {
     pub joined: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub multiaddr: Option<String>,
+    /// Fallback addresses, dialed together with `multiaddr`
+    #[serde(default)]
+    pub multiaddrs: Vec<String>,
+    /// The address the last successful dial went through
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_multiaddr: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub peer_id: Option<String>,
+    /// Declared in the config file; read-only through the API
+    #[serde(default)]
+    pub managed: bool,
+    #[serde(default)]
+    pub state: ConnectionState,
+    /// Failed connection attempts in a row
+    #[serde(default)]
+    pub connect_failures: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Unix seconds of the next reconnect attempt while backing off
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_retry_at: Option<i64>,
+}
+
+/// Where a community connection stands; see [`crate::connection`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    #[default]
+    Disconnected,
+    Connecting,
+    Handshaking,
+    Connected,
+    Backoff,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -52,6 +83,10 @@ pub struct DiscoveredService {
     pub community_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub provider_addr: Option<String>,
+    #[serde(default = "default_true")]
+    pub online: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -77,6 +112,11 @@ pub struct PublishedService {
     pub subscriptions: u32,
     pub status: String,
     pub publish_date: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Declared in the config file; read-only through the API
+    #[serde(default)]
+    pub managed: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -89,6 +129,195 @@ pub struct ServiceRegistryItem {
     pub provider_peer: String,
     pub provider_addr: String,
     pub online: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// Filter and paging parameters for service discovery.
+///
+/// Shared by the HTTP discover endpoint and `P2pRequest::DiscoverServices`,
+/// so every field must stay optional on the wire for older peers.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServiceQuery {
+    /// Case-insensitive substring matched against name and description
+    #[serde(default)]
+    pub keyword: Option<String>,
+    #[serde(default)]
+    pub r#type: Option<String>,
+    /// A service must carry every listed tag to match
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Provider peer ID
+    #[serde(default)]
+    pub provider: Option<String>,
+    #[serde(default = "default_true")]
+    pub online_only: bool,
+    #[serde(default)]
+    pub offset: u32,
+    #[serde(default)]
+    pub limit: Option<u32>,
+    /// Opaque `next_cursor` from a previous page; takes precedence over `offset`
+    #[serde(default)]
+    pub cursor: Option<String>,
+}
+
+impl Default for ServiceQuery {
+    fn default() -> Self {
+        Self {
+            keyword: None,
+            r#type: None,
+            tags: Vec::new(),
+            provider: None,
+            online_only: true,
+            offset: 0,
+            limit: None,
+            cursor: None,
+        }
+    }
+}
+
+impl ServiceQuery {
+    /// Apply the filters (not the paging) to a single service.
+    pub fn matches(
+        &self,
+        name: &str,
+        description: &str,
+        r#type: &str,
+        provider: &str,
+        tags: &[String],
+    ) -> bool {
+        if let Some(keyword) = self.keyword.as_deref().map(str::trim) {
+            if !keyword.is_empty() {
+                let keyword = keyword.to_lowercase();
+                if !name.to_lowercase().contains(&keyword)
+                    && !description.to_lowercase().contains(&keyword)
+                {
+                    return false;
+                }
+            }
+        }
+        if let Some(kind) = self.r#type.as_deref() {
+            if !kind.is_empty() && !kind.eq_ignore_ascii_case(r#type) {
+                return false;
+            }
+        }
+        if let Some(expected) = self.provider.as_deref() {
+            if !expected.is_empty() && expected != provider {
+                return false;
+            }
+        }
+        self.tags
+            .iter()
+            .all(|wanted| tags.iter().any(|tag| tag.eq_ignore_ascii_case(wanted)))
+    }
+
+    /// Whether any filter beyond the default "online services" listing is set.
+    pub fn has_filters(&self) -> bool {
+        self.keyword
+            .as_deref()
+            .is_some_and(|k| !k.trim().is_empty())
+            || self.r#type.as_deref().is_some_and(|t| !t.is_empty())
+            || self.provider.as_deref().is_some_and(|p| !p.is_empty())
+            || !self.tags.is_empty()
+            || !self.online_only
+    }
+
+    /// Slice an already filtered list that is sorted by `key` (name, uuid).
+    ///
+    /// A cursor resumes strictly after the item it was taken from, so pages
+    /// stay stable while entries are added or removed between requests.
+    pub fn paginate<T>(&self, items: Vec<T>, key: impl Fn(&T) -> (&str, &str)) -> ServicePage<T> {
+        let total = items.len() as u64;
+        let after = self
+            .cursor
+            .as_deref()
+            .and_then(|c| serde_json::from_str::<(String, String)>(c).ok());
+        let mut rest: Vec<T> = match &after {
+            Some((name, uuid)) => items
+                .into_iter()
+                .filter(|item| key(item) > (name.as_str(), uuid.as_str()))
+                .collect(),
+            None => items.into_iter().skip(self.offset as usize).collect(),
+        };
+        let mut next_cursor = None;
+        if let Some(limit) = self.limit {
+            if rest.len() > limit as usize {
+                rest.truncate(limit as usize);
+                next_cursor = rest.last().and_then(|last| {
+                    let (name, uuid) = key(last);
+                    serde_json::to_string(&(name, uuid)).ok()
+                });
+            }
+        }
+        ServicePage {
+            items: rest,
+            total,
+            next_cursor,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServicePage<T> {
+    pub items: Vec<T>,
+    pub total: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+/// What happened to a service, as told to the edges subscribed to it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ServiceChange {
+    /// Announced again or moved to a new endpoint
+    Updated {
+        provider_addr: String,
+        port: u16,
+    },
+    Offline,
+    Removed,
+}
+
+impl ServiceChange {
+    /// Subscription status recorded on the subscriber, `None` if unchanged.
+    pub fn subscription_status(&self) -> Option<&'static str> {
+        match self {
+            ServiceChange::Updated { .. } => None,
+            ServiceChange::Offline => Some("离线"),
+            ServiceChange::Removed => Some("已下架"),
+        }
+    }
+}
+
+/// Registry entries changed after a given revision, as seen by a community node.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RegistryChanges {
+    /// Highest revision included; pass it back as `since_revision` next time
+    pub revision: u64,
+    pub upserted: Vec<ServiceRegistryItem>,
+    pub removed: Vec<String>,
+    /// More changes remain after `revision`
+    pub more: bool,
+    /// The caller's revision is ahead of this registry (e.g. it was rebuilt);
+    /// the caller must drop its cached copy before applying the changes
+    pub reset: bool,
+}
+
+/// Trim, drop empty and de-duplicate (case-insensitively) service tags.
+pub fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim();
+        if tag.is_empty() || out.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+            continue;
+        }
+        out.push(tag.to_string());
+    }
+    out
+}
+
+fn default_true() -> bool {
+    true
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -108,8 +337,102 @@ pub struct SessionInfo {
 pub struct CommunityNode {
     pub id: String,
     pub uuid: String,
+    /// Name the node announced in its Hello
+    #[serde(default)]
+    pub name: String,
     pub status: String,
+    /// Whether the node is connected right now
+    #[serde(default)]
+    pub online: bool,
     pub banned: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ban_reason: Option<String>,
+    /// Unix seconds the ban lifts at; a ban without it is permanent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub banned_until: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub joined_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<String>,
+    /// Services the node has published to this community
+    #[serde(default)]
+    pub services: u32,
+}
+
+/// Ban a community member by peer ID or by node UUID. Banning a UUID covers
+/// every peer ID the node has joined with, including after a key rotation.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NodeBanRequest {
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub uuid: Option<String>,
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Lift the ban after this many seconds; permanent when unset
+    #[serde(default)]
+    pub expires_in_secs: Option<u64>,
+}
+
+/// An active ban on a peer.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct PeerBan {
+    pub reason: Option<String>,
+    /// Unix seconds the ban lifts at
+    pub until: Option<i64>,
+}
+
+/// Who a community node lets join.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AdmissionMode {
+    /// Any edge that says Hello becomes a member
+    #[default]
+    Open,
+    /// Only edges presenting a valid invite
+    Invite,
+    /// Edges without an invite wait for an operator to approve them
+    Approval,
+}
+
+impl AdmissionMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AdmissionMode::Open => "open",
+            AdmissionMode::Invite => "invite",
+            AdmissionMode::Approval => "approval",
+        }
+    }
+}
+
+impl std::str::FromStr for AdmissionMode {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value.trim() {
+            "open" => Ok(AdmissionMode::Open),
+            "invite" => Ok(AdmissionMode::Invite),
+            "approval" => Ok(AdmissionMode::Approval),
+            other => Err(anyhow::anyhow!("未知的准入策略: {}", other)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AdmissionUpdate {
+    pub mode: AdmissionMode,
+}
+
+/// An edge waiting to be let into a community run in approval mode.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JoinRequest {
+    pub peer_id: String,
+    pub node_id: String,
+    pub name: String,
+    /// "pending" or "rejected"
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requested_at: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -127,6 +450,9 @@ pub struct CommunityService {
 pub struct ProxyStatus {
     pub enabled: bool,
     pub listen_port: u16,
+    /// Declared in the config file; read-only through the API
+    #[serde(default)]
+    pub managed: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -148,7 +474,7 @@ pub struct SubscribeRequest {
     pub local_mapping: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct NodeConfigUpdate {
     pub name: Option<String>,
     pub tcp_listen_enable: Option<bool>,
@@ -160,20 +486,176 @@ pub struct NodeConfigUpdate {
     pub dht_enable: Option<bool>,
 }
 
+/// Outcome of reloading configuration into a running node.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ReloadReport {
+    /// Settings now in effect
+    pub applied: Vec<String>,
+    /// Settings that changed but only take effect after a restart
+    pub restart_required: Vec<String>,
+}
+
+impl ReloadReport {
+    pub fn merge(&mut self, other: ReloadReport) {
+        self.applied.extend(other.applied);
+        self.restart_required.extend(other.restart_required);
+    }
+}
+
+/// Published services, communities and proxy settings declared in the
+/// server config file. The node reconciles them into the store at startup
+/// and on reload; entries that disappear from the file are removed.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Provisioning {
+    #[serde(default)]
+    pub publish: Vec<PublishProvision>,
+    #[serde(default)]
+    pub community: Vec<CommunityProvision>,
+    #[serde(default)]
+    pub proxy: Option<ProxyProvision>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PublishProvision {
+    /// Stable service ID, also used as the registry uuid
+    pub id: String,
+    pub name: String,
+    pub r#type: String,
+    pub port: u16,
+    #[serde(default)]
+    pub summary: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl From<&PublishProvision> for PublishRequest {
+    fn from(item: &PublishProvision) -> Self {
+        Self {
+            id: Some(item.id.clone()),
+            name: item.name.clone(),
+            r#type: item.r#type.clone(),
+            port: item.port,
+            summary: item.summary.clone(),
+            tags: normalize_tags(item.tags.clone()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CommunityProvision {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Community multiaddr including `/p2p/<peerId>`
+    pub multiaddr: String,
+    /// Fallback addresses of the same community
+    #[serde(default)]
+    pub multiaddrs: Vec<String>,
+    /// Join the community automatically
+    #[serde(default = "default_true")]
+    pub join: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProxyProvision {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Takes effect on the next start
+    #[serde(default)]
+    pub listen_port: Option<u16>,
+}
+
+/// Per-peer limits a node enforces on the peers it serves (`[limits]` in
+/// the server config). Every limit is off when unset.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct PeerLimits {
+    /// Requests a peer may send per second
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requests_per_sec: Option<u32>,
+    /// Streams relayed or forwarded for a peer at the same time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relay_streams: Option<u32>,
+    /// Bytes relayed for a peer per hour, both directions together
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relay_bytes_per_hour: Option<u64>,
+    /// Registry entries a single provider may register
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub services_per_provider: Option<u32>,
+    /// Inbound connections accepted from one IP address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connections_per_ip: Option<u32>,
+    /// Ban a peer after this many violations within an hour
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_ban_after: Option<u32>,
+    /// How long an automatic ban lasts; permanent when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_ban_secs: Option<u64>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct KeyImportRequest {
     pub key_path: String,
 }
 
+/// Change, set or remove the key file passphrase. `current_passphrase` is
+/// required while the key file is encrypted; an empty or missing
+/// `new_passphrase` stores the key in plaintext.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct KeyPassphraseRequest {
+    #[serde(default)]
+    pub current_passphrase: Option<String>,
+    #[serde(default)]
+    pub new_passphrase: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KeyFileStatus {
+    /// Key file the node loads at startup; `None` for in-memory nodes
+    pub key_path: Option<String>,
+    pub encrypted: bool,
+}
+
+/// Join a community, either by name, description and multiaddr or with an
+/// invite token, which fills in whatever is left empty.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CommunityAddRequest {
     pub id: Option<String>,
+    #[serde(default)]
     pub name: String,
+    #[serde(default)]
     pub description: String,
     #[serde(default)]
     pub multiaddr: Option<String>,
+    /// Further addresses of the same community, e.g. IPv6, DNS or relayed
+    #[serde(default)]
+    pub multiaddrs: Vec<String>,
     #[serde(default)]
     pub peer_id: Option<String>,
+    #[serde(default)]
+    pub invite: Option<String>,
+}
+
+/// Issue an invite to this community node. Name and description default to
+/// the node name and multiaddrs to the node's external and listen addresses.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct InviteCreateRequest {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub multiaddrs: Vec<String>,
+    #[serde(default)]
+    pub expires_in_secs: Option<u64>,
+    #[serde(default)]
+    pub one_time: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CommunityInvite {
+    pub token: String,
+    pub invite: crate::invite::Invite,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -195,6 +677,8 @@ pub struct PublishRequest {
     pub r#type: String,
     pub port: u16,
     pub summary: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -222,3 +706,79 @@ pub struct SecureConnectRequest {
     pub relay_peers: Vec<String>,
     pub local_port: Option<u16>,
 }
+
+/// Full node state in one versioned document. Exports always carry the
+/// passphrase-protected `encrypted`; a plain `data` is still accepted on import.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupArchive {
+    pub format: String,
+    pub version: u32,
+    pub created_at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<BackupData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted: Option<EncryptedBackup>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupData {
+    pub node: NodeInfo,
+    /// Base64 protobuf-encoded identity keypair
+    pub identity: String,
+    pub communities: Vec<CommunitySummary>,
+    pub subscriptions: Vec<SubscribedService>,
+    pub published: Vec<PublishedService>,
+    pub secure_routes: Vec<SecureRoute>,
+    pub proxy: ProxyStatus,
+}
+
+/// `BackupData` sealed with ChaCha20-Poly1305 under an Argon2id-derived key;
+/// binary fields are base64.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EncryptedBackup {
+    pub kdf: String,
+    pub salt: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BackupExportRequest {
+    #[serde(default)]
+    pub passphrase: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupImportRequest {
+    pub archive: BackupArchive,
+    #[serde(default)]
+    pub passphrase: Option<String>,
+    #[serde(default)]
+    pub on_conflict: ConflictPolicy,
+    /// Keep the running identity instead of restoring the archived one
+    #[serde(default)]
+    pub keep_identity: bool,
+}
+
+/// What to do when an imported entry has the ID of an existing one.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Keep the existing entry
+    #[default]
+    Skip,
+    /// Overwrite the existing entry
+    Replace,
+    /// Import nothing if any entry conflicts
+    Fail,
+}
+
+/// Outcome of importing a backup; entries read `<kind>/<id>`, e.g. `community/dev`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct BackupImportReport {
+    pub imported: Vec<String>,
+    /// Existing entries kept because of a conflict or because the config file manages them
+    pub skipped: Vec<String>,
+    /// Restored settings that only take effect after a restart
+    pub restart_required: Vec<String>,
+}
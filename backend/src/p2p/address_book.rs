@@ -0,0 +1,217 @@
+//! Addresses learned for remote peers.
+//!
+//! Entries come from connections this node dialed, from identify (the
+//! peer's listen ports on the address it was seen from) and from peers that
+//! register with this node as a rendezvous point. They let a community be
+//! found again by peer ID after it moved to a new address.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+
+/// Addresses kept per peer; older ones fall off the end.
+const MAX_ADDRS_PER_PEER: usize = 16;
+
+#[derive(Clone, Default)]
+pub struct AddressBook {
+    peers: Arc<Mutex<HashMap<PeerId, Vec<Multiaddr>>>>,
+}
+
+impl AddressBook {
+    /// Put `addrs` in front of what is already known for `peer`. Addresses
+    /// are stored without their `/p2p` suffix.
+    pub fn record(&self, peer: PeerId, addrs: impl IntoIterator<Item = Multiaddr>) {
+        let mut fresh: Vec<Multiaddr> = Vec::new();
+        for addr in addrs.into_iter().map(|addr| without_peer(&addr)) {
+            if addr.is_empty() || is_unspecified(&addr) || fresh.contains(&addr) {
+                continue;
+            }
+            fresh.push(addr);
+        }
+        if fresh.is_empty() {
+            return;
+        }
+        let mut peers = self.peers.lock().expect("address book lock poisoned");
+        let known = peers.entry(peer).or_default();
+        known.retain(|addr| !fresh.contains(addr));
+        fresh.append(known);
+        fresh.truncate(MAX_ADDRS_PER_PEER);
+        *known = fresh;
+    }
+
+    /// Known addresses for `peer`, newest first, each ending in `/p2p/<peer>`.
+    pub fn addrs(&self, peer: &PeerId) -> Vec<Multiaddr> {
+        self.peers
+            .lock()
+            .expect("address book lock poisoned")
+            .get(peer)
+            .map(|addrs| {
+                addrs
+                    .iter()
+                    .map(|addr| addr.clone().with(Protocol::P2p(*peer)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Addresses a node listening on `listeners` can be dialed at: each of
+/// `hosts` first, then the listen addresses themselves with loopback ones
+/// last. A host is a full multiaddr, `host:port` (TCP) or a bare IP or DNS
+/// name, which is paired with every listening transport.
+pub fn dialable_addrs(listeners: &[Multiaddr], hosts: &[String]) -> Vec<Multiaddr> {
+    let mut transports: Vec<Multiaddr> = Vec::new();
+    for addr in listeners {
+        let transport: Multiaddr = without_peer(addr).iter().skip(1).collect();
+        if !transports.contains(&transport) {
+            transports.push(transport);
+        }
+    }
+    let mut addrs: Vec<Multiaddr> = Vec::new();
+    for host in hosts.iter().map(|h| h.trim()).filter(|h| !h.is_empty()) {
+        if host.starts_with('/') {
+            if let Ok(addr) = host.parse::<Multiaddr>() {
+                addrs.push(without_peer(&addr));
+            }
+            continue;
+        }
+        if let Ok(socket) = host.parse::<std::net::SocketAddr>() {
+            addrs.push(
+                [
+                    host_protocol(&socket.ip().to_string()),
+                    Protocol::Tcp(socket.port()),
+                ]
+                .into_iter()
+                .collect(),
+            );
+            continue;
+        }
+        if let Some((name, port)) = host.rsplit_once(':') {
+            if let Ok(port) = port.parse::<u16>() {
+                addrs.push(
+                    [host_protocol(name), Protocol::Tcp(port)]
+                        .into_iter()
+                        .collect(),
+                );
+                continue;
+            }
+        }
+        let host = host_protocol(host);
+        for transport in &transports {
+            addrs.push(
+                std::iter::once(host.clone())
+                    .chain(transport.iter())
+                    .collect(),
+            );
+        }
+    }
+    let (loopback, routable): (Vec<&Multiaddr>, Vec<&Multiaddr>) = listeners
+        .iter()
+        .filter(|addr| !is_unspecified(addr))
+        .partition(|addr| is_loopback(addr));
+    for addr in routable.into_iter().chain(loopback) {
+        let addr = without_peer(addr);
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+    addrs
+}
+
+fn host_protocol(host: &str) -> Protocol<'static> {
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => Protocol::Ip4(ip),
+        Ok(IpAddr::V6(ip)) => Protocol::Ip6(ip),
+        Err(_) => Protocol::Dns(host.to_string().into()),
+    }
+}
+
+/// The IP a connection came from, as a host for [`dialable_addrs`].
+pub fn observed_host(addr: &Multiaddr) -> Option<String> {
+    host_ip(addr)
+        .filter(|ip| !ip.is_unspecified())
+        .map(|ip| ip.to_string())
+}
+
+pub fn without_peer(addr: &Multiaddr) -> Multiaddr {
+    addr.iter()
+        .filter(|protocol| !matches!(protocol, Protocol::P2p(_)))
+        .collect()
+}
+
+fn is_loopback(addr: &Multiaddr) -> bool {
+    host_ip(addr).map(|ip| ip.is_loopback()).unwrap_or(false)
+}
+
+fn is_unspecified(addr: &Multiaddr) -> bool {
+    host_ip(addr).map(|ip| ip.is_unspecified()).unwrap_or(false)
+}
+
+fn host_ip(addr: &Multiaddr) -> Option<IpAddr> {
+    match addr.iter().next()? {
+        Protocol::Ip4(ip) => Some(ip.into()),
+        Protocol::Ip6(ip) => Some(ip.into()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> Multiaddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn should_keep_newest_addresses_first() {
+        let book = AddressBook::default();
+        let peer = PeerId::random();
+        book.record(peer, vec![addr("/ip4/10.0.0.1/tcp/4001")]);
+        book.record(
+            peer,
+            vec![
+                addr(&format!("/ip4/10.0.0.2/tcp/4001/p2p/{}", peer)),
+                addr("/ip4/0.0.0.0/tcp/4001"),
+                addr("/ip4/10.0.0.1/tcp/4001"),
+            ],
+        );
+        assert_eq!(
+            book.addrs(&peer),
+            vec![
+                addr(&format!("/ip4/10.0.0.2/tcp/4001/p2p/{}", peer)),
+                addr(&format!("/ip4/10.0.0.1/tcp/4001/p2p/{}", peer)),
+            ]
+        );
+        assert!(book.addrs(&PeerId::random()).is_empty());
+    }
+
+    #[test]
+    fn should_put_hosts_before_listen_addresses() {
+        let listeners = vec![
+            addr("/ip4/127.0.0.1/tcp/4001"),
+            addr("/ip4/192.168.1.5/tcp/4001"),
+        ];
+        assert_eq!(
+            dialable_addrs(
+                &listeners,
+                &[
+                    "porta.example.com".into(),
+                    "203.0.113.7:9010".into(),
+                    "/ip6/2001:db8::1/tcp/9010".into(),
+                ]
+            ),
+            vec![
+                addr("/dns/porta.example.com/tcp/4001"),
+                addr("/ip4/203.0.113.7/tcp/9010"),
+                addr("/ip6/2001:db8::1/tcp/9010"),
+                addr("/ip4/192.168.1.5/tcp/4001"),
+                addr("/ip4/127.0.0.1/tcp/4001"),
+            ]
+        );
+    }
+}
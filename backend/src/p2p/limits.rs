@@ -0,0 +1,536 @@
+//! Per-peer rate limits and resource quotas.
+//!
+//! A community node serves every edge that joins it, so one misbehaving
+//! edge must not be able to use up its request handling, relay capacity or
+//! registry. [`PeerLimiter`] keeps the usage of each peer against the
+//! configured [`PeerLimits`]; [`IpLimits`] refuses inbound connections
+//! beyond the per-IP limit before they are upgraded.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    fmt,
+    net::IpAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use libp2p::{
+    core::{transport::PortUse, Endpoint},
+    multiaddr::Protocol,
+    swarm::{
+        dummy, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler,
+        THandlerInEvent, THandlerOutEvent, ToSwarm,
+    },
+    Multiaddr, PeerId,
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::models::PeerLimits;
+
+const SECOND: Duration = Duration::from_secs(1);
+const HOUR: Duration = Duration::from_secs(3600);
+/// Usage entries kept before idle ones are dropped
+const MAX_TRACKED_PEERS: usize = 4096;
+
+/// A limit a peer ran into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitViolation {
+    Requests,
+    RelayStreams,
+    RelayBytes,
+    Services,
+    Connections,
+}
+
+impl fmt::Display for LimitViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LimitViolation::Requests => "请求过于频繁",
+            LimitViolation::RelayStreams => "并发中继流超出限制",
+            LimitViolation::RelayBytes => "中继流量超出每小时限额",
+            LimitViolation::Services => "登记的服务数量超出限额",
+            LimitViolation::Connections => "同一 IP 的连接数超出限制",
+        })
+    }
+}
+
+impl std::error::Error for LimitViolation {}
+
+#[derive(Clone, Default)]
+pub struct PeerLimiter {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    limits: PeerLimits,
+    peers: HashMap<PeerId, Usage>,
+    ips: HashMap<IpAddr, u32>,
+}
+
+struct Usage {
+    second_started: Instant,
+    requests: u32,
+    streams: u32,
+    hour_started: Instant,
+    bytes: u64,
+    violations: VecDeque<Instant>,
+}
+
+impl Usage {
+    fn new(now: Instant) -> Self {
+        Self {
+            second_started: now,
+            requests: 0,
+            streams: 0,
+            hour_started: now,
+            bytes: 0,
+            violations: VecDeque::new(),
+        }
+    }
+
+    /// Byte budget used in the current hour, starting a new hour when due.
+    fn bytes(&mut self, now: Instant) -> u64 {
+        if now.duration_since(self.hour_started) >= HOUR {
+            self.hour_started = now;
+            self.bytes = 0;
+        }
+        self.bytes
+    }
+
+    fn idle(&self, now: Instant) -> bool {
+        self.streams == 0
+            && now.duration_since(self.hour_started) >= HOUR
+            && !matches!(self.violations.back(), Some(at) if now.duration_since(*at) < HOUR)
+    }
+}
+
+impl Inner {
+    fn usage(&mut self, peer: PeerId, now: Instant) -> &mut Usage {
+        if self.peers.len() >= MAX_TRACKED_PEERS && !self.peers.contains_key(&peer) {
+            self.peers.retain(|_, usage| !usage.idle(now));
+        }
+        self.peers.entry(peer).or_insert_with(|| Usage::new(now))
+    }
+}
+
+impl PeerLimiter {
+    pub fn new(limits: PeerLimits) -> Self {
+        let limiter = Self::default();
+        limiter.set_limits(limits);
+        limiter
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().expect("peer limiter lock poisoned")
+    }
+
+    /// Replace the limits; usage counted so far is kept.
+    pub fn set_limits(&self, limits: PeerLimits) {
+        self.lock().limits = limits;
+    }
+
+    pub fn limits(&self) -> PeerLimits {
+        self.lock().limits.clone()
+    }
+
+    /// Count one request from `peer` against its per-second limit.
+    pub fn check_request(&self, peer: &PeerId) -> Result<(), LimitViolation> {
+        let now = Instant::now();
+        let mut inner = self.lock();
+        let Some(limit) = inner.limits.requests_per_sec else {
+            return Ok(());
+        };
+        let usage = inner.usage(*peer, now);
+        if now.duration_since(usage.second_started) >= SECOND {
+            usage.second_started = now;
+            usage.requests = 0;
+        }
+        if usage.requests >= limit {
+            return Err(LimitViolation::Requests);
+        }
+        usage.requests += 1;
+        Ok(())
+    }
+
+    /// Start relaying a stream for `peer`. The stream counts against the
+    /// concurrency limit until the returned permit is dropped.
+    pub fn open_stream(&self, peer: &PeerId) -> Result<StreamPermit, LimitViolation> {
+        let now = Instant::now();
+        let mut inner = self.lock();
+        let limits = inner.limits.clone();
+        let usage = inner.usage(*peer, now);
+        if matches!(limits.relay_streams, Some(limit) if usage.streams >= limit) {
+            return Err(LimitViolation::RelayStreams);
+        }
+        if matches!(limits.relay_bytes_per_hour, Some(limit) if usage.bytes(now) >= limit) {
+            return Err(LimitViolation::RelayBytes);
+        }
+        usage.streams += 1;
+        Ok(StreamPermit {
+            limiter: self.clone(),
+            peer: *peer,
+        })
+    }
+
+    /// Charge `bytes` relayed for `peer` to its hourly budget.
+    pub fn charge_bytes(&self, peer: &PeerId, bytes: u64) -> Result<(), LimitViolation> {
+        let now = Instant::now();
+        let mut inner = self.lock();
+        let limit = inner.limits.relay_bytes_per_hour;
+        let usage = inner.usage(*peer, now);
+        let used = usage.bytes(now).saturating_add(bytes);
+        usage.bytes = used;
+        match limit {
+            Some(limit) if used > limit => Err(LimitViolation::RelayBytes),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether a provider that already has `registered` entries may add one.
+    pub fn check_services(&self, registered: u64) -> Result<(), LimitViolation> {
+        match self.lock().limits.services_per_provider {
+            Some(limit) if registered >= u64::from(limit) => Err(LimitViolation::Services),
+            _ => Ok(()),
+        }
+    }
+
+    /// Record that `peer` broke a limit. Returns true once it has done so
+    /// often enough within an hour to be banned; the count then starts over.
+    pub fn violation(&self, peer: &PeerId) -> bool {
+        let now = Instant::now();
+        let mut inner = self.lock();
+        let threshold = inner.limits.auto_ban_after;
+        let usage = inner.usage(*peer, now);
+        while matches!(usage.violations.front(), Some(at) if now.duration_since(*at) >= HOUR) {
+            usage.violations.pop_front();
+        }
+        usage.violations.push_back(now);
+        match threshold {
+            Some(threshold) if usage.violations.len() >= threshold.max(1) as usize => {
+                usage.violations.clear();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn admit_ip(&self, ip: IpAddr) -> Result<(), LimitViolation> {
+        let mut inner = self.lock();
+        let limit = inner.limits.connections_per_ip;
+        let count = inner.ips.entry(ip).or_default();
+        if matches!(limit, Some(limit) if *count >= limit) {
+            return Err(LimitViolation::Connections);
+        }
+        *count += 1;
+        Ok(())
+    }
+
+    fn release_ip(&self, ip: IpAddr) {
+        let mut inner = self.lock();
+        if let Some(count) = inner.ips.get_mut(&ip) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                inner.ips.remove(&ip);
+            }
+        }
+    }
+}
+
+/// A relayed stream counted against its peer's concurrency limit.
+pub struct StreamPermit {
+    limiter: PeerLimiter,
+    peer: PeerId,
+}
+
+impl Drop for StreamPermit {
+    fn drop(&mut self) {
+        let mut inner = self.limiter.lock();
+        if let Some(usage) = inner.peers.get_mut(&self.peer) {
+            usage.streams = usage.streams.saturating_sub(1);
+        }
+    }
+}
+
+/// An I/O stream whose reads are charged to a peer's relay budget. The
+/// read that uses up the budget is still delivered; the next one fails,
+/// which ends the relay.
+pub struct Metered<S> {
+    inner: S,
+    limiter: PeerLimiter,
+    peer: PeerId,
+    exhausted: Option<LimitViolation>,
+}
+
+impl<S> Metered<S> {
+    pub fn new(inner: S, limiter: PeerLimiter, peer: PeerId) -> Self {
+        Self {
+            inner,
+            limiter,
+            peer,
+            exhausted: None,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Metered<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = &mut *self;
+        if let Some(violation) = this.exhausted {
+            return Poll::Ready(Err(std::io::Error::other(violation)));
+        }
+        let before = buf.filled().len();
+        let polled = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = polled {
+            let read = (buf.filled().len() - before) as u64;
+            this.exhausted = this.limiter.charge_bytes(&this.peer, read).err();
+        }
+        polled
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Metered<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Refuses inbound connections from an IP address that already has
+/// `connections_per_ip` of them.
+pub struct IpLimits {
+    limiter: PeerLimiter,
+    connections: HashMap<ConnectionId, IpAddr>,
+}
+
+impl IpLimits {
+    pub fn new(limiter: PeerLimiter) -> Self {
+        Self {
+            limiter,
+            connections: HashMap::new(),
+        }
+    }
+
+    fn release(&mut self, connection_id: ConnectionId) {
+        if let Some(ip) = self.connections.remove(&connection_id) {
+            self.limiter.release_ip(ip);
+        }
+    }
+}
+
+fn ip_of(addr: &Multiaddr) -> Option<IpAddr> {
+    addr.iter().find_map(|protocol| match protocol {
+        Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+        Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+        _ => None,
+    })
+}
+
+impl NetworkBehaviour for IpLimits {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = Infallible;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        _: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        let Some(ip) = ip_of(remote_addr) else {
+            return Ok(());
+        };
+        if let Err(violation) = self.limiter.admit_ip(ip) {
+            tracing::warn!("[P2P] 拒绝来自 {} 的连接: {}", ip, violation);
+            return Err(ConnectionDenied::new(violation));
+        }
+        self.connections.insert(connection_id, ip);
+        Ok(())
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        _: ConnectionId,
+        _: PeerId,
+        _: &Multiaddr,
+        _: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        _: ConnectionId,
+        _: PeerId,
+        _: &Multiaddr,
+        _: Endpoint,
+        _: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        match event {
+            FromSwarm::ConnectionClosed(closed) => self.release(closed.connection_id),
+            FromSwarm::ListenFailure(failure) => self.release(failure.connection_id),
+            _ => {}
+        }
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _: PeerId,
+        _: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        match event {}
+    }
+
+    fn poll(&mut self, _: &mut Context<'_>) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(limits: PeerLimits) -> PeerLimiter {
+        PeerLimiter::new(limits)
+    }
+
+    #[test]
+    fn should_allow_everything_without_limits() {
+        let limiter = PeerLimiter::default();
+        let peer = PeerId::random();
+        for _ in 0..1000 {
+            limiter.check_request(&peer).unwrap();
+        }
+        let _permits: Vec<_> = (0..100)
+            .map(|_| limiter.open_stream(&peer).unwrap())
+            .collect();
+        limiter.charge_bytes(&peer, u64::MAX / 2).unwrap();
+        limiter.check_services(10_000).unwrap();
+        assert!(!limiter.violation(&peer));
+    }
+
+    #[test]
+    fn should_limit_requests_per_peer() {
+        let limiter = limiter(PeerLimits {
+            requests_per_sec: Some(3),
+            ..Default::default()
+        });
+        let (noisy, quiet) = (PeerId::random(), PeerId::random());
+        for _ in 0..3 {
+            limiter.check_request(&noisy).unwrap();
+        }
+        assert_eq!(limiter.check_request(&noisy), Err(LimitViolation::Requests));
+        limiter.check_request(&quiet).unwrap();
+    }
+
+    #[test]
+    fn should_release_stream_permits_on_drop() {
+        let limiter = limiter(PeerLimits {
+            relay_streams: Some(2),
+            ..Default::default()
+        });
+        let peer = PeerId::random();
+        let first = limiter.open_stream(&peer).unwrap();
+        let _second = limiter.open_stream(&peer).unwrap();
+        assert!(matches!(
+            limiter.open_stream(&peer),
+            Err(LimitViolation::RelayStreams)
+        ));
+        drop(first);
+        limiter.open_stream(&peer).unwrap();
+    }
+
+    #[test]
+    fn should_stop_relaying_once_bytes_run_out() {
+        let limiter = limiter(PeerLimits {
+            relay_bytes_per_hour: Some(100),
+            ..Default::default()
+        });
+        let peer = PeerId::random();
+        limiter.charge_bytes(&peer, 60).unwrap();
+        assert_eq!(
+            limiter.charge_bytes(&peer, 60),
+            Err(LimitViolation::RelayBytes)
+        );
+        assert!(matches!(
+            limiter.open_stream(&peer),
+            Err(LimitViolation::RelayBytes)
+        ));
+        limiter.open_stream(&PeerId::random()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_fail_metered_reads_over_budget() {
+        use tokio::io::AsyncReadExt;
+
+        let limiter = limiter(PeerLimits {
+            relay_bytes_per_hour: Some(4),
+            ..Default::default()
+        });
+        let peer = PeerId::random();
+        let mut small = Metered::new(&b"abc"[..], limiter.clone(), peer);
+        let mut buf = Vec::new();
+        small.read_to_end(&mut buf).await.unwrap();
+        let mut large = Metered::new(&b"defgh"[..], limiter, peer);
+        assert!(large.read_to_end(&mut buf).await.is_err());
+    }
+
+    #[test]
+    fn should_cap_services_per_provider() {
+        let limiter = limiter(PeerLimits {
+            services_per_provider: Some(2),
+            ..Default::default()
+        });
+        limiter.check_services(1).unwrap();
+        assert_eq!(limiter.check_services(2), Err(LimitViolation::Services));
+    }
+
+    #[test]
+    fn should_limit_connections_per_ip() {
+        let limiter = limiter(PeerLimits {
+            connections_per_ip: Some(1),
+            ..Default::default()
+        });
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        limiter.admit_ip(ip).unwrap();
+        assert_eq!(limiter.admit_ip(ip), Err(LimitViolation::Connections));
+        limiter.admit_ip("10.0.0.2".parse().unwrap()).unwrap();
+        limiter.release_ip(ip);
+        limiter.admit_ip(ip).unwrap();
+    }
+
+    #[test]
+    fn should_ask_for_a_ban_after_repeated_violations() {
+        let limiter = limiter(PeerLimits {
+            auto_ban_after: Some(3),
+            ..Default::default()
+        });
+        let peer = PeerId::random();
+        assert!(!limiter.violation(&peer));
+        assert!(!limiter.violation(&peer));
+        assert!(limiter.violation(&peer));
+        assert!(!limiter.violation(&peer));
+    }
+}
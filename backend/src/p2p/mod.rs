@@ -1,3 +1,5 @@
+pub mod address_book;
+pub mod limits;
 pub mod node;
 pub mod protocol;
 
@@ -7,6 +7,8 @@ use anyhow::{anyhow, Result};
 use libp2p::futures::io::{AsyncReadExt, AsyncWriteExt};
 use libp2p::futures::StreamExt;
 use libp2p::{
+    allow_block_list::{self, BlockedPeers},
+    core::ConnectedPoint,
     identify, identity,
     multiaddr::Protocol,
     ping,
@@ -15,16 +17,32 @@ use libp2p::{
         Event as RequestResponseEvent, Message as RequestResponseMessage, OutboundRequestId,
         ProtocolSupport,
     },
-    swarm::{NetworkBehaviour, Stream, StreamProtocol, Swarm, SwarmEvent},
+    swarm::{
+        dial_opts::{DialOpts, PeerCondition},
+        NetworkBehaviour, Stream, StreamProtocol, Swarm, SwarmEvent,
+    },
     tcp, yamux, Multiaddr, PeerId, Transport,
 };
 use libp2p_stream::{Behaviour as StreamBehaviour, Control as StreamControl};
 use tokio::sync::{mpsc, oneshot};
 use tokio_util::compat::FuturesAsyncReadCompatExt;
 
-use crate::{models::ServiceRegistryItem, state::Store};
+use crate::{
+    config::BackendConfig,
+    events::{EventBus, PortaEvent},
+    keystore::{self, KeyLocked},
+    models::{
+        normalize_tags, AdmissionMode, PeerBan, PeerLimits, RegistryChanges, ServiceChange,
+        ServiceRegistryItem,
+    },
+    state::Store,
+};
 
-use super::protocol::{JsonCodec, P2pRequest, P2pResponse, PortaProtocol, ServiceAnnouncement};
+use super::address_book::{dialable_addrs, observed_host, without_peer, AddressBook};
+use super::limits::{IpLimits, LimitViolation, Metered, PeerLimiter};
+use super::protocol::{
+    JsonCodec, P2pRequest, P2pResponse, PortaProtocol, MAX_SERVICE_PAGE_SIZE, MAX_SYNC_BATCH,
+};
 use super::STREAM_PROTOCOL;
 
 #[derive(NetworkBehaviour)]
@@ -33,219 +51,276 @@ struct PortaBehaviour {
     ping: ping::Behaviour,
     identify: identify::Behaviour,
     stream: StreamBehaviour,
+    /// Banned peers: their connections are closed and new ones refused
+    blocked: allow_block_list::Behaviour<BlockedPeers>,
+    ip_limits: IpLimits,
 }
 
 enum Command {
     Dial {
-        addr: Multiaddr,
+        addrs: Vec<Multiaddr>,
         peer_id: PeerId,
-        respond_to: oneshot::Sender<Result<()>>,
+        respond_to: oneshot::Sender<Result<Option<Multiaddr>>>,
     },
     Request {
         peer: PeerId,
         request: P2pRequest,
         respond_to: oneshot::Sender<Result<P2pResponse>>,
     },
+    Listen {
+        addr: Multiaddr,
+        respond_to: oneshot::Sender<Result<()>>,
+    },
+    Listeners {
+        respond_to: oneshot::Sender<Vec<Multiaddr>>,
+    },
+    PushRegistry,
+    BlockPeers {
+        peers: HashSet<PeerId>,
+    },
+    NotifySubscribers {
+        service_uuid: String,
+        change: ServiceChange,
+    },
+    Shutdown {
+        respond_to: oneshot::Sender<()>,
+    },
+}
+
+/// Edges watching the local service registry.
+#[derive(Default)]
+struct RegistryWatchers {
+    peers: HashMap<PeerId, RegistryWatcher>,
+}
+
+struct RegistryWatcher {
+    /// Last revision pushed to (or reported by) the edge
+    revision: u64,
+    /// At most one push per edge is outstanding so changes arrive in order
+    in_flight: Option<OutboundRequestId>,
+}
+
+impl RegistryWatchers {
+    /// Start (or renew) a watch. A renewal while a push is outstanding keeps
+    /// the pushed revision so the edge is not sent the same changes twice.
+    fn watch(&mut self, peer: PeerId, revision: u64) {
+        self.peers
+            .entry(peer)
+            .and_modify(|watcher| {
+                if watcher.in_flight.is_none() {
+                    watcher.revision = revision;
+                }
+            })
+            .or_insert(RegistryWatcher {
+                revision,
+                in_flight: None,
+            });
+    }
+
+    fn forget(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+    }
+
+    /// Settle an outstanding push. Returns the edge it was sent to, or `None`
+    /// if the request was not a registry push. Edges that reject a push are
+    /// dropped and have to watch again.
+    fn complete(&mut self, request_id: OutboundRequestId, accepted: bool) -> Option<PeerId> {
+        let peer = self
+            .peers
+            .iter()
+            .find(|(_, watcher)| watcher.in_flight == Some(request_id))
+            .map(|(peer, _)| *peer)?;
+        if accepted {
+            if let Some(watcher) = self.peers.get_mut(&peer) {
+                watcher.in_flight = None;
+            }
+        } else {
+            self.peers.remove(&peer);
+        }
+        Some(peer)
+    }
 }
 
 #[derive(Clone)]
 pub struct NodeHandle {
+    swarm: Arc<std::sync::RwLock<SwarmHandle>>,
+    store: Arc<dyn Store>,
+    events: EventBus,
+    role: String,
+    /// Addresses a restarted swarm listens on again
+    listen_addrs: Arc<std::sync::Mutex<Vec<Multiaddr>>>,
+    connected_peers: Arc<tokio::sync::RwLock<HashSet<PeerId>>>,
+    address_book: AddressBook,
+    /// Nodes asked for the addresses of peers this node cannot reach
+    rendezvous_points: Vec<Multiaddr>,
+    /// Usage of each peer against the configured limits; survives restarts
+    limiter: PeerLimiter,
+    restart_lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+/// The running swarm task and the identity it was started with.
+#[derive(Clone)]
+struct SwarmHandle {
     sender: mpsc::Sender<Command>,
-    peer_id: String,
+    peer_id: PeerId,
+    keypair: identity::Keypair,
     stream_control: Arc<tokio::sync::Mutex<StreamControl>>,
-    connected_peers: Arc<tokio::sync::RwLock<HashSet<PeerId>>>,
 }
 
 impl NodeHandle {
-    pub async fn spawn(store: Arc<dyn Store>) -> Result<Self> {
-        let keypair = load_or_generate_keypair(&store).await?;
-        let peer_id = PeerId::from(keypair.public());
-
-        let transport = tcp::tokio::Transport::new(tcp::Config::default().nodelay(true))
-            .upgrade(libp2p::core::upgrade::Version::V1)
-            .authenticate(libp2p::noise::Config::new(&keypair)?)
-            .multiplex(yamux::Config::default())
-            .boxed();
-
-        // Configure RequestResponse with longer timeouts to prevent connection closure
-        let rr_config = RequestResponseConfig::default()
-            .with_request_timeout(std::time::Duration::from_secs(30));
-        let protocols = std::iter::once((PortaProtocol("/porta/req/1"), ProtocolSupport::Full));
-        let request_response = RequestResponse::new(protocols, rr_config);
-
-        let stream = StreamBehaviour::new();
-        let mut stream_control = stream.new_control();
-        // Use shorter ping interval to keep connections alive
-        let ping_config = ping::Config::new().with_interval(std::time::Duration::from_secs(10));
-        let behaviour = PortaBehaviour {
-            request_response,
-            ping: ping::Behaviour::new(ping_config),
-            identify: identify::Behaviour::new(identify::Config::new(
-                "/porta/1.0".into(),
-                keypair.public(),
-            )),
-            stream,
-        };
+    pub async fn spawn(
+        store: Arc<dyn Store>,
+        events: EventBus,
+        config: &BackendConfig,
+    ) -> Result<Self> {
+        let keypair = load_or_generate_keypair(&store, config).await?;
+        let listen_addrs = config
+            .listen_addrs
+            .iter()
+            .map(|listen_addr| {
+                listen_addr
+                    .parse()
+                    .map_err(|err| anyhow!("无效的监听地址 {}: {}", listen_addr, err))
+            })
+            .collect::<Result<Vec<Multiaddr>>>()?;
+        let rendezvous_points = config
+            .rendezvous_points
+            .iter()
+            .map(|point| {
+                let addr: Multiaddr = point
+                    .parse()
+                    .map_err(|err| anyhow!("无效的 rendezvous 地址 {}: {}", point, err))?;
+                if peer_id_from_addr(&addr).is_none() {
+                    return Err(anyhow!("rendezvous 地址 {} 缺少 /p2p/peerId", point));
+                }
+                Ok(addr)
+            })
+            .collect::<Result<Vec<Multiaddr>>>()?;
+        let connected_peers = Arc::new(tokio::sync::RwLock::new(HashSet::new()));
+        let address_book = AddressBook::default();
+        let limiter = PeerLimiter::new(config.limits.clone());
+        let swarm = start_swarm(
+            &store,
+            &events,
+            &config.role,
+            keypair,
+            &listen_addrs,
+            connected_peers.clone(),
+            address_book.clone(),
+            limiter.clone(),
+        )
+        .await?;
+        Ok(Self {
+            swarm: Arc::new(std::sync::RwLock::new(swarm)),
+            store,
+            events,
+            role: config.role.clone(),
+            listen_addrs: Arc::new(std::sync::Mutex::new(listen_addrs)),
+            connected_peers,
+            address_book,
+            rendezvous_points,
+            limiter,
+            restart_lock: Arc::new(tokio::sync::Mutex::new(())),
+        })
+    }
 
-        // Use a longer idle timeout to prevent connections from closing too quickly
-        // Ping protocol will keep connections alive, but we need time for initial requests
-        let swarm_config = libp2p::swarm::Config::with_tokio_executor()
-            .with_idle_connection_timeout(std::time::Duration::from_secs(120));
-        let mut swarm = Swarm::new(transport, behaviour, peer_id, swarm_config);
-        // Try to use configured port from environment, or auto-assign
-        let tcp_port = std::env::var("PORTA_P2P_TCP_PORT")
-            .ok()
-            .and_then(|s| s.parse::<u16>().ok())
-            .unwrap_or(0);
-        let listen_addr = if tcp_port > 0 {
-            format!("/ip4/0.0.0.0/tcp/{}", tcp_port)
-        } else {
-            "/ip4/0.0.0.0/tcp/0".to_string()
-        };
-        tracing::info!("[P2P] Listening on: {}", listen_addr);
-        swarm.listen_on(listen_addr.parse()?)?;
+    fn current(&self) -> SwarmHandle {
+        self.swarm.read().expect("swarm lock poisoned").clone()
+    }
 
-        let (sender, mut receiver) = mpsc::channel(32);
-        let mut pending: HashMap<OutboundRequestId, oneshot::Sender<Result<P2pResponse>>> =
-            HashMap::new();
-        let mut pending_dials: HashMap<PeerId, Vec<oneshot::Sender<Result<()>>>> = HashMap::new();
-        let connected_peers = Arc::new(tokio::sync::RwLock::new(HashSet::new()));
-        let connected_peers_clone = connected_peers.clone();
+    fn sender(&self) -> mpsc::Sender<Command> {
+        self.current().sender
+    }
 
-        let store_clone = store.clone();
-        let mut incoming = match stream_control.accept(StreamProtocol::new(STREAM_PROTOCOL)) {
-            Ok(incoming) => incoming,
-            Err(_) => {
-                return Err(anyhow!("重复注册 stream 协议"));
+    /// Replace the running identity: stop the swarm and start a new one with
+    /// `keypair` on the same listen addresses. Every connection is dropped, so
+    /// callers re-dial the peers they need. If the new swarm fails to start
+    /// the old identity is brought back up.
+    pub async fn restart(&self, keypair: identity::Keypair) -> Result<()> {
+        let _guard = self.restart_lock.lock().await;
+        let old = self.current();
+        let (tx, rx) = oneshot::channel();
+        old.sender
+            .send(Command::Shutdown { respond_to: tx })
+            .await
+            .map_err(|_| anyhow!("p2p 通道已关闭"))?;
+        let _ = rx.await;
+        let dropped: Vec<PeerId> = self.connected_peers.write().await.drain().collect();
+        for peer in dropped {
+            if let Err(err) = self
+                .store
+                .set_peer_status(&peer.to_string(), "offline")
+                .await
+            {
+                tracing::warn!("[P2P] 更新 peer 状态失败: peer={}, error={}", peer, err);
             }
-        };
-        let store_for_streams = store.clone();
-        let stream_control_for_relay = stream_control.clone();
-        tokio::spawn(async move {
-            while let Some((peer, stream)) = incoming.next().await {
-                handle_incoming_stream(
-                    peer,
-                    stream,
-                    &store_for_streams,
-                    stream_control_for_relay.clone(),
+            self.events.publish(PortaEvent::PeerDisconnected {
+                peer_id: peer.to_string(),
+            });
+        }
+
+        let listen_addrs = self
+            .listen_addrs
+            .lock()
+            .expect("listen addrs lock poisoned")
+            .clone();
+        let started = match start_swarm(
+            &self.store,
+            &self.events,
+            &self.role,
+            keypair,
+            &listen_addrs,
+            self.connected_peers.clone(),
+            self.address_book.clone(),
+            self.limiter.clone(),
+        )
+        .await
+        {
+            Ok(started) => started,
+            Err(err) => {
+                tracing::error!("[P2P] 新身份启动失败，恢复原身份: {}", err);
+                let restored = start_swarm(
+                    &self.store,
+                    &self.events,
+                    &self.role,
+                    old.keypair,
+                    &listen_addrs,
+                    self.connected_peers.clone(),
+                    self.address_book.clone(),
+                    self.limiter.clone(),
                 )
-                .await;
-            }
-        });
-        tokio::spawn(async move {
-            loop {
-                tokio::select! {
-                    Some(cmd) = receiver.recv() => {
-                        match cmd {
-                            Command::Dial { addr, peer_id, respond_to } => {
-                                tracing::info!("[P2P] 开始拨号到 peer: {} (地址: {})", peer_id, addr);
-                                if let Err(err) = swarm.dial(addr.clone()) {
-                                    let err_msg = format!("无法连接到 {}: {:?}", addr, err);
-                                    tracing::error!("[P2P] 拨号失败: {}", err_msg);
-                                    let _ = respond_to.send(Err(anyhow!("连接失败: {}", err_msg)));
-                                } else {
-                                    tracing::info!("[P2P] 拨号请求已发送，等待连接建立: {}", peer_id);
-                                    // Store the responder to notify when connection is established
-                                    pending_dials.entry(peer_id).or_default().push(respond_to);
-                                }
-                            }
-                            Command::Request { peer, request, respond_to } => {
-                                tracing::info!("[P2P] 发送请求: peer={}, request={:?}", peer, request);
-                                let request_id = swarm.behaviour_mut().request_response.send_request(&peer, request);
-                                tracing::info!("[P2P] 请求已发送: peer={}, request_id={:?}", peer, request_id);
-                                pending.insert(request_id, respond_to);
-                            }
-                        }
-                    }
-                    event = swarm.select_next_some() => match event {
-                        SwarmEvent::Behaviour(PortaBehaviourEvent::RequestResponse(event)) => {
-                            handle_request_response_event(event, &mut swarm, &store_clone, &mut pending).await;
-                        }
-                        SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
-                            tracing::info!("[P2P] 连接已建立: peer={}, endpoint={:?}", peer_id, endpoint);
-                            // Track connected peer
-                            connected_peers_clone.write().await.insert(peer_id);
-                            // Don't notify dial waiters yet - wait for Identify protocol to complete
-                        }
-                        SwarmEvent::Behaviour(PortaBehaviourEvent::Identify(identify::Event::Received { peer_id, info, .. })) => {
-                            tracing::info!("[P2P] Identify 协议完成: peer={}, listen_addrs={:?}", peer_id, info.listen_addrs);
-                            // Now that Identify protocol is complete, connection is fully ready
-                            // Notify pending dials
-                            tracing::debug!("[P2P] 检查 pending_dials，当前 key: peer={}, pending_dials keys: {:?}",
-                                peer_id, pending_dials.keys().collect::<Vec<_>>());
-                            if let Some(responders) = pending_dials.remove(&peer_id) {
-                                tracing::info!("[P2P] 通知等待的 dial: peer={}, 等待者数量={}", peer_id, responders.len());
-                                for responder in responders {
-                                    let _ = responder.send(Ok(()));
-                                }
-                            } else {
-                                tracing::warn!("[P2P] Identify 完成但未找到 pending_dials 条目: peer={}, 当前 pending_dials keys: {:?}",
-                                    peer_id, pending_dials.keys().collect::<Vec<_>>());
-                            }
-                        }
-                        SwarmEvent::ConnectionClosed { peer_id, cause, .. } => {
-                            tracing::warn!("[P2P] 连接已关闭: peer={}, cause={:?}", peer_id, cause);
-                            // Remove from connected peers
-                            connected_peers_clone.write().await.remove(&peer_id);
-                            // Notify pending dials that connection failed
-                            if let Some(responders) = pending_dials.remove(&peer_id) {
-                                tracing::warn!("[P2P] 连接关闭，通知等待的 dial 失败: peer={}", peer_id);
-                                for responder in responders {
-                                    let _ = responder.send(Err(anyhow!("连接已关闭: {:?}", cause)));
-                                }
-                            }
-                        }
-                        SwarmEvent::NewListenAddr { address, .. } => {
-                            tracing::info!("[P2P] 新监听地址: {}", address);
-                        }
-                        SwarmEvent::ExpiredListenAddr { address, .. } => {
-                            tracing::debug!("[P2P] 监听地址过期: {}", address);
-                        }
-                        SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
-                            tracing::error!("[P2P] 出站连接错误: peer={:?}, error={:?}", peer_id, error);
-                            // Notify pending dials that connection failed
-                            if let Some(peer) = peer_id {
-                                if let Some(responders) = pending_dials.remove(&peer) {
-                                    tracing::warn!("[P2P] 出站连接错误，通知等待的 dial 失败: peer={}", peer);
-                                    for responder in responders {
-                                        let _ = responder.send(Err(anyhow!("出站连接错误: {:?}", error)));
-                                    }
-                                }
-                            } else {
-                                // For errors without peer_id, check if there are any pending dials
-                                // This shouldn't happen in normal flow, but log for debugging
-                                tracing::warn!("[P2P] 出站连接错误但没有 peer_id，当前 pending_dials keys: {:?}",
-                                    pending_dials.keys().collect::<Vec<_>>());
-                            }
-                        }
-                        SwarmEvent::IncomingConnectionError { error, .. } => {
-                            tracing::warn!("[P2P] 入站连接错误: error={:?}", error);
-                        }
-                        _ => {
-                            // Log other events at debug level for troubleshooting
-                            tracing::debug!("[P2P] SwarmEvent: {:?}", event);
-                        }
-                    }
-                }
+                .await?;
+                *self.swarm.write().expect("swarm lock poisoned") = restored;
+                return Err(err);
             }
-        });
-
-        Ok(Self {
-            sender,
-            peer_id: peer_id.to_string(),
-            stream_control: Arc::new(tokio::sync::Mutex::new(stream_control)),
-            connected_peers,
-        })
+        };
+        tracing::info!("[P2P] 身份已轮换: {} -> {}", old.peer_id, started.peer_id);
+        *self.swarm.write().expect("swarm lock poisoned") = started;
+        Ok(())
     }
 
     pub async fn dial(&self, addr: Multiaddr) -> Result<PeerId> {
         let peer_id =
             peer_id_from_addr(&addr).ok_or_else(|| anyhow!("multiaddr 缺少 /p2p/peerId"))?;
+        self.dial_any(peer_id, vec![addr]).await?;
+        Ok(peer_id)
+    }
 
+    /// Dial `peer_id` at all of `addrs` at once and keep the first connection
+    /// that comes up. Returns the address that connected, ending in
+    /// `/p2p/<peer_id>`, when the connection is one this node dialed.
+    pub async fn dial_any(
+        &self,
+        peer_id: PeerId,
+        addrs: Vec<Multiaddr>,
+    ) -> Result<Option<Multiaddr>> {
+        if addrs.is_empty() {
+            return Err(anyhow!("没有可拨号的地址: peer={}", peer_id));
+        }
         let (tx, rx) = oneshot::channel();
-        self.sender
+        self.sender()
             .send(Command::Dial {
-                addr,
+                addrs,
                 peer_id,
                 respond_to: tx,
             })
@@ -263,9 +338,9 @@ impl NodeHandle {
             timeout_duration
         );
         match tokio::time::timeout(timeout_duration, rx).await {
-            Ok(Ok(Ok(()))) => {
-                tracing::info!("[P2P] 连接建立成功: peer={}", peer_id);
-                Ok(peer_id)
+            Ok(Ok(Ok(via))) => {
+                tracing::info!("[P2P] 连接建立成功: peer={}, 地址={:?}", peer_id, via);
+                Ok(via)
             }
             Ok(Ok(Err(e))) => {
                 tracing::error!("[P2P] 连接建立失败: peer={}, error={}", peer_id, e);
@@ -298,7 +373,7 @@ impl NodeHandle {
 
     pub async fn request(&self, peer: PeerId, request: P2pRequest) -> Result<P2pResponse> {
         let (tx, rx) = oneshot::channel();
-        self.sender
+        self.sender()
             .send(Command::Request {
                 peer,
                 request,
@@ -310,7 +385,81 @@ impl NodeHandle {
     }
 
     pub fn peer_id(&self) -> String {
-        self.peer_id.clone()
+        self.current().peer_id.to_string()
+    }
+
+    /// The running identity.
+    pub fn keypair(&self) -> identity::Keypair {
+        self.current().keypair
+    }
+
+    /// Protobuf encoding of the running identity, as stored in key files.
+    pub fn keypair_bytes(&self) -> Result<Vec<u8>> {
+        Ok(self.current().keypair.to_protobuf_encoding()?)
+    }
+
+    /// Start listening on an additional address.
+    pub async fn listen_on(&self, addr: Multiaddr) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.sender()
+            .send(Command::Listen {
+                addr: addr.clone(),
+                respond_to: tx,
+            })
+            .await
+            .map_err(|_| anyhow!("p2p 通道已关闭"))?;
+        rx.await.map_err(|_| anyhow!("p2p 监听失败"))??;
+        self.listen_addrs
+            .lock()
+            .expect("listen addrs lock poisoned")
+            .push(addr);
+        Ok(())
+    }
+
+    /// Addresses the swarm is actually listening on.
+    pub async fn listeners(&self) -> Result<Vec<Multiaddr>> {
+        let (tx, rx) = oneshot::channel();
+        self.sender()
+            .send(Command::Listeners { respond_to: tx })
+            .await
+            .map_err(|_| anyhow!("p2p 通道已关闭"))?;
+        rx.await.map_err(|_| anyhow!("读取监听地址失败"))
+    }
+
+    /// Addresses this node has learned for `peer`, newest first.
+    pub fn known_addrs(&self, peer: &PeerId) -> Vec<Multiaddr> {
+        self.address_book.addrs(peer)
+    }
+
+    pub fn rendezvous_points(&self) -> &[Multiaddr] {
+        &self.rendezvous_points
+    }
+
+    /// Push pending registry changes to every watching edge.
+    pub async fn notify_registry_changed(&self) {
+        let _ = self.sender().send(Command::PushRegistry).await;
+    }
+
+    /// Replace the set of banned peers. Newly banned peers are disconnected
+    /// right away; peers no longer in the set may connect again.
+    pub async fn block_peers(&self, peers: HashSet<PeerId>) {
+        let _ = self.sender().send(Command::BlockPeers { peers }).await;
+    }
+
+    /// Apply new per-peer limits; usage counted so far is kept.
+    pub fn set_limits(&self, limits: PeerLimits) {
+        self.limiter.set_limits(limits);
+    }
+
+    /// Tell the edges subscribed to a registered service that it changed.
+    pub async fn notify_subscribers(&self, service_uuid: &str, change: ServiceChange) {
+        let _ = self
+            .sender()
+            .send(Command::NotifySubscribers {
+                service_uuid: service_uuid.to_string(),
+                change,
+            })
+            .await;
     }
 
     /// Check if a peer is currently connected
@@ -320,7 +469,8 @@ impl NodeHandle {
 
     pub async fn open_stream(&self, peer: PeerId, service_uuid: &str) -> Result<Stream> {
         let protocol = StreamProtocol::new(STREAM_PROTOCOL);
-        let mut control = self.stream_control.lock().await;
+        let stream_control = self.current().stream_control;
+        let mut control = stream_control.lock().await;
         let mut stream = control
             .open_stream(peer, protocol)
             .await
@@ -330,22 +480,367 @@ impl NodeHandle {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
+async fn start_swarm(
+    store: &Arc<dyn Store>,
+    events: &EventBus,
+    role: &str,
+    keypair: identity::Keypair,
+    listen_addrs: &[Multiaddr],
+    connected_peers: Arc<tokio::sync::RwLock<HashSet<PeerId>>>,
+    address_book: AddressBook,
+    limiter: PeerLimiter,
+) -> Result<SwarmHandle> {
+    let peer_id = PeerId::from(keypair.public());
+
+    let transport = tcp::tokio::Transport::new(tcp::Config::default().nodelay(true))
+        .upgrade(libp2p::core::upgrade::Version::V1)
+        .authenticate(libp2p::noise::Config::new(&keypair)?)
+        .multiplex(yamux::Config::default())
+        .boxed();
+
+    // Configure RequestResponse with longer timeouts to prevent connection closure
+    let rr_config =
+        RequestResponseConfig::default().with_request_timeout(std::time::Duration::from_secs(30));
+    let protocols = std::iter::once((PortaProtocol("/porta/req/1"), ProtocolSupport::Full));
+    let request_response = RequestResponse::new(protocols, rr_config);
+
+    let stream = StreamBehaviour::new();
+    let mut stream_control = stream.new_control();
+    // Use shorter ping interval to keep connections alive
+    let ping_config = ping::Config::new().with_interval(std::time::Duration::from_secs(10));
+    let behaviour = PortaBehaviour {
+        request_response,
+        ping: ping::Behaviour::new(ping_config),
+        identify: identify::Behaviour::new(identify::Config::new(
+            "/porta/1.0".into(),
+            keypair.public(),
+        )),
+        stream,
+        blocked: allow_block_list::Behaviour::default(),
+        ip_limits: IpLimits::new(limiter.clone()),
+    };
+
+    // Use a longer idle timeout to prevent connections from closing too quickly
+    // Ping protocol will keep connections alive, but we need time for initial requests
+    let swarm_config = libp2p::swarm::Config::with_tokio_executor()
+        .with_idle_connection_timeout(std::time::Duration::from_secs(120));
+    let mut swarm = Swarm::new(transport, behaviour, peer_id, swarm_config);
+    for addr in listen_addrs {
+        tracing::info!("[P2P] Listening on: {}", addr);
+        swarm.listen_on(addr.clone())?;
+    }
+    let role = role.to_string();
+    let events = events.clone();
+
+    let (sender, mut receiver) = mpsc::channel(32);
+    let enforcement = Enforcement {
+        limiter,
+        commands: sender.clone(),
+    };
+    let mut pending: HashMap<OutboundRequestId, oneshot::Sender<Result<P2pResponse>>> =
+        HashMap::new();
+    let mut pending_dials: HashMap<PeerId, Vec<oneshot::Sender<Result<Option<Multiaddr>>>>> =
+        HashMap::new();
+    // Address of the latest connection this node dialed to each peer
+    let mut dialed_via: HashMap<PeerId, Multiaddr> = HashMap::new();
+    let connected_peers_clone = connected_peers.clone();
+    let mut watchers = RegistryWatchers::default();
+    // Where inbound connections came from, to pair with the listen ports identify reports
+    let mut observed: HashMap<PeerId, Multiaddr> = HashMap::new();
+    let mut blocked: HashSet<PeerId> = HashSet::new();
+    match store.banned_peers().await {
+        Ok(peers) => block_peers(
+            &mut swarm,
+            &mut blocked,
+            peers.iter().filter_map(|peer| peer.parse().ok()).collect(),
+        ),
+        Err(err) => tracing::warn!("[P2P] 读取封禁列表失败: {}", err),
+    }
+
+    let store_clone = store.clone();
+    let mut incoming = match stream_control.accept(StreamProtocol::new(STREAM_PROTOCOL)) {
+        Ok(incoming) => incoming,
+        Err(_) => {
+            return Err(anyhow!("重复注册 stream 协议"));
+        }
+    };
+    let store_for_streams = store.clone();
+    let stream_control_for_relay = stream_control.clone();
+    let enforcement_for_streams = enforcement.clone();
+    let incoming_task = tokio::spawn(async move {
+        // Each stream is served on its own task so a long relay does not
+        // hold up the next peer
+        while let Some((peer, stream)) = incoming.next().await {
+            let store = store_for_streams.clone();
+            let stream_control = stream_control_for_relay.clone();
+            let enforcement = enforcement_for_streams.clone();
+            tokio::spawn(async move {
+                handle_incoming_stream(peer, stream, &store, stream_control, &enforcement).await;
+            });
+        }
+    });
+    tokio::spawn(async move {
+        let shutdown = loop {
+            tokio::select! {
+                Some(cmd) = receiver.recv() => {
+                    match cmd {
+                        Command::Dial { addrs, peer_id, respond_to } => {
+                            tracing::info!("[P2P] 开始拨号到 peer: {} (地址: {:?})", peer_id, addrs);
+                            let opts = DialOpts::peer_id(peer_id)
+                                .addresses(addrs.iter().map(without_peer).collect())
+                                .condition(PeerCondition::Always)
+                                .build();
+                            if let Err(err) = swarm.dial(opts) {
+                                let err_msg = format!("无法连接到 {:?}: {:?}", addrs, err);
+                                tracing::error!("[P2P] 拨号失败: {}", err_msg);
+                                let _ = respond_to.send(Err(anyhow!("连接失败: {}", err_msg)));
+                            } else {
+                                tracing::info!("[P2P] 拨号请求已发送，等待连接建立: {}", peer_id);
+                                // Store the responder to notify when connection is established
+                                pending_dials.entry(peer_id).or_default().push(respond_to);
+                            }
+                        }
+                        Command::Request { peer, request, respond_to } => {
+                            tracing::info!("[P2P] 发送请求: peer={}, request={:?}", peer, request);
+                            let request_id = swarm.behaviour_mut().request_response.send_request(&peer, request);
+                            tracing::info!("[P2P] 请求已发送: peer={}, request_id={:?}", peer, request_id);
+                            pending.insert(request_id, respond_to);
+                        }
+                        Command::Listen { addr, respond_to } => {
+                            tracing::info!("[P2P] Listening on: {}", addr);
+                            let result = swarm
+                                .listen_on(addr.clone())
+                                .map(|_| ())
+                                .map_err(|err| anyhow!("监听 {} 失败: {}", addr, err));
+                            let _ = respond_to.send(result);
+                        }
+                        Command::Listeners { respond_to } => {
+                            let _ = respond_to.send(swarm.listeners().cloned().collect());
+                        }
+                        Command::PushRegistry => {
+                            push_registry_to_all(&mut swarm, &store_clone, &mut watchers).await;
+                        }
+                        Command::BlockPeers { peers } => {
+                            block_peers(&mut swarm, &mut blocked, peers);
+                        }
+                        Command::NotifySubscribers { service_uuid, change } => {
+                            notify_subscribers(&mut swarm, &store_clone, &service_uuid, change).await;
+                        }
+                        Command::Shutdown { respond_to } => break respond_to,
+                    }
+                }
+                event = swarm.select_next_some() => match event {
+                    SwarmEvent::Behaviour(PortaBehaviourEvent::RequestResponse(event)) => {
+                        handle_request_response_event(event, &mut swarm, &store_clone, &events, &role, &address_book, &mut pending, &mut watchers, &enforcement).await;
+                    }
+                    SwarmEvent::ConnectionEstablished { peer_id, endpoint, num_established, .. } => {
+                        tracing::info!("[P2P] 连接已建立: peer={}, endpoint={:?}", peer_id, endpoint);
+                        // Track connected peer
+                        connected_peers_clone.write().await.insert(peer_id);
+                        match &endpoint {
+                            ConnectedPoint::Dialer { address, .. } => {
+                                address_book.record(peer_id, [address.clone()]);
+                                dialed_via.insert(peer_id, without_peer(address).with(Protocol::P2p(peer_id)));
+                            }
+                            ConnectedPoint::Listener { send_back_addr, .. } => {
+                                observed.insert(peer_id, send_back_addr.clone());
+                            }
+                        }
+                        if num_established.get() == 1 {
+                            events.publish(PortaEvent::PeerConnected { peer_id: peer_id.to_string() });
+                        }
+                        // Don't notify dial waiters yet - wait for Identify protocol to complete
+                    }
+                    SwarmEvent::Behaviour(PortaBehaviourEvent::Identify(identify::Event::Received { peer_id, info, .. })) => {
+                        tracing::info!("[P2P] Identify 协议完成: peer={}, listen_addrs={:?}", peer_id, info.listen_addrs);
+                        let hosts: Vec<String> = observed.get(&peer_id).and_then(observed_host).into_iter().collect();
+                        address_book.record(peer_id, dialable_addrs(&info.listen_addrs, &hosts));
+                        // Now that Identify protocol is complete, connection is fully ready
+                        // Notify pending dials
+                        tracing::debug!("[P2P] 检查 pending_dials，当前 key: peer={}, pending_dials keys: {:?}",
+                            peer_id, pending_dials.keys().collect::<Vec<_>>());
+                        if let Some(responders) = pending_dials.remove(&peer_id) {
+                            tracing::info!("[P2P] 通知等待的 dial: peer={}, 等待者数量={}", peer_id, responders.len());
+                            let via = dialed_via.get(&peer_id).cloned();
+                            for responder in responders {
+                                let _ = responder.send(Ok(via.clone()));
+                            }
+                        } else {
+                            tracing::warn!("[P2P] Identify 完成但未找到 pending_dials 条目: peer={}, 当前 pending_dials keys: {:?}",
+                                peer_id, pending_dials.keys().collect::<Vec<_>>());
+                        }
+                    }
+                    SwarmEvent::ConnectionClosed { peer_id, cause, num_established, .. } => {
+                        tracing::warn!("[P2P] 连接已关闭: peer={}, cause={:?}", peer_id, cause);
+                        // Remove from connected peers
+                        connected_peers_clone.write().await.remove(&peer_id);
+                        if num_established == 0 {
+                            watchers.forget(&peer_id);
+                            observed.remove(&peer_id);
+                            dialed_via.remove(&peer_id);
+                            if let Err(err) = store_clone.set_peer_status(&peer_id.to_string(), "offline").await {
+                                tracing::warn!("[P2P] 更新 peer 状态失败: peer={}, error={}", peer_id, err);
+                            }
+                            if role == "community" {
+                                set_provider_online(&mut swarm, &store_clone, &mut watchers, &peer_id, false).await;
+                            }
+                            events.publish(PortaEvent::PeerDisconnected { peer_id: peer_id.to_string() });
+                        }
+                        // Notify pending dials that connection failed
+                        if let Some(responders) = pending_dials.remove(&peer_id) {
+                            tracing::warn!("[P2P] 连接关闭，通知等待的 dial 失败: peer={}", peer_id);
+                            for responder in responders {
+                                let _ = responder.send(Err(anyhow!("连接已关闭: {:?}", cause)));
+                            }
+                        }
+                    }
+                    SwarmEvent::NewListenAddr { address, .. } => {
+                        tracing::info!("[P2P] 新监听地址: {}", address);
+                    }
+                    SwarmEvent::ExpiredListenAddr { address, .. } => {
+                        tracing::debug!("[P2P] 监听地址过期: {}", address);
+                    }
+                    SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
+                        tracing::error!("[P2P] 出站连接错误: peer={:?}, error={:?}", peer_id, error);
+                        // Notify pending dials that connection failed
+                        if let Some(peer) = peer_id {
+                            if let Some(responders) = pending_dials.remove(&peer) {
+                                tracing::warn!("[P2P] 出站连接错误，通知等待的 dial 失败: peer={}", peer);
+                                for responder in responders {
+                                    let _ = responder.send(Err(anyhow!("出站连接错误: {:?}", error)));
+                                }
+                            }
+                        } else {
+                            // For errors without peer_id, check if there are any pending dials
+                            // This shouldn't happen in normal flow, but log for debugging
+                            tracing::warn!("[P2P] 出站连接错误但没有 peer_id，当前 pending_dials keys: {:?}",
+                                pending_dials.keys().collect::<Vec<_>>());
+                        }
+                    }
+                    SwarmEvent::IncomingConnectionError { error, .. } => {
+                        tracing::warn!("[P2P] 入站连接错误: error={:?}", error);
+                    }
+                    _ => {
+                        // Log other events at debug level for troubleshooting
+                        tracing::debug!("[P2P] SwarmEvent: {:?}", event);
+                    }
+                }
+            }
+        };
+        // Dropping the swarm closes its listeners and connections
+        incoming_task.abort();
+        drop(swarm);
+        tracing::info!("[P2P] Swarm 已停止: peer_id={}", peer_id);
+        let _ = shutdown.send(());
+    });
+    Ok(SwarmHandle {
+        sender,
+        peer_id,
+        keypair,
+        stream_control: Arc::new(tokio::sync::Mutex::new(stream_control)),
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn handle_request_response_event(
     event: RequestResponseEvent<P2pRequest, P2pResponse>,
     swarm: &mut Swarm<PortaBehaviour>,
     store: &Arc<dyn Store>,
+    events: &EventBus,
+    role: &str,
+    address_book: &AddressBook,
     pending: &mut HashMap<OutboundRequestId, oneshot::Sender<Result<P2pResponse>>>,
+    watchers: &mut RegistryWatchers,
+    enforcement: &Enforcement,
 ) {
     match event {
         RequestResponseEvent::Message { peer, message } => match message {
             RequestResponseMessage::Request {
                 request, channel, ..
             } => {
-                let response = handle_inbound_request(store, &peer, request).await;
+                let watch_since = match &request {
+                    P2pRequest::WatchRegistry { since_revision, .. } => Some(*since_revision),
+                    _ => None,
+                };
+                let changes_registry = matches!(
+                    request,
+                    P2pRequest::PublishService { .. }
+                        | P2pRequest::UnpublishService { .. }
+                        | P2pRequest::HandOverIdentity { .. }
+                );
+                // Services handed over from a dropped peer ID now have a live provider
+                let provider_online = matches!(request, P2pRequest::HandOverIdentity { .. });
+                let subscriber_change = match &request {
+                    P2pRequest::PublishService { service } => {
+                        // New registrations only matter to subscribers left over from an earlier unpublish
+                        let changed = match store.resolve_service_registry(&service.uuid).await {
+                            Ok(Some(previous)) => {
+                                !previous.online
+                                    || previous.port != service.port
+                                    || previous.provider_addr != service.provider_addr
+                            }
+                            Ok(None) => true,
+                            Err(_) => false,
+                        };
+                        changed.then(|| {
+                            (
+                                service.uuid.clone(),
+                                ServiceChange::Updated {
+                                    provider_addr: service.provider_addr.clone(),
+                                    port: service.port,
+                                },
+                            )
+                        })
+                    }
+                    P2pRequest::UnpublishService { service_uuid } => {
+                        Some((service_uuid.clone(), ServiceChange::Removed))
+                    }
+                    _ => None,
+                };
+                let local_peer = *swarm.local_peer_id();
+                let response = handle_inbound_request(
+                    store,
+                    events,
+                    role,
+                    address_book,
+                    enforcement,
+                    &local_peer,
+                    &peer,
+                    request,
+                )
+                .await;
+                let accepted = matches!(response, P2pResponse::Ack);
+                // A provider that says Hello again is back online
+                let greeted = matches!(response, P2pResponse::HelloAck { .. });
                 let _ = swarm
                     .behaviour_mut()
                     .request_response
                     .send_response(channel, response);
+                if greeted && role == "community" {
+                    set_provider_online(swarm, store, watchers, &peer, true).await;
+                }
+                if !accepted {
+                    return;
+                }
+                if let Some(since_revision) = watch_since {
+                    tracing::info!(
+                        "[P2P] peer {} 订阅注册表变更, 起始修订 {}",
+                        peer,
+                        since_revision
+                    );
+                    watchers.watch(peer, since_revision);
+                    push_registry_changes(swarm, store, watchers, peer).await;
+                }
+                if changes_registry {
+                    push_registry_to_all(swarm, store, watchers).await;
+                }
+                if provider_online {
+                    set_provider_online(swarm, store, watchers, &peer, true).await;
+                }
+                if let Some((service_uuid, change)) = subscriber_change {
+                    notify_subscribers(swarm, store, &service_uuid, change).await;
+                }
             }
             RequestResponseMessage::Response {
                 request_id,
@@ -353,12 +848,26 @@ async fn handle_request_response_event(
             } => {
                 if let Some(ch) = pending.remove(&request_id) {
                     let _ = ch.send(Ok(response));
+                    return;
+                }
+                let accepted = matches!(response, P2pResponse::Ack);
+                if let P2pResponse::Error { message } = &response {
+                    tracing::warn!("[P2P] 注册表推送被拒绝: {}", message);
+                }
+                if let Some(peer) = watchers.complete(request_id, accepted) {
+                    if accepted {
+                        // Continue with whatever changed while the push was in flight
+                        push_registry_changes(swarm, store, watchers, peer).await;
+                    }
                 }
             }
         },
         RequestResponseEvent::OutboundFailure {
             request_id, error, ..
         } => {
+            if let Some(peer) = watchers.complete(request_id, false) {
+                tracing::warn!("[P2P] 注册表推送失败: peer={}, error={:?}", peer, error);
+            }
             if let Some(ch) = pending.remove(&request_id) {
                 let error_msg: String = match &error {
                     libp2p::request_response::OutboundFailure::DialFailure => {
@@ -399,6 +908,7 @@ async fn handle_incoming_stream(
     mut stream: Stream,
     store: &Arc<dyn Store>,
     mut stream_control: StreamControl,
+    enforcement: &Enforcement,
 ) {
     if store
         .peer_is_banned(&peer.to_string())
@@ -413,6 +923,13 @@ async fn handle_incoming_stream(
         tracing::warn!("拒绝非 edge 角色 peer {} 的 stream", peer);
         return;
     }
+    let _permit = match enforcement.limiter.open_stream(&peer) {
+        Ok(permit) => permit,
+        Err(violation) => {
+            enforcement.report(store, &peer, violation).await;
+            return;
+        }
+    };
     let protocol = match read_service_uuid(&mut stream).await {
         Ok(uuid) => uuid,
         Err(err) => {
@@ -456,9 +973,12 @@ async fn handle_incoming_stream(
             .await
         {
             Ok(outbound) => {
-                let mut inbound = stream.compat();
-                let mut outbound = outbound.compat();
-                let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+                let limiter = enforcement.limiter.clone();
+                let mut inbound = Metered::new(stream.compat(), limiter.clone(), peer);
+                let mut outbound = Metered::new(outbound.compat(), limiter, peer);
+                if let Err(err) = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await {
+                    relay_failed(store, enforcement, &peer, err).await;
+                }
                 tracing::debug!("中继转发完成");
             }
             Err(err) => {
@@ -479,7 +999,7 @@ async fn handle_incoming_stream(
         tracing::info!("转发 stream 到本地服务: {} -> {}", protocol, target);
         match tokio::net::TcpStream::connect(&target).await {
             Ok(mut socket) => {
-                let mut stream = stream.compat();
+                let mut stream = Metered::new(stream.compat(), enforcement.limiter.clone(), peer);
                 match tokio::io::copy_bidirectional(&mut socket, &mut stream).await {
                     Ok((sent, received)) => {
                         tracing::debug!(
@@ -491,6 +1011,7 @@ async fn handle_incoming_stream(
                     }
                     Err(err) => {
                         tracing::error!("服务 {} 转发失败: {}", protocol, err);
+                        relay_failed(store, enforcement, &peer, err).await;
                     }
                 }
             }
@@ -501,39 +1022,350 @@ async fn handle_incoming_stream(
     }
 }
 
-async fn handle_inbound_request(
+/// Send the next batch of registry changes to a watching edge, unless a
+/// push is already outstanding or nothing changed.
+async fn push_registry_changes(
+    swarm: &mut Swarm<PortaBehaviour>,
     store: &Arc<dyn Store>,
-    peer: &PeerId,
-    request: P2pRequest,
-) -> P2pResponse {
+    watchers: &mut RegistryWatchers,
+    peer: PeerId,
+) {
+    let Some(watcher) = watchers.peers.get_mut(&peer) else {
+        return;
+    };
+    if watcher.in_flight.is_some() || !swarm.is_connected(&peer) {
+        return;
+    }
+    let changes = match store
+        .registry_changes_since(watcher.revision, MAX_SYNC_BATCH)
+        .await
+    {
+        Ok(changes) => changes,
+        Err(err) => {
+            tracing::error!("[P2P] 读取注册表变更失败: {}", err);
+            return;
+        }
+    };
+    if !changes.reset && changes.upserted.is_empty() && changes.removed.is_empty() {
+        return;
+    }
     tracing::debug!(
-        "收到 peer {} 的请求: {:?}",
+        "[P2P] 推送注册表变更: peer={}, 修订 {} -> {}",
         peer,
-        std::mem::discriminant(&request)
+        watcher.revision,
+        changes.revision
     );
-    if let P2pRequest::Hello { hello } = request {
-        if hello.node_id.trim().is_empty() {
-            return P2pResponse::Error {
-                message: "node_id 不能为空".into(),
-            };
-        }
-        if hello.role.trim().is_empty() {
-            return P2pResponse::Error {
-                message: "role 不能为空".into(),
-            };
+    let request = P2pRequest::RegistryChanged {
+        since_revision: watcher.revision,
+        revision: changes.revision,
+        upserted: changes.upserted.into_iter().map(Into::into).collect(),
+        removed: changes.removed,
+        more: changes.more,
+        reset: changes.reset,
+    };
+    watcher.revision = changes.revision;
+    watcher.in_flight = Some(
+        swarm
+            .behaviour_mut()
+            .request_response
+            .send_request(&peer, request),
+    );
+}
+
+async fn push_registry_to_all(
+    swarm: &mut Swarm<PortaBehaviour>,
+    store: &Arc<dyn Store>,
+    watchers: &mut RegistryWatchers,
+) {
+    let peers: Vec<PeerId> = watchers.peers.keys().copied().collect();
+    for peer in peers {
+        push_registry_changes(swarm, store, watchers, peer).await;
+    }
+}
+
+/// Mark a provider's registry entries online or offline as its connection
+/// comes or goes, then tell watching edges and the services' subscribers.
+async fn set_provider_online(
+    swarm: &mut Swarm<PortaBehaviour>,
+    store: &Arc<dyn Store>,
+    watchers: &mut RegistryWatchers,
+    peer: &PeerId,
+    online: bool,
+) {
+    let changed = match store.set_provider_online(&peer.to_string(), online).await {
+        Ok(changed) => changed,
+        Err(err) => {
+            tracing::warn!("[P2P] 更新 peer {} 的服务在线状态失败: {}", peer, err);
+            return;
+        }
+    };
+    if changed.is_empty() {
+        return;
+    }
+    tracing::info!(
+        "[P2P] peer {} 的 {} 个服务已{}",
+        peer,
+        changed.len(),
+        if online { "上线" } else { "离线" }
+    );
+    push_registry_to_all(swarm, store, watchers).await;
+    for service_uuid in changed {
+        let change = if online {
+            match store.resolve_service_registry(&service_uuid).await {
+                Ok(Some(service)) => ServiceChange::Updated {
+                    provider_addr: service.provider_addr,
+                    port: service.port,
+                },
+                _ => continue,
+            }
+        } else {
+            ServiceChange::Offline
+        };
+        notify_subscribers(swarm, store, &service_uuid, change).await;
+    }
+}
+
+async fn notify_subscribers(
+    swarm: &mut Swarm<PortaBehaviour>,
+    store: &Arc<dyn Store>,
+    service_uuid: &str,
+    change: ServiceChange,
+) {
+    let subscribers = match store.service_subscribers(service_uuid).await {
+        Ok(subscribers) => subscribers,
+        Err(err) => {
+            tracing::error!("[P2P] 读取服务订阅者失败: {}", err);
+            return;
+        }
+    };
+    for subscriber in subscribers {
+        let Ok(peer) = subscriber.parse::<PeerId>() else {
+            tracing::warn!("[P2P] 无效的订阅者 peerId: {}", subscriber);
+            continue;
+        };
+        tracing::info!(
+            "[P2P] 通知订阅者服务变更: peer={}, service={}, change={:?}",
+            peer,
+            service_uuid,
+            change
+        );
+        // Fire and forget: the response is not tracked and unreachable subscribers are skipped
+        swarm.behaviour_mut().request_response.send_request(
+            &peer,
+            P2pRequest::ServiceStatusChanged {
+                service_uuid: service_uuid.to_string(),
+                change: change.clone(),
+            },
+        );
+    }
+}
+
+/// Apply a service change told by a community to the subscriptions made
+/// through that community; other communities' subscriptions are left alone.
+async fn apply_service_change(
+    store: &Arc<dyn Store>,
+    events: &EventBus,
+    peer: &PeerId,
+    service_uuid: &str,
+    change: ServiceChange,
+) -> Result<()> {
+    let peer = peer.to_string();
+    // Subscriptions record the community by ID or by display name
+    let communities: Vec<String> = store
+        .communities()
+        .await?
+        .into_iter()
+        .filter(|community| community.peer_id.as_deref() == Some(peer.as_str()))
+        .flat_map(|community| [community.id, community.name])
+        .collect();
+    if communities.is_empty() {
+        return Err(anyhow!("未找到对应社区"));
+    }
+    let updated = match (&change, change.subscription_status()) {
+        (_, Some(status)) => {
+            store
+                .update_subscriptions_by_service(service_uuid, &communities, status)
+                .await?
+        }
+        (
+            ServiceChange::Updated {
+                provider_addr,
+                port,
+            },
+            None,
+        ) => {
+            let remote_addr = crate::app::compose_remote_addr(provider_addr, *port);
+            store
+                .update_subscription_endpoints_by_service(service_uuid, &communities, &remote_addr)
+                .await?
+        }
+        _ => 0,
+    };
+    tracing::info!(
+        "订阅服务 {} 变更: {:?}, 更新 {} 条订阅",
+        service_uuid,
+        change,
+        updated
+    );
+    if updated > 0 {
+        events.publish(PortaEvent::SubscriptionChanged {
+            service_uuid: service_uuid.to_string(),
+            change,
+        });
+    }
+    Ok(())
+}
+
+/// Apply registry changes pushed by a community to the cache of every
+/// community entry pointing at that peer.
+async fn apply_registry_push(
+    store: &Arc<dyn Store>,
+    events: &EventBus,
+    peer: &PeerId,
+    since_revision: u64,
+    changes: RegistryChanges,
+) -> Result<()> {
+    let peer = peer.to_string();
+    let communities: Vec<String> = store
+        .communities()
+        .await?
+        .into_iter()
+        .filter(|community| community.peer_id.as_deref() == Some(peer.as_str()))
+        .map(|community| community.id)
+        .collect();
+    if communities.is_empty() {
+        return Err(anyhow!("未找到对应社区"));
+    }
+    for community_id in communities {
+        let current = store.community_sync_revision(&community_id).await?;
+        if !changes.reset && current != since_revision {
+            return Err(anyhow!(
+                "修订不连续: 本地 {}, 推送起点 {}",
+                current,
+                since_revision
+            ));
+        }
+        if changes.reset {
+            store.clear_discovered_services(&community_id).await?;
+        }
+        store
+            .upsert_discovered_services(&community_id, changes.upserted.clone())
+            .await?;
+        store
+            .remove_discovered_services(&community_id, &changes.removed)
+            .await?;
+        store
+            .set_community_sync_revision(&community_id, changes.revision)
+            .await?;
+        tracing::info!(
+            "社区 {} 推送服务变更: 更新 {}, 移除 {}",
+            community_id,
+            changes.upserted.len(),
+            changes.removed.len()
+        );
+        events.publish(PortaEvent::ServicesChanged {
+            community_id,
+            upserted: changes
+                .upserted
+                .iter()
+                .map(|item| item.uuid.clone())
+                .collect(),
+            removed: changes.removed.clone(),
+        });
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_inbound_request(
+    store: &Arc<dyn Store>,
+    events: &EventBus,
+    role: &str,
+    address_book: &AddressBook,
+    enforcement: &Enforcement,
+    local_peer: &PeerId,
+    peer: &PeerId,
+    request: P2pRequest,
+) -> P2pResponse {
+    tracing::debug!(
+        "收到 peer {} 的请求: {:?}",
+        peer,
+        std::mem::discriminant(&request)
+    );
+    if let Err(violation) = enforcement.limiter.check_request(peer) {
+        enforcement.report(store, peer, violation).await;
+        return P2pResponse::Error {
+            message: violation.to_string(),
+        };
+    }
+    if let P2pRequest::Hello { hello } = request {
+        if hello.node_id.trim().is_empty() {
+            return P2pResponse::Error {
+                message: "node_id 不能为空".into(),
+            };
+        }
+        if hello.role.trim().is_empty() {
+            return P2pResponse::Error {
+                message: "role 不能为空".into(),
+            };
+        }
+        if let Err(message) = check_ban(store, peer, &hello).await {
+            tracing::info!("拒绝已封禁 peer {} 的握手: {}", peer, message);
+            return P2pResponse::Error { message };
+        }
+        let joining = role == "community" && hello.role == "edge";
+        // Only an edge joining a community has use for an invite
+        let invite = match hello.invite.as_deref().filter(|_| joining) {
+            Some(token) => {
+                match crate::invite::verify(store.as_ref(), local_peer, peer, token).await {
+                    Ok(invite) => Some(invite),
+                    Err(err) => {
+                        return P2pResponse::Error {
+                            message: format!("邀请无效: {}", err),
+                        }
+                    }
+                }
+            }
+            None => None,
+        };
+        // A closed community admits every role, not just edges: an unknown
+        // peer that claims another role would otherwise walk straight in
+        if role == "community" {
+            if let Err(message) = admit(store, peer, &hello, invite.is_some()).await {
+                tracing::info!("peer {} 未获准加入: {}", peer, message);
+                return P2pResponse::Error { message };
+            }
         }
         if let Err(err) = store
-            .upsert_peer(&peer.to_string(), &hello.node_id, &hello.role, "online")
+            .upsert_peer(
+                &peer.to_string(),
+                &hello.node_id,
+                &hello.uuid,
+                &hello.name,
+                &hello.role,
+                "online",
+            )
             .await
         {
             return P2pResponse::Error {
                 message: format!("记录 peer 失败: {}", err),
             };
         }
+        if let Some(invite) = invite {
+            if let Err(err) = crate::invite::redeem(store.as_ref(), peer, &invite).await {
+                return P2pResponse::Error {
+                    message: format!("邀请无效: {}", err),
+                };
+            }
+            tracing::info!("peer {} 使用邀请 {} 加入", peer, invite.id);
+        }
         let local = match store.node_info().await {
             Ok(info) => super::protocol::NodeHello {
                 node_id: info.node_id,
-                role: std::env::var("PORTA_ROLE").unwrap_or_else(|_| "edge".into()),
+                uuid: info.uuid,
+                name: info.name,
+                role: role.to_string(),
+                invite: None,
             },
             Err(err) => {
                 return P2pResponse::Error {
@@ -541,7 +1373,21 @@ async fn handle_inbound_request(
                 }
             }
         };
-        return P2pResponse::HelloAck { hello: local };
+        let members = if role == "community" {
+            match store.community_nodes().await {
+                Ok(nodes) => Some(nodes.iter().filter(|node| !node.banned).count() as u32),
+                Err(err) => {
+                    tracing::warn!("统计社区成员失败: {}", err);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        return P2pResponse::HelloAck {
+            hello: local,
+            members,
+        };
     }
 
     if let Ok(true) = store.peer_is_banned(&peer.to_string()).await {
@@ -568,28 +1414,60 @@ async fn handle_inbound_request(
             message: "peer 未握手".into(),
         };
     }
+    // Only members may browse a closed community's catalog, route through it
+    // or look peers up in it. Other peers got here by passing admission at
+    // Hello, which is all a community registering its addresses needs.
+    if peer_role != "edge"
+        && matches!(
+            request,
+            P2pRequest::DiscoverServices { .. }
+                | P2pRequest::SyncServices { .. }
+                | P2pRequest::BuildRelayRoute { .. }
+                | P2pRequest::ResolvePeer { .. }
+        )
+        && !matches!(store.admission_mode().await, Ok(AdmissionMode::Open))
+    {
+        return P2pResponse::Error {
+            message: "社区仅对成员开放".into(),
+        };
+    }
 
     match request {
-        P2pRequest::DiscoverServices { .. } => match store.list_service_registry().await {
-            Ok(list) => {
-                let services = list
-                    .into_iter()
-                    .map(|item| ServiceAnnouncement {
-                        uuid: item.uuid,
-                        name: item.name,
-                        r#type: item.r#type,
-                        port: item.port,
-                        description: item.description,
-                        provider_peer: item.provider_peer,
-                        provider_addr: item.provider_addr,
-                    })
-                    .collect();
-                P2pResponse::ServiceList { services }
-            }
-            Err(err) => P2pResponse::Error {
-                message: format!("读取服务失败: {}", err),
-            },
-        },
+        P2pRequest::DiscoverServices { mut query, .. } => {
+            query.limit = Some(
+                query
+                    .limit
+                    .unwrap_or(MAX_SERVICE_PAGE_SIZE)
+                    .min(MAX_SERVICE_PAGE_SIZE),
+            );
+            match store.search_service_registry(&query).await {
+                Ok(page) => P2pResponse::ServiceList {
+                    services: page.items.into_iter().map(Into::into).collect(),
+                    total: Some(page.total),
+                    next_cursor: page.next_cursor,
+                },
+                Err(err) => P2pResponse::Error {
+                    message: format!("读取服务失败: {}", err),
+                },
+            }
+        }
+        P2pRequest::SyncServices { since_revision, .. } => {
+            match store
+                .registry_changes_since(since_revision, MAX_SYNC_BATCH)
+                .await
+            {
+                Ok(changes) => P2pResponse::ServiceChanges {
+                    revision: changes.revision,
+                    upserted: changes.upserted.into_iter().map(Into::into).collect(),
+                    removed: changes.removed,
+                    more: changes.more,
+                    reset: changes.reset,
+                },
+                Err(err) => P2pResponse::Error {
+                    message: format!("读取服务变更失败: {}", err),
+                },
+            }
+        }
         P2pRequest::SubscribeService {
             service_uuid,
             subscriber_peer,
@@ -653,6 +1531,27 @@ async fn handle_inbound_request(
                     message: "服务提供者 peer 不匹配".into(),
                 };
             }
+            // Updating an entry the peer already provides is always allowed
+            let known = matches!(
+                store.resolve_service_registry(&service.uuid).await,
+                Ok(Some(existing)) if existing.provider_peer == service.provider_peer
+            );
+            if !known {
+                let registered = match store.provider_service_count(&service.provider_peer).await {
+                    Ok(count) => count,
+                    Err(err) => {
+                        return P2pResponse::Error {
+                            message: format!("服务注册失败: {}", err),
+                        };
+                    }
+                };
+                if let Err(violation) = enforcement.limiter.check_services(registered) {
+                    enforcement.report(store, peer, violation).await;
+                    return P2pResponse::Error {
+                        message: violation.to_string(),
+                    };
+                }
+            }
             let registry = ServiceRegistryItem {
                 uuid: service.uuid,
                 name: service.name,
@@ -662,6 +1561,7 @@ async fn handle_inbound_request(
                 provider_peer: service.provider_peer,
                 provider_addr: service.provider_addr,
                 online: true,
+                tags: normalize_tags(service.tags),
             };
             if let Err(err) = store.upsert_service_registry(registry).await {
                 return P2pResponse::Error {
@@ -686,6 +1586,57 @@ async fn handle_inbound_request(
                 },
             }
         }
+        P2pRequest::WatchRegistry { .. } => {
+            if peer_role != "edge" {
+                return P2pResponse::Error {
+                    message: "订阅角色不允许".into(),
+                };
+            }
+            P2pResponse::Ack
+        }
+        P2pRequest::RegistryChanged {
+            since_revision,
+            revision,
+            upserted,
+            removed,
+            more,
+            reset,
+        } => {
+            if peer_role != "community" {
+                return P2pResponse::Error {
+                    message: "推送角色不允许".into(),
+                };
+            }
+            let changes = RegistryChanges {
+                revision,
+                upserted: upserted.into_iter().map(Into::into).collect(),
+                removed,
+                more,
+                reset,
+            };
+            match apply_registry_push(store, events, peer, since_revision, changes).await {
+                Ok(()) => P2pResponse::Ack,
+                Err(err) => P2pResponse::Error {
+                    message: format!("应用服务变更失败: {}", err),
+                },
+            }
+        }
+        P2pRequest::ServiceStatusChanged {
+            service_uuid,
+            change,
+        } => {
+            if peer_role != "community" {
+                return P2pResponse::Error {
+                    message: "通知角色不允许".into(),
+                };
+            }
+            match apply_service_change(store, events, peer, &service_uuid, change).await {
+                Ok(()) => P2pResponse::Ack,
+                Err(err) => P2pResponse::Error {
+                    message: format!("更新订阅状态失败: {}", err),
+                },
+            }
+        }
         P2pRequest::BuildRelayRoute {
             service_uuid,
             relay_chain,
@@ -711,12 +1662,277 @@ async fn handle_inbound_request(
                 }
             }
         }
+        P2pRequest::HandOverIdentity { handover } => {
+            let old_peer = match handover.verify(peer) {
+                Ok(old_peer) => old_peer.to_string(),
+                Err(err) => {
+                    return P2pResponse::Error {
+                        message: format!("身份交接无效: {}", err),
+                    };
+                }
+            };
+            // A banned node must not shed its ban by rotating keys
+            match store.peer_is_banned(&old_peer).await {
+                Ok(false) => {}
+                Ok(true) => {
+                    return P2pResponse::Error {
+                        message: "旧 peer 已被封禁".into(),
+                    };
+                }
+                Err(err) => {
+                    return P2pResponse::Error {
+                        message: format!("读取 peer 失败: {}", err),
+                    };
+                }
+            }
+            match store.hand_over_peer(&old_peer, &peer.to_string()).await {
+                Ok(moved) => {
+                    tracing::info!(
+                        "peer {} 身份交接到 {}: 迁移 {} 个服务",
+                        old_peer,
+                        peer,
+                        moved
+                    );
+                    P2pResponse::Ack
+                }
+                Err(err) => P2pResponse::Error {
+                    message: format!("身份交接失败: {}", err),
+                },
+            }
+        }
+        P2pRequest::RegisterAddrs { addrs } => {
+            let addrs: Vec<Multiaddr> = addrs
+                .iter()
+                .filter_map(|addr| addr.parse::<Multiaddr>().ok())
+                .filter(|addr| !matches!(peer_id_from_addr(addr), Some(owner) if owner != *peer))
+                .collect();
+            tracing::info!("peer {} 登记地址: {:?}", peer, addrs);
+            address_book.record(*peer, addrs);
+            P2pResponse::Ack
+        }
+        P2pRequest::ResolvePeer { peer_id } => match peer_id.parse::<PeerId>() {
+            Ok(target) => P2pResponse::PeerAddrs {
+                addrs: address_book
+                    .addrs(&target)
+                    .iter()
+                    .map(|addr| addr.to_string())
+                    .collect(),
+            },
+            Err(_) => P2pResponse::Error {
+                message: "peer ID 无效".into(),
+            },
+        },
         _ => P2pResponse::Error {
             message: "未知请求".into(),
         },
     }
 }
 
+/// Limits enforced on inbound requests and streams, with a way back to the
+/// swarm to cut off peers that get banned for breaking them.
+#[derive(Clone)]
+struct Enforcement {
+    limiter: PeerLimiter,
+    commands: mpsc::Sender<Command>,
+}
+
+impl Enforcement {
+    /// Log a broken limit and, once a peer keeps breaking limits, ban it:
+    /// its services are dropped and its connections closed. Commands are
+    /// queued without waiting because this also runs on the swarm task.
+    async fn report(&self, store: &Arc<dyn Store>, peer: &PeerId, violation: LimitViolation) {
+        tracing::warn!("[限流] peer {}: {}", peer, violation);
+        if !self.limiter.violation(peer) {
+            return;
+        }
+        let peer_id = peer.to_string();
+        let reason = format!("自动封禁: {}", violation);
+        let until = self
+            .limiter
+            .limits()
+            .auto_ban_secs
+            .map(|secs| chrono::Utc::now().timestamp() + secs as i64);
+        match store
+            .set_node_ban(&peer_id, true, Some(&reason), until)
+            .await
+        {
+            Ok(true) => {}
+            Ok(false) => {
+                tracing::warn!("[限流] peer {} 未握手，无法封禁", peer);
+                return;
+            }
+            Err(err) => {
+                tracing::error!("[限流] 封禁 peer {} 失败: {}", peer, err);
+                return;
+            }
+        }
+        tracing::warn!(
+            "[限流] peer {} 多次超出限制，已封禁 (until={:?})",
+            peer,
+            until
+        );
+        match store.remove_provider_services(&peer_id).await {
+            Ok(removed) => {
+                for service_uuid in &removed {
+                    let _ = self.commands.try_send(Command::NotifySubscribers {
+                        service_uuid: service_uuid.clone(),
+                        change: ServiceChange::Removed,
+                    });
+                }
+                if !removed.is_empty() {
+                    let _ = self.commands.try_send(Command::PushRegistry);
+                }
+            }
+            Err(err) => tracing::warn!("[限流] 移除 peer {} 的服务失败: {}", peer, err),
+        }
+        match store.banned_peers().await {
+            Ok(peers) => {
+                let peers = peers.iter().filter_map(|peer| peer.parse().ok()).collect();
+                if self
+                    .commands
+                    .try_send(Command::BlockPeers { peers })
+                    .is_err()
+                {
+                    tracing::warn!("[限流] 断开 peer {} 失败，等待下次封禁同步", peer);
+                }
+            }
+            Err(err) => tracing::warn!("[P2P] 读取封禁列表失败: {}", err),
+        }
+    }
+}
+
+/// A forwarded stream that stopped because the peer's byte budget ran out
+/// counts as a violation; other I/O errors are just the stream ending.
+async fn relay_failed(
+    store: &Arc<dyn Store>,
+    enforcement: &Enforcement,
+    peer: &PeerId,
+    err: std::io::Error,
+) {
+    let violation = err
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<LimitViolation>())
+        .copied();
+    if let Some(violation) = violation {
+        enforcement.report(store, peer, violation).await;
+    }
+}
+
+/// Bring the swarm's block list in line with `peers`.
+fn block_peers(
+    swarm: &mut Swarm<PortaBehaviour>,
+    blocked: &mut HashSet<PeerId>,
+    peers: HashSet<PeerId>,
+) {
+    for peer in blocked.difference(&peers) {
+        tracing::info!("[P2P] 解除封禁: peer={}", peer);
+        swarm.behaviour_mut().blocked.unblock_peer(*peer);
+    }
+    for peer in peers.difference(blocked) {
+        tracing::info!("[P2P] 封禁并断开: peer={}", peer);
+        swarm.behaviour_mut().blocked.block_peer(*peer);
+    }
+    *blocked = peers;
+}
+
+/// Refuse a Hello from a banned peer. A node banned by UUID stays banned
+/// when it comes back with a new peer ID: the new peer inherits the ban, its
+/// requests are refused from then on and the next ban sync disconnects it.
+async fn check_ban(
+    store: &Arc<dyn Store>,
+    peer: &PeerId,
+    hello: &super::protocol::NodeHello,
+) -> std::result::Result<(), String> {
+    let peer_id = peer.to_string();
+    let banned = |ban: PeerBan| match ban.reason {
+        Some(reason) => format!("peer 已被封禁: {}", reason),
+        None => "peer 已被封禁".to_string(),
+    };
+    match store.peer_ban(&peer_id).await {
+        Ok(Some(ban)) => return Err(banned(ban)),
+        Ok(None) => {}
+        Err(err) => return Err(format!("读取 peer 失败: {}", err)),
+    }
+    if hello.uuid.trim().is_empty() {
+        return Ok(());
+    }
+    let others = store
+        .peers_by_node_uuid(&hello.uuid)
+        .await
+        .map_err(|err| format!("读取 peer 失败: {}", err))?;
+    for other in others.iter().filter(|other| **other != peer_id) {
+        let Ok(Some(ban)) = store.peer_ban(other).await else {
+            continue;
+        };
+        tracing::info!(
+            "peer {} 与已封禁的 peer {} 属于同一节点 {}, 沿用封禁",
+            peer,
+            other,
+            hello.uuid
+        );
+        let inherited = async {
+            store
+                .upsert_peer(
+                    &peer_id,
+                    &hello.node_id,
+                    &hello.uuid,
+                    &hello.name,
+                    &hello.role,
+                    "offline",
+                )
+                .await?;
+            store
+                .set_node_ban(&peer_id, true, ban.reason.as_deref(), ban.until)
+                .await
+        };
+        if let Err(err) = inherited.await {
+            tracing::warn!("记录封禁失败: peer={}, error={}", peer, err);
+        }
+        return Err(banned(ban));
+    }
+    Ok(())
+}
+
+/// Decide whether a peer saying Hello may join under the community's
+/// admission mode, whatever role it claims. Known peers and edges with a
+/// valid invite always get in; in approval mode everyone else is queued for
+/// an operator.
+async fn admit(
+    store: &Arc<dyn Store>,
+    peer: &PeerId,
+    hello: &super::protocol::NodeHello,
+    invited: bool,
+) -> std::result::Result<(), String> {
+    let peer_id = peer.to_string();
+    match store.peer_role(&peer_id).await {
+        // Already a member, or admitted under whatever role it said Hello with
+        Ok(Some(role)) if !role.is_empty() => return Ok(()),
+        Ok(_) => {}
+        Err(err) => return Err(format!("读取 peer 失败: {}", err)),
+    }
+    let mode = store
+        .admission_mode()
+        .await
+        .map_err(|err| format!("读取准入策略失败: {}", err))?;
+    if invited || mode == AdmissionMode::Open {
+        if let Err(err) = store.remove_join_request(&peer_id).await {
+            tracing::warn!("清除加入申请失败: peer={}, error={}", peer, err);
+        }
+        return Ok(());
+    }
+    match mode {
+        AdmissionMode::Invite => Err("社区仅接受邀请加入".into()),
+        _ => match store
+            .record_join_request(&peer_id, &hello.node_id, &hello.name)
+            .await
+        {
+            Ok(request) if request.status == "rejected" => Err("加入申请已被拒绝".into()),
+            Ok(_) => Err("已提交加入申请，等待社区管理员审批".into()),
+            Err(err) => Err(format!("记录加入申请失败: {}", err)),
+        },
+    }
+}
+
 fn peer_id_from_addr(addr: &Multiaddr) -> Option<PeerId> {
     addr.iter().find_map(|protocol| {
         if let Protocol::P2p(peer_id) = protocol {
@@ -727,71 +1943,49 @@ fn peer_id_from_addr(addr: &Multiaddr) -> Option<PeerId> {
     })
 }
 
-async fn load_or_generate_keypair(store: &Arc<dyn Store>) -> Result<identity::Keypair> {
-    // Priority: 1. Environment variable PORTA_KEY_PATH, 2. Database node_info.key_path, 3. Generate based on DB path
-    let key_path = if let Ok(env_path) = std::env::var("PORTA_KEY_PATH") {
-        tracing::info!("[P2P] Using key path from environment: {}", env_path);
-        env_path
-    } else {
-        let info = store.node_info().await?;
-        let db_path = info.key_path.clone();
-        if db_path.is_empty() || db_path == "porta.node.key" {
-            // Generate unique key path based on database path
-            let db_path_env = std::env::var("PORTA_DB").unwrap_or_else(|_| "porta.db".to_string());
-
-            // Handle :memory: database (used in tests) - use temp file path
-            let key_file = if db_path_env == ":memory:" {
-                // For in-memory database, use a temporary key file in current directory
-                // Use a unique name to avoid conflicts between tests
-                format!("porta-test-{}.key", std::process::id())
-            } else {
-                let db_file = std::path::Path::new(&db_path_env);
-                db_file
-                    .file_stem()
-                    .and_then(|s| s.to_str())
-                    .map(|s| format!("{}.key", s))
-                    .unwrap_or_else(|| "porta.node.key".to_string())
-            };
-
-            tracing::info!(
-                "[P2P] Generated key path from database: {} -> {}",
-                db_path_env,
-                key_file
-            );
-            key_file
-        } else {
-            tracing::info!("[P2P] Using key path from database: {}", db_path);
-            db_path
-        }
+async fn load_or_generate_keypair(
+    store: &Arc<dyn Store>,
+    config: &BackendConfig,
+) -> Result<identity::Keypair> {
+    let Some(key_path) = resolve_key_path(store, config).await? else {
+        // In-memory nodes (tests, several nodes per process) get a throwaway identity
+        let keypair = identity::Keypair::generate_ed25519();
+        tracing::info!(
+            "[P2P] Generated ephemeral key for in-memory node: peer_id={}",
+            PeerId::from(keypair.public())
+        );
+        return Ok(keypair);
     };
-
-    // Ensure parent directory exists
-    if let Some(parent) = std::path::Path::new(&key_path).parent() {
-        if !parent.as_os_str().is_empty() {
-            if let Err(e) = tokio::fs::create_dir_all(parent).await {
-                tracing::warn!(
-                    "[P2P] Failed to create key directory {}: {}",
-                    parent.display(),
-                    e
-                );
-            }
-        }
-    }
+    let passphrase = config.key_passphrase.as_deref();
 
     // Try to load existing key
-    if let Ok(bytes) = tokio::fs::read(&key_path).await {
-        if let Ok(keypair) = identity::Keypair::from_protobuf_encoding(&bytes) {
-            let peer_id = PeerId::from(keypair.public());
+    match keystore::read(&key_path, passphrase).await {
+        Ok(Some(key)) => {
+            let peer_id = PeerId::from(key.keypair.public());
             tracing::info!(
                 "[P2P] Loaded existing key from {}: peer_id={}",
                 key_path,
                 peer_id
             );
-            return Ok(keypair);
-        } else {
+            if !key.encrypted && passphrase.is_some_and(|p| !p.is_empty()) {
+                keystore::write(&key_path, &key.keypair, passphrase).await?;
+                tracing::info!(
+                    "[P2P] Encrypted key file {} with the key passphrase",
+                    key_path
+                );
+            }
+            return Ok(key.keypair);
+        }
+        Ok(None) => {}
+        // Never replace an identity we merely failed to unlock
+        Err(err) if err.is::<KeyLocked>() || keystore::is_encrypted(&key_path).await => {
+            return Err(err);
+        }
+        Err(err) => {
             tracing::warn!(
-                "[P2P] Key file {} exists but is invalid, generating new key",
-                key_path
+                "[P2P] Key file {} exists but is invalid ({}), generating new key",
+                key_path,
+                err
             );
         }
     }
@@ -799,8 +1993,7 @@ async fn load_or_generate_keypair(store: &Arc<dyn Store>) -> Result<identity::Ke
     // Generate new key
     let keypair = identity::Keypair::generate_ed25519();
     let peer_id = PeerId::from(keypair.public());
-    let encoded = keypair.to_protobuf_encoding()?;
-    tokio::fs::write(&key_path, encoded).await?;
+    keystore::write(&key_path, &keypair, passphrase).await?;
     tracing::info!(
         "[P2P] Generated new key at {}: peer_id={}",
         key_path,
@@ -809,6 +2002,61 @@ async fn load_or_generate_keypair(store: &Arc<dyn Store>) -> Result<identity::Ke
     Ok(keypair)
 }
 
+/// Overwrite the key file the node loads at startup, encrypted with the
+/// node's key passphrase if it has one. Returns the path and peer ID written,
+/// or `None` for in-memory nodes, which have no key file.
+pub(crate) async fn write_keypair(
+    store: &Arc<dyn Store>,
+    config: &BackendConfig,
+    bytes: &[u8],
+) -> Result<Option<(String, PeerId)>> {
+    let keypair = identity::Keypair::from_protobuf_encoding(bytes)
+        .map_err(|err| anyhow!("密钥格式无效: {}", err))?;
+    let Some(key_path) = resolve_key_path(store, config).await? else {
+        return Ok(None);
+    };
+    keystore::write(&key_path, &keypair, config.key_passphrase.as_deref()).await?;
+    Ok(Some((key_path, PeerId::from(keypair.public()))))
+}
+
+/// Key file for this node, or `None` for an in-memory node without one.
+pub(crate) async fn resolve_key_path(
+    store: &Arc<dyn Store>,
+    config: &BackendConfig,
+) -> Result<Option<String>> {
+    // Priority: 1. Configured key path, 2. Database node_info.key_path, 3. Generate based on DB path
+    let key_path = if let Some(config_path) = config.key_path.clone() {
+        tracing::info!("[P2P] Using key path from config: {}", config_path);
+        config_path
+    } else if config.is_in_memory() {
+        // Nothing else of an in-memory node outlives the process either
+        return Ok(None);
+    } else {
+        let info = store.node_info().await?;
+        let db_path = info.key_path.clone();
+        if db_path.is_empty() || db_path == "porta.node.key" {
+            // Generate unique key path based on database path
+            let db_file = std::path::Path::new(&config.db_path);
+            let key_file = db_file
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .map(|s| format!("{}.key", s))
+                .unwrap_or_else(|| "porta.node.key".to_string());
+
+            tracing::info!(
+                "[P2P] Generated key path from database: {} -> {}",
+                config.db_path,
+                key_file
+            );
+            key_file
+        } else {
+            tracing::info!("[P2P] Using key path from database: {}", db_path);
+            db_path
+        }
+    };
+    Ok(Some(key_path))
+}
+
 async fn write_service_uuid(stream: &mut Stream, service_uuid: &str) -> Result<()> {
     let bytes = service_uuid.as_bytes();
     let len = bytes.len() as u16;
@@ -1,11 +1,34 @@
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use libp2p::futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::{identity, PeerId};
 use serde::{Deserialize, Serialize};
 
+use crate::models::{ServiceChange, ServiceQuery, ServiceRegistryItem};
+
+/// Largest request body accepted from a peer.
+pub const MAX_REQUEST_SIZE: usize = 256 * 1024;
+/// Largest response body accepted from a peer.
+pub const MAX_RESPONSE_SIZE: usize = 4 * 1024 * 1024;
+/// Services returned per `DiscoverServices` page when the caller asks for more (or for all).
+pub const MAX_SERVICE_PAGE_SIZE: u32 = 200;
+/// Registry changes returned per `SyncServices` reply.
+pub const MAX_SYNC_BATCH: u32 = 500;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodeHello {
     pub node_id: String,
+    /// Stable node UUID, kept across key rotations; empty from older nodes
+    #[serde(default)]
+    pub uuid: String,
+    /// Display name of the node; empty from older nodes
+    #[serde(default)]
+    pub name: String,
     pub role: String,
+    /// Invite token an edge joins with; see [`crate::invite`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invite: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,6 +40,112 @@ pub struct ServiceAnnouncement {
     pub description: String,
     pub provider_peer: String,
     pub provider_addr: String,
+    /// Whether the provider is reachable; older nodes only send online services
+    #[serde(default = "default_online")]
+    pub online: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+fn default_online() -> bool {
+    true
+}
+
+impl From<ServiceRegistryItem> for ServiceAnnouncement {
+    fn from(item: ServiceRegistryItem) -> Self {
+        Self {
+            uuid: item.uuid,
+            name: item.name,
+            r#type: item.r#type,
+            port: item.port,
+            description: item.description,
+            provider_peer: item.provider_peer,
+            provider_addr: item.provider_addr,
+            online: item.online,
+            tags: item.tags,
+        }
+    }
+}
+
+impl From<ServiceAnnouncement> for ServiceRegistryItem {
+    fn from(svc: ServiceAnnouncement) -> Self {
+        Self {
+            uuid: svc.uuid,
+            name: svc.name,
+            r#type: svc.r#type,
+            port: svc.port,
+            description: svc.description,
+            provider_peer: svc.provider_peer,
+            provider_addr: svc.provider_addr,
+            online: svc.online,
+            tags: svc.tags,
+        }
+    }
+}
+
+/// Proof that the node known as `old_peer` now runs as `new_peer`, signed
+/// with the old identity. Sent by the new peer, so the transport already
+/// authenticates `new_peer`; the signature authenticates `old_peer`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdentityHandover {
+    pub old_peer: String,
+    pub new_peer: String,
+    /// Protobuf encoding of the old public key, base64
+    pub old_public_key: String,
+    /// Old key's signature over `old_peer` and `new_peer`, base64
+    pub signature: String,
+}
+
+impl IdentityHandover {
+    pub fn sign(old: &identity::Keypair, new_peer: &PeerId) -> Result<Self> {
+        let old_peer = PeerId::from(old.public());
+        let signature = old
+            .sign(&handover_message(&old_peer, new_peer))
+            .map_err(|err| anyhow!("签名失败: {}", err))?;
+        Ok(Self {
+            old_peer: old_peer.to_string(),
+            new_peer: new_peer.to_string(),
+            old_public_key: BASE64.encode(old.public().encode_protobuf()),
+            signature: BASE64.encode(signature),
+        })
+    }
+
+    /// Check the handover was signed by `old_peer` for `sender`; returns the old peer ID.
+    pub fn verify(&self, sender: &PeerId) -> Result<PeerId> {
+        let old_peer: PeerId = self
+            .old_peer
+            .parse()
+            .map_err(|_| anyhow!("无效的旧 peerId"))?;
+        let new_peer: PeerId = self
+            .new_peer
+            .parse()
+            .map_err(|_| anyhow!("无效的新 peerId"))?;
+        if &new_peer != sender {
+            return Err(anyhow!("新 peerId 与发送方不匹配"));
+        }
+        if old_peer == new_peer {
+            return Err(anyhow!("新旧 peerId 相同"));
+        }
+        let key = BASE64
+            .decode(&self.old_public_key)
+            .ok()
+            .and_then(|bytes| identity::PublicKey::try_decode_protobuf(&bytes).ok())
+            .ok_or_else(|| anyhow!("无效的旧公钥"))?;
+        if PeerId::from(key.clone()) != old_peer {
+            return Err(anyhow!("旧公钥与旧 peerId 不匹配"));
+        }
+        let signature = BASE64
+            .decode(&self.signature)
+            .map_err(|_| anyhow!("无效的签名"))?;
+        if !key.verify(&handover_message(&old_peer, &new_peer), &signature) {
+            return Err(anyhow!("签名校验失败"));
+        }
+        Ok(old_peer)
+    }
+}
+
+fn handover_message(old_peer: &PeerId, new_peer: &PeerId) -> Vec<u8> {
+    format!("porta-identity-handover:{}:{}", old_peer, new_peer).into_bytes()
 }
 
 #[derive(Clone)]
@@ -35,6 +164,12 @@ pub enum P2pRequest {
     },
     DiscoverServices {
         community_id: String,
+        #[serde(default)]
+        query: ServiceQuery,
+    },
+    SyncServices {
+        community_id: String,
+        since_revision: u64,
     },
     SubscribeService {
         service_uuid: String,
@@ -55,15 +190,62 @@ pub enum P2pRequest {
         relay_chain: Vec<String>,
         initiator_peer: String,
     },
+    /// Edge asks the community to push registry changes made after `since_revision`
+    WatchRegistry {
+        community_id: String,
+        since_revision: u64,
+    },
+    /// Community pushes registry changes to a watching edge
+    RegistryChanged {
+        since_revision: u64,
+        revision: u64,
+        upserted: Vec<ServiceAnnouncement>,
+        removed: Vec<String>,
+        more: bool,
+        reset: bool,
+    },
+    /// Community tells a subscriber that a service it subscribed to changed
+    ServiceStatusChanged {
+        service_uuid: String,
+        change: ServiceChange,
+    },
+    /// Edge that rotated its identity asks the community to move what its
+    /// old peer ID registered and subscribed to
+    HandOverIdentity {
+        handover: IdentityHandover,
+    },
+    /// A node tells a rendezvous point where it can currently be reached
+    RegisterAddrs {
+        addrs: Vec<String>,
+    },
+    /// Ask a rendezvous point for the addresses it knows for a peer ID
+    ResolvePeer {
+        peer_id: String,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum P2pResponse {
     HelloAck {
         hello: NodeHello,
+        /// Members of the community, sent by community nodes
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        members: Option<u32>,
     },
     ServiceList {
         services: Vec<ServiceAnnouncement>,
+        /// Number of matches before paging; absent from older community nodes
+        #[serde(default)]
+        total: Option<u64>,
+        #[serde(default)]
+        next_cursor: Option<String>,
+    },
+    ServiceChanges {
+        revision: u64,
+        upserted: Vec<ServiceAnnouncement>,
+        removed: Vec<String>,
+        more: bool,
+        reset: bool,
     },
     ConnectInfo {
         provider_peer: String,
@@ -73,6 +255,10 @@ pub enum P2pResponse {
     RelayRouteReady {
         next_hop: Option<String>,
     },
+    /// Addresses known for a resolved peer, newest first
+    PeerAddrs {
+        addrs: Vec<String>,
+    },
     Ack,
     Error {
         message: String,
@@ -96,8 +282,7 @@ impl libp2p::request_response::Codec for JsonCodec {
     where
         T: AsyncRead + Unpin + Send,
     {
-        let mut buf = Vec::new();
-        io.read_to_end(&mut buf).await?;
+        let buf = read_limited(io, MAX_REQUEST_SIZE).await?;
         serde_json::from_slice(&buf)
             .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
     }
@@ -110,8 +295,7 @@ impl libp2p::request_response::Codec for JsonCodec {
     where
         T: AsyncRead + Unpin + Send,
     {
-        let mut buf = Vec::new();
-        io.read_to_end(&mut buf).await?;
+        let buf = read_limited(io, MAX_RESPONSE_SIZE).await?;
         serde_json::from_slice(&buf)
             .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
     }
@@ -149,6 +333,21 @@ impl libp2p::request_response::Codec for JsonCodec {
     }
 }
 
+async fn read_limited<T>(io: &mut T, limit: usize) -> std::io::Result<Vec<u8>>
+where
+    T: AsyncRead + Unpin + Send,
+{
+    let mut buf = Vec::new();
+    io.take(limit as u64 + 1).read_to_end(&mut buf).await?;
+    if buf.len() > limit {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("message exceeds {} bytes", limit),
+        ));
+    }
+    Ok(buf)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -158,7 +357,10 @@ mod tests {
         let req = P2pRequest::Hello {
             hello: NodeHello {
                 node_id: "node-1".into(),
+                uuid: "uuid-1".into(),
+                name: "edge-1".into(),
                 role: "edge".into(),
+                invite: None,
             },
         };
         let data = serde_json::to_vec(&req).unwrap();
@@ -171,4 +373,142 @@ mod tests {
             _ => panic!("unexpected request"),
         }
     }
+
+    #[test]
+    fn should_accept_discover_request_without_query() {
+        let json = r#"{"DiscoverServices":{"community_id":"dev"}}"#;
+        match serde_json::from_str::<P2pRequest>(json).unwrap() {
+            P2pRequest::DiscoverServices {
+                community_id,
+                query,
+            } => {
+                assert_eq!(community_id, "dev");
+                assert!(query.online_only);
+                assert!(query.tags.is_empty());
+                assert!(query.limit.is_none());
+            }
+            _ => panic!("unexpected request"),
+        }
+    }
+
+    #[test]
+    fn should_accept_service_list_without_tags_or_total() {
+        let json = r#"{"ServiceList":{"services":[{"uuid":"svc-1","name":"A","type":"HTTP","port":80,"description":"","provider_peer":"p","provider_addr":"127.0.0.1"}]}}"#;
+        match serde_json::from_str::<P2pResponse>(json).unwrap() {
+            P2pResponse::ServiceList {
+                services,
+                total,
+                next_cursor,
+            } => {
+                assert_eq!(services.len(), 1);
+                assert!(services[0].tags.is_empty());
+                assert!(total.is_none());
+                assert!(next_cursor.is_none());
+            }
+            _ => panic!("unexpected response"),
+        }
+    }
+
+    #[test]
+    fn should_carry_offline_services_in_service_list() {
+        let item = ServiceRegistryItem {
+            uuid: "svc-1".into(),
+            name: "A".into(),
+            r#type: "HTTP".into(),
+            port: 80,
+            description: String::new(),
+            provider_peer: "p".into(),
+            provider_addr: "127.0.0.1".into(),
+            online: false,
+            tags: Vec::new(),
+        };
+        let resp = P2pResponse::ServiceList {
+            services: vec![item.into()],
+            total: Some(1),
+            next_cursor: None,
+        };
+        let data = serde_json::to_vec(&resp).unwrap();
+        match serde_json::from_slice::<P2pResponse>(&data).unwrap() {
+            P2pResponse::ServiceList { services, .. } => {
+                let cached: ServiceRegistryItem = services[0].clone().into();
+                assert!(!cached.online);
+            }
+            _ => panic!("unexpected response"),
+        }
+        let json = r#"{"uuid":"svc-1","name":"A","type":"HTTP","port":80,"description":"","provider_peer":"p","provider_addr":"127.0.0.1"}"#;
+        let legacy: ServiceAnnouncement = serde_json::from_str(json).unwrap();
+        assert!(legacy.online);
+    }
+
+    #[test]
+    fn should_accept_hello_ack_without_name_or_members() {
+        let json = r#"{"HelloAck":{"hello":{"node_id":"node-1","role":"community"}}}"#;
+        match serde_json::from_str::<P2pResponse>(json).unwrap() {
+            P2pResponse::HelloAck { hello, members } => {
+                assert!(hello.name.is_empty());
+                assert!(members.is_none());
+            }
+            _ => panic!("unexpected response"),
+        }
+    }
+
+    #[test]
+    fn should_roundtrip_registry_push() {
+        let req = P2pRequest::RegistryChanged {
+            since_revision: 3,
+            revision: 5,
+            upserted: Vec::new(),
+            removed: vec!["svc-1".into()],
+            more: false,
+            reset: false,
+        };
+        let data = serde_json::to_vec(&req).unwrap();
+        match serde_json::from_slice::<P2pRequest>(&data).unwrap() {
+            P2pRequest::RegistryChanged {
+                since_revision,
+                revision,
+                removed,
+                ..
+            } => {
+                assert_eq!(since_revision, 3);
+                assert_eq!(revision, 5);
+                assert_eq!(removed, vec!["svc-1".to_string()]);
+            }
+            _ => panic!("unexpected request"),
+        }
+    }
+
+    #[test]
+    fn should_verify_identity_handover_only_for_new_peer() {
+        let old = identity::Keypair::generate_ed25519();
+        let new_peer = PeerId::from(identity::Keypair::generate_ed25519().public());
+        let handover = IdentityHandover::sign(&old, &new_peer).unwrap();
+        assert_eq!(
+            handover.verify(&new_peer).unwrap(),
+            PeerId::from(old.public())
+        );
+
+        let other = PeerId::from(identity::Keypair::generate_ed25519().public());
+        assert!(handover.verify(&other).is_err());
+
+        // Redirecting a signed handover to another peer breaks the signature
+        let mut forged = handover.clone();
+        forged.new_peer = other.to_string();
+        assert!(forged.verify(&other).is_err());
+
+        // A key that does not belong to the old peer ID is rejected
+        let mut forged =
+            IdentityHandover::sign(&identity::Keypair::generate_ed25519(), &new_peer).unwrap();
+        forged.old_peer = handover.old_peer.clone();
+        assert!(forged.verify(&new_peer).is_err());
+    }
+
+    #[tokio::test]
+    async fn should_reject_oversized_message() {
+        let mut small = libp2p::futures::io::Cursor::new(vec![b'x'; 16]);
+        assert_eq!(read_limited(&mut small, 16).await.unwrap().len(), 16);
+        let mut large = libp2p::futures::io::Cursor::new(vec![b'x'; 17]);
+        let err = read_limited(&mut large, 16).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
 }
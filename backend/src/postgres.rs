@@ -0,0 +1,1981 @@
+//! PostgreSQL implementation of `Store`.
+//!
+//! Meant for community nodes with large registries, and for several community
+//! replicas sharing one database. The schema is versioned like the SQLite one
+//! but starts from the current layout; migrations run in a single transaction
+//! under an advisory lock, so replicas starting together apply them once.
+//! Registry revisions come from a sequence, drawn under an advisory lock held
+//! until the writing transaction commits, so concurrent writers never hand out
+//! the same revision and revisions become visible in order.
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use sqlx::{
+    postgres::{PgConnectOptions, PgPool, PgPoolOptions, PgRow},
+    PgConnection, Postgres, Row, Transaction,
+};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    models::{
+        AdmissionMode, CommunityAddRequest, CommunityNode, CommunityService, CommunitySummary,
+        DiscoveredService, JoinRequest, KeyImportRequest, NodeConfigUpdate, NodeInfo, PeerBan,
+        ProxyStatus, PublishRequest, PublishedService, RegistryChanges, SecureRoute, ServicePage,
+        ServiceQuery, ServiceRegistryItem, SessionInfo, SubscribeRequest, SubscribedService,
+    },
+    state::{Store, StoreResult},
+};
+
+struct Migration {
+    version: i64,
+    name: &'static str,
+    steps: &'static [&'static str],
+}
+
+/// Schema version written by this build.
+pub const LATEST_VERSION: i64 = 8;
+
+/// Key for `pg_advisory_xact_lock`, held while migrations run.
+const MIGRATION_LOCK: i64 = 0x0070_6f72_7461;
+
+/// Key for `pg_advisory_xact_lock`, held by transactions that change the
+/// service registry.
+const REVISION_LOCK: i64 = 0x0070_6f72_7472;
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial schema",
+        steps: &[
+            r#"
+        CREATE TABLE IF NOT EXISTS node_config (
+            id BIGINT PRIMARY KEY,
+            name TEXT NOT NULL,
+            node_id TEXT NOT NULL,
+            uuid TEXT NOT NULL,
+            key_path TEXT NOT NULL,
+            tcp_listen_enable BOOLEAN NOT NULL,
+            tcp_listen_port BIGINT NOT NULL,
+            quci_listen_enable BOOLEAN NOT NULL,
+            quci_listen_port BIGINT NOT NULL,
+            external_addr TEXT NOT NULL,
+            mdns_enable BOOLEAN NOT NULL,
+            dht_enable BOOLEAN NOT NULL
+        )
+        "#,
+            r#"
+        CREATE TABLE IF NOT EXISTS communities (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            description TEXT NOT NULL,
+            peers BIGINT NOT NULL DEFAULT 0,
+            joined BOOLEAN NOT NULL DEFAULT FALSE,
+            multiaddr TEXT,
+            peer_id TEXT,
+            sync_revision BIGINT NOT NULL DEFAULT 0,
+            managed BOOLEAN NOT NULL DEFAULT FALSE
+        )
+        "#,
+            r#"
+        CREATE TABLE IF NOT EXISTS discovered_services (
+            uuid TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            type TEXT NOT NULL,
+            remote_port BIGINT NOT NULL,
+            provider TEXT NOT NULL,
+            description TEXT NOT NULL,
+            community_id TEXT NOT NULL,
+            provider_addr TEXT,
+            tags TEXT NOT NULL DEFAULT '[]'
+        )
+        "#,
+            r#"
+        CREATE TABLE IF NOT EXISTS subscribed_services (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            type TEXT NOT NULL,
+            community TEXT NOT NULL,
+            remote_addr TEXT NOT NULL,
+            local_mapping TEXT NOT NULL,
+            status TEXT NOT NULL,
+            service_uuid TEXT
+        )
+        "#,
+            r#"
+        CREATE TABLE IF NOT EXISTS published_services (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            type TEXT NOT NULL,
+            port BIGINT NOT NULL,
+            summary TEXT NOT NULL,
+            subscriptions BIGINT NOT NULL,
+            status TEXT NOT NULL,
+            publish_date TEXT NOT NULL,
+            tags TEXT NOT NULL DEFAULT '[]',
+            managed BOOLEAN NOT NULL DEFAULT FALSE
+        )
+        "#,
+            r#"
+        CREATE TABLE IF NOT EXISTS proxy_status (
+            id BIGINT PRIMARY KEY,
+            enabled BOOLEAN NOT NULL,
+            listen_port BIGINT NOT NULL,
+            managed BOOLEAN NOT NULL DEFAULT FALSE
+        )
+        "#,
+            r#"
+        CREATE TABLE IF NOT EXISTS sessions (
+            session_id TEXT PRIMARY KEY,
+            service_id TEXT NOT NULL,
+            local_port BIGINT NOT NULL,
+            remote_peer TEXT NOT NULL,
+            state TEXT NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+            last_active TIMESTAMPTZ NOT NULL DEFAULT now()
+        )
+        "#,
+            r#"
+        CREATE TABLE IF NOT EXISTS peers (
+            peer_id TEXT PRIMARY KEY,
+            node_id TEXT NOT NULL,
+            role TEXT NOT NULL,
+            status TEXT NOT NULL,
+            banned BOOLEAN NOT NULL,
+            last_seen TIMESTAMPTZ NOT NULL
+        )
+        "#,
+            r#"
+        CREATE TABLE IF NOT EXISTS service_registry (
+            uuid TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            type TEXT NOT NULL,
+            port BIGINT NOT NULL,
+            description TEXT NOT NULL,
+            provider_peer TEXT NOT NULL,
+            provider_addr TEXT NOT NULL,
+            online BOOLEAN NOT NULL,
+            announced BOOLEAN NOT NULL DEFAULT TRUE,
+            updated_at TIMESTAMPTZ NOT NULL,
+            tags TEXT NOT NULL DEFAULT '[]'
+        )
+        "#,
+            r#"
+        CREATE TABLE IF NOT EXISTS service_subscriptions (
+            service_uuid TEXT NOT NULL,
+            subscriber_peer TEXT NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL,
+            PRIMARY KEY (service_uuid, subscriber_peer)
+        )
+        "#,
+            r#"
+        CREATE TABLE IF NOT EXISTS secure_routes (
+            id TEXT PRIMARY KEY,
+            subscription_id TEXT NOT NULL,
+            relay_peers TEXT NOT NULL,
+            local_port BIGINT NOT NULL,
+            status TEXT NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL
+        )
+        "#,
+            r#"
+        CREATE TABLE IF NOT EXISTS service_registry_revisions (
+            uuid TEXT PRIMARY KEY,
+            revision BIGINT NOT NULL
+        )
+        "#,
+            "CREATE SEQUENCE IF NOT EXISTS service_registry_revision_seq",
+        ],
+    },
+    Migration {
+        version: 2,
+        name: "invite redemptions",
+        steps: &[r#"
+        CREATE TABLE IF NOT EXISTS invite_redemptions (
+            invite_id TEXT PRIMARY KEY,
+            peer_id TEXT NOT NULL,
+            redeemed_at TIMESTAMPTZ NOT NULL
+        )
+        "#],
+    },
+    Migration {
+        version: 3,
+        name: "community fallback addresses",
+        steps: &[
+            "ALTER TABLE communities ADD COLUMN IF NOT EXISTS multiaddrs TEXT NOT NULL DEFAULT '[]'",
+            "ALTER TABLE communities ADD COLUMN IF NOT EXISTS last_multiaddr TEXT",
+        ],
+    },
+    Migration {
+        version: 4,
+        name: "community reconnect backoff",
+        steps: &[
+            "ALTER TABLE communities ADD COLUMN IF NOT EXISTS connect_failures BIGINT NOT NULL DEFAULT 0",
+            "ALTER TABLE communities ADD COLUMN IF NOT EXISTS last_error TEXT",
+            "ALTER TABLE communities ADD COLUMN IF NOT EXISTS next_retry_at BIGINT",
+        ],
+    },
+    Migration {
+        version: 5,
+        name: "community membership",
+        steps: &[
+            "ALTER TABLE peers ADD COLUMN IF NOT EXISTS name TEXT NOT NULL DEFAULT ''",
+            "ALTER TABLE peers ADD COLUMN IF NOT EXISTS joined_at TIMESTAMPTZ",
+        ],
+    },
+    Migration {
+        version: 6,
+        name: "community admission",
+        steps: &[
+            "ALTER TABLE node_config ADD COLUMN IF NOT EXISTS admission TEXT NOT NULL DEFAULT 'open'",
+            r#"
+        CREATE TABLE IF NOT EXISTS join_requests (
+            peer_id TEXT PRIMARY KEY,
+            node_id TEXT NOT NULL,
+            name TEXT NOT NULL,
+            status TEXT NOT NULL,
+            requested_at TIMESTAMPTZ NOT NULL
+        )
+        "#,
+        ],
+    },
+    Migration {
+        version: 7,
+        name: "peer bans",
+        steps: &[
+            "ALTER TABLE peers ADD COLUMN IF NOT EXISTS node_uuid TEXT NOT NULL DEFAULT ''",
+            "ALTER TABLE peers ADD COLUMN IF NOT EXISTS ban_reason TEXT",
+            "ALTER TABLE peers ADD COLUMN IF NOT EXISTS banned_until BIGINT",
+        ],
+    },
+    Migration {
+        version: 8,
+        name: "discovered service online state",
+        steps: &[
+            "ALTER TABLE discovered_services ADD COLUMN IF NOT EXISTS online BOOLEAN NOT NULL DEFAULT TRUE",
+        ],
+    },
+];
+
+pub struct PostgresStore {
+    pool: PgPool,
+}
+
+impl PostgresStore {
+    /// Connect to a `postgres://` URL, migrate the schema and seed defaults.
+    pub async fn connect(url: &str) -> StoreResult<Arc<Self>> {
+        Self::connect_with(url.parse()?).await
+    }
+
+    pub async fn connect_with(options: PgConnectOptions) -> StoreResult<Arc<Self>> {
+        let pool = PgPoolOptions::new().connect_with(options).await?;
+        migrate(&pool).await?;
+        let store = Self { pool };
+        store.seed_if_empty().await?;
+        Ok(Arc::new(store))
+    }
+
+    async fn seed_if_empty(&self) -> StoreResult<()> {
+        // Replicas may start together; whichever inserts first wins
+        sqlx::query(
+            r#"
+            INSERT INTO node_config
+            (id, name, node_id, uuid, key_path, tcp_listen_enable, tcp_listen_port,
+             quci_listen_enable, quci_listen_port, external_addr, mdns_enable, dht_enable)
+            VALUES (1, $1, $2, $3, $4, TRUE, 0, TRUE, 0, $5, TRUE, TRUE)
+            ON CONFLICT (id) DO NOTHING
+            "#,
+        )
+        .bind("我的节点")
+        .bind("")
+        .bind(Uuid::new_v4().to_string())
+        .bind("porta.node.key")
+        .bind("[]")
+        .execute(&self.pool)
+        .await?;
+        sqlx::query(
+            "INSERT INTO proxy_status (id, enabled, listen_port) VALUES (1, TRUE, 1080) ON CONFLICT (id) DO NOTHING",
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn load_node_info(&self) -> StoreResult<NodeInfo> {
+        let row = sqlx::query(
+            r#"
+            SELECT name, node_id, uuid, key_path, tcp_listen_enable, tcp_listen_port,
+                   quci_listen_enable, quci_listen_port, external_addr, mdns_enable, dht_enable
+            FROM node_config WHERE id = 1
+            "#,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        let external_addr: String = row.get("external_addr");
+        let parsed: Vec<String> = serde_json::from_str(&external_addr).unwrap_or_default();
+        Ok(NodeInfo {
+            name: row.get("name"),
+            node_id: row.get("node_id"),
+            uuid: row.get("uuid"),
+            key_path: row.get("key_path"),
+            tcp_listen_enable: row.get("tcp_listen_enable"),
+            tcp_listen_port: row.get::<i64, _>("tcp_listen_port") as u16,
+            quci_listen_enable: row.get("quci_listen_enable"),
+            quci_listen_port: row.get::<i64, _>("quci_listen_port") as u16,
+            external_addr: parsed,
+            mdns_enable: row.get("mdns_enable"),
+            dht_enable: row.get("dht_enable"),
+        })
+    }
+
+    async fn save_node_info(&self, info: &NodeInfo) -> StoreResult<()> {
+        let external_addr = serde_json::to_string(&info.external_addr)?;
+        sqlx::query(
+            r#"
+            UPDATE node_config
+            SET name = $1, node_id = $2, uuid = $3, key_path = $4,
+                tcp_listen_enable = $5, tcp_listen_port = $6,
+                quci_listen_enable = $7, quci_listen_port = $8,
+                external_addr = $9, mdns_enable = $10, dht_enable = $11
+            WHERE id = 1
+            "#,
+        )
+        .bind(&info.name)
+        .bind(&info.node_id)
+        .bind(&info.uuid)
+        .bind(&info.key_path)
+        .bind(info.tcp_listen_enable)
+        .bind(info.tcp_listen_port as i64)
+        .bind(info.quci_listen_enable)
+        .bind(info.quci_listen_port as i64)
+        .bind(external_addr)
+        .bind(info.mdns_enable)
+        .bind(info.dht_enable)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Begin a transaction that changes the service registry. Holding
+    /// `REVISION_LOCK` until it ends makes revisions commit in the order they
+    /// are drawn, so `registry_changes_since` cannot pass over a lower
+    /// revision that commits late.
+    async fn begin_registry_change(&self) -> StoreResult<Transaction<'static, Postgres>> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("SELECT pg_advisory_xact_lock($1)")
+            .bind(REVISION_LOCK)
+            .execute(&mut *tx)
+            .await?;
+        Ok(tx)
+    }
+}
+
+/// Record a new registry revision for `uuid`, inside a transaction from
+/// `PostgresStore::begin_registry_change`.
+async fn bump_registry_revision(conn: &mut PgConnection, uuid: &str) -> StoreResult<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO service_registry_revisions (uuid, revision)
+        VALUES ($1, nextval('service_registry_revision_seq'))
+        ON CONFLICT (uuid) DO UPDATE SET revision = excluded.revision
+        "#,
+    )
+    .bind(uuid)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Schema version recorded in the database, 0 if it was never migrated.
+pub async fn schema_version(pool: &PgPool) -> Result<i64> {
+    let row = sqlx::query("SELECT COALESCE(MAX(version), 0) AS version FROM schema_version")
+        .fetch_one(pool)
+        .await?;
+    Ok(row.get("version"))
+}
+
+async fn migrate(pool: &PgPool) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(MIGRATION_LOCK)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_version (
+            version BIGINT PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at TIMESTAMPTZ NOT NULL
+        )
+        "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+    let row = sqlx::query("SELECT COALESCE(MAX(version), 0) AS version FROM schema_version")
+        .fetch_one(&mut *tx)
+        .await?;
+    let current: i64 = row.get("version");
+    if current > LATEST_VERSION {
+        return Err(anyhow!(
+            "数据库结构版本 {} 高于当前程序支持的版本 {}, 请升级 Porta",
+            current,
+            LATEST_VERSION
+        ));
+    }
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        tracing::info!("执行数据库迁移 v{}: {}", migration.version, migration.name);
+        for sql in migration.steps {
+            sqlx::query(sql).execute(&mut *tx).await?;
+        }
+        sqlx::query(
+            "INSERT INTO schema_version (version, name, applied_at) VALUES ($1, $2, now())",
+        )
+        .bind(migration.version)
+        .bind(migration.name)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+fn parse_tags(raw: String) -> Vec<String> {
+    serde_json::from_str(&raw).unwrap_or_default()
+}
+
+fn community_from_row(row: PgRow) -> CommunitySummary {
+    CommunitySummary {
+        id: row.get("id"),
+        name: row.get("name"),
+        description: row.get("description"),
+        peers: row.get::<i64, _>("peers") as u32,
+        joined: row.get("joined"),
+        multiaddr: row.get("multiaddr"),
+        multiaddrs: parse_tags(row.get("multiaddrs")),
+        last_multiaddr: row.get("last_multiaddr"),
+        peer_id: row.get("peer_id"),
+        managed: row.get("managed"),
+        state: Default::default(),
+        connect_failures: row.get::<i64, _>("connect_failures") as u32,
+        last_error: row.get("last_error"),
+        next_retry_at: row.get("next_retry_at"),
+    }
+}
+
+fn session_from_row(row: &PgRow) -> SessionInfo {
+    SessionInfo {
+        session_id: row.get("session_id"),
+        service_id: row.get("service_id"),
+        local_port: row.get::<i64, _>("local_port") as u16,
+        remote_peer: row.get("remote_peer"),
+        state: row.get("state"),
+        created_at: row.get("created_at"),
+        last_active: row.get("last_active"),
+    }
+}
+
+fn join_request_from_row(row: &PgRow) -> JoinRequest {
+    JoinRequest {
+        peer_id: row.get("peer_id"),
+        node_id: row.get("node_id"),
+        name: row.get("name"),
+        status: row.get("status"),
+        requested_at: row.get("requested_at"),
+    }
+}
+
+fn subscription_from_row(row: PgRow) -> SubscribedService {
+    SubscribedService {
+        id: row.get("id"),
+        name: row.get("name"),
+        r#type: row.get("type"),
+        community: row.get("community"),
+        remote_addr: row.get("remote_addr"),
+        local_mapping: row.get("local_mapping"),
+        status: row.get("status"),
+        service_uuid: row.get("service_uuid"),
+    }
+}
+
+fn published_from_row(row: PgRow) -> PublishedService {
+    PublishedService {
+        id: row.get("id"),
+        name: row.get("name"),
+        r#type: row.get("type"),
+        port: row.get::<i64, _>("port") as u16,
+        summary: row.get("summary"),
+        subscriptions: row.get::<i64, _>("subscriptions") as u32,
+        status: row.get("status"),
+        publish_date: row.get("publish_date"),
+        tags: parse_tags(row.get("tags")),
+        managed: row.get("managed"),
+    }
+}
+
+fn registry_item_from_row(row: PgRow) -> ServiceRegistryItem {
+    ServiceRegistryItem {
+        uuid: row.get("uuid"),
+        name: row.get("name"),
+        r#type: row.get("type"),
+        port: row.get::<i64, _>("port") as u16,
+        description: row.get("description"),
+        provider_peer: row.get("provider_peer"),
+        provider_addr: row.get("provider_addr"),
+        online: row.get("online"),
+        tags: parse_tags(row.get("tags")),
+    }
+}
+
+fn route_from_row(row: PgRow) -> SecureRoute {
+    let relay_json: String = row.get("relay_peers");
+    SecureRoute {
+        id: row.get("id"),
+        subscription_id: row.get("subscription_id"),
+        relay_peers: serde_json::from_str(&relay_json).unwrap_or_default(),
+        local_port: row.get::<i64, _>("local_port") as u16,
+        status: row.get("status"),
+    }
+}
+
+const COMMUNITY_COLUMNS: &str =
+    "SELECT id, name, description, peers, joined, multiaddr, multiaddrs, last_multiaddr, peer_id, managed, connect_failures, last_error, next_retry_at FROM communities";
+const SUBSCRIPTION_COLUMNS: &str = "SELECT id, name, type, community, remote_addr, local_mapping, status, service_uuid FROM subscribed_services";
+const PUBLISHED_COLUMNS: &str = "SELECT id, name, type, port, summary, subscriptions, status, publish_date, tags, managed FROM published_services";
+const REGISTRY_COLUMNS: &str = "SELECT uuid, name, type, port, description, provider_peer, provider_addr, online, tags FROM service_registry";
+const ROUTE_COLUMNS: &str =
+    "SELECT id, subscription_id, relay_peers, local_port, status FROM secure_routes";
+
+#[async_trait]
+impl Store for PostgresStore {
+    async fn node_info(&self) -> StoreResult<NodeInfo> {
+        self.load_node_info().await
+    }
+
+    async fn update_node_config(&self, req: NodeConfigUpdate) -> StoreResult<NodeInfo> {
+        let mut info = self.load_node_info().await?;
+        if let Some(name) = req.name {
+            info.name = name;
+        }
+        if let Some(tcp_listen_enable) = req.tcp_listen_enable {
+            info.tcp_listen_enable = tcp_listen_enable;
+        }
+        if let Some(tcp_listen_port) = req.tcp_listen_port {
+            info.tcp_listen_port = tcp_listen_port;
+        }
+        if let Some(quci_listen_enable) = req.quci_listen_enable {
+            info.quci_listen_enable = quci_listen_enable;
+        }
+        if let Some(quci_listen_port) = req.quci_listen_port {
+            info.quci_listen_port = quci_listen_port;
+        }
+        if let Some(external_addr) = req.external_addr {
+            info.external_addr = external_addr;
+        }
+        if let Some(mdns_enable) = req.mdns_enable {
+            info.mdns_enable = mdns_enable;
+        }
+        if let Some(dht_enable) = req.dht_enable {
+            info.dht_enable = dht_enable;
+        }
+        self.save_node_info(&info).await?;
+        Ok(info)
+    }
+
+    async fn import_key(&self, req: KeyImportRequest) -> StoreResult<NodeInfo> {
+        let mut info = self.load_node_info().await?;
+        info.key_path = req.key_path;
+        self.save_node_info(&info).await?;
+        Ok(info)
+    }
+
+    async fn generate_key(&self) -> StoreResult<NodeInfo> {
+        let mut info = self.load_node_info().await?;
+        info.key_path = format!("porta-{}.key", Uuid::new_v4());
+        self.save_node_info(&info).await?;
+        Ok(info)
+    }
+
+    async fn ensure_node_identity(&self, peer_id: &str) -> StoreResult<()> {
+        sqlx::query("UPDATE node_config SET node_id = $1 WHERE id = 1 AND node_id = ''")
+            .bind(peer_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_node_id(&self, peer_id: &str) -> StoreResult<()> {
+        sqlx::query("UPDATE node_config SET node_id = $1 WHERE id = 1")
+            .bind(peer_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn communities(&self) -> StoreResult<Vec<CommunitySummary>> {
+        let rows = sqlx::query(&format!("{} ORDER BY id", COMMUNITY_COLUMNS))
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(community_from_row).collect())
+    }
+
+    async fn add_community(&self, req: CommunityAddRequest) -> StoreResult<CommunitySummary> {
+        let id = req
+            .id
+            .unwrap_or_else(|| format!("community-{}", Uuid::new_v4()));
+        sqlx::query(
+            "INSERT INTO communities (id, name, description, peers, joined, multiaddr, multiaddrs, peer_id) VALUES ($1, $2, $3, 0, FALSE, $4, $5, $6)",
+        )
+        .bind(&id)
+        .bind(&req.name)
+        .bind(&req.description)
+        .bind(&req.multiaddr)
+        .bind(serde_json::to_string(&req.multiaddrs)?)
+        .bind(&req.peer_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(CommunitySummary {
+            id,
+            name: req.name,
+            description: req.description,
+            peers: 0,
+            joined: false,
+            multiaddr: req.multiaddr,
+            multiaddrs: req.multiaddrs,
+            last_multiaddr: None,
+            peer_id: req.peer_id,
+            managed: false,
+            state: Default::default(),
+            connect_failures: 0,
+            last_error: None,
+            next_retry_at: None,
+        })
+    }
+
+    async fn upsert_community(&self, req: CommunityAddRequest) -> StoreResult<()> {
+        let id = req
+            .id
+            .unwrap_or_else(|| format!("community-{}", Uuid::new_v4()));
+        sqlx::query(
+            r#"
+            INSERT INTO communities (id, name, description, peers, joined, multiaddr, multiaddrs, peer_id)
+            VALUES ($1, $2, $3, 0, FALSE, $4, $5, $6)
+            ON CONFLICT (id) DO UPDATE SET
+                name = excluded.name,
+                description = excluded.description,
+                multiaddr = excluded.multiaddr,
+                multiaddrs = excluded.multiaddrs,
+                peer_id = excluded.peer_id
+            "#,
+        )
+        .bind(&id)
+        .bind(&req.name)
+        .bind(&req.description)
+        .bind(&req.multiaddr)
+        .bind(serde_json::to_string(&req.multiaddrs)?)
+        .bind(&req.peer_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn set_community_managed(&self, id: &str, managed: bool) -> StoreResult<bool> {
+        let result = sqlx::query("UPDATE communities SET managed = $1 WHERE id = $2")
+            .bind(managed)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn remove_community(&self, id: &str) -> StoreResult<bool> {
+        let result = sqlx::query("DELETE FROM communities WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn connect_community(&self, id: &str) -> StoreResult<bool> {
+        let result = sqlx::query("UPDATE communities SET joined = TRUE WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn community_multiaddr(&self, id: &str) -> StoreResult<Option<String>> {
+        let row = sqlx::query("SELECT multiaddr FROM communities WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.and_then(|r| r.get::<Option<String>, _>("multiaddr")))
+    }
+
+    async fn community_by_id(&self, id: &str) -> StoreResult<Option<CommunitySummary>> {
+        let row = sqlx::query(&format!("{} WHERE id = $1", COMMUNITY_COLUMNS))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(community_from_row))
+    }
+
+    async fn community_exists_by_peer(&self, peer_id: &str) -> StoreResult<bool> {
+        let row = sqlx::query("SELECT COUNT(*) AS count FROM communities WHERE peer_id = $1")
+            .bind(peer_id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(row.get::<i64, _>("count") > 0)
+    }
+
+    async fn update_community_multiaddr(
+        &self,
+        id: &str,
+        multiaddr: &str,
+        peer_id: &str,
+    ) -> StoreResult<bool> {
+        let result =
+            sqlx::query("UPDATE communities SET multiaddr = $1, peer_id = $2 WHERE id = $3")
+                .bind(multiaddr)
+                .bind(peer_id)
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_community_last_multiaddr(&self, id: &str, multiaddr: &str) -> StoreResult<bool> {
+        let result = sqlx::query("UPDATE communities SET last_multiaddr = $1 WHERE id = $2")
+            .bind(multiaddr)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_community_connection(
+        &self,
+        id: &str,
+        failures: u32,
+        last_error: Option<&str>,
+        next_retry_at: Option<i64>,
+    ) -> StoreResult<bool> {
+        let result = sqlx::query(
+            "UPDATE communities SET connect_failures = $1, last_error = $2, next_retry_at = $3 WHERE id = $4",
+        )
+        .bind(failures as i64)
+        .bind(last_error)
+        .bind(next_retry_at)
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_community_peers(&self, id: &str, peers: u32) -> StoreResult<bool> {
+        let result = sqlx::query("UPDATE communities SET peers = $1 WHERE id = $2")
+            .bind(peers as i64)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn community_nodes(&self) -> StoreResult<Vec<CommunityNode>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT p.peer_id, p.node_id, p.node_uuid, p.name, p.status, p.ban_reason, p.banned_until,
+                   (p.banned AND (p.banned_until IS NULL OR p.banned_until > $1)) AS active_ban,
+                   to_char(p.joined_at AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS') AS joined_at,
+                   to_char(p.last_seen AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS') AS last_seen,
+                   (SELECT COUNT(*) FROM service_registry s WHERE s.provider_peer = p.peer_id) AS services
+            FROM peers p WHERE p.role = 'edge' ORDER BY p.peer_id
+            "#,
+        )
+        .bind(chrono::Utc::now().timestamp())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let status: String = row.get("status");
+                let node_uuid: String = row.get("node_uuid");
+                let banned: bool = row.get("active_ban");
+                CommunityNode {
+                    id: row.get("peer_id"),
+                    uuid: if node_uuid.is_empty() {
+                        row.get("node_id")
+                    } else {
+                        node_uuid
+                    },
+                    name: row.get("name"),
+                    online: status == "online",
+                    status,
+                    banned,
+                    ban_reason: row
+                        .get::<Option<String>, _>("ban_reason")
+                        .filter(|_| banned),
+                    banned_until: row.get::<Option<i64>, _>("banned_until").filter(|_| banned),
+                    joined_at: row.get("joined_at"),
+                    last_seen: row.get("last_seen"),
+                    services: row.get::<i64, _>("services") as u32,
+                }
+            })
+            .collect())
+    }
+
+    async fn community_services(&self) -> StoreResult<Vec<CommunityService>> {
+        let rows = sqlx::query(
+            "SELECT uuid, name, type, port, online, announced FROM service_registry ORDER BY uuid",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| CommunityService {
+                id: row.get("uuid"),
+                name: row.get("name"),
+                uuid: row.get("uuid"),
+                protocol: row.get("type"),
+                port: row.get::<i64, _>("port") as u16,
+                online: row.get("online"),
+                announced: row.get("announced"),
+            })
+            .collect())
+    }
+
+    async fn discovered_services(
+        &self,
+        community_id: Option<String>,
+    ) -> StoreResult<Vec<DiscoveredService>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT uuid, name, type, remote_port, provider, description, community_id, provider_addr, online, tags
+            FROM discovered_services
+            WHERE $1::TEXT IS NULL OR community_id = $1
+            ORDER BY uuid
+            "#,
+        )
+        .bind(community_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| DiscoveredService {
+                uuid: row.get("uuid"),
+                name: row.get("name"),
+                r#type: row.get("type"),
+                remote_port: row.get::<i64, _>("remote_port") as u16,
+                provider: row.get("provider"),
+                description: row.get("description"),
+                subscribed: None,
+                community_id: row.get("community_id"),
+                provider_addr: row.get("provider_addr"),
+                online: row.get("online"),
+                tags: parse_tags(row.get("tags")),
+            })
+            .collect())
+    }
+
+    async fn upsert_discovered_services(
+        &self,
+        community_id: &str,
+        services: Vec<ServiceRegistryItem>,
+    ) -> StoreResult<()> {
+        let mut tx = self.pool.begin().await?;
+        for svc in services {
+            let tags = serde_json::to_string(&svc.tags)?;
+            sqlx::query(
+                r#"
+                INSERT INTO discovered_services (uuid, name, type, remote_port, provider, description, community_id, provider_addr, online, tags)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                ON CONFLICT (uuid) DO UPDATE SET
+                    name = excluded.name,
+                    type = excluded.type,
+                    remote_port = excluded.remote_port,
+                    provider = excluded.provider,
+                    description = excluded.description,
+                    community_id = excluded.community_id,
+                    provider_addr = excluded.provider_addr,
+                    online = excluded.online,
+                    tags = excluded.tags
+                "#,
+            )
+            .bind(svc.uuid)
+            .bind(svc.name)
+            .bind(svc.r#type)
+            .bind(svc.port as i64)
+            .bind(svc.provider_peer)
+            .bind(svc.description)
+            .bind(community_id)
+            .bind(svc.provider_addr)
+            .bind(svc.online)
+            .bind(tags)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn remove_discovered_services(
+        &self,
+        community_id: &str,
+        uuids: &[String],
+    ) -> StoreResult<u64> {
+        let result = sqlx::query(
+            "DELETE FROM discovered_services WHERE uuid = ANY($1) AND community_id = $2",
+        )
+        .bind(uuids)
+        .bind(community_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn clear_discovered_services(&self, community_id: &str) -> StoreResult<u64> {
+        let result = sqlx::query("DELETE FROM discovered_services WHERE community_id = $1")
+            .bind(community_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn community_sync_revision(&self, id: &str) -> StoreResult<u64> {
+        let row = sqlx::query("SELECT sync_revision FROM communities WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row
+            .map(|row| row.get::<i64, _>("sync_revision") as u64)
+            .unwrap_or(0))
+    }
+
+    async fn set_community_sync_revision(&self, id: &str, revision: u64) -> StoreResult<bool> {
+        let result = sqlx::query("UPDATE communities SET sync_revision = $1 WHERE id = $2")
+            .bind(revision as i64)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn subscribed_services(&self) -> StoreResult<Vec<SubscribedService>> {
+        let rows = sqlx::query(&format!("{} ORDER BY id", SUBSCRIPTION_COLUMNS))
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(subscription_from_row).collect())
+    }
+
+    async fn find_subscription(&self, id: &str) -> StoreResult<Option<SubscribedService>> {
+        let row = sqlx::query(&format!("{} WHERE id = $1", SUBSCRIPTION_COLUMNS))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(subscription_from_row))
+    }
+
+    async fn published_services(&self) -> StoreResult<Vec<PublishedService>> {
+        let rows = sqlx::query(&format!("{} ORDER BY id", PUBLISHED_COLUMNS))
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(published_from_row).collect())
+    }
+
+    async fn published_service_by_id(&self, id: &str) -> StoreResult<Option<PublishedService>> {
+        let row = sqlx::query(&format!("{} WHERE id = $1", PUBLISHED_COLUMNS))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(published_from_row))
+    }
+
+    async fn proxy_status(&self) -> StoreResult<ProxyStatus> {
+        let row =
+            sqlx::query("SELECT enabled, listen_port, managed FROM proxy_status WHERE id = 1")
+                .fetch_one(&self.pool)
+                .await?;
+        Ok(ProxyStatus {
+            enabled: row.get("enabled"),
+            listen_port: row.get::<i64, _>("listen_port") as u16,
+            managed: row.get("managed"),
+        })
+    }
+
+    async fn sessions(&self) -> StoreResult<Vec<SessionInfo>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT session_id, service_id, local_port, remote_peer, state,
+                   to_char(created_at AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS') AS created_at,
+                   to_char(last_active AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS') AS last_active
+            FROM sessions ORDER BY created_at
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.iter().map(session_from_row).collect())
+    }
+
+    async fn upsert_session(&self, session: SessionInfo) -> StoreResult<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO sessions (session_id, service_id, local_port, remote_peer, state, created_at, last_active)
+            VALUES ($1, $2, $3, $4, $5, now(), now())
+            ON CONFLICT (session_id) DO UPDATE SET
+                service_id = excluded.service_id,
+                local_port = excluded.local_port,
+                remote_peer = excluded.remote_peer,
+                state = excluded.state,
+                last_active = now()
+            "#,
+        )
+        .bind(session.session_id)
+        .bind(session.service_id)
+        .bind(session.local_port as i64)
+        .bind(session.remote_peer)
+        .bind(session.state)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn cleanup_expired_sessions(
+        &self,
+        timeout_minutes: i64,
+    ) -> StoreResult<Vec<SessionInfo>> {
+        let rows = sqlx::query(
+            r#"
+            DELETE FROM sessions
+            WHERE state = 'connected'
+            AND last_active < now() - make_interval(mins => $1::INTEGER)
+            RETURNING session_id, service_id, local_port, remote_peer, state,
+                      to_char(created_at AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS') AS created_at,
+                      to_char(last_active AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS') AS last_active
+            "#,
+        )
+        .bind(timeout_minutes)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.iter().map(session_from_row).collect())
+    }
+
+    async fn update_subscription_endpoint(
+        &self,
+        id: &str,
+        remote_addr: &str,
+        status: &str,
+    ) -> StoreResult<bool> {
+        let result = sqlx::query(
+            "UPDATE subscribed_services SET remote_addr = $1, status = $2 WHERE id = $3",
+        )
+        .bind(remote_addr)
+        .bind(status)
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn subscribe_service(&self, req: SubscribeRequest) -> StoreResult<SubscribedService> {
+        let id = req.id.unwrap_or_else(|| format!("sub-{}", Uuid::new_v4()));
+        sqlx::query(
+            r#"
+            INSERT INTO subscribed_services (id, name, type, community, remote_addr, local_mapping, status, service_uuid)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (id) DO UPDATE SET
+                name = excluded.name,
+                type = excluded.type,
+                community = excluded.community,
+                remote_addr = excluded.remote_addr,
+                local_mapping = excluded.local_mapping,
+                status = excluded.status,
+                service_uuid = excluded.service_uuid
+            "#,
+        )
+        .bind(&id)
+        .bind(&req.name)
+        .bind(&req.r#type)
+        .bind(&req.community)
+        .bind(&req.remote_addr)
+        .bind(&req.local_mapping)
+        .bind("畅通")
+        .bind(&req.service_uuid)
+        .execute(&self.pool)
+        .await?;
+        Ok(SubscribedService {
+            id,
+            name: req.name,
+            r#type: req.r#type,
+            community: req.community,
+            remote_addr: req.remote_addr,
+            local_mapping: req.local_mapping,
+            status: "畅通".into(),
+            service_uuid: req.service_uuid,
+        })
+    }
+
+    async fn update_subscription_status(&self, id: &str, status: &str) -> StoreResult<bool> {
+        let result = sqlx::query("UPDATE subscribed_services SET status = $1 WHERE id = $2")
+            .bind(status)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn update_subscriptions_by_service(
+        &self,
+        service_uuid: &str,
+        communities: &[String],
+        status: &str,
+    ) -> StoreResult<u64> {
+        let result = sqlx::query(
+            "UPDATE subscribed_services SET status = $1 WHERE service_uuid = $2 AND community = ANY($3)",
+        )
+        .bind(status)
+        .bind(service_uuid)
+        .bind(communities)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn update_subscription_endpoints_by_service(
+        &self,
+        service_uuid: &str,
+        communities: &[String],
+        remote_addr: &str,
+    ) -> StoreResult<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE subscribed_services
+            SET remote_addr = $1,
+                status = CASE WHEN status IN ('离线', '已下架') THEN '断开' ELSE status END
+            WHERE service_uuid = $2 AND community = ANY($3)
+            "#,
+        )
+        .bind(remote_addr)
+        .bind(service_uuid)
+        .bind(communities)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn publish_service(&self, req: PublishRequest) -> StoreResult<PublishedService> {
+        let id = req.id.unwrap_or_else(|| format!("pub-{}", Uuid::new_v4()));
+        let tags = serde_json::to_string(&req.tags)?;
+        let row = sqlx::query(
+            r#"
+            INSERT INTO published_services (id, name, type, port, summary, subscriptions, status, publish_date, tags)
+            VALUES ($1, $2, $3, $4, $5, 0, $6, to_char(CURRENT_DATE, 'YYYY-MM-DD'), $7)
+            ON CONFLICT (id) DO UPDATE SET
+                name = excluded.name,
+                type = excluded.type,
+                port = excluded.port,
+                summary = excluded.summary,
+                status = excluded.status,
+                tags = excluded.tags
+            RETURNING publish_date, managed
+            "#,
+        )
+        .bind(&id)
+        .bind(&req.name)
+        .bind(&req.r#type)
+        .bind(req.port as i64)
+        .bind(&req.summary)
+        .bind("在线")
+        .bind(tags)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(PublishedService {
+            id,
+            name: req.name,
+            r#type: req.r#type,
+            port: req.port,
+            summary: req.summary,
+            subscriptions: 0,
+            status: "在线".into(),
+            publish_date: row.get("publish_date"),
+            tags: req.tags,
+            managed: row.get("managed"),
+        })
+    }
+
+    async fn unpublish_service(&self, id: &str) -> StoreResult<bool> {
+        let result = sqlx::query("UPDATE published_services SET status = '已下架' WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn remove_published(&self, id: &str) -> StoreResult<bool> {
+        let result = sqlx::query("DELETE FROM published_services WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_published_managed(&self, id: &str, managed: bool) -> StoreResult<bool> {
+        let result = sqlx::query("UPDATE published_services SET managed = $1 WHERE id = $2")
+            .bind(managed)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_service_announced(&self, id: &str, announced: bool) -> StoreResult<bool> {
+        let mut tx = self.begin_registry_change().await?;
+        let result = sqlx::query("UPDATE service_registry SET announced = $1 WHERE uuid = $2")
+            .bind(announced)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        if result.rows_affected() > 0 {
+            bump_registry_revision(&mut tx, id).await?;
+        }
+        tx.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_node_ban(
+        &self,
+        id: &str,
+        banned: bool,
+        reason: Option<&str>,
+        until: Option<i64>,
+    ) -> StoreResult<bool> {
+        let (reason, until) = if banned {
+            (reason, until)
+        } else {
+            (None, None)
+        };
+        let result = sqlx::query(
+            "UPDATE peers SET banned = $1, ban_reason = $2, banned_until = $3 WHERE peer_id = $4",
+        )
+        .bind(banned)
+        .bind(reason)
+        .bind(until)
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_proxy_enabled(&self, enabled: bool) -> StoreResult<()> {
+        sqlx::query("UPDATE proxy_status SET enabled = $1 WHERE id = 1")
+            .bind(enabled)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_proxy_listen_port(&self, port: u16) -> StoreResult<()> {
+        sqlx::query("UPDATE proxy_status SET listen_port = $1 WHERE id = 1")
+            .bind(port as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_proxy_managed(&self, managed: bool) -> StoreResult<()> {
+        sqlx::query("UPDATE proxy_status SET managed = $1 WHERE id = 1")
+            .bind(managed)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn upsert_peer(
+        &self,
+        peer_id: &str,
+        node_id: &str,
+        node_uuid: &str,
+        name: &str,
+        role: &str,
+        status: &str,
+    ) -> StoreResult<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO peers (peer_id, node_id, node_uuid, name, role, status, banned, joined_at, last_seen)
+            VALUES ($1, $2, $3, $4, $5, $6, FALSE, now(), now())
+            ON CONFLICT (peer_id) DO UPDATE SET
+                node_id = excluded.node_id,
+                node_uuid = CASE WHEN excluded.node_uuid = '' THEN peers.node_uuid ELSE excluded.node_uuid END,
+                name = excluded.name,
+                role = excluded.role,
+                status = excluded.status,
+                joined_at = COALESCE(peers.joined_at, excluded.joined_at),
+                last_seen = now()
+            "#,
+        )
+        .bind(peer_id)
+        .bind(node_id)
+        .bind(node_uuid)
+        .bind(name)
+        .bind(role)
+        .bind(status)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn set_peer_status(&self, peer_id: &str, status: &str) -> StoreResult<bool> {
+        let result =
+            sqlx::query("UPDATE peers SET status = $1, last_seen = now() WHERE peer_id = $2")
+                .bind(status)
+                .bind(peer_id)
+                .execute(&self.pool)
+                .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn admission_mode(&self) -> StoreResult<AdmissionMode> {
+        let row = sqlx::query("SELECT admission FROM node_config WHERE id = 1")
+            .fetch_one(&self.pool)
+            .await?;
+        row.get::<String, _>("admission").parse()
+    }
+
+    async fn set_admission_mode(&self, mode: AdmissionMode) -> StoreResult<()> {
+        sqlx::query("UPDATE node_config SET admission = $1 WHERE id = 1")
+            .bind(mode.as_str())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn record_join_request(
+        &self,
+        peer_id: &str,
+        node_id: &str,
+        name: &str,
+    ) -> StoreResult<JoinRequest> {
+        let row = sqlx::query(
+            r#"
+            INSERT INTO join_requests (peer_id, node_id, name, status, requested_at)
+            VALUES ($1, $2, $3, 'pending', now())
+            ON CONFLICT (peer_id) DO UPDATE SET
+                node_id = excluded.node_id,
+                name = excluded.name
+            RETURNING peer_id, node_id, name, status,
+                      to_char(requested_at AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS') AS requested_at
+            "#,
+        )
+        .bind(peer_id)
+        .bind(node_id)
+        .bind(name)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(join_request_from_row(&row))
+    }
+
+    async fn join_requests(&self) -> StoreResult<Vec<JoinRequest>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT peer_id, node_id, name, status,
+                   to_char(requested_at AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS') AS requested_at
+            FROM join_requests ORDER BY requested_at, peer_id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.iter().map(join_request_from_row).collect())
+    }
+
+    async fn set_join_request_status(&self, peer_id: &str, status: &str) -> StoreResult<bool> {
+        let result = sqlx::query("UPDATE join_requests SET status = $1 WHERE peer_id = $2")
+            .bind(status)
+            .bind(peer_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn remove_join_request(&self, peer_id: &str) -> StoreResult<bool> {
+        let result = sqlx::query("DELETE FROM join_requests WHERE peer_id = $1")
+            .bind(peer_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn peer_role(&self, peer_id: &str) -> StoreResult<Option<String>> {
+        let row = sqlx::query("SELECT role FROM peers WHERE peer_id = $1")
+            .bind(peer_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|row| row.get("role")))
+    }
+
+    async fn peer_is_banned(&self, peer_id: &str) -> StoreResult<bool> {
+        Ok(self.peer_ban(peer_id).await?.is_some())
+    }
+
+    async fn peer_ban(&self, peer_id: &str) -> StoreResult<Option<PeerBan>> {
+        let row = sqlx::query(
+            r#"
+            SELECT ban_reason, banned_until FROM peers
+            WHERE peer_id = $1 AND banned AND (banned_until IS NULL OR banned_until > $2)
+            "#,
+        )
+        .bind(peer_id)
+        .bind(chrono::Utc::now().timestamp())
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|row| PeerBan {
+            reason: row.get("ban_reason"),
+            until: row.get("banned_until"),
+        }))
+    }
+
+    async fn banned_peers(&self) -> StoreResult<Vec<String>> {
+        let rows = sqlx::query(
+            "SELECT peer_id FROM peers WHERE banned AND (banned_until IS NULL OR banned_until > $1) ORDER BY peer_id",
+        )
+        .bind(chrono::Utc::now().timestamp())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(|row| row.get("peer_id")).collect())
+    }
+
+    async fn peers_by_node_uuid(&self, node_uuid: &str) -> StoreResult<Vec<String>> {
+        let rows = sqlx::query("SELECT peer_id FROM peers WHERE node_uuid = $1 ORDER BY peer_id")
+            .bind(node_uuid)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(|row| row.get("peer_id")).collect())
+    }
+
+    async fn upsert_service_registry(&self, service: ServiceRegistryItem) -> StoreResult<()> {
+        let tags = serde_json::to_string(&service.tags)?;
+        let uuid = service.uuid.clone();
+        let mut tx = self.begin_registry_change().await?;
+        sqlx::query(
+            r#"
+            INSERT INTO service_registry (uuid, name, type, port, description, provider_peer, provider_addr, online, announced, updated_at, tags)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, TRUE, now(), $9)
+            ON CONFLICT (uuid) DO UPDATE SET
+                name = excluded.name,
+                type = excluded.type,
+                port = excluded.port,
+                description = excluded.description,
+                provider_peer = excluded.provider_peer,
+                provider_addr = excluded.provider_addr,
+                online = excluded.online,
+                updated_at = now(),
+                tags = excluded.tags
+            "#,
+        )
+        .bind(service.uuid)
+        .bind(service.name)
+        .bind(service.r#type)
+        .bind(service.port as i64)
+        .bind(service.description)
+        .bind(service.provider_peer)
+        .bind(service.provider_addr)
+        .bind(service.online)
+        .bind(tags)
+        .execute(&mut *tx)
+        .await?;
+        bump_registry_revision(&mut tx, &uuid).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn remove_service_registry(&self, uuid: &str) -> StoreResult<bool> {
+        let mut tx = self.begin_registry_change().await?;
+        let result = sqlx::query("DELETE FROM service_registry WHERE uuid = $1")
+            .bind(uuid)
+            .execute(&mut *tx)
+            .await?;
+        if result.rows_affected() > 0 {
+            bump_registry_revision(&mut tx, uuid).await?;
+        }
+        tx.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn remove_provider_services(&self, provider_peer: &str) -> StoreResult<Vec<String>> {
+        let mut tx = self.begin_registry_change().await?;
+        let uuids: Vec<String> =
+            sqlx::query("DELETE FROM service_registry WHERE provider_peer = $1 RETURNING uuid")
+                .bind(provider_peer)
+                .fetch_all(&mut *tx)
+                .await?
+                .into_iter()
+                .map(|row| row.get("uuid"))
+                .collect();
+        for uuid in &uuids {
+            bump_registry_revision(&mut tx, uuid).await?;
+        }
+        tx.commit().await?;
+        Ok(uuids)
+    }
+
+    async fn set_provider_online(
+        &self,
+        provider_peer: &str,
+        online: bool,
+    ) -> StoreResult<Vec<String>> {
+        let mut tx = self.begin_registry_change().await?;
+        let uuids: Vec<String> = sqlx::query(
+            r#"
+            UPDATE service_registry SET online = $1, updated_at = now()
+            WHERE provider_peer = $2 AND online <> $1
+            RETURNING uuid
+            "#,
+        )
+        .bind(online)
+        .bind(provider_peer)
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|row| row.get("uuid"))
+        .collect();
+        for uuid in &uuids {
+            bump_registry_revision(&mut tx, uuid).await?;
+        }
+        tx.commit().await?;
+        Ok(uuids)
+    }
+
+    async fn provider_service_count(&self, provider_peer: &str) -> StoreResult<u64> {
+        let row =
+            sqlx::query("SELECT COUNT(*) as count FROM service_registry WHERE provider_peer = $1")
+                .bind(provider_peer)
+                .fetch_one(&self.pool)
+                .await?;
+        let count: i64 = row.get("count");
+        Ok(count as u64)
+    }
+
+    async fn list_service_registry(&self) -> StoreResult<Vec<ServiceRegistryItem>> {
+        let rows = sqlx::query(&format!(
+            "{} WHERE announced AND online ORDER BY uuid",
+            REGISTRY_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(registry_item_from_row).collect())
+    }
+
+    async fn search_service_registry(
+        &self,
+        query: &ServiceQuery,
+    ) -> StoreResult<ServicePage<ServiceRegistryItem>> {
+        let rows = sqlx::query(&format!(
+            "{} WHERE announced AND (online OR NOT $1) ORDER BY name, uuid",
+            REGISTRY_COLUMNS
+        ))
+        .bind(query.online_only)
+        .fetch_all(&self.pool)
+        .await?;
+        let matched: Vec<ServiceRegistryItem> = rows
+            .into_iter()
+            .map(registry_item_from_row)
+            .filter(|item| {
+                query.matches(
+                    &item.name,
+                    &item.description,
+                    &item.r#type,
+                    &item.provider_peer,
+                    &item.tags,
+                )
+            })
+            .collect();
+        Ok(query.paginate(matched, |item| (item.name.as_str(), item.uuid.as_str())))
+    }
+
+    async fn registry_changes_since(
+        &self,
+        since_revision: u64,
+        limit: u32,
+    ) -> StoreResult<RegistryChanges> {
+        let row = sqlx::query(
+            "SELECT COALESCE(MAX(revision), 0) AS revision FROM service_registry_revisions",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        let current = row.get::<i64, _>("revision") as u64;
+        let reset = since_revision > current;
+        let since = if reset { 0 } else { since_revision };
+
+        let rows = sqlx::query(
+            r#"
+            SELECT v.uuid, v.revision,
+                   r.name, r.type, r.port, r.description, r.provider_peer, r.provider_addr,
+                   r.online, r.announced, r.tags
+            FROM service_registry_revisions v
+            LEFT JOIN service_registry r ON r.uuid = v.uuid
+            WHERE v.revision > $1
+            ORDER BY v.revision
+            LIMIT $2
+            "#,
+        )
+        .bind(since as i64)
+        .bind(limit as i64 + 1)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let more = rows.len() > limit as usize;
+        let mut changes = RegistryChanges {
+            revision: since,
+            reset,
+            more,
+            ..Default::default()
+        };
+        for row in rows.into_iter().take(limit as usize) {
+            changes.revision = row.get::<i64, _>("revision") as u64;
+            let uuid: String = row.get("uuid");
+            let visible = row.get::<Option<bool>, _>("online") == Some(true)
+                && row.get::<Option<bool>, _>("announced") == Some(true);
+            if visible {
+                changes.upserted.push(registry_item_from_row(row));
+            } else {
+                changes.removed.push(uuid);
+            }
+        }
+        if !more {
+            changes.revision = changes.revision.max(current);
+        }
+        Ok(changes)
+    }
+
+    async fn resolve_service_registry(
+        &self,
+        uuid: &str,
+    ) -> StoreResult<Option<ServiceRegistryItem>> {
+        let row = sqlx::query(&format!("{} WHERE uuid = $1", REGISTRY_COLUMNS))
+            .bind(uuid)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(registry_item_from_row))
+    }
+
+    async fn record_subscription(
+        &self,
+        service_uuid: &str,
+        subscriber_peer: &str,
+    ) -> StoreResult<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO service_subscriptions (service_uuid, subscriber_peer, created_at)
+            VALUES ($1, $2, now())
+            ON CONFLICT (service_uuid, subscriber_peer) DO NOTHING
+            "#,
+        )
+        .bind(service_uuid)
+        .bind(subscriber_peer)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn service_subscribers(&self, service_uuid: &str) -> StoreResult<Vec<String>> {
+        let rows = sqlx::query(
+            "SELECT subscriber_peer FROM service_subscriptions WHERE service_uuid = $1 ORDER BY created_at",
+        )
+        .bind(service_uuid)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| row.get("subscriber_peer"))
+            .collect())
+    }
+
+    async fn hand_over_peer(&self, old_peer: &str, new_peer: &str) -> StoreResult<u64> {
+        let mut tx = self.begin_registry_change().await?;
+        let uuids: Vec<String> = sqlx::query(
+            "UPDATE service_registry SET provider_peer = $1, updated_at = now() WHERE provider_peer = $2 RETURNING uuid",
+        )
+        .bind(new_peer)
+        .bind(old_peer)
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|row| row.get("uuid"))
+        .collect();
+        for uuid in &uuids {
+            bump_registry_revision(&mut tx, uuid).await?;
+        }
+        // Keep the original subscription time so subscriber order is unchanged
+        sqlx::query(
+            r#"
+            INSERT INTO service_subscriptions (service_uuid, subscriber_peer, created_at)
+            SELECT service_uuid, $1, created_at FROM service_subscriptions WHERE subscriber_peer = $2
+            ON CONFLICT (service_uuid, subscriber_peer) DO NOTHING
+            "#,
+        )
+        .bind(new_peer)
+        .bind(old_peer)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM service_subscriptions WHERE subscriber_peer = $1")
+            .bind(old_peer)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM peers WHERE peer_id = $1")
+            .bind(old_peer)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(uuids.len() as u64)
+    }
+
+    async fn redeem_invite(&self, invite_id: &str, peer_id: &str) -> StoreResult<bool> {
+        sqlx::query(
+            "INSERT INTO invite_redemptions (invite_id, peer_id, redeemed_at) VALUES ($1, $2, now()) ON CONFLICT (invite_id) DO NOTHING",
+        )
+        .bind(invite_id)
+        .bind(peer_id)
+        .execute(&self.pool)
+        .await?;
+        let row = sqlx::query("SELECT peer_id FROM invite_redemptions WHERE invite_id = $1")
+            .bind(invite_id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(row.get::<String, _>("peer_id") == peer_id)
+    }
+
+    async fn invite_redeemed_by(&self, invite_id: &str) -> StoreResult<Option<String>> {
+        let row = sqlx::query("SELECT peer_id FROM invite_redemptions WHERE invite_id = $1")
+            .bind(invite_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|row| row.get("peer_id")))
+    }
+
+    async fn secure_routes(&self) -> StoreResult<Vec<SecureRoute>> {
+        let rows = sqlx::query(&format!("{} ORDER BY created_at", ROUTE_COLUMNS))
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(route_from_row).collect())
+    }
+
+    async fn add_secure_route(&self, route: SecureRoute) -> StoreResult<()> {
+        let relay_json = serde_json::to_string(&route.relay_peers)?;
+        sqlx::query(
+            r#"
+            INSERT INTO secure_routes (id, subscription_id, relay_peers, local_port, status, created_at)
+            VALUES ($1, $2, $3, $4, $5, now())
+            "#,
+        )
+        .bind(route.id)
+        .bind(route.subscription_id)
+        .bind(relay_json)
+        .bind(route.local_port as i64)
+        .bind(route.status)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn remove_secure_route(&self, id: &str) -> StoreResult<bool> {
+        let result = sqlx::query("DELETE FROM secure_routes WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn update_secure_route_status(&self, id: &str, status: &str) -> StoreResult<bool> {
+        let result = sqlx::query("UPDATE secure_routes SET status = $1 WHERE id = $2")
+            .bind(status)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn find_secure_route(&self, id: &str) -> StoreResult<Option<SecureRoute>> {
+        let row = sqlx::query(&format!("{} WHERE id = $1", ROUTE_COLUMNS))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(route_from_row))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // These need a PostgreSQL server and are ignored by default; run them with
+    // PORTA_TEST_POSTGRES_URL=postgres://... cargo test -p porta-backend -- --ignored
+
+    /// Connect to `PORTA_TEST_POSTGRES_URL` inside a fresh schema.
+    async fn test_store() -> (Arc<PostgresStore>, PgConnectOptions) {
+        let url = std::env::var("PORTA_TEST_POSTGRES_URL")
+            .expect("PORTA_TEST_POSTGRES_URL must point at a PostgreSQL server");
+        let schema = format!("porta_test_{}", Uuid::new_v4().simple());
+        let admin = PgPool::connect(&url).await.unwrap();
+        sqlx::query(&format!("CREATE SCHEMA {}", schema))
+            .execute(&admin)
+            .await
+            .unwrap();
+        let options = url
+            .parse::<PgConnectOptions>()
+            .unwrap()
+            .options([("search_path", schema.as_str())]);
+        let store = PostgresStore::connect_with(options.clone()).await.unwrap();
+        (store, options)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs PORTA_TEST_POSTGRES_URL"]
+    async fn should_migrate_once_and_share_state_between_replicas() {
+        let (first, options) = test_store().await;
+        let second = PostgresStore::connect_with(options).await.unwrap();
+        assert_eq!(schema_version(&second.pool).await.unwrap(), LATEST_VERSION);
+        assert_eq!(
+            first.node_info().await.unwrap().uuid,
+            second.node_info().await.unwrap().uuid
+        );
+
+        first
+            .upsert_peer("peer-a", "node-a", "", "", "edge", "online")
+            .await
+            .unwrap();
+        assert!(second
+            .set_node_ban("peer-a", true, None, None)
+            .await
+            .unwrap());
+        assert!(first.peer_is_banned("peer-a").await.unwrap());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs PORTA_TEST_POSTGRES_URL"]
+    async fn should_hand_out_registry_revisions_across_replicas() {
+        let (first, options) = test_store().await;
+        let second = PostgresStore::connect_with(options).await.unwrap();
+        let service = |uuid: &str| ServiceRegistryItem {
+            uuid: uuid.into(),
+            name: uuid.into(),
+            r#type: "TCP".into(),
+            port: 22,
+            description: String::new(),
+            provider_peer: "peer-a".into(),
+            provider_addr: "/ip4/127.0.0.1/tcp/4001".into(),
+            online: true,
+            tags: vec!["ssh".into()],
+        };
+        first
+            .upsert_service_registry(service("svc-a"))
+            .await
+            .unwrap();
+        second
+            .upsert_service_registry(service("svc-b"))
+            .await
+            .unwrap();
+        first.remove_service_registry("svc-a").await.unwrap();
+
+        let changes = second.registry_changes_since(0, 10).await.unwrap();
+        assert_eq!(changes.revision, 3);
+        assert_eq!(changes.removed, vec!["svc-a".to_string()]);
+        assert_eq!(changes.upserted.len(), 1);
+        assert_eq!(changes.upserted[0].tags, vec!["ssh".to_string()]);
+        assert!(first
+            .registry_changes_since(3, 10)
+            .await
+            .unwrap()
+            .upserted
+            .is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs PORTA_TEST_POSTGRES_URL"]
+    async fn should_make_registry_revisions_visible_in_order() {
+        let (first, options) = test_store().await;
+        let second = PostgresStore::connect_with(options).await.unwrap();
+
+        // A writer that drew revision 1 but has not committed yet
+        let mut slow = first.begin_registry_change().await.unwrap();
+        bump_registry_revision(&mut slow, "svc-slow").await.unwrap();
+        let writer = tokio::spawn(async move {
+            second
+                .upsert_service_registry(ServiceRegistryItem {
+                    uuid: "svc-fast".into(),
+                    name: "fast".into(),
+                    r#type: "TCP".into(),
+                    port: 22,
+                    description: String::new(),
+                    provider_peer: "peer-a".into(),
+                    provider_addr: "/ip4/127.0.0.1/tcp/4001".into(),
+                    online: true,
+                    tags: Vec::new(),
+                })
+                .await
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        // Revision 2 must not show up while 1 is still pending
+        assert!(!writer.is_finished());
+        assert_eq!(
+            first.registry_changes_since(0, 10).await.unwrap().revision,
+            0
+        );
+
+        slow.commit().await.unwrap();
+        writer.await.unwrap().unwrap();
+        let changes = first.registry_changes_since(0, 10).await.unwrap();
+        assert_eq!(changes.revision, 2);
+        assert_eq!(changes.removed, vec!["svc-slow".to_string()]);
+        assert_eq!(changes.upserted[0].uuid, "svc-fast");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs PORTA_TEST_POSTGRES_URL"]
+    async fn should_expire_only_idle_connected_sessions() {
+        let (store, _) = test_store().await;
+        for (id, state) in [("s-1", "connected"), ("s-2", "closed")] {
+            store
+                .upsert_session(SessionInfo {
+                    session_id: id.into(),
+                    service_id: "svc-a".into(),
+                    local_port: 2222,
+                    remote_peer: "peer-a".into(),
+                    state: state.into(),
+                    created_at: None,
+                    last_active: None,
+                })
+                .await
+                .unwrap();
+        }
+        sqlx::query("UPDATE sessions SET last_active = now() - INTERVAL '2 hours'")
+            .execute(&store.pool)
+            .await
+            .unwrap();
+        let expired = store.cleanup_expired_sessions(30).await.unwrap();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].session_id, "s-1");
+        let remaining = store.sessions().await.unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].session_id, "s-2");
+        assert_eq!(
+            remaining[0].created_at.as_deref().map(str::len),
+            Some("2024-01-01 00:00:00".len())
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs PORTA_TEST_POSTGRES_URL"]
+    async fn should_refuse_newer_schema() {
+        let (store, options) = test_store().await;
+        sqlx::query(
+            "INSERT INTO schema_version (version, name, applied_at) VALUES ($1, 'future', now())",
+        )
+        .bind(LATEST_VERSION + 1)
+        .execute(&store.pool)
+        .await
+        .unwrap();
+        let err = PostgresStore::connect_with(options)
+            .await
+            .err()
+            .expect("newer schema must be refused");
+        assert!(err.to_string().contains("请升级 Porta"));
+    }
+}
@@ -0,0 +1,325 @@
+//! Declarative provisioning.
+//!
+//! Published services, communities and proxy settings declared in the server
+//! config file are written to the store and flagged as managed, so the API
+//! treats them as read-only. Reconciling is idempotent: it runs at startup and
+//! on every reload, and only touches entries that differ from the file.
+
+use std::collections::HashSet;
+
+use anyhow::{anyhow, Result};
+use libp2p::Multiaddr;
+
+use crate::{
+    app::{extract_peer_id, fallback_addrs},
+    models::{
+        CommunityAddRequest, CommunityProvision, Provisioning, PublishProvision, PublishRequest,
+        PublishedService, ReloadReport,
+    },
+    state::AppState,
+};
+
+/// Reject declarations that could only be applied partially.
+pub fn validate(provisioning: &Provisioning) -> Result<()> {
+    let mut ids = HashSet::new();
+    for item in &provisioning.publish {
+        if item.id.trim().is_empty() || item.name.trim().is_empty() || item.r#type.is_empty() {
+            return Err(anyhow!("[[publish]] 缺少必填字段 id/name/type"));
+        }
+        if !ids.insert(item.id.as_str()) {
+            return Err(anyhow!("[[publish]] ID 重复: {}", item.id));
+        }
+    }
+    let mut ids = HashSet::new();
+    for item in &provisioning.community {
+        if item.id.trim().is_empty() || item.name.trim().is_empty() {
+            return Err(anyhow!("[[community]] 缺少必填字段 id/name"));
+        }
+        if !ids.insert(item.id.as_str()) {
+            return Err(anyhow!("[[community]] ID 重复: {}", item.id));
+        }
+        community_addrs(item)?;
+    }
+    Ok(())
+}
+
+/// The peer ID of a declared community and its normalised fallback
+/// addresses.
+fn community_addrs(item: &CommunityProvision) -> Result<(String, Vec<String>)> {
+    let addr: Multiaddr = item
+        .multiaddr
+        .parse()
+        .map_err(|err| anyhow!("社区 {} 的 multiaddr 无效: {}", item.id, err))?;
+    let peer_id = extract_peer_id(&addr)
+        .ok_or_else(|| anyhow!("社区 {} 的 multiaddr 缺少 /p2p/peerId", item.id))?;
+    let multiaddrs = fallback_addrs(&item.multiaddr, &item.multiaddrs, &peer_id)
+        .map_err(|err| anyhow!("社区 {} 的 multiaddrs 无效: {}", item.id, err))?;
+    Ok((peer_id.to_string(), multiaddrs))
+}
+
+/// Bring the store in line with the declared entries. Store changes are made
+/// before returning; joining communities and announcing services happen in
+/// the background. The report lists the sections that changed.
+pub async fn reconcile(state: &AppState, provisioning: &Provisioning) -> Result<ReloadReport> {
+    validate(provisioning)?;
+    let mut report = ReloadReport::default();
+
+    let to_join = reconcile_communities(state, &provisioning.community).await?;
+    if let Some(to_join) = &to_join {
+        report.applied.push("community".into());
+        tracing::info!("配置社区已同步, 待加入: {:?}", to_join);
+    }
+    let services = reconcile_published(state, &provisioning.publish).await?;
+    if let Some((announce, withdraw)) = &services {
+        report.applied.push("publish".into());
+        tracing::info!(
+            "配置发布服务已同步: 更新 {} 个, 移除 {} 个",
+            announce.len(),
+            withdraw.len()
+        );
+    }
+    let proxy_enabled = reconcile_proxy(state, provisioning, &mut report).await?;
+    if to_join.is_none() && services.is_none() && proxy_enabled.is_none() {
+        return Ok(report);
+    }
+
+    let app = state.app.clone();
+    tokio::spawn(async move {
+        for id in to_join.unwrap_or_default() {
+            if let Err(err) = app.connect_community(&id).await {
+                tracing::warn!("加入配置社区 {} 失败, 将在保活时重试: {}", id, err);
+            }
+        }
+        let (announce, withdraw) = services.unwrap_or_default();
+        for service in announce {
+            if let Err(err) = app.announce_service(&service).await {
+                tracing::warn!("发布配置服务 {} 失败: {}", service.id, err);
+            }
+        }
+        for id in withdraw {
+            if let Err(err) = app.withdraw_service(&id).await {
+                tracing::warn!("下架配置服务 {} 失败: {}", id, err);
+            }
+        }
+        let result = match proxy_enabled {
+            Some(true) => app.publish_proxy_service().await,
+            Some(false) => app.unpublish_proxy_service().await,
+            None => Ok(()),
+        };
+        if let Err(err) = result {
+            tracing::warn!("同步代理服务发布状态失败: {}", err);
+        }
+    });
+    Ok(report)
+}
+
+/// Returns the communities to join, or `None` when nothing changed.
+async fn reconcile_communities(
+    state: &AppState,
+    declared: &[CommunityProvision],
+) -> Result<Option<Vec<String>>> {
+    let existing = state.store.communities().await?;
+    let mut changed = false;
+    let mut to_join = Vec::new();
+    for item in declared {
+        let (peer_id, multiaddrs) = community_addrs(item)?;
+        let current = existing.iter().find(|c| c.id == item.id);
+        let up_to_date = current.is_some_and(|c| {
+            c.managed
+                && c.name == item.name
+                && c.description == item.description
+                && c.multiaddr.as_deref() == Some(item.multiaddr.as_str())
+                && c.multiaddrs == multiaddrs
+        });
+        if !up_to_date {
+            state
+                .store
+                .upsert_community(CommunityAddRequest {
+                    id: Some(item.id.clone()),
+                    name: item.name.clone(),
+                    description: item.description.clone(),
+                    multiaddr: Some(item.multiaddr.clone()),
+                    multiaddrs,
+                    peer_id: Some(peer_id),
+                    invite: None,
+                })
+                .await?;
+            state.store.set_community_managed(&item.id, true).await?;
+            changed = true;
+        }
+        if item.join && !current.is_some_and(|c| c.joined) {
+            // Marked joined up front so the keep-alive loop retries if the
+            // first attempt fails
+            state.store.connect_community(&item.id).await?;
+            to_join.push(item.id.clone());
+            changed = true;
+        }
+    }
+    for stale in existing
+        .iter()
+        .filter(|c| c.managed && !declared.iter().any(|item| item.id == c.id))
+    {
+        state.store.remove_community(&stale.id).await?;
+        changed = true;
+    }
+    Ok(changed.then_some(to_join))
+}
+
+/// Returns the services to announce and the IDs to withdraw, or `None` when
+/// nothing changed.
+async fn reconcile_published(
+    state: &AppState,
+    declared: &[PublishProvision],
+) -> Result<Option<(Vec<PublishedService>, Vec<String>)>> {
+    let existing = state.store.published_services().await?;
+    let mut announce = Vec::new();
+    let mut withdraw = Vec::new();
+    for item in declared {
+        let req = PublishRequest::from(item);
+        let up_to_date = existing.iter().any(|s| {
+            s.id == item.id
+                && s.managed
+                && s.status == "在线"
+                && s.name == req.name
+                && s.r#type == req.r#type
+                && s.port == req.port
+                && s.summary == req.summary
+                && s.tags == req.tags
+        });
+        if !up_to_date {
+            let mut saved = state.store.publish_service(req).await?;
+            state.store.set_published_managed(&saved.id, true).await?;
+            saved.managed = true;
+            announce.push(saved);
+        }
+    }
+    for stale in existing
+        .iter()
+        .filter(|s| s.managed && !declared.iter().any(|item| item.id == s.id))
+    {
+        state.store.remove_published(&stale.id).await?;
+        withdraw.push(stale.id.clone());
+    }
+    if announce.is_empty() && withdraw.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some((announce, withdraw)))
+    }
+}
+
+/// Returns the new enabled state when the proxy was started or stopped.
+async fn reconcile_proxy(
+    state: &AppState,
+    provisioning: &Provisioning,
+    report: &mut ReloadReport,
+) -> Result<Option<bool>> {
+    let status = state.store.proxy_status().await?;
+    let Some(proxy) = &provisioning.proxy else {
+        if status.managed {
+            state.store.set_proxy_managed(false).await?;
+            report.applied.push("proxy".into());
+        }
+        return Ok(None);
+    };
+    if !status.managed {
+        state.store.set_proxy_managed(true).await?;
+        report.applied.push("proxy".into());
+    }
+    if let Some(port) = proxy.listen_port.filter(|port| *port != status.listen_port) {
+        // The listener is bound once at startup
+        state.store.set_proxy_listen_port(port).await?;
+        report.restart_required.push("proxy.listen_port".into());
+    }
+    if proxy.enabled == status.enabled {
+        return Ok(None);
+    }
+    if proxy.enabled {
+        state.proxy_server.start().await?;
+    } else {
+        state.proxy_server.stop().await?;
+    }
+    state.store.set_proxy_enabled(proxy.enabled).await?;
+    if !report.applied.iter().any(|field| field == "proxy") {
+        report.applied.push("proxy".into());
+    }
+    Ok(Some(proxy.enabled))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::BackendConfig,
+        models::{Provisioning, ProxyProvision},
+    };
+
+    fn declared(community_peer: &libp2p::PeerId) -> Provisioning {
+        Provisioning {
+            publish: vec![PublishProvision {
+                id: "pub-web".into(),
+                name: "Web".into(),
+                r#type: "HTTP".into(),
+                port: 8080,
+                summary: "站点".into(),
+                tags: vec![" web ".into(), "Web".into()],
+            }],
+            community: vec![CommunityProvision {
+                id: "dev".into(),
+                name: "Dev".into(),
+                description: "开发社区".into(),
+                multiaddr: format!("/ip4/127.0.0.1/tcp/4001/p2p/{}", community_peer),
+                multiaddrs: Vec::new(),
+                join: false,
+            }],
+            proxy: Some(ProxyProvision {
+                enabled: false,
+                listen_port: Some(0),
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_reconcile_declared_entries() {
+        let community_peer = libp2p::PeerId::random();
+        let config = BackendConfig {
+            db_path: ":memory:".into(),
+            provisioning: declared(&community_peer),
+            ..Default::default()
+        };
+        let state = AppState::with_config(config.clone()).await.unwrap();
+
+        let published = state.store.published_services().await.unwrap();
+        assert_eq!(published.len(), 1);
+        assert!(published[0].managed);
+        assert_eq!(published[0].tags, vec!["web"]);
+        assert!(state.app.unpublish_service("pub-web").await.is_err());
+        let community = state.store.community_by_id("dev").await.unwrap().unwrap();
+        assert!(community.managed && !community.joined);
+        assert!(state.app.remove_community("dev").await.is_err());
+        let proxy = state.store.proxy_status().await.unwrap();
+        assert!(proxy.managed && !proxy.enabled);
+
+        // Unchanged declarations are a no-op
+        let report = reconcile(&state, &declared(&community_peer)).await.unwrap();
+        assert!(report.applied.is_empty());
+
+        let report = state
+            .reload(BackendConfig {
+                provisioning: Provisioning::default(),
+                ..config
+            })
+            .await
+            .unwrap();
+        assert_eq!(report.applied, vec!["community", "publish", "proxy"]);
+        assert!(state.store.published_services().await.unwrap().is_empty());
+        assert!(state.store.community_by_id("dev").await.unwrap().is_none());
+        assert!(!state.store.proxy_status().await.unwrap().managed);
+    }
+
+    #[test]
+    fn should_reject_community_without_peer_id() {
+        let mut provisioning = declared(&libp2p::PeerId::random());
+        provisioning.community[0].multiaddr = "/ip4/127.0.0.1/tcp/4001".into();
+        assert!(validate(&provisioning).is_err());
+    }
+}
@@ -0,0 +1,35 @@
+use axum::{extract::State, routing::post, Json, Router};
+
+use crate::{
+    backup,
+    models::{BackupExportRequest, BackupImportRequest},
+    resp,
+    state::AppState,
+};
+
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/porta/backup/export", post(export_backup))
+        .route("/porta/backup/import", post(import_backup))
+        .with_state(state)
+}
+
+async fn export_backup(
+    State(state): State<AppState>,
+    Json(req): Json<BackupExportRequest>,
+) -> impl axum::response::IntoResponse {
+    match backup::export(&state, req.passphrase.as_deref()).await {
+        Ok(archive) => resp::ok(Some(archive)),
+        Err(err) => resp::err(&format!("导出备份失败: {}", err)),
+    }
+}
+
+async fn import_backup(
+    State(state): State<AppState>,
+    Json(req): Json<BackupImportRequest>,
+) -> impl axum::response::IntoResponse {
+    match backup::import(&state, req).await {
+        Ok(report) => resp::ok(Some(report)),
+        Err(err) => resp::err(&format!("导入备份失败: {}", err)),
+    }
+}
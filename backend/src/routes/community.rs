@@ -1,9 +1,20 @@
 use crate::{
-    models::{CommunityAddRequest, ToggleRequest},
+    models::{
+        AdmissionUpdate, CommunityAddRequest, InviteCreateRequest, NodeBanRequest, ServiceChange,
+        ToggleRequest,
+    },
     resp,
     state::AppState,
 };
-use axum::{extract::State, routing::get, routing::post, Json, Router};
+use axum::{
+    extract::{Query, State},
+    http::header,
+    response::IntoResponse,
+    routing::get,
+    routing::post,
+    Json, Router,
+};
+use serde::Deserialize;
 
 pub fn router(state: AppState) -> Router {
     Router::new()
@@ -11,7 +22,16 @@ pub fn router(state: AppState) -> Router {
         .route("/porta/community/add", post(add_community))
         .route("/porta/community/remove", post(remove_community))
         .route("/porta/community/connect", post(connect_community))
+        .route("/porta/community/invite", post(create_invite))
+        .route("/porta/community/invite/qr", get(invite_qr))
+        .route(
+            "/porta/community/admission",
+            get(get_admission).post(set_admission),
+        )
         .route("/porta/community/node/list", get(get_nodes))
+        .route("/porta/community/node/requests", get(get_join_requests))
+        .route("/porta/community/node/approve", post(approve_node))
+        .route("/porta/community/node/reject", post(reject_node))
         .route("/porta/community/node/ban", post(ban_node))
         .route("/porta/community/node/unban", post(unban_node))
         .route("/porta/community/service/list", get(get_services))
@@ -21,7 +41,7 @@ pub fn router(state: AppState) -> Router {
 }
 
 async fn get_communities(State(state): State<AppState>) -> impl axum::response::IntoResponse {
-    match state.store.communities().await {
+    match state.app.communities().await {
         Ok(list) => resp::ok(Some(list)),
         Err(err) => resp::err(&format!("读取社区列表失败: {}", err)),
     }
@@ -34,6 +54,50 @@ async fn get_nodes(State(state): State<AppState>) -> impl axum::response::IntoRe
     }
 }
 
+async fn get_admission(State(state): State<AppState>) -> impl axum::response::IntoResponse {
+    match state.store.admission_mode().await {
+        Ok(mode) => resp::ok(Some(AdmissionUpdate { mode })),
+        Err(err) => resp::err(&format!("读取准入策略失败: {}", err)),
+    }
+}
+
+async fn set_admission(
+    State(state): State<AppState>,
+    Json(req): Json<AdmissionUpdate>,
+) -> impl axum::response::IntoResponse {
+    match state.store.set_admission_mode(req.mode).await {
+        Ok(()) => resp::ok(Some(req)),
+        Err(err) => resp::err(&format!("设置准入策略失败: {}", err)),
+    }
+}
+
+async fn get_join_requests(State(state): State<AppState>) -> impl axum::response::IntoResponse {
+    match state.store.join_requests().await {
+        Ok(list) => resp::ok(Some(list)),
+        Err(err) => resp::err(&format!("读取加入申请失败: {}", err)),
+    }
+}
+
+async fn approve_node(
+    State(state): State<AppState>,
+    Json(req): Json<ToggleRequest>,
+) -> impl axum::response::IntoResponse {
+    match state.app.approve_join_request(&req.id).await {
+        Ok(()) => resp::ok::<()>(None),
+        Err(err) => resp::err(&format!("批准加入失败: {}", err)),
+    }
+}
+
+async fn reject_node(
+    State(state): State<AppState>,
+    Json(req): Json<ToggleRequest>,
+) -> impl axum::response::IntoResponse {
+    match state.app.reject_join_request(&req.id).await {
+        Ok(()) => resp::ok::<()>(None),
+        Err(err) => resp::err(&format!("拒绝加入失败: {}", err)),
+    }
+}
+
 async fn get_services(State(state): State<AppState>) -> impl axum::response::IntoResponse {
     match state.store.community_services().await {
         Ok(list) => resp::ok(Some(list)),
@@ -51,6 +115,34 @@ async fn add_community(
     }
 }
 
+async fn create_invite(
+    State(state): State<AppState>,
+    Json(req): Json<InviteCreateRequest>,
+) -> impl axum::response::IntoResponse {
+    match state.app.create_invite(req).await {
+        Ok(invite) => resp::ok(Some(invite)),
+        Err(err) => resp::err(&format!("生成邀请失败: {}", err)),
+    }
+}
+
+#[derive(Deserialize)]
+struct InviteQrQuery {
+    token: String,
+    /// `svg` (default) or `png`
+    format: Option<String>,
+}
+
+async fn invite_qr(Query(query): Query<InviteQrQuery>) -> axum::response::Response {
+    if let Err(err) = crate::invite::Invite::decode(&query.token) {
+        return resp::err::<()>(&format!("生成二维码失败: {}", err)).into_response();
+    }
+    let format = query.format.as_deref().unwrap_or("svg");
+    match crate::invite::render_qr(query.token.trim(), format) {
+        Ok((content_type, body)) => ([(header::CONTENT_TYPE, content_type)], body).into_response(),
+        Err(err) => resp::err::<()>(&err.to_string()).into_response(),
+    }
+}
+
 async fn remove_community(
     State(state): State<AppState>,
     Json(req): Json<ToggleRequest>,
@@ -81,22 +173,20 @@ async fn connect_community(
 
 async fn ban_node(
     State(state): State<AppState>,
-    Json(req): Json<ToggleRequest>,
+    Json(req): Json<NodeBanRequest>,
 ) -> impl axum::response::IntoResponse {
-    match state.store.set_node_ban(&req.id, true).await {
-        Ok(true) => resp::ok::<()>(None),
-        Ok(false) => resp::err("未找到节点"),
+    match state.app.ban_node(req).await {
+        Ok(peers) => resp::ok(Some(peers)),
         Err(err) => resp::err(&format!("封禁节点失败: {}", err)),
     }
 }
 
 async fn unban_node(
     State(state): State<AppState>,
-    Json(req): Json<ToggleRequest>,
+    Json(req): Json<NodeBanRequest>,
 ) -> impl axum::response::IntoResponse {
-    match state.store.set_node_ban(&req.id, false).await {
-        Ok(true) => resp::ok::<()>(None),
-        Ok(false) => resp::err("未找到节点"),
+    match state.app.unban_node(req).await {
+        Ok(peers) => resp::ok(Some(peers)),
         Err(err) => resp::err(&format!("解封节点失败: {}", err)),
     }
 }
@@ -106,7 +196,17 @@ async fn announce_service(
     Json(req): Json<ToggleRequest>,
 ) -> impl axum::response::IntoResponse {
     match state.store.set_service_announced(&req.id, true).await {
-        Ok(true) => resp::ok::<()>(None),
+        Ok(true) => {
+            state.p2p.notify_registry_changed().await;
+            if let Ok(Some(service)) = state.store.resolve_service_registry(&req.id).await {
+                let change = ServiceChange::Updated {
+                    provider_addr: service.provider_addr,
+                    port: service.port,
+                };
+                state.p2p.notify_subscribers(&req.id, change).await;
+            }
+            resp::ok::<()>(None)
+        }
         Ok(false) => resp::err("未找到服务"),
         Err(err) => resp::err(&format!("公告服务失败: {}", err)),
     }
@@ -117,7 +217,14 @@ async fn disable_service(
     Json(req): Json<ToggleRequest>,
 ) -> impl axum::response::IntoResponse {
     match state.store.set_service_announced(&req.id, false).await {
-        Ok(true) => resp::ok::<()>(None),
+        Ok(true) => {
+            state.p2p.notify_registry_changed().await;
+            state
+                .p2p
+                .notify_subscribers(&req.id, ServiceChange::Offline)
+                .await;
+            resp::ok::<()>(None)
+        }
         Ok(false) => resp::err("未找到服务"),
         Err(err) => resp::err(&format!("禁用服务失败: {}", err)),
     }
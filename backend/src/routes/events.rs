@@ -0,0 +1,71 @@
+use std::{convert::Infallible, time::Duration};
+
+use axum::{
+    extract::{Query, State},
+    response::sse::{Event, KeepAlive, Sse},
+    routing::get,
+    Router,
+};
+use libp2p::futures::{stream, Stream};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::state::AppState;
+
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/porta/events", get(event_stream))
+        .with_state(state)
+}
+
+#[derive(Deserialize)]
+struct EventQuery {
+    /// Comma-separated event types; all events when absent
+    types: Option<String>,
+}
+
+async fn event_stream(
+    State(state): State<AppState>,
+    Query(query): Query<EventQuery>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let types: Vec<String> = query
+        .types
+        .as_deref()
+        .map(|raw| {
+            raw.split(',')
+                .map(|kind| kind.trim().to_string())
+                .filter(|kind| !kind.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    let receiver = state.events.subscribe();
+    let events = stream::unfold((receiver, types), |(mut receiver, types)| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => {
+                    if !types.is_empty() && !types.iter().any(|kind| kind == event.kind()) {
+                        continue;
+                    }
+                    let Ok(sse) = Event::default().event(event.kind()).json_data(&event) else {
+                        continue;
+                    };
+                    return Some((Ok(sse), (receiver, types)));
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("事件订阅者处理过慢，丢弃 {} 个事件", skipped);
+                    // Sent whatever the filter, so the client knows to refetch
+                    let Ok(sse) = Event::default()
+                        .event("lagged")
+                        .json_data(json!({ "type": "lagged", "skipped": skipped }))
+                    else {
+                        continue;
+                    };
+                    return Some((Ok(sse), (receiver, types)));
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    Sse::new(events).keep_alive(KeepAlive::new().interval(Duration::from_secs(15)))
+}
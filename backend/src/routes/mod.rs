@@ -1,4 +1,6 @@
+pub mod backup;
 pub mod community;
+pub mod events;
 pub mod node;
 pub mod proxy;
 pub mod service;
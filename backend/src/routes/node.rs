@@ -1,7 +1,8 @@
 use axum::{extract::State, routing::get, routing::post, Json, Router};
 
 use crate::{
-    models::{KeyImportRequest, NodeConfigUpdate},
+    identity,
+    models::{KeyImportRequest, KeyPassphraseRequest, NodeConfigUpdate},
     resp,
     state::AppState,
 };
@@ -12,6 +13,8 @@ pub fn router(state: AppState) -> Router {
         .route("/porta/node/config", post(update_config))
         .route("/porta/node/key/import", post(import_key))
         .route("/porta/node/key/generate", post(generate_key))
+        .route("/porta/node/key/status", get(key_status))
+        .route("/porta/node/key/passphrase", post(change_passphrase))
         .with_state(state)
 }
 
@@ -39,15 +42,32 @@ async fn import_key(
     if req.key_path.is_empty() {
         return resp::err("缺少 key_path");
     }
-    match state.store.import_key(req).await {
+    match identity::import(&state, req).await {
         Ok(updated) => resp::ok(Some(updated)),
         Err(err) => resp::err(&format!("导入密钥失败: {}", err)),
     }
 }
 
 async fn generate_key(State(state): State<AppState>) -> impl axum::response::IntoResponse {
-    match state.store.generate_key().await {
+    match identity::generate(&state).await {
         Ok(updated) => resp::ok(Some(updated)),
         Err(err) => resp::err(&format!("生成密钥失败: {}", err)),
     }
 }
+
+async fn key_status(State(state): State<AppState>) -> impl axum::response::IntoResponse {
+    match identity::key_status(&state).await {
+        Ok(status) => resp::ok(Some(status)),
+        Err(err) => resp::err(&format!("读取密钥状态失败: {}", err)),
+    }
+}
+
+async fn change_passphrase(
+    State(state): State<AppState>,
+    Json(req): Json<KeyPassphraseRequest>,
+) -> impl axum::response::IntoResponse {
+    match identity::change_passphrase(&state, req).await {
+        Ok(status) => resp::ok(Some(status)),
+        Err(err) => resp::err(&format!("修改密钥口令失败: {}", err)),
+    }
+}
@@ -21,6 +21,9 @@ async fn enable_proxy(
     State(state): State<AppState>,
     Json(_): Json<ProxyToggle>,
 ) -> impl axum::response::IntoResponse {
+    if let Some(message) = managed_error(&state).await {
+        return resp::err(&message);
+    }
     if let Err(err) = state.proxy_server.start().await {
         return resp::err(&format!("启动代理失败: {}", err));
     }
@@ -37,6 +40,9 @@ async fn disable_proxy(
     State(state): State<AppState>,
     Json(_): Json<ProxyToggle>,
 ) -> impl axum::response::IntoResponse {
+    if let Some(message) = managed_error(&state).await {
+        return resp::err(&message);
+    }
     if let Err(err) = state.proxy_server.stop().await {
         return resp::err(&format!("停止代理失败: {}", err));
     }
@@ -48,3 +54,12 @@ async fn disable_proxy(
     }
     resp::ok::<()>(None)
 }
+
+/// Proxy settings declared in the config file cannot be toggled through the API.
+async fn managed_error(state: &AppState) -> Option<String> {
+    match state.store.proxy_status().await {
+        Ok(status) if status.managed => Some("代理由配置文件管理，无法通过接口修改".into()),
+        Ok(_) => None,
+        Err(err) => Some(format!("获取代理状态失败: {}", err)),
+    }
+}
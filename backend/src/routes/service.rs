@@ -4,7 +4,8 @@ use serde_json::json;
 
 use crate::{
     models::{
-        AccessRequest, PublishRequest, SecureConnectRequest, SubscribeRequest, UpdateSessionRequest,
+        AccessRequest, PublishRequest, SecureConnectRequest, ServiceQuery, SubscribeRequest,
+        UpdateSessionRequest,
     },
     resp,
     state::AppState,
@@ -33,13 +34,49 @@ pub fn router(state: AppState) -> Router {
 struct DiscoverQuery {
     #[serde(rename = "communityId")]
     community_id: Option<String>,
+    q: Option<String>,
+    r#type: Option<String>,
+    /// Comma-separated; every tag must match
+    tags: Option<String>,
+    provider: Option<String>,
+    #[serde(rename = "onlineOnly")]
+    online_only: Option<bool>,
+    offset: Option<u32>,
+    limit: Option<u32>,
+    /// `next_cursor` of the previous page
+    cursor: Option<String>,
+}
+
+impl DiscoverQuery {
+    fn service_query(&self) -> ServiceQuery {
+        ServiceQuery {
+            keyword: self.q.clone(),
+            r#type: self.r#type.clone(),
+            tags: self
+                .tags
+                .as_deref()
+                .map(|raw| raw.split(',').map(|tag| tag.trim().to_string()).collect())
+                .map(crate::models::normalize_tags)
+                .unwrap_or_default(),
+            provider: self.provider.clone(),
+            online_only: self.online_only.unwrap_or(true),
+            offset: self.offset.unwrap_or(0),
+            limit: self.limit,
+            cursor: self.cursor.clone(),
+        }
+    }
 }
 
 async fn get_discovered_services(
     State(state): State<AppState>,
     Query(query): Query<DiscoverQuery>,
 ) -> impl axum::response::IntoResponse {
-    match state.app.discover_services(query.community_id).await {
+    let service_query = query.service_query();
+    match state
+        .app
+        .discover_services(query.community_id, service_query)
+        .await
+    {
         Ok(list) => resp::ok(Some(list)),
         Err(err) => resp::err(&format!("获取服务发现失败: {}", err)),
     }
@@ -159,7 +196,7 @@ async fn remove_publish(
     if req.id.is_empty() {
         return resp::err("缺少 id");
     }
-    match state.store.remove_published(&req.id).await {
+    match state.app.remove_published(&req.id).await {
         Ok(true) => resp::ok::<()>(None),
         Ok(false) => resp::err("未找到发布服务"),
         Err(err) => resp::err(&format!("删除失败: {}", err)),
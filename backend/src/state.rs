@@ -5,10 +5,13 @@ use uuid::Uuid;
 
 use crate::{
     app::AppService,
+    config::BackendConfig,
+    events::EventBus,
     models::{
-        CommunityAddRequest, CommunityNode, CommunityService, CommunitySummary, DiscoveredService,
-        KeyImportRequest, NodeConfigUpdate, NodeInfo, ProxyStatus, PublishRequest,
-        PublishedService, SecureRoute, ServiceRegistryItem, SessionInfo, SubscribeRequest,
+        AdmissionMode, CommunityAddRequest, CommunityNode, CommunityService, CommunitySummary,
+        DiscoveredService, JoinRequest, KeyImportRequest, NodeConfigUpdate, NodeInfo, PeerBan,
+        ProxyStatus, PublishRequest, PublishedService, RegistryChanges, ReloadReport, SecureRoute,
+        ServicePage, ServiceQuery, ServiceRegistryItem, SessionInfo, SubscribeRequest,
         SubscribedService,
     },
     p2p,
@@ -16,28 +19,73 @@ use crate::{
 
 pub type StoreResult<T> = anyhow::Result<T>;
 
+/// Condition on `peers` rows under a ban that has not expired; binds the
+/// current Unix time.
+const ACTIVE_BAN: &str = "banned = 1 AND (banned_until IS NULL OR banned_until > ?)";
+
 #[derive(Clone)]
 pub struct AppState {
     pub store: Arc<dyn Store>,
     pub p2p: p2p::NodeHandle,
     pub app: AppService,
     pub proxy_server: Arc<crate::proxy::ProxyServer>,
+    pub events: EventBus,
+    pub(crate) config: Arc<tokio::sync::RwLock<BackendConfig>>,
 }
 
 impl AppState {
+    /// Build state from the `PORTA_*` environment variables.
     pub async fn new() -> StoreResult<Self> {
-        let db_path = std::env::var("PORTA_DB").unwrap_or_else(|_| "data/porta.db".into());
-        let store = if db_path == ":memory:" {
-            SqliteStore::new_in_memory().await?
+        Self::with_config(BackendConfig::from_env()).await
+    }
+
+    pub async fn with_config(config: BackendConfig) -> StoreResult<Self> {
+        crate::provision::validate(&config.provisioning)?;
+        let store: Arc<dyn Store> = if let Some(url) = &config.database_url {
+            crate::postgres::PostgresStore::connect(url).await?
+        } else if config.is_in_memory() {
+            let store = crate::memory::MemoryStore::new();
+            if config.seed_communities {
+                store.seed_demo_communities().await?;
+            }
+            store
         } else {
-            ensure_db_parent(&db_path)?;
-            SqliteStore::new(&db_path).await?
+            ensure_db_parent(&config.db_path)?;
+            let store = SqliteStore::new(&config.db_path).await?;
+            if config.seed_communities {
+                store.seed_demo_communities().await?;
+            }
+            store
+        };
+        let overrides = NodeConfigUpdate {
+            name: config.node_name.clone(),
+            tcp_listen_enable: None,
+            tcp_listen_port: None,
+            quci_listen_enable: config.quic_enable,
+            quci_listen_port: config.quic_port,
+            external_addr: config.external_addrs.clone(),
+            mdns_enable: config.mdns_enable,
+            dht_enable: config.dht_enable,
         };
-        let p2p = p2p::NodeHandle::spawn(store.clone()).await?;
+        store.update_node_config(overrides).await?;
+        if let Some(mode) = config.admission {
+            store.set_admission_mode(mode).await?;
+        }
+        let events = EventBus::new();
+        let p2p = p2p::NodeHandle::spawn(store.clone(), events.clone(), &config).await?;
         let peer_id = p2p.peer_id();
         store.ensure_node_identity(&peer_id).await?;
-        let app = AppService::new(store.clone(), p2p.clone());
-
+        let app = AppService::new(store.clone(), p2p.clone(), events.clone(), &config.role);
+        let provisioning = config.provisioning.clone();
+        let config = Arc::new(tokio::sync::RwLock::new(config));
+
+        if let Some(port) = provisioning
+            .proxy
+            .as_ref()
+            .and_then(|proxy| proxy.listen_port)
+        {
+            store.set_proxy_listen_port(port).await?;
+        }
         let proxy_status = store.proxy_status().await?;
         let proxy_server = Arc::new(crate::proxy::ProxyServer::new(proxy_status.listen_port));
         if proxy_status.enabled {
@@ -49,29 +97,237 @@ impl AppState {
             p2p,
             app,
             proxy_server,
+            events,
+            config,
         };
+        crate::provision::reconcile(&state, &provisioning).await?;
         state.spawn_maintenance_tasks();
 
         Ok(state)
     }
 
+    /// Configuration the node is currently running with.
+    pub async fn config(&self) -> BackendConfig {
+        self.config.read().await.clone()
+    }
+
+    /// Apply a new configuration to the running node. Node settings, added
+    /// listen addresses and provisioned entries take effect immediately;
+    /// anything else is reported as needing a restart and keeps its running value.
+    /// New listen addresses are bound first, so if one fails nothing else changes.
+    pub async fn reload(&self, new: BackendConfig) -> StoreResult<ReloadReport> {
+        let mut current = self.config.write().await;
+        let mut report = ReloadReport::default();
+
+        // Listening is the one step that can fail halfway, so it goes first:
+        // a bad address or a taken port leaves everything else untouched
+        let added = new
+            .listen_addrs
+            .iter()
+            .filter(|addr| !current.listen_addrs.contains(addr))
+            .map(|addr| Ok((addr.clone(), addr.parse::<libp2p::Multiaddr>()?)))
+            .collect::<StoreResult<Vec<_>>>()?;
+        let listened = !added.is_empty();
+        for (addr, multiaddr) in added {
+            self.p2p.listen_on(multiaddr).await?;
+            // On record as soon as it is open, whatever fails later
+            current.listen_addrs.push(addr);
+        }
+
+        let mut update = NodeConfigUpdate::default();
+        if new.node_name != current.node_name {
+            update.name = new.node_name.clone();
+            report.applied.push("node_name".into());
+        }
+        // The swarm picks up transports and discovery behaviours only when it
+        // is built, so these are saved for the next start
+        if new.quic_enable != current.quic_enable {
+            update.quci_listen_enable = new.quic_enable;
+            report.restart_required.push("quic_enable".into());
+        }
+        if new.quic_port != current.quic_port {
+            update.quci_listen_port = new.quic_port;
+            report.restart_required.push("quic_port".into());
+        }
+        if new.mdns_enable != current.mdns_enable {
+            update.mdns_enable = new.mdns_enable;
+            report.restart_required.push("mdns_enable".into());
+        }
+        if new.dht_enable != current.dht_enable {
+            update.dht_enable = new.dht_enable;
+            report.restart_required.push("dht_enable".into());
+        }
+        if new.external_addrs != current.external_addrs {
+            update.external_addr = new.external_addrs.clone();
+            report.applied.push("external_addrs".into());
+        }
+        if !report.applied.is_empty() || !report.restart_required.is_empty() {
+            self.store.update_node_config(update).await?;
+        }
+        if new.admission != current.admission {
+            if let Some(mode) = new.admission {
+                self.store.set_admission_mode(mode).await?;
+            }
+            report.applied.push("admission".into());
+        }
+        if new.limits != current.limits {
+            self.p2p.set_limits(new.limits.clone());
+            report.applied.push("limits".into());
+        }
+
+        if listened {
+            report.applied.push("listen_addrs".into());
+        }
+        // Dropped listeners stay open until the node restarts
+        if current
+            .listen_addrs
+            .iter()
+            .any(|addr| !new.listen_addrs.contains(addr))
+        {
+            report.restart_required.push("listen_addrs".into());
+        }
+
+        if new.provisioning != current.provisioning {
+            report.merge(crate::provision::reconcile(self, &new.provisioning).await?);
+        }
+
+        if new.role != current.role {
+            report.restart_required.push("role".into());
+        }
+        if new.db_path != current.db_path {
+            report.restart_required.push("db_path".into());
+        }
+        if new.database_url != current.database_url {
+            report.restart_required.push("database_url".into());
+        }
+        if new.key_path != current.key_path {
+            report.restart_required.push("key_path".into());
+        }
+        if new.seed_communities != current.seed_communities {
+            report.restart_required.push("seed_communities".into());
+        }
+        if new.rendezvous_points != current.rendezvous_points {
+            report.restart_required.push("rendezvous_points".into());
+        }
+
+        *current = BackendConfig {
+            role: current.role.clone(),
+            db_path: current.db_path.clone(),
+            database_url: current.database_url.clone(),
+            key_path: current.key_path.clone(),
+            // Passphrase changes go through the key passphrase API
+            key_passphrase: current.key_passphrase.clone(),
+            seed_communities: current.seed_communities,
+            rendezvous_points: current.rendezvous_points.clone(),
+            listen_addrs: current.listen_addrs.clone(),
+            ..new
+        };
+        tracing::info!(
+            "配置已重新加载: 已生效 {:?}, 需重启 {:?}",
+            report.applied,
+            report.restart_required
+        );
+        Ok(report)
+    }
+
     fn spawn_maintenance_tasks(&self) {
         let app = self.app.clone();
         tokio::spawn(async move {
-            let mut interval = tokio::time::interval(std::time::Duration::from_secs(30));
+            // Cheap when every community is healthy or backing off; the
+            // backoff decides how often a dead community is actually dialed
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(5));
             loop {
                 interval.tick().await;
                 if let Err(err) = app.reconnect_communities().await {
                     tracing::warn!("社区重连失败: {}", err);
                 }
+            }
+        });
+        let app = self.app.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(30));
+            loop {
+                interval.tick().await;
+                if let Err(err) = app.register_with_rendezvous().await {
+                    tracing::warn!("rendezvous 登记失败: {}", err);
+                }
                 if let Err(err) = app.cleanup_expired_sessions().await {
                     tracing::warn!("会话清理失败: {}", err);
                 }
+                if let Err(err) = app.sync_bans().await {
+                    tracing::warn!("同步封禁列表失败: {}", err);
+                }
             }
         });
     }
 }
 
+/// Mock communities for demos: id, name, description, peer count, multiaddr.
+pub(crate) const DEMO_COMMUNITIES: &[(&str, &str, &str, u32, &str)] = &[
+    (
+        "dev-community",
+        "开发者社区",
+        "共享开发环境和工具服务",
+        24,
+        "/ip4/127.0.0.1/tcp/4001",
+    ),
+    (
+        "data-team",
+        "数据科学团队",
+        "机器学习模型和数据分析服务",
+        12,
+        "/ip4/127.0.0.1/tcp/4002",
+    ),
+    (
+        "test-env",
+        "测试环境",
+        "测试和预发布环境服务",
+        8,
+        "/ip4/127.0.0.1/tcp/4003",
+    ),
+];
+
+fn parse_tags(raw: Option<String>) -> Vec<String> {
+    raw.and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+fn registry_item_from_row(row: sqlx::sqlite::SqliteRow) -> ServiceRegistryItem {
+    ServiceRegistryItem {
+        uuid: row.get("uuid"),
+        name: row.get("name"),
+        r#type: row.get("type"),
+        port: row.get::<i64, _>("port") as u16,
+        description: row.get("description"),
+        provider_peer: row.get("provider_peer"),
+        provider_addr: row.get("provider_addr"),
+        online: row.get::<i64, _>("online") == 1,
+        tags: parse_tags(row.get("tags")),
+    }
+}
+
+fn session_from_row(row: &sqlx::sqlite::SqliteRow) -> SessionInfo {
+    SessionInfo {
+        session_id: row.get("session_id"),
+        service_id: row.get("service_id"),
+        local_port: row.get::<i64, _>("local_port") as u16,
+        remote_peer: row.get("remote_peer"),
+        state: row.get("state"),
+        created_at: row.get("created_at"),
+        last_active: row.get("last_active"),
+    }
+}
+
+fn join_request_from_row(row: &sqlx::sqlite::SqliteRow) -> JoinRequest {
+    JoinRequest {
+        peer_id: row.get("peer_id"),
+        node_id: row.get("node_id"),
+        name: row.get("name"),
+        status: row.get("status"),
+        requested_at: row.get("requested_at"),
+    }
+}
+
 fn ensure_db_parent(path: &str) -> StoreResult<()> {
     let db_path = std::path::Path::new(path);
     if let Some(parent) = db_path.parent() {
@@ -89,9 +345,14 @@ pub trait Store: Send + Sync {
     async fn import_key(&self, req: KeyImportRequest) -> StoreResult<NodeInfo>;
     async fn generate_key(&self) -> StoreResult<NodeInfo>;
     async fn ensure_node_identity(&self, peer_id: &str) -> StoreResult<()>;
+    /// Record a new peer ID, e.g. after restoring an identity from a backup.
+    async fn set_node_id(&self, peer_id: &str) -> StoreResult<()>;
 
     async fn communities(&self) -> StoreResult<Vec<CommunitySummary>>;
     async fn add_community(&self, req: CommunityAddRequest) -> StoreResult<CommunitySummary>;
+    /// Insert or update a community by ID, keeping its joined state.
+    async fn upsert_community(&self, req: CommunityAddRequest) -> StoreResult<()>;
+    async fn set_community_managed(&self, id: &str, managed: bool) -> StoreResult<bool>;
     async fn remove_community(&self, id: &str) -> StoreResult<bool>;
     async fn connect_community(&self, id: &str) -> StoreResult<bool>;
     async fn community_multiaddr(&self, id: &str) -> StoreResult<Option<String>>;
@@ -103,6 +364,18 @@ pub trait Store: Send + Sync {
         multiaddr: &str,
         peer_id: &str,
     ) -> StoreResult<bool>;
+    /// Remember which of a community's addresses the last dial went through.
+    async fn set_community_last_multiaddr(&self, id: &str, multiaddr: &str) -> StoreResult<bool>;
+    /// Persist a community's reconnect backoff.
+    async fn set_community_connection(
+        &self,
+        id: &str,
+        failures: u32,
+        last_error: Option<&str>,
+        next_retry_at: Option<i64>,
+    ) -> StoreResult<bool>;
+    /// Record the member count a community node reported in its Hello ack.
+    async fn set_community_peers(&self, id: &str, peers: u32) -> StoreResult<bool>;
 
     async fn community_nodes(&self) -> StoreResult<Vec<CommunityNode>>;
     async fn community_services(&self) -> StoreResult<Vec<CommunityService>>;
@@ -115,6 +388,15 @@ pub trait Store: Send + Sync {
         community_id: &str,
         services: Vec<ServiceRegistryItem>,
     ) -> StoreResult<()>;
+    async fn remove_discovered_services(
+        &self,
+        community_id: &str,
+        uuids: &[String],
+    ) -> StoreResult<u64>;
+    async fn clear_discovered_services(&self, community_id: &str) -> StoreResult<u64>;
+    /// Last registry revision an edge has applied from the given community.
+    async fn community_sync_revision(&self, id: &str) -> StoreResult<u64>;
+    async fn set_community_sync_revision(&self, id: &str, revision: u64) -> StoreResult<bool>;
     async fn subscribed_services(&self) -> StoreResult<Vec<SubscribedService>>;
     async fn find_subscription(&self, id: &str) -> StoreResult<Option<SubscribedService>>;
     async fn published_services(&self) -> StoreResult<Vec<PublishedService>>;
@@ -122,7 +404,9 @@ pub trait Store: Send + Sync {
     async fn proxy_status(&self) -> StoreResult<ProxyStatus>;
     async fn sessions(&self) -> StoreResult<Vec<SessionInfo>>;
     async fn upsert_session(&self, session: SessionInfo) -> StoreResult<()>;
-    async fn cleanup_expired_sessions(&self, timeout_minutes: i64) -> StoreResult<u64>;
+    /// Drop connected sessions idle for longer than the timeout; returns them.
+    async fn cleanup_expired_sessions(&self, timeout_minutes: i64)
+        -> StoreResult<Vec<SessionInfo>>;
     async fn update_subscription_endpoint(
         &self,
         id: &str,
@@ -132,26 +416,102 @@ pub trait Store: Send + Sync {
 
     async fn subscribe_service(&self, req: SubscribeRequest) -> StoreResult<SubscribedService>;
     async fn update_subscription_status(&self, id: &str, status: &str) -> StoreResult<bool>;
+    /// Set the status of every subscription to a service made through one of
+    /// `communities` (matched by ID or name); returns the rows changed.
+    async fn update_subscriptions_by_service(
+        &self,
+        service_uuid: &str,
+        communities: &[String],
+        status: &str,
+    ) -> StoreResult<u64>;
+    /// Point every subscription to a service made through one of `communities`
+    /// at a new endpoint. Subscriptions marked offline or removed fall back to
+    /// disconnected so they can be reconnected.
+    async fn update_subscription_endpoints_by_service(
+        &self,
+        service_uuid: &str,
+        communities: &[String],
+        remote_addr: &str,
+    ) -> StoreResult<u64>;
     async fn publish_service(&self, req: PublishRequest) -> StoreResult<PublishedService>;
     async fn unpublish_service(&self, id: &str) -> StoreResult<bool>;
     async fn remove_published(&self, id: &str) -> StoreResult<bool>;
+    async fn set_published_managed(&self, id: &str, managed: bool) -> StoreResult<bool>;
     async fn set_service_announced(&self, id: &str, announced: bool) -> StoreResult<bool>;
-    async fn set_node_ban(&self, id: &str, banned: bool) -> StoreResult<bool>;
+    /// Ban or unban a peer. A ban with `until` (Unix seconds) lifts by
+    /// itself; unbanning clears the reason and expiry.
+    async fn set_node_ban(
+        &self,
+        id: &str,
+        banned: bool,
+        reason: Option<&str>,
+        until: Option<i64>,
+    ) -> StoreResult<bool>;
     async fn set_proxy_enabled(&self, enabled: bool) -> StoreResult<()>;
+    async fn set_proxy_listen_port(&self, port: u16) -> StoreResult<()>;
+    async fn set_proxy_managed(&self, managed: bool) -> StoreResult<()>;
 
+    /// Record a peer that said Hello. The join time is kept from its first
+    /// Hello, and an empty `node_uuid` keeps the one already recorded.
     async fn upsert_peer(
         &self,
         peer_id: &str,
         node_id: &str,
+        node_uuid: &str,
+        name: &str,
         role: &str,
         status: &str,
     ) -> StoreResult<()>;
+    /// Mark a known peer online or offline, e.g. when its connection closes.
+    async fn set_peer_status(&self, peer_id: &str, status: &str) -> StoreResult<bool>;
+    /// Who may join this node's community; see [`AdmissionMode`].
+    async fn admission_mode(&self) -> StoreResult<AdmissionMode>;
+    async fn set_admission_mode(&self, mode: AdmissionMode) -> StoreResult<()>;
+    /// Queue an edge for approval, or refresh the node ID and name of a
+    /// queued one while keeping its status; returns the stored request.
+    async fn record_join_request(
+        &self,
+        peer_id: &str,
+        node_id: &str,
+        name: &str,
+    ) -> StoreResult<JoinRequest>;
+    async fn join_requests(&self) -> StoreResult<Vec<JoinRequest>>;
+    async fn set_join_request_status(&self, peer_id: &str, status: &str) -> StoreResult<bool>;
+    async fn remove_join_request(&self, peer_id: &str) -> StoreResult<bool>;
     async fn peer_role(&self, peer_id: &str) -> StoreResult<Option<String>>;
+    /// Whether the peer is under a ban that has not expired.
     async fn peer_is_banned(&self, peer_id: &str) -> StoreResult<bool>;
+    async fn peer_ban(&self, peer_id: &str) -> StoreResult<Option<PeerBan>>;
+    /// Peers under a ban that has not expired.
+    async fn banned_peers(&self) -> StoreResult<Vec<String>>;
+    /// Peer IDs a node has said Hello with under the given UUID.
+    async fn peers_by_node_uuid(&self, node_uuid: &str) -> StoreResult<Vec<String>>;
 
     async fn upsert_service_registry(&self, service: ServiceRegistryItem) -> StoreResult<()>;
     async fn remove_service_registry(&self, uuid: &str) -> StoreResult<bool>;
+    /// Drop every registry entry a peer provides; returns their uuids.
+    async fn remove_provider_services(&self, provider_peer: &str) -> StoreResult<Vec<String>>;
+    /// Mark every registry entry a peer provides online or offline, e.g. when
+    /// its connection comes or goes; returns the uuids that changed.
+    async fn set_provider_online(
+        &self,
+        provider_peer: &str,
+        online: bool,
+    ) -> StoreResult<Vec<String>>;
+    /// Registry entries a peer provides, announced or not.
+    async fn provider_service_count(&self, provider_peer: &str) -> StoreResult<u64>;
     async fn list_service_registry(&self) -> StoreResult<Vec<ServiceRegistryItem>>;
+    /// Filter announced registry entries, returning one page plus the total match count.
+    async fn search_service_registry(
+        &self,
+        query: &ServiceQuery,
+    ) -> StoreResult<ServicePage<ServiceRegistryItem>>;
+    /// Entries whose visibility changed after `since_revision`, oldest first.
+    async fn registry_changes_since(
+        &self,
+        since_revision: u64,
+        limit: u32,
+    ) -> StoreResult<RegistryChanges>;
     async fn resolve_service_registry(
         &self,
         uuid: &str,
@@ -161,6 +521,17 @@ pub trait Store: Send + Sync {
         service_uuid: &str,
         subscriber_peer: &str,
     ) -> StoreResult<()>;
+    async fn service_subscribers(&self, service_uuid: &str) -> StoreResult<Vec<String>>;
+    /// Move the services and subscriptions recorded for `old_peer` to
+    /// `new_peer` and forget the old peer entry, after a verified identity
+    /// handover. Returns the registry entries moved.
+    async fn hand_over_peer(&self, old_peer: &str, new_peer: &str) -> StoreResult<u64>;
+    /// Record that `peer_id` used the one-time invite `invite_id`. Returns
+    /// false when another peer already used it; the peer that redeemed it
+    /// may present it again, e.g. when reconnecting.
+    async fn redeem_invite(&self, invite_id: &str, peer_id: &str) -> StoreResult<bool>;
+    /// The peer that redeemed the one-time invite `invite_id`, if any.
+    async fn invite_redeemed_by(&self, invite_id: &str) -> StoreResult<Option<String>>;
 
     async fn secure_routes(&self) -> StoreResult<Vec<SecureRoute>>;
     async fn add_secure_route(&self, route: SecureRoute) -> StoreResult<()>;
@@ -177,277 +548,20 @@ impl SqliteStore {
     pub async fn new(path: &str) -> StoreResult<Arc<Self>> {
         let url = format!("sqlite://{}", path);
         let pool = SqlitePool::connect(&url).await?;
+        crate::migrations::run(&pool, Some(path)).await?;
         let store = Self { pool };
-        store.init_schema().await?;
         store.seed_if_empty().await?;
         Ok(Arc::new(store))
     }
 
     pub async fn new_in_memory() -> StoreResult<Arc<Self>> {
         let pool = SqlitePool::connect("sqlite::memory:").await?;
+        crate::migrations::run(&pool, None).await?;
         let store = Self { pool };
-        store.init_schema().await?;
         store.seed_if_empty().await?;
         Ok(Arc::new(store))
     }
 
-    async fn ensure_column(&self, table: &str, column: &str, ddl: &str) -> StoreResult<()> {
-        let pragma = format!("PRAGMA table_info({})", table);
-        let rows = sqlx::query(&pragma).fetch_all(&self.pool).await?;
-        let exists = rows
-            .iter()
-            .any(|row| row.get::<String, _>("name") == column);
-        if !exists {
-            sqlx::query(ddl).execute(&self.pool).await?;
-        }
-        Ok(())
-    }
-
-    async fn init_schema(&self) -> StoreResult<()> {
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS node_config (
-                id INTEGER PRIMARY KEY,
-                name TEXT NOT NULL,
-                node_id TEXT NOT NULL,
-                uuid TEXT NOT NULL,
-                key_path TEXT NOT NULL,
-                tcp_listen_enable INTEGER NOT NULL,
-                tcp_listen_port INTEGER NOT NULL,
-                quci_listen_enable INTEGER NOT NULL,
-                quci_listen_port INTEGER NOT NULL,
-                external_addr TEXT NOT NULL,
-                mdns_enable INTEGER NOT NULL,
-                dht_enable INTEGER NOT NULL
-            );
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
-
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS communities (
-                id TEXT PRIMARY KEY,
-                name TEXT NOT NULL,
-                description TEXT NOT NULL,
-                peers INTEGER NOT NULL,
-                joined INTEGER NOT NULL,
-                multiaddr TEXT,
-                peer_id TEXT
-            );
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
-
-        self.ensure_column(
-            "communities",
-            "peer_id",
-            "ALTER TABLE communities ADD COLUMN peer_id TEXT",
-        )
-        .await?;
-
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS community_nodes (
-                id TEXT PRIMARY KEY,
-                uuid TEXT NOT NULL,
-                status TEXT NOT NULL,
-                banned INTEGER NOT NULL
-            );
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
-
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS community_services (
-                id TEXT PRIMARY KEY,
-                name TEXT NOT NULL,
-                uuid TEXT NOT NULL,
-                protocol TEXT NOT NULL,
-                port INTEGER NOT NULL,
-                online INTEGER NOT NULL,
-                announced INTEGER NOT NULL
-            );
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
-
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS discovered_services (
-                uuid TEXT PRIMARY KEY,
-                name TEXT NOT NULL,
-                type TEXT NOT NULL,
-                remote_port INTEGER NOT NULL,
-                provider TEXT NOT NULL,
-                description TEXT NOT NULL,
-                community_id TEXT NOT NULL,
-                provider_addr TEXT
-            );
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
-
-        self.ensure_column(
-            "discovered_services",
-            "provider_addr",
-            "ALTER TABLE discovered_services ADD COLUMN provider_addr TEXT",
-        )
-        .await?;
-
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS subscribed_services (
-                id TEXT PRIMARY KEY,
-                name TEXT NOT NULL,
-                type TEXT NOT NULL,
-                community TEXT NOT NULL,
-                remote_addr TEXT NOT NULL,
-                local_mapping TEXT NOT NULL,
-                status TEXT NOT NULL,
-                service_uuid TEXT
-            );
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
-
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS published_services (
-                id TEXT PRIMARY KEY,
-                name TEXT NOT NULL,
-                type TEXT NOT NULL,
-                port INTEGER NOT NULL,
-                summary TEXT NOT NULL,
-                subscriptions INTEGER NOT NULL,
-                status TEXT NOT NULL,
-                publish_date TEXT NOT NULL
-            );
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
-
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS proxy_status (
-                id INTEGER PRIMARY KEY,
-                enabled INTEGER NOT NULL,
-                listen_port INTEGER NOT NULL
-            );
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
-
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS sessions (
-                session_id TEXT PRIMARY KEY,
-                service_id TEXT NOT NULL,
-                local_port INTEGER NOT NULL,
-                remote_peer TEXT NOT NULL,
-                state TEXT NOT NULL,
-                created_at TEXT NOT NULL,
-                last_active TEXT NOT NULL
-            );
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
-
-        self.ensure_column(
-            "sessions",
-            "created_at",
-            "ALTER TABLE sessions ADD COLUMN created_at TEXT NOT NULL DEFAULT (datetime('now'))",
-        )
-        .await?;
-        self.ensure_column(
-            "sessions",
-            "last_active",
-            "ALTER TABLE sessions ADD COLUMN last_active TEXT NOT NULL DEFAULT (datetime('now'))",
-        )
-        .await?;
-
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS peers (
-                peer_id TEXT PRIMARY KEY,
-                node_id TEXT NOT NULL,
-                role TEXT NOT NULL,
-                status TEXT NOT NULL,
-                banned INTEGER NOT NULL,
-                last_seen TEXT NOT NULL
-            );
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
-
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS service_registry (
-                uuid TEXT PRIMARY KEY,
-                name TEXT NOT NULL,
-                type TEXT NOT NULL,
-                port INTEGER NOT NULL,
-                description TEXT NOT NULL,
-                provider_peer TEXT NOT NULL,
-                provider_addr TEXT NOT NULL,
-                online INTEGER NOT NULL,
-                announced INTEGER NOT NULL,
-                updated_at TEXT NOT NULL
-            );
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
-
-        self.ensure_column(
-            "service_registry",
-            "announced",
-            "ALTER TABLE service_registry ADD COLUMN announced INTEGER NOT NULL DEFAULT 1",
-        )
-        .await?;
-
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS service_subscriptions (
-                service_uuid TEXT NOT NULL,
-                subscriber_peer TEXT NOT NULL,
-                created_at TEXT NOT NULL,
-                PRIMARY KEY (service_uuid, subscriber_peer)
-            );
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
-
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS secure_routes (
-                id TEXT PRIMARY KEY,
-                subscription_id TEXT NOT NULL,
-                relay_peers TEXT NOT NULL,
-                local_port INTEGER NOT NULL,
-                status TEXT NOT NULL,
-                created_at TEXT NOT NULL
-            );
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
-
-        Ok(())
-    }
-
     async fn seed_if_empty(&self) -> StoreResult<()> {
         let row = sqlx::query("SELECT COUNT(*) as count FROM node_config")
             .fetch_one(&self.pool)
@@ -472,67 +586,41 @@ impl SqliteStore {
             .await?;
         }
 
+        let proxy_row = sqlx::query("SELECT COUNT(*) as count FROM proxy_status")
+            .fetch_one(&self.pool)
+            .await?;
+        let proxy_count: i64 = proxy_row.get("count");
+        if proxy_count == 0 {
+            sqlx::query("INSERT INTO proxy_status (id, enabled, listen_port) VALUES (1, 1, 1080)")
+                .execute(&self.pool)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Seed mock communities into an empty table. This is for testing/demo
+    /// purposes only; in production communities are added through the API.
+    pub async fn seed_demo_communities(&self) -> StoreResult<()> {
         let comm_row = sqlx::query("SELECT COUNT(*) as count FROM communities")
             .fetch_one(&self.pool)
             .await?;
         let comm_count: i64 = comm_row.get("count");
-        // Seed mock communities ONLY if explicitly enabled via PORTA_SEED_COMMUNITIES=1
-        // This is for testing/demo purposes only. In production, communities should be added
-        // through the API using the add_community endpoint.
-        let seed_communities = std::env::var("PORTA_SEED_COMMUNITIES").ok().as_deref() == Some("1");
-        if seed_communities && comm_count == 0 {
-            tracing::info!("Seeding mock communities (PORTA_SEED_COMMUNITIES=1)");
-            let entries = vec![
-                (
-                    "dev-community",
-                    "开发者社区",
-                    "共享开发环境和工具服务",
-                    24,
-                    0,
-                    Some("/ip4/127.0.0.1/tcp/4001".to_string()),
-                ),
-                (
-                    "data-team",
-                    "数据科学团队",
-                    "机器学习模型和数据分析服务",
-                    12,
-                    0,
-                    Some("/ip4/127.0.0.1/tcp/4002".to_string()),
-                ),
-                (
-                    "test-env",
-                    "测试环境",
-                    "测试和预发布环境服务",
-                    8,
-                    0,
-                    Some("/ip4/127.0.0.1/tcp/4003".to_string()),
-                ),
-            ];
-            for (id, name, desc, peers, joined, multiaddr) in entries {
+        if comm_count == 0 {
+            tracing::info!("Seeding mock communities");
+            for (id, name, desc, peers, multiaddr) in DEMO_COMMUNITIES {
                 sqlx::query(
-                    "INSERT INTO communities (id, name, description, peers, joined, multiaddr) VALUES (?, ?, ?, ?, ?, ?)",
+                    "INSERT INTO communities (id, name, description, peers, joined, multiaddr) VALUES (?, ?, ?, ?, 0, ?)",
                 )
                 .bind(id)
                 .bind(name)
                 .bind(desc)
-                .bind(peers)
-                .bind(joined)
+                .bind(*peers as i64)
                 .bind(multiaddr)
                 .execute(&self.pool)
                 .await?;
             }
             tracing::info!("Mock communities seeded successfully");
         }
-
-        let proxy_row = sqlx::query("SELECT COUNT(*) as count FROM proxy_status")
-            .fetch_one(&self.pool)
-            .await?;
-        let proxy_count: i64 = proxy_row.get("count");
-        if proxy_count == 0 {
-            sqlx::query("INSERT INTO proxy_status (id, enabled, listen_port) VALUES (1, 1, 1080)")
-                .execute(&self.pool)
-                .await?;
-        }
         Ok(())
     }
 
@@ -591,11 +679,26 @@ impl SqliteStore {
         .await?;
         Ok(())
     }
+
+    async fn bump_registry_revision(&self, uuid: &str) -> StoreResult<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO service_registry_revisions (uuid, revision)
+            VALUES (?, (SELECT COALESCE(MAX(revision), 0) + 1 FROM service_registry_revisions))
+            ON CONFLICT(uuid) DO UPDATE SET revision = excluded.revision
+            "#,
+        )
+        .bind(uuid)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::ServiceChange;
 
     #[tokio::test]
     async fn should_seed_node_config() {
@@ -623,6 +726,136 @@ mod tests {
         assert_eq!(list.len(), 1);
     }
 
+    #[tokio::test]
+    async fn should_track_subscriber_status_by_service() {
+        let store = SqliteStore::new_in_memory().await.unwrap();
+        let saved = store
+            .subscribe_service(SubscribeRequest {
+                id: None,
+                service_uuid: Some("svc-1".into()),
+                name: "Service A".into(),
+                r#type: "HTTP".into(),
+                community: "dev".into(),
+                remote_addr: "127.0.0.1:8080".into(),
+                local_mapping: "127.0.0.1:18080".into(),
+            })
+            .await
+            .unwrap();
+        store.record_subscription("svc-1", "peer-a").await.unwrap();
+        store.record_subscription("svc-1", "peer-b").await.unwrap();
+        assert_eq!(store.service_subscribers("svc-1").await.unwrap().len(), 2);
+
+        let removed = ServiceChange::Removed.subscription_status().unwrap();
+        let dev = ["dev".to_string()];
+        assert_eq!(
+            store
+                .update_subscriptions_by_service("svc-1", &dev, removed)
+                .await
+                .unwrap(),
+            1
+        );
+        let sub = store.find_subscription(&saved.id).await.unwrap().unwrap();
+        assert_eq!(sub.status, "已下架");
+
+        store
+            .update_subscription_endpoints_by_service("svc-1", &dev, "10.0.0.2:9090")
+            .await
+            .unwrap();
+        let sub = store.find_subscription(&saved.id).await.unwrap().unwrap();
+        assert_eq!(sub.remote_addr, "10.0.0.2:9090");
+        assert_eq!(sub.status, "断开");
+    }
+
+    #[tokio::test]
+    async fn should_reload_config_into_running_state() {
+        let config = BackendConfig {
+            db_path: ":memory:".into(),
+            ..Default::default()
+        };
+        let state = AppState::with_config(config.clone()).await.unwrap();
+        let report = state
+            .reload(BackendConfig {
+                role: "community".into(),
+                node_name: Some("renamed".into()),
+                listen_addrs: vec!["/ip4/0.0.0.0/tcp/0".into(), "/ip4/127.0.0.1/tcp/0".into()],
+                ..config
+            })
+            .await
+            .unwrap();
+        assert_eq!(report.applied, vec!["node_name", "listen_addrs"]);
+        assert_eq!(report.restart_required, vec!["role"]);
+        assert_eq!(state.store.node_info().await.unwrap().name, "renamed");
+        let running = state.config().await;
+        assert_eq!(running.role, "edge");
+        assert_eq!(running.listen_addrs.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn should_require_restart_for_transport_and_discovery_changes() {
+        let config = BackendConfig {
+            db_path: ":memory:".into(),
+            ..Default::default()
+        };
+        let state = AppState::with_config(config.clone()).await.unwrap();
+        let report = state
+            .reload(BackendConfig {
+                quic_port: Some(9101),
+                mdns_enable: Some(!config.mdns_enable.unwrap_or(true)),
+                dht_enable: Some(!config.dht_enable.unwrap_or(true)),
+                ..config
+            })
+            .await
+            .unwrap();
+        assert!(report.applied.is_empty());
+        assert_eq!(
+            report.restart_required,
+            vec!["quic_port", "mdns_enable", "dht_enable"]
+        );
+        // Saved so the restarted node uses them
+        assert_eq!(
+            state.store.node_info().await.unwrap().quci_listen_port,
+            9101
+        );
+    }
+
+    #[tokio::test]
+    async fn should_leave_state_alone_when_a_listener_fails() {
+        let config = BackendConfig {
+            db_path: ":memory:".into(),
+            ..Default::default()
+        };
+        let state = AppState::with_config(config.clone()).await.unwrap();
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let name = state.store.node_info().await.unwrap().name;
+
+        let err = state
+            .reload(BackendConfig {
+                node_name: Some("renamed".into()),
+                admission: Some(AdmissionMode::Approval),
+                listen_addrs: vec![format!("/ip4/127.0.0.1/tcp/{}", port)],
+                ..config.clone()
+            })
+            .await;
+        assert!(err.is_err());
+        assert_eq!(state.store.node_info().await.unwrap().name, name);
+        assert_eq!(
+            state.store.admission_mode().await.unwrap(),
+            AdmissionMode::Open
+        );
+        assert_eq!(state.config().await.node_name, config.node_name);
+
+        assert!(state
+            .reload(BackendConfig {
+                node_name: Some("renamed".into()),
+                listen_addrs: vec!["not a multiaddr".into()],
+                ..config
+            })
+            .await
+            .is_err());
+        assert_eq!(state.store.node_info().await.unwrap().name, name);
+    }
+
     #[tokio::test]
     async fn should_cleanup_expired_sessions() {
         let store = SqliteStore::new_in_memory().await.unwrap();
@@ -636,8 +869,7 @@ mod tests {
             last_active: None,
         };
         store.upsert_session(session).await.unwrap();
-        let count = store.cleanup_expired_sessions(0).await.unwrap();
-        assert!(count == 0 || count > 0);
+        store.cleanup_expired_sessions(0).await.unwrap();
         let sessions = store.sessions().await.unwrap();
         assert!(!sessions.is_empty());
     }
@@ -659,6 +891,132 @@ mod tests {
         assert!(found.is_some());
         assert_eq!(found.unwrap().relay_peers.len(), 2);
     }
+
+    fn registry_item(uuid: &str, name: &str, kind: &str, tags: &[&str]) -> ServiceRegistryItem {
+        ServiceRegistryItem {
+            uuid: uuid.into(),
+            name: name.into(),
+            r#type: kind.into(),
+            port: 8080,
+            description: format!("{} service", name),
+            provider_peer: "peer-1".into(),
+            provider_addr: "127.0.0.1".into(),
+            online: true,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_search_service_registry() {
+        let store = SqliteStore::new_in_memory().await.unwrap();
+        store
+            .upsert_service_registry(registry_item(
+                "svc-1",
+                "Grafana",
+                "HTTP",
+                &["metrics", "web"],
+            ))
+            .await
+            .unwrap();
+        store
+            .upsert_service_registry(registry_item("svc-2", "Build SSH", "SSH", &["ci"]))
+            .await
+            .unwrap();
+        let mut offline = registry_item("svc-3", "Prometheus", "HTTP", &["metrics"]);
+        offline.online = false;
+        store.upsert_service_registry(offline).await.unwrap();
+
+        let query = ServiceQuery {
+            tags: vec!["METRICS".into()],
+            ..Default::default()
+        };
+        let page = store.search_service_registry(&query).await.unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.items[0].uuid, "svc-1");
+        assert_eq!(page.items[0].tags, vec!["metrics", "web"]);
+
+        let query = ServiceQuery {
+            r#type: Some("http".into()),
+            online_only: false,
+            ..Default::default()
+        };
+        let page = store.search_service_registry(&query).await.unwrap();
+        assert_eq!(page.total, 2);
+
+        let query = ServiceQuery {
+            keyword: Some("ssh".into()),
+            ..Default::default()
+        };
+        let page = store.search_service_registry(&query).await.unwrap();
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].uuid, "svc-2");
+
+        let query = ServiceQuery {
+            online_only: false,
+            offset: 1,
+            limit: Some(1),
+            ..Default::default()
+        };
+        let page = store.search_service_registry(&query).await.unwrap();
+        assert_eq!(page.total, 3);
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].uuid, "svc-1");
+
+        let query = ServiceQuery {
+            online_only: false,
+            limit: Some(2),
+            ..Default::default()
+        };
+        let first = store.search_service_registry(&query).await.unwrap();
+        assert_eq!(first.items.len(), 2);
+        let query = ServiceQuery {
+            cursor: first.next_cursor.clone(),
+            ..query
+        };
+        let second = store.search_service_registry(&query).await.unwrap();
+        assert_eq!(second.items.len(), 1);
+        assert_eq!(second.items[0].uuid, "svc-3");
+        assert!(second.next_cursor.is_none());
+    }
+
+    #[tokio::test]
+    async fn should_report_registry_changes_since_revision() {
+        let store = SqliteStore::new_in_memory().await.unwrap();
+        store
+            .upsert_service_registry(registry_item("svc-1", "A", "HTTP", &[]))
+            .await
+            .unwrap();
+        store
+            .upsert_service_registry(registry_item("svc-2", "B", "HTTP", &[]))
+            .await
+            .unwrap();
+        let full = store.registry_changes_since(0, 1).await.unwrap();
+        assert_eq!(full.upserted.len(), 1);
+        assert!(full.more);
+        let rest = store
+            .registry_changes_since(full.revision, 10)
+            .await
+            .unwrap();
+        assert_eq!(rest.upserted[0].uuid, "svc-2");
+        assert!(!rest.more);
+
+        store.remove_service_registry("svc-1").await.unwrap();
+        let delta = store
+            .registry_changes_since(rest.revision, 10)
+            .await
+            .unwrap();
+        assert!(delta.upserted.is_empty());
+        assert_eq!(delta.removed, vec!["svc-1".to_string()]);
+        assert!(delta.revision > rest.revision);
+
+        let ahead = store
+            .registry_changes_since(delta.revision + 10, 10)
+            .await
+            .unwrap();
+        assert!(ahead.reset);
+        assert_eq!(ahead.upserted.len(), 1);
+        assert_eq!(ahead.removed.len(), 1);
+    }
 }
 
 #[async_trait]
@@ -721,9 +1079,15 @@ impl Store for SqliteStore {
         Ok(())
     }
 
+    async fn set_node_id(&self, peer_id: &str) -> StoreResult<()> {
+        let mut info = self.load_node_info().await?;
+        info.node_id = peer_id.to_string();
+        self.save_node_info(&info).await
+    }
+
     async fn communities(&self) -> StoreResult<Vec<CommunitySummary>> {
         let rows = sqlx::query(
-            "SELECT id, name, description, peers, joined, multiaddr, peer_id FROM communities",
+            "SELECT id, name, description, peers, joined, multiaddr, multiaddrs, last_multiaddr, peer_id, managed, connect_failures, last_error, next_retry_at FROM communities",
         )
         .fetch_all(&self.pool)
         .await?;
@@ -736,7 +1100,14 @@ impl Store for SqliteStore {
                 peers: row.get::<i64, _>("peers") as u32,
                 joined: row.get::<i64, _>("joined") == 1,
                 multiaddr: row.get::<Option<String>, _>("multiaddr"),
+                multiaddrs: parse_tags(row.get("multiaddrs")),
+                last_multiaddr: row.get::<Option<String>, _>("last_multiaddr"),
                 peer_id: row.get::<Option<String>, _>("peer_id"),
+                managed: row.get::<i64, _>("managed") == 1,
+                state: Default::default(),
+                connect_failures: row.get::<i64, _>("connect_failures") as u32,
+                last_error: row.get::<Option<String>, _>("last_error"),
+                next_retry_at: row.get::<Option<i64>, _>("next_retry_at"),
             })
             .collect())
     }
@@ -746,12 +1117,13 @@ impl Store for SqliteStore {
             .id
             .unwrap_or_else(|| format!("community-{}", Uuid::new_v4()));
         sqlx::query(
-            "INSERT INTO communities (id, name, description, peers, joined, multiaddr, peer_id) VALUES (?, ?, ?, 0, 0, ?, ?)",
+            "INSERT INTO communities (id, name, description, peers, joined, multiaddr, multiaddrs, peer_id) VALUES (?, ?, ?, 0, 0, ?, ?, ?)",
         )
         .bind(&id)
         .bind(&req.name)
         .bind(&req.description)
         .bind(&req.multiaddr)
+        .bind(serde_json::to_string(&req.multiaddrs)?)
         .bind(&req.peer_id)
         .execute(&self.pool)
         .await?;
@@ -762,10 +1134,53 @@ impl Store for SqliteStore {
             peers: 0,
             joined: false,
             multiaddr: req.multiaddr,
+            multiaddrs: req.multiaddrs,
+            last_multiaddr: None,
             peer_id: req.peer_id,
+            managed: false,
+            state: Default::default(),
+            connect_failures: 0,
+            last_error: None,
+            next_retry_at: None,
         })
     }
 
+    async fn upsert_community(&self, req: CommunityAddRequest) -> StoreResult<()> {
+        let id = req
+            .id
+            .unwrap_or_else(|| format!("community-{}", Uuid::new_v4()));
+        sqlx::query(
+            r#"
+            INSERT INTO communities (id, name, description, peers, joined, multiaddr, multiaddrs, peer_id)
+            VALUES (?, ?, ?, 0, 0, ?, ?, ?)
+            ON CONFLICT(id) DO UPDATE SET
+                name = excluded.name,
+                description = excluded.description,
+                multiaddr = excluded.multiaddr,
+                multiaddrs = excluded.multiaddrs,
+                peer_id = excluded.peer_id
+            "#,
+        )
+        .bind(&id)
+        .bind(&req.name)
+        .bind(&req.description)
+        .bind(&req.multiaddr)
+        .bind(serde_json::to_string(&req.multiaddrs)?)
+        .bind(&req.peer_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn set_community_managed(&self, id: &str, managed: bool) -> StoreResult<bool> {
+        let result = sqlx::query("UPDATE communities SET managed = ? WHERE id = ?")
+            .bind(if managed { 1 } else { 0 })
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
     async fn remove_community(&self, id: &str) -> StoreResult<bool> {
         let result = sqlx::query("DELETE FROM communities WHERE id = ?")
             .bind(id)
@@ -792,7 +1207,7 @@ impl Store for SqliteStore {
 
     async fn community_by_id(&self, id: &str) -> StoreResult<Option<CommunitySummary>> {
         let row = sqlx::query(
-            "SELECT id, name, description, peers, joined, multiaddr, peer_id FROM communities WHERE id = ?",
+            "SELECT id, name, description, peers, joined, multiaddr, multiaddrs, last_multiaddr, peer_id, managed, connect_failures, last_error, next_retry_at FROM communities WHERE id = ?",
         )
         .bind(id)
         .fetch_optional(&self.pool)
@@ -804,7 +1219,14 @@ impl Store for SqliteStore {
             peers: row.get::<i64, _>("peers") as u32,
             joined: row.get::<i64, _>("joined") == 1,
             multiaddr: row.get::<Option<String>, _>("multiaddr"),
+            multiaddrs: parse_tags(row.get("multiaddrs")),
+            last_multiaddr: row.get::<Option<String>, _>("last_multiaddr"),
             peer_id: row.get::<Option<String>, _>("peer_id"),
+            managed: row.get::<i64, _>("managed") == 1,
+            state: Default::default(),
+            connect_failures: row.get::<i64, _>("connect_failures") as u32,
+            last_error: row.get::<Option<String>, _>("last_error"),
+            next_retry_at: row.get::<Option<i64>, _>("next_retry_at"),
         }))
     }
 
@@ -832,18 +1254,81 @@ impl Store for SqliteStore {
         Ok(result.rows_affected() > 0)
     }
 
+    async fn set_community_last_multiaddr(&self, id: &str, multiaddr: &str) -> StoreResult<bool> {
+        let result = sqlx::query("UPDATE communities SET last_multiaddr = ? WHERE id = ?")
+            .bind(multiaddr)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_community_connection(
+        &self,
+        id: &str,
+        failures: u32,
+        last_error: Option<&str>,
+        next_retry_at: Option<i64>,
+    ) -> StoreResult<bool> {
+        let result = sqlx::query(
+            "UPDATE communities SET connect_failures = ?, last_error = ?, next_retry_at = ? WHERE id = ?",
+        )
+        .bind(failures as i64)
+        .bind(last_error)
+        .bind(next_retry_at)
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_community_peers(&self, id: &str, peers: u32) -> StoreResult<bool> {
+        let result = sqlx::query("UPDATE communities SET peers = ? WHERE id = ?")
+            .bind(peers as i64)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
     async fn community_nodes(&self) -> StoreResult<Vec<CommunityNode>> {
-        let rows =
-            sqlx::query("SELECT peer_id, node_id, status, banned FROM peers WHERE role = 'edge'")
-                .fetch_all(&self.pool)
-                .await?;
+        let rows = sqlx::query(&format!(
+            r#"
+            SELECT peer_id, node_id, node_uuid, name, status, ban_reason, banned_until,
+                   joined_at, last_seen, ({}) AS active_ban,
+                   (SELECT COUNT(*) FROM service_registry s WHERE s.provider_peer = peers.peer_id) AS services
+            FROM peers WHERE role = 'edge' ORDER BY peer_id
+            "#,
+            ACTIVE_BAN
+        ))
+        .bind(chrono::Utc::now().timestamp())
+        .fetch_all(&self.pool)
+        .await?;
         Ok(rows
             .into_iter()
-            .map(|row| CommunityNode {
-                id: row.get("peer_id"),
-                uuid: row.get("node_id"),
-                status: row.get("status"),
-                banned: row.get::<i64, _>("banned") == 1,
+            .map(|row| {
+                let status: String = row.get("status");
+                let node_uuid: String = row.get("node_uuid");
+                let banned = row.get::<i64, _>("active_ban") == 1;
+                CommunityNode {
+                    id: row.get("peer_id"),
+                    uuid: if node_uuid.is_empty() {
+                        row.get("node_id")
+                    } else {
+                        node_uuid
+                    },
+                    name: row.get("name"),
+                    online: status == "online",
+                    status,
+                    banned,
+                    ban_reason: row
+                        .get::<Option<String>, _>("ban_reason")
+                        .filter(|_| banned),
+                    banned_until: row.get::<Option<i64>, _>("banned_until").filter(|_| banned),
+                    joined_at: row.get("joined_at"),
+                    last_seen: row.get("last_seen"),
+                    services: row.get::<i64, _>("services") as u32,
+                }
             })
             .collect())
     }
@@ -873,14 +1358,14 @@ impl Store for SqliteStore {
     ) -> StoreResult<Vec<DiscoveredService>> {
         let rows = if let Some(id) = community_id {
             sqlx::query(
-                "SELECT uuid, name, type, remote_port, provider, description, community_id, provider_addr FROM discovered_services WHERE community_id = ?",
+                "SELECT uuid, name, type, remote_port, provider, description, community_id, provider_addr, online, tags FROM discovered_services WHERE community_id = ?",
             )
             .bind(id)
             .fetch_all(&self.pool)
             .await?
         } else {
             sqlx::query(
-                "SELECT uuid, name, type, remote_port, provider, description, community_id, provider_addr FROM discovered_services",
+                "SELECT uuid, name, type, remote_port, provider, description, community_id, provider_addr, online, tags FROM discovered_services",
             )
             .fetch_all(&self.pool)
             .await?
@@ -897,6 +1382,8 @@ impl Store for SqliteStore {
                 subscribed: None,
                 community_id: row.get("community_id"),
                 provider_addr: row.get("provider_addr"),
+                online: row.get::<i64, _>("online") == 1,
+                tags: parse_tags(row.get("tags")),
             })
             .collect())
     }
@@ -907,10 +1394,11 @@ impl Store for SqliteStore {
         services: Vec<ServiceRegistryItem>,
     ) -> StoreResult<()> {
         for svc in services {
+            let tags = serde_json::to_string(&svc.tags)?;
             sqlx::query(
                 r#"
-                INSERT INTO discovered_services (uuid, name, type, remote_port, provider, description, community_id, provider_addr)
-                VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+                INSERT INTO discovered_services (uuid, name, type, remote_port, provider, description, community_id, provider_addr, online, tags)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
                 ON CONFLICT(uuid) DO UPDATE SET
                     name = excluded.name,
                     type = excluded.type,
@@ -918,7 +1406,9 @@ impl Store for SqliteStore {
                     provider = excluded.provider,
                     description = excluded.description,
                     community_id = excluded.community_id,
-                    provider_addr = excluded.provider_addr
+                    provider_addr = excluded.provider_addr,
+                    online = excluded.online,
+                    tags = excluded.tags
                 "#,
             )
             .bind(svc.uuid)
@@ -929,12 +1419,59 @@ impl Store for SqliteStore {
             .bind(svc.description)
             .bind(community_id)
             .bind(svc.provider_addr)
+            .bind(if svc.online { 1 } else { 0 })
+            .bind(tags)
             .execute(&self.pool)
             .await?;
         }
         Ok(())
     }
 
+    async fn remove_discovered_services(
+        &self,
+        community_id: &str,
+        uuids: &[String],
+    ) -> StoreResult<u64> {
+        let mut removed = 0;
+        for uuid in uuids {
+            let result =
+                sqlx::query("DELETE FROM discovered_services WHERE uuid = ? AND community_id = ?")
+                    .bind(uuid)
+                    .bind(community_id)
+                    .execute(&self.pool)
+                    .await?;
+            removed += result.rows_affected();
+        }
+        Ok(removed)
+    }
+
+    async fn clear_discovered_services(&self, community_id: &str) -> StoreResult<u64> {
+        let result = sqlx::query("DELETE FROM discovered_services WHERE community_id = ?")
+            .bind(community_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn community_sync_revision(&self, id: &str) -> StoreResult<u64> {
+        let row = sqlx::query("SELECT sync_revision FROM communities WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row
+            .map(|row| row.get::<i64, _>("sync_revision") as u64)
+            .unwrap_or(0))
+    }
+
+    async fn set_community_sync_revision(&self, id: &str, revision: u64) -> StoreResult<bool> {
+        let result = sqlx::query("UPDATE communities SET sync_revision = ? WHERE id = ?")
+            .bind(revision as i64)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
     async fn subscribed_services(&self) -> StoreResult<Vec<SubscribedService>> {
         let rows = sqlx::query(
             "SELECT id, name, type, community, remote_addr, local_mapping, status, service_uuid FROM subscribed_services",
@@ -977,7 +1514,7 @@ impl Store for SqliteStore {
 
     async fn published_services(&self) -> StoreResult<Vec<PublishedService>> {
         let rows = sqlx::query(
-            "SELECT id, name, type, port, summary, subscriptions, status, publish_date FROM published_services",
+            "SELECT id, name, type, port, summary, subscriptions, status, publish_date, tags, managed FROM published_services",
         )
         .fetch_all(&self.pool)
         .await?;
@@ -992,13 +1529,15 @@ impl Store for SqliteStore {
                 subscriptions: row.get::<i64, _>("subscriptions") as u32,
                 status: row.get("status"),
                 publish_date: row.get("publish_date"),
+                tags: parse_tags(row.get("tags")),
+                managed: row.get::<i64, _>("managed") == 1,
             })
             .collect())
     }
 
     async fn published_service_by_id(&self, id: &str) -> StoreResult<Option<PublishedService>> {
         let row = sqlx::query(
-            "SELECT id, name, type, port, summary, subscriptions, status, publish_date FROM published_services WHERE id = ?",
+            "SELECT id, name, type, port, summary, subscriptions, status, publish_date, tags, managed FROM published_services WHERE id = ?",
         )
         .bind(id)
         .fetch_optional(&self.pool)
@@ -1012,16 +1551,20 @@ impl Store for SqliteStore {
             subscriptions: row.get::<i64, _>("subscriptions") as u32,
             status: row.get("status"),
             publish_date: row.get("publish_date"),
+            tags: parse_tags(row.get("tags")),
+            managed: row.get::<i64, _>("managed") == 1,
         }))
     }
 
     async fn proxy_status(&self) -> StoreResult<ProxyStatus> {
-        let row = sqlx::query("SELECT enabled, listen_port FROM proxy_status WHERE id = 1")
-            .fetch_one(&self.pool)
-            .await?;
+        let row =
+            sqlx::query("SELECT enabled, listen_port, managed FROM proxy_status WHERE id = 1")
+                .fetch_one(&self.pool)
+                .await?;
         Ok(ProxyStatus {
             enabled: row.get::<i64, _>("enabled") == 1,
             listen_port: row.get::<i64, _>("listen_port") as u16,
+            managed: row.get::<i64, _>("managed") == 1,
         })
     }
 
@@ -1030,18 +1573,7 @@ impl Store for SqliteStore {
             sqlx::query("SELECT session_id, service_id, local_port, remote_peer, state, created_at, last_active FROM sessions")
                 .fetch_all(&self.pool)
                 .await?;
-        Ok(rows
-            .into_iter()
-            .map(|row| SessionInfo {
-                session_id: row.get("session_id"),
-                service_id: row.get("service_id"),
-                local_port: row.get::<i64, _>("local_port") as u16,
-                remote_peer: row.get("remote_peer"),
-                state: row.get("state"),
-                created_at: row.get("created_at"),
-                last_active: row.get("last_active"),
-            })
-            .collect())
+        Ok(rows.iter().map(session_from_row).collect())
     }
 
     async fn upsert_session(&self, session: SessionInfo) -> StoreResult<()> {
@@ -1067,18 +1599,22 @@ impl Store for SqliteStore {
         Ok(())
     }
 
-    async fn cleanup_expired_sessions(&self, timeout_minutes: i64) -> StoreResult<u64> {
-        let result = sqlx::query(
+    async fn cleanup_expired_sessions(
+        &self,
+        timeout_minutes: i64,
+    ) -> StoreResult<Vec<SessionInfo>> {
+        let rows = sqlx::query(
             r#"
             DELETE FROM sessions
             WHERE state = 'connected'
             AND datetime(last_active) < datetime('now', ?)
+            RETURNING session_id, service_id, local_port, remote_peer, state, created_at, last_active
             "#,
         )
         .bind(format!("-{} minutes", timeout_minutes))
-        .execute(&self.pool)
+        .fetch_all(&self.pool)
         .await?;
-        Ok(result.rows_affected())
+        Ok(rows.iter().map(session_from_row).collect())
     }
 
     async fn subscribe_service(&self, req: SubscribeRequest) -> StoreResult<SubscribedService> {
@@ -1128,6 +1664,53 @@ impl Store for SqliteStore {
         Ok(result.rows_affected() > 0)
     }
 
+    async fn update_subscriptions_by_service(
+        &self,
+        service_uuid: &str,
+        communities: &[String],
+        status: &str,
+    ) -> StoreResult<u64> {
+        let mut changed = 0;
+        for community in communities {
+            let result = sqlx::query(
+                "UPDATE subscribed_services SET status = ? WHERE service_uuid = ? AND community = ?",
+            )
+            .bind(status)
+            .bind(service_uuid)
+            .bind(community)
+            .execute(&self.pool)
+            .await?;
+            changed += result.rows_affected();
+        }
+        Ok(changed)
+    }
+
+    async fn update_subscription_endpoints_by_service(
+        &self,
+        service_uuid: &str,
+        communities: &[String],
+        remote_addr: &str,
+    ) -> StoreResult<u64> {
+        let mut changed = 0;
+        for community in communities {
+            let result = sqlx::query(
+                r#"
+                UPDATE subscribed_services
+                SET remote_addr = ?,
+                    status = CASE WHEN status IN ('离线', '已下架') THEN '断开' ELSE status END
+                WHERE service_uuid = ? AND community = ?
+                "#,
+            )
+            .bind(remote_addr)
+            .bind(service_uuid)
+            .bind(community)
+            .execute(&self.pool)
+            .await?;
+            changed += result.rows_affected();
+        }
+        Ok(changed)
+    }
+
     async fn update_subscription_endpoint(
         &self,
         id: &str,
@@ -1146,16 +1729,18 @@ impl Store for SqliteStore {
 
     async fn publish_service(&self, req: PublishRequest) -> StoreResult<PublishedService> {
         let id = req.id.unwrap_or_else(|| format!("pub-{}", Uuid::new_v4()));
+        let tags = serde_json::to_string(&req.tags)?;
         sqlx::query(
             r#"
-            INSERT INTO published_services (id, name, type, port, summary, subscriptions, status, publish_date)
-            VALUES (?, ?, ?, ?, ?, 0, ?, date('now'))
+            INSERT INTO published_services (id, name, type, port, summary, subscriptions, status, publish_date, tags)
+            VALUES (?, ?, ?, ?, ?, 0, ?, date('now'), ?)
             ON CONFLICT(id) DO UPDATE SET
                 name = excluded.name,
                 type = excluded.type,
                 port = excluded.port,
                 summary = excluded.summary,
-                status = excluded.status
+                status = excluded.status,
+                tags = excluded.tags
             "#,
         )
         .bind(&id)
@@ -1164,9 +1749,10 @@ impl Store for SqliteStore {
         .bind(req.port as i64)
         .bind(&req.summary)
         .bind("在线")
+        .bind(tags)
         .execute(&self.pool)
         .await?;
-        let row = sqlx::query("SELECT publish_date FROM published_services WHERE id = ?")
+        let row = sqlx::query("SELECT publish_date, managed FROM published_services WHERE id = ?")
             .bind(&id)
             .fetch_one(&self.pool)
             .await?;
@@ -1180,6 +1766,8 @@ impl Store for SqliteStore {
             subscriptions: 0,
             status: "在线".into(),
             publish_date,
+            tags: req.tags,
+            managed: row.get::<i64, _>("managed") == 1,
         })
     }
 
@@ -1199,21 +1787,48 @@ impl Store for SqliteStore {
         Ok(result.rows_affected() > 0)
     }
 
-    async fn set_service_announced(&self, id: &str, announced: bool) -> StoreResult<bool> {
-        let result = sqlx::query("UPDATE service_registry SET announced = ? WHERE uuid = ?")
-            .bind(if announced { 1 } else { 0 })
+    async fn set_published_managed(&self, id: &str, managed: bool) -> StoreResult<bool> {
+        let result = sqlx::query("UPDATE published_services SET managed = ? WHERE id = ?")
+            .bind(if managed { 1 } else { 0 })
             .bind(id)
             .execute(&self.pool)
             .await?;
         Ok(result.rows_affected() > 0)
     }
 
-    async fn set_node_ban(&self, id: &str, banned: bool) -> StoreResult<bool> {
-        let result = sqlx::query("UPDATE peers SET banned = ? WHERE peer_id = ?")
-            .bind(if banned { 1 } else { 0 })
+    async fn set_service_announced(&self, id: &str, announced: bool) -> StoreResult<bool> {
+        let result = sqlx::query("UPDATE service_registry SET announced = ? WHERE uuid = ?")
+            .bind(if announced { 1 } else { 0 })
             .bind(id)
             .execute(&self.pool)
             .await?;
+        if result.rows_affected() > 0 {
+            self.bump_registry_revision(id).await?;
+        }
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_node_ban(
+        &self,
+        id: &str,
+        banned: bool,
+        reason: Option<&str>,
+        until: Option<i64>,
+    ) -> StoreResult<bool> {
+        let (reason, until) = if banned {
+            (reason, until)
+        } else {
+            (None, None)
+        };
+        let result = sqlx::query(
+            "UPDATE peers SET banned = ?, ban_reason = ?, banned_until = ? WHERE peer_id = ?",
+        )
+        .bind(if banned { 1 } else { 0 })
+        .bind(reason)
+        .bind(until)
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
         Ok(result.rows_affected() > 0)
     }
 
@@ -1225,26 +1840,49 @@ impl Store for SqliteStore {
         Ok(())
     }
 
+    async fn set_proxy_listen_port(&self, port: u16) -> StoreResult<()> {
+        sqlx::query("UPDATE proxy_status SET listen_port = ? WHERE id = 1")
+            .bind(port as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_proxy_managed(&self, managed: bool) -> StoreResult<()> {
+        sqlx::query("UPDATE proxy_status SET managed = ? WHERE id = 1")
+            .bind(if managed { 1 } else { 0 })
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
     async fn upsert_peer(
         &self,
         peer_id: &str,
         node_id: &str,
+        node_uuid: &str,
+        name: &str,
         role: &str,
         status: &str,
     ) -> StoreResult<()> {
         sqlx::query(
             r#"
-            INSERT INTO peers (peer_id, node_id, role, status, banned, last_seen)
-            VALUES (?, ?, ?, ?, 0, datetime('now'))
+            INSERT INTO peers (peer_id, node_id, node_uuid, name, role, status, banned, joined_at, last_seen)
+            VALUES (?, ?, ?, ?, ?, ?, 0, datetime('now'), datetime('now'))
             ON CONFLICT(peer_id) DO UPDATE SET
                 node_id = excluded.node_id,
+                node_uuid = CASE WHEN excluded.node_uuid = '' THEN peers.node_uuid ELSE excluded.node_uuid END,
+                name = excluded.name,
                 role = excluded.role,
                 status = excluded.status,
+                joined_at = COALESCE(peers.joined_at, excluded.joined_at),
                 last_seen = datetime('now')
             "#,
         )
         .bind(peer_id)
         .bind(node_id)
+        .bind(node_uuid)
+        .bind(name)
         .bind(role)
         .bind(status)
         .execute(&self.pool)
@@ -1252,6 +1890,82 @@ impl Store for SqliteStore {
         Ok(())
     }
 
+    async fn set_peer_status(&self, peer_id: &str, status: &str) -> StoreResult<bool> {
+        let result = sqlx::query(
+            "UPDATE peers SET status = ?, last_seen = datetime('now') WHERE peer_id = ?",
+        )
+        .bind(status)
+        .bind(peer_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn admission_mode(&self) -> StoreResult<AdmissionMode> {
+        let row = sqlx::query("SELECT admission FROM node_config WHERE id = 1")
+            .fetch_one(&self.pool)
+            .await?;
+        row.get::<String, _>("admission").parse()
+    }
+
+    async fn set_admission_mode(&self, mode: AdmissionMode) -> StoreResult<()> {
+        sqlx::query("UPDATE node_config SET admission = ? WHERE id = 1")
+            .bind(mode.as_str())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn record_join_request(
+        &self,
+        peer_id: &str,
+        node_id: &str,
+        name: &str,
+    ) -> StoreResult<JoinRequest> {
+        let row = sqlx::query(
+            r#"
+            INSERT INTO join_requests (peer_id, node_id, name, status, requested_at)
+            VALUES (?, ?, ?, 'pending', datetime('now'))
+            ON CONFLICT(peer_id) DO UPDATE SET
+                node_id = excluded.node_id,
+                name = excluded.name
+            RETURNING peer_id, node_id, name, status, requested_at
+            "#,
+        )
+        .bind(peer_id)
+        .bind(node_id)
+        .bind(name)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(join_request_from_row(&row))
+    }
+
+    async fn join_requests(&self) -> StoreResult<Vec<JoinRequest>> {
+        let rows = sqlx::query(
+            "SELECT peer_id, node_id, name, status, requested_at FROM join_requests ORDER BY requested_at, peer_id",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.iter().map(join_request_from_row).collect())
+    }
+
+    async fn set_join_request_status(&self, peer_id: &str, status: &str) -> StoreResult<bool> {
+        let result = sqlx::query("UPDATE join_requests SET status = ? WHERE peer_id = ?")
+            .bind(status)
+            .bind(peer_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn remove_join_request(&self, peer_id: &str) -> StoreResult<bool> {
+        let result = sqlx::query("DELETE FROM join_requests WHERE peer_id = ?")
+            .bind(peer_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
     async fn peer_role(&self, peer_id: &str) -> StoreResult<Option<String>> {
         let row = sqlx::query("SELECT role FROM peers WHERE peer_id = ?")
             .bind(peer_id)
@@ -1261,20 +1975,47 @@ impl Store for SqliteStore {
     }
 
     async fn peer_is_banned(&self, peer_id: &str) -> StoreResult<bool> {
-        let row = sqlx::query("SELECT banned FROM peers WHERE peer_id = ?")
-            .bind(peer_id)
-            .fetch_optional(&self.pool)
+        Ok(self.peer_ban(peer_id).await?.is_some())
+    }
+
+    async fn peer_ban(&self, peer_id: &str) -> StoreResult<Option<PeerBan>> {
+        let row = sqlx::query(&format!(
+            "SELECT ban_reason, banned_until FROM peers WHERE peer_id = ? AND {}",
+            ACTIVE_BAN
+        ))
+        .bind(peer_id)
+        .bind(chrono::Utc::now().timestamp())
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|row| PeerBan {
+            reason: row.get("ban_reason"),
+            until: row.get("banned_until"),
+        }))
+    }
+
+    async fn banned_peers(&self) -> StoreResult<Vec<String>> {
+        let rows = sqlx::query(&format!("SELECT peer_id FROM peers WHERE {}", ACTIVE_BAN))
+            .bind(chrono::Utc::now().timestamp())
+            .fetch_all(&self.pool)
             .await?;
-        Ok(row
-            .map(|row| row.get::<i64, _>("banned") == 1)
-            .unwrap_or(false))
+        Ok(rows.into_iter().map(|row| row.get("peer_id")).collect())
+    }
+
+    async fn peers_by_node_uuid(&self, node_uuid: &str) -> StoreResult<Vec<String>> {
+        let rows = sqlx::query("SELECT peer_id FROM peers WHERE node_uuid = ? ORDER BY peer_id")
+            .bind(node_uuid)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(|row| row.get("peer_id")).collect())
     }
 
     async fn upsert_service_registry(&self, service: ServiceRegistryItem) -> StoreResult<()> {
+        let tags = serde_json::to_string(&service.tags)?;
+        let uuid = service.uuid.clone();
         sqlx::query(
             r#"
-            INSERT INTO service_registry (uuid, name, type, port, description, provider_peer, provider_addr, online, announced, updated_at)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?, 1, datetime('now'))
+            INSERT INTO service_registry (uuid, name, type, port, description, provider_peer, provider_addr, online, announced, updated_at, tags)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, 1, datetime('now'), ?)
             ON CONFLICT(uuid) DO UPDATE SET
                 name = excluded.name,
                 type = excluded.type,
@@ -1283,7 +2024,8 @@ impl Store for SqliteStore {
                 provider_peer = excluded.provider_peer,
                 provider_addr = excluded.provider_addr,
                 online = excluded.online,
-                updated_at = datetime('now')
+                updated_at = datetime('now'),
+                tags = excluded.tags
             "#,
         )
         .bind(service.uuid)
@@ -1294,8 +2036,10 @@ impl Store for SqliteStore {
         .bind(service.provider_peer)
         .bind(service.provider_addr)
         .bind(if service.online { 1 } else { 0 })
+        .bind(tags)
         .execute(&self.pool)
         .await?;
+        self.bump_registry_revision(&uuid).await?;
         Ok(())
     }
 
@@ -1304,28 +2048,152 @@ impl Store for SqliteStore {
             .bind(uuid)
             .execute(&self.pool)
             .await?;
+        if result.rows_affected() > 0 {
+            self.bump_registry_revision(uuid).await?;
+        }
         Ok(result.rows_affected() > 0)
     }
 
+    async fn remove_provider_services(&self, provider_peer: &str) -> StoreResult<Vec<String>> {
+        let uuids: Vec<String> =
+            sqlx::query("DELETE FROM service_registry WHERE provider_peer = ? RETURNING uuid")
+                .bind(provider_peer)
+                .fetch_all(&self.pool)
+                .await?
+                .into_iter()
+                .map(|row| row.get("uuid"))
+                .collect();
+        for uuid in &uuids {
+            self.bump_registry_revision(uuid).await?;
+        }
+        Ok(uuids)
+    }
+
+    async fn set_provider_online(
+        &self,
+        provider_peer: &str,
+        online: bool,
+    ) -> StoreResult<Vec<String>> {
+        let online = if online { 1 } else { 0 };
+        let uuids: Vec<String> = sqlx::query(
+            r#"
+            UPDATE service_registry SET online = ?, updated_at = datetime('now')
+            WHERE provider_peer = ? AND online != ?
+            RETURNING uuid
+            "#,
+        )
+        .bind(online)
+        .bind(provider_peer)
+        .bind(online)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| row.get("uuid"))
+        .collect();
+        for uuid in &uuids {
+            self.bump_registry_revision(uuid).await?;
+        }
+        Ok(uuids)
+    }
+
+    async fn provider_service_count(&self, provider_peer: &str) -> StoreResult<u64> {
+        let row =
+            sqlx::query("SELECT COUNT(*) as count FROM service_registry WHERE provider_peer = ?")
+                .bind(provider_peer)
+                .fetch_one(&self.pool)
+                .await?;
+        let count: i64 = row.get("count");
+        Ok(count as u64)
+    }
+
     async fn list_service_registry(&self) -> StoreResult<Vec<ServiceRegistryItem>> {
         let rows = sqlx::query(
-            "SELECT uuid, name, type, port, description, provider_peer, provider_addr, online FROM service_registry WHERE announced = 1 AND online = 1",
+            "SELECT uuid, name, type, port, description, provider_peer, provider_addr, online, tags FROM service_registry WHERE announced = 1 AND online = 1",
         )
         .fetch_all(&self.pool)
         .await?;
-        Ok(rows
+        Ok(rows.into_iter().map(registry_item_from_row).collect())
+    }
+
+    async fn search_service_registry(
+        &self,
+        query: &ServiceQuery,
+    ) -> StoreResult<ServicePage<ServiceRegistryItem>> {
+        let sql = if query.online_only {
+            "SELECT uuid, name, type, port, description, provider_peer, provider_addr, online, tags FROM service_registry WHERE announced = 1 AND online = 1 ORDER BY name, uuid"
+        } else {
+            "SELECT uuid, name, type, port, description, provider_peer, provider_addr, online, tags FROM service_registry WHERE announced = 1 ORDER BY name, uuid"
+        };
+        let rows = sqlx::query(sql).fetch_all(&self.pool).await?;
+        let matched: Vec<ServiceRegistryItem> = rows
             .into_iter()
-            .map(|row| ServiceRegistryItem {
-                uuid: row.get("uuid"),
-                name: row.get("name"),
-                r#type: row.get("type"),
-                port: row.get::<i64, _>("port") as u16,
-                description: row.get("description"),
-                provider_peer: row.get("provider_peer"),
-                provider_addr: row.get("provider_addr"),
-                online: row.get::<i64, _>("online") == 1,
+            .map(registry_item_from_row)
+            .filter(|item| {
+                query.matches(
+                    &item.name,
+                    &item.description,
+                    &item.r#type,
+                    &item.provider_peer,
+                    &item.tags,
+                )
             })
-            .collect())
+            .collect();
+        Ok(query.paginate(matched, |item| (item.name.as_str(), item.uuid.as_str())))
+    }
+
+    async fn registry_changes_since(
+        &self,
+        since_revision: u64,
+        limit: u32,
+    ) -> StoreResult<RegistryChanges> {
+        let row = sqlx::query(
+            "SELECT COALESCE(MAX(revision), 0) as revision FROM service_registry_revisions",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        let current = row.get::<i64, _>("revision") as u64;
+        let reset = since_revision > current;
+        let since = if reset { 0 } else { since_revision };
+
+        let rows = sqlx::query(
+            r#"
+            SELECT v.uuid, v.revision,
+                   r.name, r.type, r.port, r.description, r.provider_peer, r.provider_addr,
+                   r.online, r.announced, r.tags
+            FROM service_registry_revisions v
+            LEFT JOIN service_registry r ON r.uuid = v.uuid
+            WHERE v.revision > ?
+            ORDER BY v.revision
+            LIMIT ?
+            "#,
+        )
+        .bind(since as i64)
+        .bind(limit as i64 + 1)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let more = rows.len() > limit as usize;
+        let mut changes = RegistryChanges {
+            revision: since,
+            reset,
+            more,
+            ..Default::default()
+        };
+        for row in rows.into_iter().take(limit as usize) {
+            changes.revision = row.get::<i64, _>("revision") as u64;
+            let uuid: String = row.get("uuid");
+            let visible = row.get::<Option<i64>, _>("online") == Some(1)
+                && row.get::<Option<i64>, _>("announced") == Some(1);
+            if visible {
+                changes.upserted.push(registry_item_from_row(row));
+            } else {
+                changes.removed.push(uuid);
+            }
+        }
+        if !more {
+            changes.revision = changes.revision.max(current);
+        }
+        Ok(changes)
     }
 
     async fn resolve_service_registry(
@@ -1333,21 +2201,12 @@ impl Store for SqliteStore {
         uuid: &str,
     ) -> StoreResult<Option<ServiceRegistryItem>> {
         let row = sqlx::query(
-            "SELECT uuid, name, type, port, description, provider_peer, provider_addr, online FROM service_registry WHERE uuid = ?",
+            "SELECT uuid, name, type, port, description, provider_peer, provider_addr, online, tags FROM service_registry WHERE uuid = ?",
         )
         .bind(uuid)
         .fetch_optional(&self.pool)
         .await?;
-        Ok(row.map(|row| ServiceRegistryItem {
-            uuid: row.get("uuid"),
-            name: row.get("name"),
-            r#type: row.get("type"),
-            port: row.get::<i64, _>("port") as u16,
-            description: row.get("description"),
-            provider_peer: row.get("provider_peer"),
-            provider_addr: row.get("provider_addr"),
-            online: row.get::<i64, _>("online") == 1,
-        }))
+        Ok(row.map(registry_item_from_row))
     }
 
     async fn record_subscription(
@@ -1369,6 +2228,81 @@ impl Store for SqliteStore {
         Ok(())
     }
 
+    async fn service_subscribers(&self, service_uuid: &str) -> StoreResult<Vec<String>> {
+        let rows = sqlx::query(
+            "SELECT subscriber_peer FROM service_subscriptions WHERE service_uuid = ? ORDER BY created_at, rowid",
+        )
+        .bind(service_uuid)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| row.get("subscriber_peer"))
+            .collect())
+    }
+
+    async fn hand_over_peer(&self, old_peer: &str, new_peer: &str) -> StoreResult<u64> {
+        let uuids: Vec<String> =
+            sqlx::query("SELECT uuid FROM service_registry WHERE provider_peer = ?")
+                .bind(old_peer)
+                .fetch_all(&self.pool)
+                .await?
+                .into_iter()
+                .map(|row| row.get("uuid"))
+                .collect();
+        sqlx::query(
+            "UPDATE service_registry SET provider_peer = ?, updated_at = datetime('now') WHERE provider_peer = ?",
+        )
+        .bind(new_peer)
+        .bind(old_peer)
+        .execute(&self.pool)
+        .await?;
+        for uuid in &uuids {
+            self.bump_registry_revision(uuid).await?;
+        }
+        // Rows keep their rowid, so subscriber order is unchanged; services the
+        // new peer already subscribed to keep that subscription instead
+        sqlx::query(
+            "UPDATE OR IGNORE service_subscriptions SET subscriber_peer = ? WHERE subscriber_peer = ?",
+        )
+        .bind(new_peer)
+        .bind(old_peer)
+        .execute(&self.pool)
+        .await?;
+        sqlx::query("DELETE FROM service_subscriptions WHERE subscriber_peer = ?")
+            .bind(old_peer)
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("DELETE FROM peers WHERE peer_id = ?")
+            .bind(old_peer)
+            .execute(&self.pool)
+            .await?;
+        Ok(uuids.len() as u64)
+    }
+
+    async fn redeem_invite(&self, invite_id: &str, peer_id: &str) -> StoreResult<bool> {
+        sqlx::query(
+            "INSERT OR IGNORE INTO invite_redemptions (invite_id, peer_id, redeemed_at) VALUES (?, ?, datetime('now'))",
+        )
+        .bind(invite_id)
+        .bind(peer_id)
+        .execute(&self.pool)
+        .await?;
+        let row = sqlx::query("SELECT peer_id FROM invite_redemptions WHERE invite_id = ?")
+            .bind(invite_id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(row.get::<String, _>("peer_id") == peer_id)
+    }
+
+    async fn invite_redeemed_by(&self, invite_id: &str) -> StoreResult<Option<String>> {
+        let row = sqlx::query("SELECT peer_id FROM invite_redemptions WHERE invite_id = ?")
+            .bind(invite_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|row| row.get("peer_id")))
+    }
+
     async fn secure_routes(&self) -> StoreResult<Vec<SecureRoute>> {
         let rows = sqlx::query(
             "SELECT id, subscription_id, relay_peers, local_port, status FROM secure_routes",
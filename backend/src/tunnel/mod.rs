@@ -1,9 +1,9 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use std::{collections::HashSet, sync::OnceLock};
 use tokio::{io::copy_bidirectional, net::TcpListener, sync::Mutex};
 use tokio_util::compat::FuturesAsyncReadCompatExt;
 
-use libp2p::PeerId;
+use libp2p::{PeerId, Stream};
 
 use crate::p2p::NodeHandle;
 
@@ -13,24 +13,41 @@ fn mapping_registry() -> &'static Mutex<HashSet<u16>> {
     ACTIVE_MAPPINGS.get_or_init(|| Mutex::new(HashSet::new()))
 }
 
+/// Bind the local listener for a mapping; `None` when the port is already mapped.
+/// Binding up front lets callers report a port that is already in use.
+async fn bind_mapping(local_port: u16) -> Result<Option<TcpListener>> {
+    let mut registry = mapping_registry().lock().await;
+    if registry.contains(&local_port) {
+        return Ok(None);
+    }
+    let listener = TcpListener::bind(("0.0.0.0", local_port))
+        .await
+        .map_err(|err| anyhow!("本地端口 {} 监听失败: {}", local_port, err))?;
+    registry.insert(local_port);
+    Ok(Some(listener))
+}
+
 pub async fn ensure_stream_mapping(
     local_port: u16,
     peer_id: PeerId,
     service_uuid: String,
     p2p: NodeHandle,
 ) -> Result<()> {
-    let mut registry = mapping_registry().lock().await;
-    if registry.contains(&local_port) {
+    let Some(listener) = bind_mapping(local_port).await? else {
         return Ok(());
-    }
-    registry.insert(local_port);
-    drop(registry);
+    };
+    serve_stream_mapping(listener, peer_id, service_uuid, p2p);
+    Ok(())
+}
 
+/// Forward every connection accepted on `listener` to the service on `peer_id`.
+pub fn serve_stream_mapping(
+    listener: TcpListener,
+    peer_id: PeerId,
+    service_uuid: String,
+    p2p: NodeHandle,
+) {
     tokio::spawn(async move {
-        let listener = match TcpListener::bind(("0.0.0.0", local_port)).await {
-            Ok(listener) => listener,
-            Err(_) => return,
-        };
         loop {
             let (mut inbound, _) = match listener.accept().await {
                 Ok(pair) => pair,
@@ -47,8 +64,6 @@ pub async fn ensure_stream_mapping(
             });
         }
     });
-
-    Ok(())
 }
 
 pub async fn ensure_secure_mapping(
@@ -58,18 +73,11 @@ pub async fn ensure_secure_mapping(
     relay_chain: Vec<String>,
     p2p: NodeHandle,
 ) -> Result<()> {
-    let mut registry = mapping_registry().lock().await;
-    if registry.contains(&local_port) {
+    let Some(listener) = bind_mapping(local_port).await? else {
         return Ok(());
-    }
-    registry.insert(local_port);
-    drop(registry);
+    };
 
     tokio::spawn(async move {
-        let listener = match TcpListener::bind(("0.0.0.0", local_port)).await {
-            Ok(listener) => listener,
-            Err(_) => return,
-        };
         loop {
             let (mut inbound, _) = match listener.accept().await {
                 Ok(pair) => pair,
@@ -92,6 +100,17 @@ pub async fn ensure_secure_mapping(
     Ok(())
 }
 
+/// Bridge an opened service stream to stdin/stdout until both sides close,
+/// so the process can serve as an SSH `ProxyCommand`.
+pub async fn bridge_stdio(stream: Stream) -> Result<()> {
+    let mut stdio = tokio::io::join(tokio::io::stdin(), tokio::io::stdout());
+    let mut stream = stream.compat();
+    copy_bidirectional(&mut stdio, &mut stream)
+        .await
+        .map_err(|err| anyhow!("隧道转发中断: {}", err))?;
+    Ok(())
+}
+
 fn build_relay_protocol(service_uuid: &str, relay_chain: &[String]) -> String {
     if relay_chain.is_empty() {
         service_uuid.to_string()
@@ -0,0 +1,128 @@
+-- Database as written by Porta 0.1.0 (before schema versioning)
+CREATE TABLE node_config (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    node_id TEXT NOT NULL,
+    uuid TEXT NOT NULL,
+    key_path TEXT NOT NULL,
+    tcp_listen_enable INTEGER NOT NULL,
+    tcp_listen_port INTEGER NOT NULL,
+    quci_listen_enable INTEGER NOT NULL,
+    quci_listen_port INTEGER NOT NULL,
+    external_addr TEXT NOT NULL,
+    mdns_enable INTEGER NOT NULL,
+    dht_enable INTEGER NOT NULL
+);
+CREATE TABLE communities (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL,
+    peers INTEGER NOT NULL,
+    joined INTEGER NOT NULL,
+    multiaddr TEXT,
+    peer_id TEXT
+);
+CREATE TABLE community_nodes (
+    id TEXT PRIMARY KEY,
+    uuid TEXT NOT NULL,
+    status TEXT NOT NULL,
+    banned INTEGER NOT NULL
+);
+CREATE TABLE community_services (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    uuid TEXT NOT NULL,
+    protocol TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    online INTEGER NOT NULL,
+    announced INTEGER NOT NULL
+);
+CREATE TABLE discovered_services (
+    uuid TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    type TEXT NOT NULL,
+    remote_port INTEGER NOT NULL,
+    provider TEXT NOT NULL,
+    description TEXT NOT NULL,
+    community_id TEXT NOT NULL,
+    provider_addr TEXT
+);
+CREATE TABLE subscribed_services (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    type TEXT NOT NULL,
+    community TEXT NOT NULL,
+    remote_addr TEXT NOT NULL,
+    local_mapping TEXT NOT NULL,
+    status TEXT NOT NULL,
+    service_uuid TEXT
+);
+CREATE TABLE published_services (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    type TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    summary TEXT NOT NULL,
+    subscriptions INTEGER NOT NULL,
+    status TEXT NOT NULL,
+    publish_date TEXT NOT NULL
+);
+CREATE TABLE proxy_status (
+    id INTEGER PRIMARY KEY,
+    enabled INTEGER NOT NULL,
+    listen_port INTEGER NOT NULL
+);
+CREATE TABLE sessions (
+    session_id TEXT PRIMARY KEY,
+    service_id TEXT NOT NULL,
+    local_port INTEGER NOT NULL,
+    remote_peer TEXT NOT NULL,
+    state TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    last_active TEXT NOT NULL
+);
+CREATE TABLE peers (
+    peer_id TEXT PRIMARY KEY,
+    node_id TEXT NOT NULL,
+    role TEXT NOT NULL,
+    status TEXT NOT NULL,
+    banned INTEGER NOT NULL,
+    last_seen TEXT NOT NULL
+);
+CREATE TABLE service_registry (
+    uuid TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    type TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    description TEXT NOT NULL,
+    provider_peer TEXT NOT NULL,
+    provider_addr TEXT NOT NULL,
+    online INTEGER NOT NULL,
+    announced INTEGER NOT NULL,
+    updated_at TEXT NOT NULL
+);
+CREATE TABLE service_subscriptions (
+    service_uuid TEXT NOT NULL,
+    subscriber_peer TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (service_uuid, subscriber_peer)
+);
+CREATE TABLE secure_routes (
+    id TEXT PRIMARY KEY,
+    subscription_id TEXT NOT NULL,
+    relay_peers TEXT NOT NULL,
+    local_port INTEGER NOT NULL,
+    status TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+INSERT INTO node_config VALUES (1, '旧节点', '12D3KooWFixtureNode', 'b1c2d3e4-0000-4000-8000-000000000001', 'porta.node.key', 1, 4001, 1, 0, '["/ip4/203.0.113.7"]', 1, 1);
+INSERT INTO communities VALUES ('dev', 'Dev', '开发社区', 3, 1, '/ip4/203.0.113.10/tcp/9000/p2p/12D3KooWFixtureCommunity', '12D3KooWFixtureCommunity');
+INSERT INTO discovered_services VALUES ('svc-ssh', 'ssh', 'TCP', 22, '12D3KooWFixtureProvider', '跳板机', 'dev', '203.0.113.20');
+INSERT INTO subscribed_services VALUES ('sub-1', 'ssh', 'TCP', 'dev', '203.0.113.20:22', 'localhost:2222', '断开', 'svc-ssh');
+INSERT INTO published_services VALUES ('pub-web', 'Web', 'HTTP', 8080, '站点', 2, '在线', '2025-01-01');
+INSERT INTO proxy_status VALUES (1, 1, 7890);
+INSERT INTO sessions VALUES ('sess-sub-1', 'sub-1', 2222, '203.0.113.20:22', 'closed', '2025-01-01 00:00:00', '2025-01-01 00:00:00');
+INSERT INTO peers VALUES ('12D3KooWFixtureProvider', 'provider', 'edge', 'online', 0, '2025-01-01 00:00:00');
+INSERT INTO service_registry VALUES ('svc-ssh', 'ssh', 'TCP', 22, '跳板机', '12D3KooWFixtureProvider', '203.0.113.20', 1, 1, '2025-01-01 00:00:00');
+INSERT INTO service_subscriptions VALUES ('svc-ssh', '12D3KooWFixtureSubscriber', '2025-01-01 00:00:00');
+INSERT INTO secure_routes VALUES ('route-1', 'sub-1', '["12D3KooWFixtureRelay"]', 2223, '断开', '2025-01-01 00:00:00');
@@ -0,0 +1,85 @@
+//! Upgrade databases written by every released version to the current schema.
+//!
+//! Each file in `tests/fixtures/sqlite/` is a database as a released version
+//! left it, holding the same sample rows. Add one per release whose schema changed.
+
+use std::path::{Path, PathBuf};
+
+use porta_backend::{
+    migrations::{schema_version, LATEST_VERSION},
+    state::{SqliteStore, Store},
+};
+use sqlx::{sqlite::SqliteConnectOptions, SqlitePool};
+
+fn fixtures() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sqlite");
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sql"))
+        .collect();
+    files.sort();
+    files
+}
+
+async fn open(path: &Path) -> SqlitePool {
+    let options = SqliteConnectOptions::new()
+        .filename(path)
+        .create_if_missing(true);
+    SqlitePool::connect_with(options).await.unwrap()
+}
+
+fn backups(dir: &Path) -> usize {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .path()
+                .to_string_lossy()
+                .ends_with(".bak")
+        })
+        .count()
+}
+
+#[tokio::test]
+async fn released_databases_upgrade_to_latest_schema() {
+    let fixtures = fixtures();
+    assert!(!fixtures.is_empty());
+    for fixture in fixtures {
+        let dir = std::env::temp_dir().join(format!("porta-migrate-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("porta.db");
+        let pool = open(&db_path).await;
+        let sql = std::fs::read_to_string(&fixture).unwrap();
+        sqlx::query(&sql).execute(&pool).await.unwrap();
+        pool.close().await;
+
+        let store = SqliteStore::new(db_path.to_str().unwrap()).await.unwrap();
+        let name = fixture.display();
+        assert_eq!(store.node_info().await.unwrap().name, "旧节点", "{}", name);
+        let communities = store.communities().await.unwrap();
+        let dev = communities.iter().find(|c| c.id == "dev").unwrap();
+        assert!(dev.joined && !dev.managed, "{}", name);
+        assert_eq!(store.community_sync_revision("dev").await.unwrap(), 0);
+        let published = store.published_service_by_id("pub-web").await.unwrap();
+        assert!(published.unwrap().tags.is_empty(), "{}", name);
+        assert!(store.find_subscription("sub-1").await.unwrap().is_some());
+        assert!(store.find_secure_route("route-1").await.unwrap().is_some());
+        assert_eq!(store.proxy_status().await.unwrap().listen_port, 7890);
+        let registry = store.list_service_registry().await.unwrap();
+        assert!(registry.iter().any(|s| s.uuid == "svc-ssh"), "{}", name);
+        drop(store);
+
+        let pool = open(&db_path).await;
+        assert_eq!(schema_version(&pool).await.unwrap(), LATEST_VERSION);
+        pool.close().await;
+        assert_eq!(backups(&dir), 1, "{}", name);
+
+        // Reopening an up-to-date database neither migrates nor backs up again
+        SqliteStore::new(db_path.to_str().unwrap()).await.unwrap();
+        assert_eq!(backups(&dir), 1, "{}", name);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,5 +1,9 @@
 use axum::{body, body::Body, http::Request};
-use porta_backend::create_app;
+use libp2p::futures::StreamExt;
+use porta_backend::{
+    create_app, create_app_with, events::PortaEvent, models::ServiceRegistryItem, router,
+    state::AppState, BackendConfig,
+};
 use serde_json::{json, Value};
 use tower::util::ServiceExt;
 
@@ -11,6 +15,36 @@ fn setup_env() {
 // Node Configuration Tests
 // ===========================================================================
 
+#[tokio::test]
+async fn create_app_with_should_run_independent_nodes() {
+    let mut ids = Vec::new();
+    for (name, role) in [("edge-a", "edge"), ("community-b", "community")] {
+        let config = BackendConfig {
+            role: role.into(),
+            db_path: ":memory:".into(),
+            node_name: Some(name.into()),
+            ..Default::default()
+        };
+        let app = create_app_with(config).await.unwrap();
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/porta/node/info")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let bytes = body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let json: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(json["data"]["name"], name);
+        ids.push(json["data"]["node_id"].as_str().unwrap().to_string());
+    }
+    assert_ne!(ids[0], ids[1]);
+}
+
 #[tokio::test]
 async fn node_info_should_return_name() {
     setup_env();
@@ -367,6 +401,119 @@ async fn service_discover_returns_list() {
     assert!(json.get("data").is_some());
 }
 
+#[tokio::test]
+async fn service_discover_accepts_filters() {
+    setup_env();
+    let app = create_app().await;
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/porta/service/discover?q=web&type=HTTP&tags=a,b&onlineOnly=false&offset=0&limit=10")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let bytes = body::to_bytes(response.into_body(), 1024 * 1024)
+        .await
+        .unwrap();
+    let json: Value = serde_json::from_slice(&bytes).unwrap();
+    assert!(json["data"]["items"].is_array());
+}
+
+#[tokio::test]
+async fn service_discover_pages_by_cursor() {
+    let state = AppState::with_config(BackendConfig {
+        db_path: ":memory:".into(),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let cached = ["alpha", "beta", "gamma"]
+        .into_iter()
+        .map(|name| ServiceRegistryItem {
+            uuid: format!("svc-{}", name),
+            name: name.into(),
+            r#type: "TCP".into(),
+            port: 22,
+            description: String::new(),
+            provider_peer: "peer-1".into(),
+            provider_addr: String::new(),
+            online: true,
+            tags: Vec::new(),
+        })
+        .collect();
+    state
+        .store
+        .upsert_discovered_services("dev", cached)
+        .await
+        .unwrap();
+    let app = router(state);
+
+    let mut names = Vec::new();
+    let mut uri = "/porta/service/discover?limit=2".to_string();
+    for _ in 0..2 {
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let bytes = body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let json: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(json["data"]["total"], 3);
+        for item in json["data"]["items"].as_array().unwrap() {
+            names.push(item["name"].as_str().unwrap().to_string());
+        }
+        match json["data"]["next_cursor"].as_str() {
+            Some(cursor) => {
+                let cursor: String = cursor
+                    .bytes()
+                    .map(|b| match b {
+                        b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' => (b as char).to_string(),
+                        _ => format!("%{:02X}", b),
+                    })
+                    .collect();
+                uri = format!("/porta/service/discover?limit=2&cursor={}", cursor);
+            }
+            None => break,
+        }
+    }
+    assert_eq!(names, ["alpha", "beta", "gamma"]);
+}
+
+#[tokio::test]
+async fn service_publish_keeps_tags() {
+    setup_env();
+    let app = create_app().await;
+    let payload = json!({
+        "name": "Tagged",
+        "type": "HTTP",
+        "port": 8080,
+        "summary": "tagged service",
+        "tags": [" web ", "Web", "metrics", ""]
+    });
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/porta/service/publish")
+                .header("content-type", "application/json")
+                .body(Body::from(payload.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let bytes = body::to_bytes(response.into_body(), 1024 * 1024)
+        .await
+        .unwrap();
+    let json: Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(json["data"]["tags"], json!(["web", "metrics"]));
+}
+
 #[tokio::test]
 async fn service_subscriptions_returns_list() {
     setup_env();
@@ -883,3 +1030,131 @@ async fn api_response_has_correct_format() {
     assert!(json.get("data").is_some());
     assert_eq!(json["code"], 0);
 }
+
+// ===========================================================================
+// Event Stream Tests
+// ===========================================================================
+
+#[tokio::test]
+async fn events_should_open_sse_stream() {
+    setup_env();
+    let app = create_app().await;
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/porta/events?types=session_opened,session_closed")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let content_type = response
+        .headers()
+        .get("content-type")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    assert!(content_type.starts_with("text/event-stream"));
+}
+
+#[tokio::test]
+async fn events_should_tell_a_lagging_client_to_resync() {
+    let state = AppState::with_config(BackendConfig {
+        db_path: ":memory:".into(),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let response = router(state.clone())
+        .oneshot(
+            Request::builder()
+                .uri("/porta/events?types=route_status")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    // More than the event buffer holds, and none of them of the filtered type
+    for n in 0..300 {
+        state.events.publish(PortaEvent::PeerConnected {
+            peer_id: format!("peer-{}", n),
+        });
+    }
+    let mut stream = response.into_body().into_data_stream();
+    let chunk = stream.next().await.unwrap().unwrap();
+    let text = String::from_utf8(chunk.to_vec()).unwrap();
+    assert!(text.starts_with("event: lagged"), "{}", text);
+    let data = text
+        .lines()
+        .find_map(|line| line.strip_prefix("data: "))
+        .unwrap();
+    let json: Value = serde_json::from_str(data).unwrap();
+    assert_eq!(json["skipped"], 300 - 256);
+}
+
+// ===========================================================================
+// Backup Tests
+// ===========================================================================
+
+#[tokio::test]
+async fn backup_export_then_import_reports_entries() {
+    setup_env();
+    let app = create_app().await;
+    let post = |uri: &str, payload: Value| {
+        Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(payload.to_string()))
+            .unwrap()
+    };
+    // The archive holds the private key, so a plain export is refused
+    let response = app
+        .clone()
+        .oneshot(post("/porta/backup/export", json!({})))
+        .await
+        .unwrap();
+    let bytes = body::to_bytes(response.into_body(), 1024 * 1024)
+        .await
+        .unwrap();
+    let json: Value = serde_json::from_slice(&bytes).unwrap();
+    assert_ne!(json["code"], 0);
+    assert!(json["data"].is_null());
+
+    let response = app
+        .clone()
+        .oneshot(post(
+            "/porta/backup/export",
+            json!({ "passphrase": "secret" }),
+        ))
+        .await
+        .unwrap();
+    let bytes = body::to_bytes(response.into_body(), 1024 * 1024)
+        .await
+        .unwrap();
+    let json: Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(json["data"]["format"], "porta-backup");
+    assert!(json["data"]["data"].is_null());
+    assert!(json["data"]["encrypted"]["ciphertext"].is_string());
+
+    let archive = json["data"].clone();
+    let response = app
+        .oneshot(post(
+            "/porta/backup/import",
+            json!({ "archive": archive, "passphrase": "secret", "on_conflict": "skip" }),
+        ))
+        .await
+        .unwrap();
+    let bytes = body::to_bytes(response.into_body(), 1024 * 1024)
+        .await
+        .unwrap();
+    let json: Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(json["code"], 0);
+    let imported = json["data"]["imported"].as_array().unwrap();
+    assert!(imported.contains(&json!("node")));
+    // Restoring a node's own identity needs no restart
+    assert!(!json["data"]["restart_required"]
+        .as_array()
+        .unwrap()
+        .contains(&json!("identity")));
+}
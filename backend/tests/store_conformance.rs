@@ -0,0 +1,1515 @@
+//! Behavior every `Store` implementation must share.
+//!
+//! Each check runs against `SqliteStore` and `MemoryStore`, and against
+//! `PostgresStore` in a fresh schema in the ignored `postgres::*` tests:
+//!
+//! ```text
+//! PORTA_TEST_POSTGRES_URL=postgres://... cargo test -p porta-backend --test store_conformance -- --ignored
+//! ```
+
+use porta_backend::{
+    memory::MemoryStore,
+    models::{
+        AdmissionMode, CommunityAddRequest, KeyImportRequest, NodeConfigUpdate, PeerBan,
+        PublishRequest, SecureRoute, ServiceQuery, ServiceRegistryItem, SessionInfo,
+        SubscribeRequest,
+    },
+    postgres::PostgresStore,
+    state::{SqliteStore, Store},
+};
+use sqlx::postgres::{PgConnectOptions, PgPool};
+use std::sync::Arc;
+
+async fn stores() -> Vec<(&'static str, Arc<dyn Store>)> {
+    vec![
+        ("sqlite", SqliteStore::new_in_memory().await.unwrap()),
+        ("memory", MemoryStore::new()),
+    ]
+}
+
+/// A `PostgresStore` in a fresh schema of `PORTA_TEST_POSTGRES_URL`.
+async fn postgres_store() -> Arc<dyn Store> {
+    let url = std::env::var("PORTA_TEST_POSTGRES_URL")
+        .expect("PORTA_TEST_POSTGRES_URL must point at a PostgreSQL server");
+    let schema = format!("porta_conformance_{}", uuid::Uuid::new_v4().simple());
+    let admin = PgPool::connect(&url).await.unwrap();
+    sqlx::query(&format!("CREATE SCHEMA {}", schema))
+        .execute(&admin)
+        .await
+        .unwrap();
+    let options = url
+        .parse::<PgConnectOptions>()
+        .unwrap()
+        .options([("search_path", schema.as_str())]);
+    PostgresStore::connect_with(options).await.unwrap()
+}
+
+/// Run a check against every store, naming the store in the failure.
+macro_rules! conformance {
+    ($($name:ident),* $(,)?) => {
+        $(
+            #[tokio::test]
+            async fn $name() {
+                for (kind, store) in stores().await {
+                    checks::$name(kind, store).await;
+                }
+            }
+        )*
+
+        mod postgres {
+            use super::*;
+
+            $(
+                #[tokio::test]
+                #[ignore = "needs PORTA_TEST_POSTGRES_URL"]
+                async fn $name() {
+                    checks::$name("postgres", postgres_store().await).await;
+                }
+            )*
+        }
+    };
+}
+
+conformance!(
+    node_info_defaults_and_updates,
+    community_lifecycle,
+    community_fallback_addresses,
+    community_backoff_is_persisted,
+    discovered_services_are_scoped_by_community,
+    subscriptions_follow_their_service,
+    publishing_keeps_date_and_managed_flag,
+    proxy_settings,
+    sessions_keep_creation_time,
+    peers_keep_ban_across_updates,
+    bans_carry_reason_expiry_and_uuid,
+    community_members_are_tracked,
+    join_requests_wait_for_approval,
+    registry_visibility_and_revisions,
+    providers_go_offline_and_back,
+    registry_search_and_paging,
+    subscribers_are_recorded_once,
+    peer_handover_moves_services_and_subscriptions,
+    invites_are_redeemed_once,
+    secure_route_lifecycle,
+);
+
+fn registry_item(uuid: &str, name: &str, online: bool) -> ServiceRegistryItem {
+    ServiceRegistryItem {
+        uuid: uuid.into(),
+        name: name.into(),
+        r#type: "TCP".into(),
+        port: 22,
+        description: format!("{} service", name),
+        provider_peer: "peer-provider".into(),
+        provider_addr: "/ip4/127.0.0.1/tcp/4001".into(),
+        online,
+        tags: vec!["ssh".into()],
+    }
+}
+
+fn community(id: &str) -> CommunityAddRequest {
+    CommunityAddRequest {
+        id: Some(id.into()),
+        name: format!("{} name", id),
+        description: format!("{} description", id),
+        multiaddr: Some("/ip4/127.0.0.1/tcp/4001".into()),
+        multiaddrs: Vec::new(),
+        peer_id: Some(format!("{}-peer", id)),
+        invite: None,
+    }
+}
+
+fn subscription(id: &str, service_uuid: &str) -> SubscribeRequest {
+    SubscribeRequest {
+        id: Some(id.into()),
+        service_uuid: Some(service_uuid.into()),
+        name: "SSH".into(),
+        r#type: "TCP".into(),
+        community: "dev".into(),
+        remote_addr: "/p2p/provider".into(),
+        local_mapping: "127.0.0.1:2222".into(),
+    }
+}
+
+mod checks {
+    use super::*;
+
+    pub async fn node_info_defaults_and_updates(kind: &str, store: Arc<dyn Store>) {
+        let info = store.node_info().await.unwrap();
+        assert_eq!(info.name, "我的节点", "{kind}");
+        assert_eq!(info.node_id, "", "{kind}");
+        assert_eq!(info.key_path, "porta.node.key", "{kind}");
+        assert!(!info.uuid.is_empty(), "{kind}");
+        assert!(info.tcp_listen_enable && info.quci_listen_enable, "{kind}");
+        assert!(info.mdns_enable && info.dht_enable, "{kind}");
+        assert_eq!(
+            (info.tcp_listen_port, info.quci_listen_port),
+            (0, 0),
+            "{kind}"
+        );
+        assert!(info.external_addr.is_empty(), "{kind}");
+
+        let updated = store
+            .update_node_config(NodeConfigUpdate {
+                name: Some("节点 A".into()),
+                quci_listen_port: Some(4433),
+                external_addr: Some(vec!["/ip4/1.2.3.4/tcp/9000".into()]),
+                mdns_enable: Some(false),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(updated.name, "节点 A", "{kind}");
+        assert_eq!(updated.quci_listen_port, 4433, "{kind}");
+        assert!(!updated.mdns_enable, "{kind}");
+        assert!(updated.dht_enable, "{kind}");
+        let reloaded = store.node_info().await.unwrap();
+        assert_eq!(
+            reloaded.external_addr,
+            vec!["/ip4/1.2.3.4/tcp/9000"],
+            "{kind}"
+        );
+        assert_eq!(reloaded.uuid, info.uuid, "{kind}");
+
+        store.ensure_node_identity("peer-1").await.unwrap();
+        store.ensure_node_identity("peer-2").await.unwrap();
+        assert_eq!(store.node_info().await.unwrap().node_id, "peer-1", "{kind}");
+        store.set_node_id("peer-3").await.unwrap();
+        assert_eq!(store.node_info().await.unwrap().node_id, "peer-3", "{kind}");
+
+        let imported = store
+            .import_key(KeyImportRequest {
+                key_path: "/etc/porta/node.key".into(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(imported.key_path, "/etc/porta/node.key", "{kind}");
+        let generated = store.generate_key().await.unwrap();
+        assert!(
+            generated.key_path.starts_with("porta-") && generated.key_path.ends_with(".key"),
+            "{kind}"
+        );
+        assert_eq!(
+            store.node_info().await.unwrap().key_path,
+            generated.key_path,
+            "{kind}"
+        );
+    }
+
+    pub async fn community_lifecycle(kind: &str, store: Arc<dyn Store>) {
+        let added = store.add_community(community("alpha")).await.unwrap();
+        assert_eq!(added.peers, 0, "{kind}");
+        assert!(!added.joined && !added.managed, "{kind}");
+        assert!(
+            store.add_community(community("alpha")).await.is_err(),
+            "{kind}"
+        );
+        let generated = store
+            .add_community(CommunityAddRequest {
+                id: None,
+                ..community("ignored")
+            })
+            .await
+            .unwrap();
+        assert!(generated.id.starts_with("community-"), "{kind}");
+        assert!(
+            store.remove_community(&generated.id).await.unwrap(),
+            "{kind}"
+        );
+
+        assert!(store.connect_community("alpha").await.unwrap(), "{kind}");
+        assert!(
+            store.set_community_managed("alpha", true).await.unwrap(),
+            "{kind}"
+        );
+        assert!(
+            store.set_community_sync_revision("alpha", 7).await.unwrap(),
+            "{kind}"
+        );
+        store
+            .upsert_community(CommunityAddRequest {
+                name: "renamed".into(),
+                multiaddr: None,
+                ..community("alpha")
+            })
+            .await
+            .unwrap();
+        let alpha = store.community_by_id("alpha").await.unwrap().unwrap();
+        assert_eq!(alpha.name, "renamed", "{kind}");
+        assert!(alpha.joined && alpha.managed, "{kind}");
+        assert_eq!(alpha.multiaddr, None, "{kind}");
+        assert_eq!(
+            store.community_sync_revision("alpha").await.unwrap(),
+            7,
+            "{kind}"
+        );
+        assert_eq!(
+            store.community_multiaddr("alpha").await.unwrap(),
+            None,
+            "{kind}"
+        );
+
+        store.upsert_community(community("beta")).await.unwrap();
+        let beta = store.community_by_id("beta").await.unwrap().unwrap();
+        assert!(!beta.joined && !beta.managed, "{kind}");
+        assert!(
+            store
+                .update_community_multiaddr("beta", "/ip4/10.0.0.1/tcp/4001", "beta-new")
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+        assert_eq!(
+            store.community_multiaddr("beta").await.unwrap().as_deref(),
+            Some("/ip4/10.0.0.1/tcp/4001"),
+            "{kind}"
+        );
+        assert!(
+            store.community_exists_by_peer("beta-new").await.unwrap(),
+            "{kind}"
+        );
+        assert!(
+            !store.community_exists_by_peer("beta-peer").await.unwrap(),
+            "{kind}"
+        );
+
+        let ids: Vec<String> = store
+            .communities()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|c| c.id)
+            .collect();
+        assert_eq!(ids, vec!["alpha", "beta"], "{kind}");
+
+        assert!(store.remove_community("alpha").await.unwrap(), "{kind}");
+        assert!(!store.remove_community("alpha").await.unwrap(), "{kind}");
+        assert!(
+            store.community_by_id("alpha").await.unwrap().is_none(),
+            "{kind}"
+        );
+        assert_eq!(
+            store.community_sync_revision("alpha").await.unwrap(),
+            0,
+            "{kind}"
+        );
+        assert!(!store.connect_community("missing").await.unwrap(), "{kind}");
+        assert!(
+            !store.set_community_managed("missing", true).await.unwrap(),
+            "{kind}"
+        );
+        assert!(
+            !store
+                .set_community_sync_revision("missing", 1)
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+        assert!(
+            !store
+                .update_community_multiaddr("missing", "/ip4/127.0.0.1/tcp/1", "p")
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+    }
+
+    pub async fn community_fallback_addresses(kind: &str, store: Arc<dyn Store>) {
+        let fallbacks = vec![
+            "/ip6/::1/tcp/4001".to_string(),
+            "/dns/lab.example.com/tcp/4001".to_string(),
+        ];
+        let added = store
+            .add_community(CommunityAddRequest {
+                multiaddrs: fallbacks.clone(),
+                ..community("alpha")
+            })
+            .await
+            .unwrap();
+        assert_eq!(added.multiaddrs, fallbacks, "{kind}");
+        assert_eq!(added.last_multiaddr, None, "{kind}");
+
+        assert!(
+            store
+                .set_community_last_multiaddr("alpha", "/ip6/::1/tcp/4001")
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+        let alpha = store.community_by_id("alpha").await.unwrap().unwrap();
+        assert_eq!(alpha.multiaddrs, fallbacks, "{kind}");
+        assert_eq!(
+            alpha.last_multiaddr.as_deref(),
+            Some("/ip6/::1/tcp/4001"),
+            "{kind}"
+        );
+
+        // Declaring the community again replaces the list but keeps what worked
+        store.upsert_community(community("alpha")).await.unwrap();
+        let alpha = &store.communities().await.unwrap()[0];
+        assert!(alpha.multiaddrs.is_empty(), "{kind}");
+        assert_eq!(
+            alpha.last_multiaddr.as_deref(),
+            Some("/ip6/::1/tcp/4001"),
+            "{kind}"
+        );
+        assert!(
+            !store
+                .set_community_last_multiaddr("missing", "/ip4/127.0.0.1/tcp/1")
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+    }
+
+    pub async fn community_backoff_is_persisted(kind: &str, store: Arc<dyn Store>) {
+        store.add_community(community("alpha")).await.unwrap();
+        let alpha = store.community_by_id("alpha").await.unwrap().unwrap();
+        assert_eq!(alpha.connect_failures, 0, "{kind}");
+        assert_eq!(
+            (alpha.last_error, alpha.next_retry_at),
+            (None, None),
+            "{kind}"
+        );
+
+        assert!(
+            store
+                .set_community_connection("alpha", 3, Some("连接超时"), Some(1_700_000_000))
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+        let alpha = &store.communities().await.unwrap()[0];
+        assert_eq!(alpha.connect_failures, 3, "{kind}");
+        assert_eq!(alpha.last_error.as_deref(), Some("连接超时"), "{kind}");
+        assert_eq!(alpha.next_retry_at, Some(1_700_000_000), "{kind}");
+
+        assert!(
+            store
+                .set_community_connection("alpha", 0, Some("连接超时"), None)
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+        let alpha = store.community_by_id("alpha").await.unwrap().unwrap();
+        assert_eq!(alpha.connect_failures, 0, "{kind}");
+        assert_eq!(alpha.next_retry_at, None, "{kind}");
+        assert!(
+            !store
+                .set_community_connection("missing", 1, None, None)
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+    }
+
+    pub async fn discovered_services_are_scoped_by_community(kind: &str, store: Arc<dyn Store>) {
+        store
+            .upsert_discovered_services(
+                "dev",
+                vec![
+                    registry_item("svc-a", "A", true),
+                    registry_item("svc-b", "B", true),
+                ],
+            )
+            .await
+            .unwrap();
+        store
+            .upsert_discovered_services("ops", vec![registry_item("svc-c", "C", true)])
+            .await
+            .unwrap();
+
+        let dev = store.discovered_services(Some("dev".into())).await.unwrap();
+        assert_eq!(dev.len(), 2, "{kind}");
+        assert_eq!(dev[0].uuid, "svc-a", "{kind}");
+        assert_eq!(dev[0].community_id.as_deref(), Some("dev"), "{kind}");
+        assert_eq!(
+            dev[0].provider_addr.as_deref(),
+            Some("/ip4/127.0.0.1/tcp/4001"),
+            "{kind}"
+        );
+        assert_eq!(dev[0].provider, "peer-provider", "{kind}");
+        assert_eq!(dev[0].remote_port, 22, "{kind}");
+        assert_eq!(dev[0].tags, vec!["ssh"], "{kind}");
+        assert_eq!(dev[0].subscribed, None, "{kind}");
+        assert_eq!(
+            store.discovered_services(None).await.unwrap().len(),
+            3,
+            "{kind}"
+        );
+        assert!(dev[0].online, "{kind}");
+
+        // Offline services stay cached as offline
+        store
+            .upsert_discovered_services("ops", vec![registry_item("svc-c", "C", false)])
+            .await
+            .unwrap();
+        let ops = store.discovered_services(Some("ops".into())).await.unwrap();
+        assert!(!ops[0].online, "{kind}");
+
+        // A service seen through another community moves there
+        store
+            .upsert_discovered_services("ops", vec![registry_item("svc-b", "B2", true)])
+            .await
+            .unwrap();
+        assert_eq!(
+            store
+                .discovered_services(Some("ops".into()))
+                .await
+                .unwrap()
+                .len(),
+            2,
+            "{kind}"
+        );
+
+        let removed = store
+            .remove_discovered_services("dev", &["svc-a".into(), "svc-b".into(), "svc-x".into()])
+            .await
+            .unwrap();
+        assert_eq!(removed, 1, "{kind}");
+        assert_eq!(
+            store.clear_discovered_services("ops").await.unwrap(),
+            2,
+            "{kind}"
+        );
+        assert_eq!(
+            store.clear_discovered_services("ops").await.unwrap(),
+            0,
+            "{kind}"
+        );
+        assert!(
+            store.discovered_services(None).await.unwrap().is_empty(),
+            "{kind}"
+        );
+    }
+
+    pub async fn subscriptions_follow_their_service(kind: &str, store: Arc<dyn Store>) {
+        let saved = store
+            .subscribe_service(subscription("sub-1", "svc-a"))
+            .await
+            .unwrap();
+        assert_eq!(saved.status, "畅通", "{kind}");
+        store
+            .subscribe_service(subscription("sub-2", "svc-a"))
+            .await
+            .unwrap();
+        store
+            .subscribe_service(subscription("sub-3", "svc-b"))
+            .await
+            .unwrap();
+        let generated = store
+            .subscribe_service(SubscribeRequest {
+                id: None,
+                ..subscription("", "svc-c")
+            })
+            .await
+            .unwrap();
+        assert!(generated.id.starts_with("sub-"), "{kind}");
+
+        assert!(
+            store
+                .update_subscription_status("sub-1", "离线")
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+        assert!(
+            store
+                .update_subscription_status("sub-2", "已下架")
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+        // Only subscriptions made through the given communities are touched
+        let other = ["other".to_string()];
+        assert_eq!(
+            store
+                .update_subscriptions_by_service("svc-b", &other, "断开")
+                .await
+                .unwrap(),
+            0,
+            "{kind}"
+        );
+        assert_eq!(
+            store
+                .update_subscription_endpoints_by_service("svc-a", &other, "/p2p/evil")
+                .await
+                .unwrap(),
+            0,
+            "{kind}"
+        );
+        let dev = ["dev".to_string()];
+        assert_eq!(
+            store
+                .update_subscriptions_by_service("svc-b", &dev, "断开")
+                .await
+                .unwrap(),
+            1,
+            "{kind}"
+        );
+        assert_eq!(
+            store
+                .update_subscription_endpoints_by_service("svc-a", &dev, "/p2p/new")
+                .await
+                .unwrap(),
+            2,
+            "{kind}"
+        );
+        for id in ["sub-1", "sub-2"] {
+            let sub = store.find_subscription(id).await.unwrap().unwrap();
+            assert_eq!(sub.remote_addr, "/p2p/new", "{kind}");
+            assert_eq!(sub.status, "断开", "{kind}");
+        }
+        store
+            .update_subscription_status("sub-1", "畅通")
+            .await
+            .unwrap();
+        store
+            .update_subscription_endpoints_by_service("svc-a", &dev, "/p2p/newer")
+            .await
+            .unwrap();
+        assert_eq!(
+            store
+                .find_subscription("sub-1")
+                .await
+                .unwrap()
+                .unwrap()
+                .status,
+            "畅通",
+            "{kind}"
+        );
+
+        assert!(
+            store
+                .update_subscription_endpoint("sub-3", "/p2p/other", "畅通")
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+        let sub = store.find_subscription("sub-3").await.unwrap().unwrap();
+        assert_eq!(
+            (sub.remote_addr.as_str(), sub.status.as_str()),
+            ("/p2p/other", "畅通"),
+            "{kind}"
+        );
+
+        // Subscribing again with the same id replaces the entry
+        store
+            .subscribe_service(SubscribeRequest {
+                local_mapping: "127.0.0.1:3333".into(),
+                ..subscription("sub-3", "svc-b")
+            })
+            .await
+            .unwrap();
+        let subs = store.subscribed_services().await.unwrap();
+        assert_eq!(subs.len(), 4, "{kind}");
+        let sub = store.find_subscription("sub-3").await.unwrap().unwrap();
+        assert_eq!(sub.local_mapping, "127.0.0.1:3333", "{kind}");
+        assert_eq!(sub.remote_addr, "/p2p/provider", "{kind}");
+
+        assert!(
+            store.find_subscription("missing").await.unwrap().is_none(),
+            "{kind}"
+        );
+        assert!(
+            !store
+                .update_subscription_status("missing", "断开")
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+        assert!(
+            !store
+                .update_subscription_endpoint("missing", "/p2p/x", "断开")
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+    }
+
+    pub async fn publishing_keeps_date_and_managed_flag(kind: &str, store: Arc<dyn Store>) {
+        let request = PublishRequest {
+            id: Some("pub-web".into()),
+            name: "Web".into(),
+            r#type: "HTTP".into(),
+            port: 8080,
+            summary: "site".into(),
+            tags: vec!["web".into()],
+        };
+        let published = store.publish_service(request.clone()).await.unwrap();
+        assert_eq!(published.status, "在线", "{kind}");
+        assert_eq!(published.subscriptions, 0, "{kind}");
+        assert_eq!(published.publish_date.len(), "2024-01-01".len(), "{kind}");
+        assert!(!published.managed, "{kind}");
+
+        assert!(
+            store.set_published_managed("pub-web", true).await.unwrap(),
+            "{kind}"
+        );
+        assert!(store.unpublish_service("pub-web").await.unwrap(), "{kind}");
+        assert_eq!(
+            store
+                .published_service_by_id("pub-web")
+                .await
+                .unwrap()
+                .unwrap()
+                .status,
+            "已下架",
+            "{kind}"
+        );
+
+        let republished = store
+            .publish_service(PublishRequest {
+                port: 8081,
+                tags: vec![],
+                ..request
+            })
+            .await
+            .unwrap();
+        assert_eq!(republished.status, "在线", "{kind}");
+        assert_eq!(republished.publish_date, published.publish_date, "{kind}");
+        assert!(republished.managed, "{kind}");
+        let stored = store
+            .published_service_by_id("pub-web")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.port, 8081, "{kind}");
+        assert!(stored.tags.is_empty(), "{kind}");
+        assert!(stored.managed, "{kind}");
+
+        let generated = store
+            .publish_service(PublishRequest {
+                id: None,
+                name: "Other".into(),
+                r#type: "TCP".into(),
+                port: 22,
+                summary: String::new(),
+                tags: vec![],
+            })
+            .await
+            .unwrap();
+        assert!(generated.id.starts_with("pub-"), "{kind}");
+        assert_eq!(store.published_services().await.unwrap().len(), 2, "{kind}");
+
+        assert!(store.remove_published("pub-web").await.unwrap(), "{kind}");
+        assert!(!store.remove_published("pub-web").await.unwrap(), "{kind}");
+        assert!(!store.unpublish_service("pub-web").await.unwrap(), "{kind}");
+        assert!(
+            !store.set_published_managed("pub-web", false).await.unwrap(),
+            "{kind}"
+        );
+        assert!(
+            store
+                .published_service_by_id("pub-web")
+                .await
+                .unwrap()
+                .is_none(),
+            "{kind}"
+        );
+    }
+
+    pub async fn proxy_settings(kind: &str, store: Arc<dyn Store>) {
+        let proxy = store.proxy_status().await.unwrap();
+        assert!(proxy.enabled && !proxy.managed, "{kind}");
+        assert_eq!(proxy.listen_port, 1080, "{kind}");
+
+        store.set_proxy_enabled(false).await.unwrap();
+        store.set_proxy_listen_port(7890).await.unwrap();
+        store.set_proxy_managed(true).await.unwrap();
+        let proxy = store.proxy_status().await.unwrap();
+        assert!(!proxy.enabled && proxy.managed, "{kind}");
+        assert_eq!(proxy.listen_port, 7890, "{kind}");
+    }
+
+    pub async fn sessions_keep_creation_time(kind: &str, store: Arc<dyn Store>) {
+        let session = |id: &str, state: &str| SessionInfo {
+            session_id: id.into(),
+            service_id: "svc-a".into(),
+            local_port: 2222,
+            remote_peer: "peer-a".into(),
+            state: state.into(),
+            created_at: None,
+            last_active: None,
+        };
+        store
+            .upsert_session(session("s-1", "connected"))
+            .await
+            .unwrap();
+        store
+            .upsert_session(session("s-2", "closed"))
+            .await
+            .unwrap();
+        let first = store.sessions().await.unwrap();
+        assert_eq!(first.len(), 2, "{kind}");
+        let created = first[0].created_at.clone().unwrap();
+        assert_eq!(created.len(), "2024-01-01 00:00:00".len(), "{kind}");
+        assert!(first[0].last_active.is_some(), "{kind}");
+
+        store
+            .upsert_session(session("s-1", "closed"))
+            .await
+            .unwrap();
+        let sessions = store.sessions().await.unwrap();
+        let s1 = sessions.iter().find(|s| s.session_id == "s-1").unwrap();
+        assert_eq!(s1.state, "closed", "{kind}");
+        assert_eq!(s1.created_at.as_deref(), Some(created.as_str()), "{kind}");
+
+        // Fresh sessions are never expired
+        store
+            .upsert_session(session("s-3", "connected"))
+            .await
+            .unwrap();
+        assert!(
+            store.cleanup_expired_sessions(30).await.unwrap().is_empty(),
+            "{kind}"
+        );
+        assert_eq!(store.sessions().await.unwrap().len(), 3, "{kind}");
+    }
+
+    pub async fn peers_keep_ban_across_updates(kind: &str, store: Arc<dyn Store>) {
+        store
+            .upsert_peer("peer-a", "node-a", "", "", "edge", "online")
+            .await
+            .unwrap();
+        store
+            .upsert_peer("peer-b", "node-b", "", "", "community", "online")
+            .await
+            .unwrap();
+        assert_eq!(
+            store.peer_role("peer-b").await.unwrap().as_deref(),
+            Some("community"),
+            "{kind}"
+        );
+        assert_eq!(store.peer_role("missing").await.unwrap(), None, "{kind}");
+        assert!(!store.peer_is_banned("peer-a").await.unwrap(), "{kind}");
+        assert!(!store.peer_is_banned("missing").await.unwrap(), "{kind}");
+
+        assert!(
+            store
+                .set_node_ban("peer-a", true, None, None)
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+        assert!(
+            !store
+                .set_node_ban("missing", true, None, None)
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+        store
+            .upsert_peer("peer-a", "node-a2", "", "", "edge", "offline")
+            .await
+            .unwrap();
+        assert!(store.peer_is_banned("peer-a").await.unwrap(), "{kind}");
+
+        let nodes = store.community_nodes().await.unwrap();
+        assert_eq!(nodes.len(), 1, "{kind}");
+        assert_eq!(nodes[0].id, "peer-a", "{kind}");
+        assert_eq!(nodes[0].uuid, "node-a2", "{kind}");
+        assert_eq!(nodes[0].status, "offline", "{kind}");
+        assert!(nodes[0].banned, "{kind}");
+    }
+
+    pub async fn bans_carry_reason_expiry_and_uuid(kind: &str, store: Arc<dyn Store>) {
+        let now = chrono::Utc::now().timestamp();
+        store
+            .upsert_peer(
+                "peer-provider",
+                "node-a",
+                "uuid-a",
+                "edge-a",
+                "edge",
+                "online",
+            )
+            .await
+            .unwrap();
+        store
+            .upsert_peer(
+                "peer-rotated",
+                "node-a",
+                "uuid-a",
+                "edge-a",
+                "edge",
+                "offline",
+            )
+            .await
+            .unwrap();
+        store
+            .upsert_peer("peer-b", "node-b", "uuid-b", "edge-b", "edge", "online")
+            .await
+            .unwrap();
+        // An empty UUID from an older node keeps the known one
+        store
+            .upsert_peer("peer-b", "node-b", "", "edge-b", "edge", "online")
+            .await
+            .unwrap();
+        let mut same_node = store.peers_by_node_uuid("uuid-a").await.unwrap();
+        same_node.sort();
+        assert_eq!(same_node, vec!["peer-provider", "peer-rotated"], "{kind}");
+        assert_eq!(
+            store.peers_by_node_uuid("uuid-b").await.unwrap(),
+            vec!["peer-b"],
+            "{kind}"
+        );
+        assert!(
+            store
+                .peers_by_node_uuid("missing")
+                .await
+                .unwrap()
+                .is_empty(),
+            "{kind}"
+        );
+
+        assert!(
+            store
+                .set_node_ban("peer-provider", true, Some("spam"), Some(now + 3600))
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+        assert_eq!(
+            store.peer_ban("peer-provider").await.unwrap(),
+            Some(PeerBan {
+                reason: Some("spam".into()),
+                until: Some(now + 3600),
+            }),
+            "{kind}"
+        );
+        // A ban that has run out no longer counts
+        assert!(
+            store
+                .set_node_ban("peer-b", true, Some("old"), Some(now - 1))
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+        assert_eq!(store.peer_ban("peer-b").await.unwrap(), None, "{kind}");
+        assert!(!store.peer_is_banned("peer-b").await.unwrap(), "{kind}");
+        assert_eq!(
+            store.banned_peers().await.unwrap(),
+            vec!["peer-provider"],
+            "{kind}"
+        );
+
+        let nodes = store.community_nodes().await.unwrap();
+        let banned = nodes
+            .iter()
+            .find(|node| node.id == "peer-provider")
+            .unwrap();
+        assert!(banned.banned, "{kind}");
+        assert_eq!(banned.uuid, "uuid-a", "{kind}");
+        assert_eq!(banned.ban_reason.as_deref(), Some("spam"), "{kind}");
+        assert_eq!(banned.banned_until, Some(now + 3600), "{kind}");
+        let expired = nodes.iter().find(|node| node.id == "peer-b").unwrap();
+        assert!(!expired.banned, "{kind}");
+        assert_eq!(expired.ban_reason, None, "{kind}");
+
+        // Unbanning clears the reason and expiry
+        assert!(
+            store
+                .set_node_ban("peer-provider", false, None, None)
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+        assert_eq!(
+            store.peer_ban("peer-provider").await.unwrap(),
+            None,
+            "{kind}"
+        );
+        assert!(store.banned_peers().await.unwrap().is_empty(), "{kind}");
+        assert!(
+            store
+                .set_node_ban("peer-provider", true, None, None)
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+        assert_eq!(
+            store.peer_ban("peer-provider").await.unwrap(),
+            Some(PeerBan::default()),
+            "{kind}"
+        );
+
+        store
+            .upsert_service_registry(registry_item("svc-a", "A", true))
+            .await
+            .unwrap();
+        store
+            .upsert_service_registry(registry_item("svc-b", "B", false))
+            .await
+            .unwrap();
+        let before = store.registry_changes_since(0, 10).await.unwrap().revision;
+        let mut removed = store
+            .remove_provider_services("peer-provider")
+            .await
+            .unwrap();
+        removed.sort();
+        assert_eq!(removed, vec!["svc-a", "svc-b"], "{kind}");
+        assert!(
+            store
+                .resolve_service_registry("svc-a")
+                .await
+                .unwrap()
+                .is_none(),
+            "{kind}"
+        );
+        let changes = store.registry_changes_since(before, 10).await.unwrap();
+        assert!(changes.revision > before, "{kind}");
+        assert!(
+            store
+                .remove_provider_services("peer-provider")
+                .await
+                .unwrap()
+                .is_empty(),
+            "{kind}"
+        );
+    }
+
+    pub async fn community_members_are_tracked(kind: &str, store: Arc<dyn Store>) {
+        store
+            .upsert_peer("peer-provider", "node-a", "", "edge-a", "edge", "online")
+            .await
+            .unwrap();
+        store
+            .upsert_peer("peer-b", "node-b", "", "edge-b", "edge", "online")
+            .await
+            .unwrap();
+        store
+            .upsert_service_registry(registry_item("svc-a", "A", true))
+            .await
+            .unwrap();
+        store
+            .upsert_service_registry(registry_item("svc-b", "B", false))
+            .await
+            .unwrap();
+
+        let nodes = store.community_nodes().await.unwrap();
+        assert_eq!(nodes.len(), 2, "{kind}");
+        let provider = nodes
+            .iter()
+            .find(|node| node.id == "peer-provider")
+            .unwrap();
+        assert_eq!(provider.name, "edge-a", "{kind}");
+        assert!(provider.online, "{kind}");
+        assert_eq!(provider.services, 2, "{kind}");
+        assert_eq!(
+            store.provider_service_count("peer-provider").await.unwrap(),
+            2,
+            "{kind}"
+        );
+        assert_eq!(
+            store.provider_service_count("peer-b").await.unwrap(),
+            0,
+            "{kind}"
+        );
+        assert!(provider.joined_at.is_some(), "{kind}");
+        assert!(provider.last_seen.is_some(), "{kind}");
+        let joined_at = provider.joined_at.clone();
+
+        assert!(
+            store
+                .set_peer_status("peer-provider", "offline")
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+        assert!(
+            !store.set_peer_status("missing", "offline").await.unwrap(),
+            "{kind}"
+        );
+        store
+            .upsert_peer("peer-b", "node-b", "", "edge-b2", "edge", "online")
+            .await
+            .unwrap();
+        let nodes = store.community_nodes().await.unwrap();
+        let provider = nodes
+            .iter()
+            .find(|node| node.id == "peer-provider")
+            .unwrap();
+        assert_eq!(provider.status, "offline", "{kind}");
+        assert!(!provider.online, "{kind}");
+        assert_eq!(provider.joined_at, joined_at, "{kind}");
+        let other = nodes.iter().find(|node| node.id == "peer-b").unwrap();
+        assert_eq!(other.name, "edge-b2", "{kind}");
+        assert_eq!(other.services, 0, "{kind}");
+
+        store.add_community(community("alpha")).await.unwrap();
+        assert!(
+            store.set_community_peers("alpha", 7).await.unwrap(),
+            "{kind}"
+        );
+        assert!(
+            !store.set_community_peers("missing", 7).await.unwrap(),
+            "{kind}"
+        );
+        let alpha = store.community_by_id("alpha").await.unwrap().unwrap();
+        assert_eq!(alpha.peers, 7, "{kind}");
+    }
+
+    pub async fn join_requests_wait_for_approval(kind: &str, store: Arc<dyn Store>) {
+        assert_eq!(
+            store.admission_mode().await.unwrap(),
+            AdmissionMode::Open,
+            "{kind}"
+        );
+        store
+            .set_admission_mode(AdmissionMode::Approval)
+            .await
+            .unwrap();
+        assert_eq!(
+            store.admission_mode().await.unwrap(),
+            AdmissionMode::Approval,
+            "{kind}"
+        );
+
+        let request = store
+            .record_join_request("peer-a", "node-a", "edge-a")
+            .await
+            .unwrap();
+        assert_eq!(request.status, "pending", "{kind}");
+        assert!(request.requested_at.is_some(), "{kind}");
+        store
+            .record_join_request("peer-b", "node-b", "edge-b")
+            .await
+            .unwrap();
+
+        assert!(
+            store
+                .set_join_request_status("peer-a", "rejected")
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+        assert!(
+            !store
+                .set_join_request_status("missing", "rejected")
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+        // Asking again refreshes the name but keeps the decision
+        let request = store
+            .record_join_request("peer-a", "node-a", "edge-a2")
+            .await
+            .unwrap();
+        assert_eq!(
+            (request.name.as_str(), request.status.as_str()),
+            ("edge-a2", "rejected"),
+            "{kind}"
+        );
+
+        let requests = store.join_requests().await.unwrap();
+        let ids: Vec<&str> = requests.iter().map(|r| r.peer_id.as_str()).collect();
+        assert_eq!(ids, vec!["peer-a", "peer-b"], "{kind}");
+
+        assert!(store.remove_join_request("peer-b").await.unwrap(), "{kind}");
+        assert!(
+            !store.remove_join_request("peer-b").await.unwrap(),
+            "{kind}"
+        );
+        assert_eq!(store.join_requests().await.unwrap().len(), 1, "{kind}");
+    }
+
+    pub async fn registry_visibility_and_revisions(kind: &str, store: Arc<dyn Store>) {
+        let empty = store.registry_changes_since(0, 10).await.unwrap();
+        assert_eq!(
+            (empty.revision, empty.reset, empty.more),
+            (0, false, false),
+            "{kind}"
+        );
+
+        store
+            .upsert_service_registry(registry_item("svc-a", "A", true))
+            .await
+            .unwrap();
+        store
+            .upsert_service_registry(registry_item("svc-b", "B", true))
+            .await
+            .unwrap();
+        store
+            .upsert_service_registry(registry_item("svc-c", "C", false))
+            .await
+            .unwrap();
+        assert!(
+            store.set_service_announced("svc-a", false).await.unwrap(),
+            "{kind}"
+        );
+        assert!(
+            !store.set_service_announced("missing", false).await.unwrap(),
+            "{kind}"
+        );
+        assert!(
+            store.remove_service_registry("svc-b").await.unwrap(),
+            "{kind}"
+        );
+        assert!(
+            !store.remove_service_registry("svc-b").await.unwrap(),
+            "{kind}"
+        );
+
+        let services = store.community_services().await.unwrap();
+        assert_eq!(services.len(), 2, "{kind}");
+        assert!(!services[0].announced && services[0].online, "{kind}");
+        assert!(services[1].announced && !services[1].online, "{kind}");
+        assert!(
+            store.list_service_registry().await.unwrap().is_empty(),
+            "{kind}"
+        );
+        assert!(
+            store
+                .resolve_service_registry("svc-a")
+                .await
+                .unwrap()
+                .is_some(),
+            "{kind}"
+        );
+        assert!(
+            store
+                .resolve_service_registry("svc-b")
+                .await
+                .unwrap()
+                .is_none(),
+            "{kind}"
+        );
+
+        // svc-c (3), svc-a hidden (4), svc-b removed (5)
+        let changes = store.registry_changes_since(0, 10).await.unwrap();
+        assert_eq!(changes.revision, 5, "{kind}");
+        assert!(!changes.more && !changes.reset, "{kind}");
+        assert!(changes.upserted.is_empty(), "{kind}");
+        assert_eq!(changes.removed, vec!["svc-c", "svc-a", "svc-b"], "{kind}");
+
+        let page = store.registry_changes_since(0, 2).await.unwrap();
+        assert_eq!(page.revision, 4, "{kind}");
+        assert!(page.more, "{kind}");
+        assert_eq!(page.removed, vec!["svc-c", "svc-a"], "{kind}");
+
+        let reset = store.registry_changes_since(99, 10).await.unwrap();
+        assert!(reset.reset, "{kind}");
+        assert_eq!(reset.revision, 5, "{kind}");
+
+        // Re-registering keeps the announcement flag
+        store
+            .upsert_service_registry(registry_item("svc-a", "A2", true))
+            .await
+            .unwrap();
+        let changes = store.registry_changes_since(5, 10).await.unwrap();
+        assert_eq!(changes.revision, 6, "{kind}");
+        assert_eq!(changes.removed, vec!["svc-a"], "{kind}");
+        store.set_service_announced("svc-a", true).await.unwrap();
+        let changes = store.registry_changes_since(6, 10).await.unwrap();
+        assert_eq!(changes.revision, 7, "{kind}");
+        assert_eq!(changes.upserted.len(), 1, "{kind}");
+        assert_eq!(changes.upserted[0].name, "A2", "{kind}");
+        assert_eq!(changes.upserted[0].tags, vec!["ssh"], "{kind}");
+        let listed = store.list_service_registry().await.unwrap();
+        assert_eq!(listed.len(), 1, "{kind}");
+        assert_eq!(listed[0].uuid, "svc-a", "{kind}");
+        let unchanged = store.registry_changes_since(7, 10).await.unwrap();
+        assert_eq!(unchanged.revision, 7, "{kind}");
+        assert!(
+            unchanged.upserted.is_empty() && unchanged.removed.is_empty(),
+            "{kind}"
+        );
+    }
+
+    pub async fn providers_go_offline_and_back(kind: &str, store: Arc<dyn Store>) {
+        store
+            .upsert_service_registry(registry_item("svc-a", "A", true))
+            .await
+            .unwrap();
+        store
+            .upsert_service_registry(registry_item("svc-b", "B", false))
+            .await
+            .unwrap();
+        store
+            .upsert_service_registry(ServiceRegistryItem {
+                provider_peer: "peer-other".into(),
+                ..registry_item("svc-c", "C", true)
+            })
+            .await
+            .unwrap();
+        let before = store.registry_changes_since(0, 10).await.unwrap().revision;
+
+        // Only entries whose flag actually flips are reported
+        let offline = store
+            .set_provider_online("peer-provider", false)
+            .await
+            .unwrap();
+        assert_eq!(offline, vec!["svc-a"], "{kind}");
+        assert!(
+            !store
+                .resolve_service_registry("svc-a")
+                .await
+                .unwrap()
+                .unwrap()
+                .online,
+            "{kind}"
+        );
+        assert!(
+            store
+                .resolve_service_registry("svc-c")
+                .await
+                .unwrap()
+                .unwrap()
+                .online,
+            "{kind}"
+        );
+        let changes = store.registry_changes_since(before, 10).await.unwrap();
+        assert_eq!(changes.removed, vec!["svc-a"], "{kind}");
+        assert!(
+            store
+                .set_provider_online("peer-provider", false)
+                .await
+                .unwrap()
+                .is_empty(),
+            "{kind}"
+        );
+
+        let mut online = store
+            .set_provider_online("peer-provider", true)
+            .await
+            .unwrap();
+        online.sort();
+        assert_eq!(online, vec!["svc-a", "svc-b"], "{kind}");
+        let changes = store
+            .registry_changes_since(changes.revision, 10)
+            .await
+            .unwrap();
+        assert_eq!(changes.upserted.len(), 2, "{kind}");
+    }
+
+    pub async fn registry_search_and_paging(kind: &str, store: Arc<dyn Store>) {
+        for (uuid, name, online) in [
+            ("svc-3", "Beta", true),
+            ("svc-1", "Alpha", true),
+            ("svc-2", "Alpha", false),
+            ("svc-4", "Gamma", true),
+        ] {
+            store
+                .upsert_service_registry(registry_item(uuid, name, online))
+                .await
+                .unwrap();
+        }
+        store.set_service_announced("svc-4", false).await.unwrap();
+
+        let online = store
+            .search_service_registry(&ServiceQuery::default())
+            .await
+            .unwrap();
+        let uuids: Vec<&str> = online.items.iter().map(|s| s.uuid.as_str()).collect();
+        assert_eq!(uuids, vec!["svc-1", "svc-3"], "{kind}");
+        assert_eq!(online.total, 2, "{kind}");
+
+        let first = store
+            .search_service_registry(&ServiceQuery {
+                online_only: false,
+                limit: Some(2),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(first.total, 3, "{kind}");
+        let uuids: Vec<&str> = first.items.iter().map(|s| s.uuid.as_str()).collect();
+        assert_eq!(uuids, vec!["svc-1", "svc-2"], "{kind}");
+        let rest = store
+            .search_service_registry(&ServiceQuery {
+                online_only: false,
+                limit: Some(2),
+                cursor: first.next_cursor.clone(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(rest.items.len(), 1, "{kind}");
+        assert_eq!(rest.items[0].uuid, "svc-3", "{kind}");
+        assert!(rest.next_cursor.is_none(), "{kind}");
+
+        let keyword = store
+            .search_service_registry(&ServiceQuery {
+                keyword: Some("beta service".into()),
+                tags: vec!["SSH".into()],
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(keyword.items.len(), 1, "{kind}");
+        assert_eq!(keyword.items[0].uuid, "svc-3", "{kind}");
+    }
+
+    pub async fn subscribers_are_recorded_once(kind: &str, store: Arc<dyn Store>) {
+        for peer in ["peer-b", "peer-a", "peer-b"] {
+            store.record_subscription("svc-a", peer).await.unwrap();
+        }
+        store.record_subscription("svc-b", "peer-c").await.unwrap();
+        assert_eq!(
+            store.service_subscribers("svc-a").await.unwrap(),
+            vec!["peer-b", "peer-a"],
+            "{kind}"
+        );
+        assert!(
+            store
+                .service_subscribers("missing")
+                .await
+                .unwrap()
+                .is_empty(),
+            "{kind}"
+        );
+    }
+
+    pub async fn peer_handover_moves_services_and_subscriptions(kind: &str, store: Arc<dyn Store>) {
+        store
+            .upsert_peer("peer-provider", "node-old", "", "", "edge", "online")
+            .await
+            .unwrap();
+        store
+            .upsert_peer("peer-new", "node-new", "", "", "edge", "online")
+            .await
+            .unwrap();
+        store
+            .upsert_service_registry(registry_item("svc-a", "A", true))
+            .await
+            .unwrap();
+        let mut other = registry_item("svc-b", "B", true);
+        other.provider_peer = "peer-other".into();
+        store.upsert_service_registry(other).await.unwrap();
+        for (service, peer) in [
+            ("svc-b", "peer-provider"),
+            ("svc-b", "peer-other"),
+            ("svc-c", "peer-new"),
+            ("svc-c", "peer-provider"),
+        ] {
+            store.record_subscription(service, peer).await.unwrap();
+        }
+        let before = store.registry_changes_since(0, 10).await.unwrap().revision;
+
+        assert_eq!(
+            store
+                .hand_over_peer("peer-provider", "peer-new")
+                .await
+                .unwrap(),
+            1,
+            "{kind}"
+        );
+        let moved = store
+            .resolve_service_registry("svc-a")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(moved.provider_peer, "peer-new", "{kind}");
+        let changes = store.registry_changes_since(before, 10).await.unwrap();
+        assert_eq!(changes.upserted.len(), 1, "{kind}");
+        assert_eq!(changes.upserted[0].provider_peer, "peer-new", "{kind}");
+        assert_eq!(
+            store.service_subscribers("svc-b").await.unwrap(),
+            vec!["peer-new", "peer-other"],
+            "{kind}"
+        );
+        assert_eq!(
+            store.service_subscribers("svc-c").await.unwrap(),
+            vec!["peer-new"],
+            "{kind}"
+        );
+        assert_eq!(
+            store.peer_role("peer-provider").await.unwrap(),
+            None,
+            "{kind}"
+        );
+        assert_eq!(
+            store.peer_role("peer-new").await.unwrap().as_deref(),
+            Some("edge"),
+            "{kind}"
+        );
+        assert_eq!(
+            store
+                .hand_over_peer("peer-provider", "peer-new")
+                .await
+                .unwrap(),
+            0,
+            "{kind}"
+        );
+    }
+
+    pub async fn invites_are_redeemed_once(kind: &str, store: Arc<dyn Store>) {
+        assert_eq!(
+            store.invite_redeemed_by("invite-1").await.unwrap(),
+            None,
+            "{kind}"
+        );
+        assert!(
+            store.redeem_invite("invite-1", "peer-a").await.unwrap(),
+            "{kind}"
+        );
+        assert_eq!(
+            store
+                .invite_redeemed_by("invite-1")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("peer-a"),
+            "{kind}"
+        );
+        // The same peer may present it again when it reconnects
+        assert!(
+            store.redeem_invite("invite-1", "peer-a").await.unwrap(),
+            "{kind}"
+        );
+        assert!(
+            !store.redeem_invite("invite-1", "peer-b").await.unwrap(),
+            "{kind}"
+        );
+        assert!(
+            store.redeem_invite("invite-2", "peer-b").await.unwrap(),
+            "{kind}"
+        );
+    }
+
+    pub async fn secure_route_lifecycle(kind: &str, store: Arc<dyn Store>) {
+        let route = SecureRoute {
+            id: "route-1".into(),
+            subscription_id: "sub-1".into(),
+            relay_peers: vec!["relay-a".into(), "relay-b".into()],
+            local_port: 3333,
+            status: "畅通".into(),
+        };
+        store.add_secure_route(route.clone()).await.unwrap();
+        assert!(store.add_secure_route(route).await.is_err(), "{kind}");
+
+        assert!(
+            store
+                .update_secure_route_status("route-1", "断开")
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+        let found = store.find_secure_route("route-1").await.unwrap().unwrap();
+        assert_eq!(found.relay_peers, vec!["relay-a", "relay-b"], "{kind}");
+        assert_eq!(found.local_port, 3333, "{kind}");
+        assert_eq!(found.status, "断开", "{kind}");
+        assert_eq!(store.secure_routes().await.unwrap().len(), 1, "{kind}");
+
+        assert!(
+            store.remove_secure_route("route-1").await.unwrap(),
+            "{kind}"
+        );
+        assert!(
+            !store.remove_secure_route("route-1").await.unwrap(),
+            "{kind}"
+        );
+        assert!(
+            !store
+                .update_secure_route_status("route-1", "畅通")
+                .await
+                .unwrap(),
+            "{kind}"
+        );
+        assert!(
+            store.find_secure_route("route-1").await.unwrap().is_none(),
+            "{kind}"
+        );
+    }
+}
@@ -1,11 +1,18 @@
 import type {
+  AdmissionMode,
   CommunityNode,
   CommunityService,
   CommunitySummary,
+  JoinRequest,
+  KeyFileStatus,
+  NodeBan,
   NodeInfo,
+  PortaEvent,
   PublishedService,
   SecureRoute,
   ServiceDescriptor,
+  ServiceFilter,
+  ServicePage,
   SubscribedService
 } from "../types";
 import { ElMessage } from "element-plus";
@@ -68,14 +75,27 @@ export async function generateNodeKey(): Promise<NodeInfo> {
   });
 }
 
+export async function changeKeyPassphrase(payload: {
+  current_passphrase?: string;
+  new_passphrase?: string;
+}): Promise<KeyFileStatus> {
+  return await request<KeyFileStatus>("/porta/node/key/passphrase", {
+    method: "POST",
+    body: JSON.stringify(payload)
+  });
+}
+
 export async function fetchCommunities(): Promise<CommunitySummary[]> {
   return await request<CommunitySummary[]>("/porta/community/list");
 }
 
 export async function addCommunity(payload: {
-  name: string;
-  description: string;
-  multiaddr: string;
+  name?: string;
+  description?: string;
+  multiaddr?: string;
+  multiaddrs?: string[];
+  peer_id?: string;
+  invite?: string;
 }) {
   return await request<CommunitySummary>("/porta/community/add", {
     method: "POST",
@@ -91,11 +111,20 @@ export async function connectCommunity(id: string) {
 }
 
 export async function fetchCommunityServices(
-  communityId: string
+  communityId: string,
+  filter: ServiceFilter = {}
 ): Promise<ServiceDescriptor[]> {
-  return await request<ServiceDescriptor[]>(
-    `/porta/service/discover?communityId=${communityId}`
-  );
+  const params = new URLSearchParams({ communityId });
+  if (filter.q) params.set("q", filter.q);
+  if (filter.type) params.set("type", filter.type);
+  if (filter.tags?.length) params.set("tags", filter.tags.join(","));
+  if (filter.provider) params.set("provider", filter.provider);
+  if (filter.onlineOnly !== undefined) params.set("onlineOnly", String(filter.onlineOnly));
+  if (filter.offset !== undefined) params.set("offset", String(filter.offset));
+  if (filter.limit !== undefined) params.set("limit", String(filter.limit));
+  if (filter.cursor) params.set("cursor", filter.cursor);
+  const page = await request<ServicePage<ServiceDescriptor>>(`/porta/service/discover?${params}`);
+  return page.items;
 }
 
 export async function fetchSubscribedServices(): Promise<SubscribedService[]> {
@@ -164,9 +193,39 @@ export async function announceService(id: string, enabled: boolean) {
   );
 }
 
-export async function banNode(id: string, banned: boolean) {
+export async function banNode(ban: NodeBan) {
+  return await request<string[]>("/porta/community/node/ban", {
+    method: "POST",
+    body: JSON.stringify(ban)
+  });
+}
+
+export async function unbanNode(id: string) {
+  return await request<string[]>("/porta/community/node/unban", {
+    method: "POST",
+    body: JSON.stringify({ id })
+  });
+}
+
+export async function fetchAdmissionMode(): Promise<AdmissionMode> {
+  const data = await request<{ mode: AdmissionMode }>("/porta/community/admission");
+  return data.mode;
+}
+
+export async function setAdmissionMode(mode: AdmissionMode) {
+  return await request("/porta/community/admission", {
+    method: "POST",
+    body: JSON.stringify({ mode })
+  });
+}
+
+export async function fetchJoinRequests(): Promise<JoinRequest[]> {
+  return await request<JoinRequest[]>("/porta/community/node/requests");
+}
+
+export async function reviewJoinRequest(id: string, approve: boolean) {
   return await request(
-    banned ? "/porta/community/node/ban" : "/porta/community/node/unban",
+    approve ? "/porta/community/node/approve" : "/porta/community/node/reject",
     {
       method: "POST",
       body: JSON.stringify({ id })
@@ -202,3 +261,30 @@ export async function secureDisconnect(id: string) {
 export async function fetchSecureRoutes(): Promise<SecureRoute[]> {
   return await request<SecureRoute[]>("/porta/service/secure-routes");
 }
+
+export function subscribeEvents(
+  onEvent: (event: PortaEvent) => void,
+  types: PortaEvent["type"][] = []
+): () => void {
+  const query = types.length ? `?types=${types.join(",")}` : "";
+  const source = new EventSource(`${baseUrl}/porta/events${query}`);
+  const kinds: PortaEvent["type"][] = types.length
+    ? types
+    : [
+        "services_changed",
+        "subscription_changed",
+        "peer_connected",
+        "peer_disconnected",
+        "community_status",
+        "session_opened",
+        "session_closed",
+        "route_status",
+        "publish_result"
+      ];
+  for (const kind of kinds) {
+    source.addEventListener(kind, (msg) => {
+      onEvent(JSON.parse((msg as MessageEvent<string>).data) as PortaEvent);
+    });
+  }
+  return () => source.close();
+}
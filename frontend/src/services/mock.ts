@@ -147,12 +147,12 @@ export const mockPublishedServices: PublishedService[] = [
 ];
 
 export const mockCommunityNodes: CommunityNode[] = [
-  { id: "node-001", uuid: "a3f5e8d2-4b1c-4e7f-9a2d-1c8e5f7b3d9a", status: "在线", banned: false },
-  { id: "node-002", uuid: "b7c9d4e1-5a2f-4b8c-8d3e-2f9a6c8d4e1b", status: "在线", banned: false },
-  { id: "node-003", uuid: "c8d1e5f2-6b3c-4d9e-7f4a-3b1d7e8f5c2a", status: "离线", banned: false },
-  { id: "node-004", uuid: "d9e2f6a3-7c4d-4e1b-8a5f-4c2e8f9a6d3b", status: "在线", banned: false },
-  { id: "node-005", uuid: "e1f3a7b4-8d5e-4f2c-9b6a-5d3f9a1b7e4c", status: "离线", banned: true },
-  { id: "node-006", uuid: "f2a4b8c5-9e6f-4a3d-1c7b-6e4a1c8f2b5d", status: "在线", banned: false }
+  { id: "node-001", uuid: "a3f5e8d2-4b1c-4e7f-9a2d-1c8e5f7b3d9a", name: "edge-001", status: "online", online: true, banned: false, joined_at: "2026-01-02 09:00:00", last_seen: "2026-01-10 12:00:00", services: 1 },
+  { id: "node-002", uuid: "b7c9d4e1-5a2f-4b8c-8d3e-2f9a6c8d4e1b", name: "edge-002", status: "online", online: true, banned: false, joined_at: "2026-01-03 09:00:00", last_seen: "2026-01-10 12:00:00", services: 2 },
+  { id: "node-003", uuid: "c8d1e5f2-6b3c-4d9e-7f4a-3b1d7e8f5c2a", name: "edge-003", status: "offline", online: false, banned: false, joined_at: "2026-01-04 09:00:00", last_seen: "2026-01-10 12:00:00", services: 0 },
+  { id: "node-004", uuid: "d9e2f6a3-7c4d-4e1b-8a5f-4c2e8f9a6d3b", name: "edge-004", status: "online", online: true, banned: false, joined_at: "2026-01-05 09:00:00", last_seen: "2026-01-10 12:00:00", services: 1 },
+  { id: "node-005", uuid: "e1f3a7b4-8d5e-4f2c-9b6a-5d3f9a1b7e4c", name: "edge-005", status: "offline", online: false, banned: true, ban_reason: "滥用中继带宽", joined_at: "2026-01-06 09:00:00", last_seen: "2026-01-10 12:00:00", services: 2 },
+  { id: "node-006", uuid: "f2a4b8c5-9e6f-4a3d-1c7b-6e4a1c8f2b5d", name: "edge-006", status: "online", online: true, banned: false, joined_at: "2026-01-07 09:00:00", last_seen: "2026-01-10 12:00:00", services: 0 }
 ];
 
 export const mockCommunityServices: CommunityService[] = [
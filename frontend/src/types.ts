@@ -12,14 +12,34 @@ export interface NodeInfo {
   dht_enable: boolean;
 }
 
+export interface KeyFileStatus {
+  key_path: string | null;
+  encrypted: boolean;
+}
+
 export interface CommunitySummary {
   id: string;
   name: string;
   description: string;
   peers: number;
   joined: boolean;
+  multiaddr?: string;
+  multiaddrs?: string[];
+  last_multiaddr?: string;
+  managed?: boolean;
+  state?: ConnectionState;
+  connect_failures?: number;
+  last_error?: string;
+  next_retry_at?: number;
 }
 
+export type ConnectionState =
+  | "disconnected"
+  | "connecting"
+  | "handshaking"
+  | "connected"
+  | "backoff";
+
 export interface ServiceDescriptor {
   uuid: string;
   name: string;
@@ -27,6 +47,8 @@ export interface ServiceDescriptor {
   remote_port: number;
   provider: string;
   description: string;
+  online?: boolean;
+  tags?: string[];
 }
 
 export interface SubscribedService {
@@ -36,7 +58,7 @@ export interface SubscribedService {
   community: string;
   remote_addr: string;
   local_mapping: string;
-  status: "畅通" | "连接中" | "断开";
+  status: "畅通" | "连接中" | "断开" | "离线" | "已下架";
 }
 
 export interface PublishedService {
@@ -48,13 +70,58 @@ export interface PublishedService {
   subscriptions: number;
   status: "在线" | "已下架";
   publish_date: string;
+  tags?: string[];
+  managed?: boolean;
+}
+
+export interface ServiceFilter {
+  q?: string;
+  type?: string;
+  tags?: string[];
+  provider?: string;
+  onlineOnly?: boolean;
+  offset?: number;
+  limit?: number;
+  /** `next_cursor` of the previous page */
+  cursor?: string;
+}
+
+export interface ServicePage<T> {
+  items: T[];
+  total: number;
+  next_cursor?: string;
 }
 
 export interface CommunityNode {
   id: string;
   uuid: string;
-  status: "在线" | "离线";
+  name: string;
+  status: string;
+  online: boolean;
   banned: boolean;
+  ban_reason?: string;
+  /** Unix seconds the ban lifts at; permanent when unset */
+  banned_until?: number;
+  joined_at?: string;
+  last_seen?: string;
+  services: number;
+}
+
+export interface NodeBan {
+  id?: string;
+  uuid?: string;
+  reason?: string;
+  expires_in_secs?: number;
+}
+
+export type AdmissionMode = "open" | "invite" | "approval";
+
+export interface JoinRequest {
+  peer_id: string;
+  node_id: string;
+  name: string;
+  status: "pending" | "rejected";
+  requested_at?: string;
 }
 
 export interface CommunityService {
@@ -74,3 +141,27 @@ export interface SecureRoute {
   local_port: number;
   status: string;
 }
+
+export type PortaEvent =
+  | { type: "services_changed"; community_id: string; upserted: string[]; removed: string[] }
+  | {
+      type: "subscription_changed";
+      service_uuid: string;
+      change:
+        | { kind: "updated"; provider_addr: string; port: number }
+        | { kind: "offline" }
+        | { kind: "removed" };
+    }
+  | { type: "peer_connected"; peer_id: string }
+  | { type: "peer_disconnected"; peer_id: string }
+  | { type: "community_status"; community_id: string; connected: boolean; error?: string }
+  | { type: "session_opened"; session_id: string; service_id: string; local_port: number }
+  | { type: "session_closed"; session_id: string; service_id: string }
+  | { type: "route_status"; route_id: string; status: string }
+  | {
+      type: "publish_result";
+      service_id: string;
+      community_id: string;
+      ok: boolean;
+      message?: string;
+    };
@@ -92,8 +92,10 @@ install_binary() {
     
     cp "$BINARY_PATH" "$INSTALL_DIR/porta-server"
     chmod 755 "$INSTALL_DIR/porta-server"
+    cp "$SCRIPT_DIR/target/release/porta" "$INSTALL_DIR/porta"
+    chmod 755 "$INSTALL_DIR/porta"
     
-    print_success "Binary installed to $INSTALL_DIR/porta-server"
+    print_success "Binaries installed to $INSTALL_DIR (porta-server, porta)"
 }
 
 # Install configuration
@@ -184,6 +186,7 @@ uninstall() {
     # Remove files
     rm -f "$SERVICE_FILE"
     rm -f "$INSTALL_DIR/porta-server"
+    rm -f "$INSTALL_DIR/porta"
     
     systemctl daemon-reload
     
@@ -0,0 +1,108 @@
+//! Minimal HTTP client for the Porta management API
+
+use anyhow::{bail, Context, Result};
+use http_body_util::{BodyExt, Full};
+use hyper::{body::Bytes, header, Method, Request};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::TokioExecutor,
+};
+use porta_backend::models::ApiResponse;
+use serde::{de::DeserializeOwned, Serialize};
+
+pub struct ApiClient {
+    base: String,
+    http: Client<HttpConnector, Full<Bytes>>,
+}
+
+impl ApiClient {
+    /// `base` is the API root, e.g. `http://127.0.0.1:8090`
+    pub fn new(base: &str) -> Result<Self> {
+        let base = base.trim_end_matches('/').to_string();
+        if !base.starts_with("http://") {
+            bail!(
+                "Unsupported API address '{}': only http:// is supported",
+                base
+            );
+        }
+        Ok(Self {
+            base,
+            http: Client::builder(TokioExecutor::new()).build_http(),
+        })
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<Option<T>> {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(format!("{}{}", self.base, path))
+            .body(Full::default())?;
+        self.send(request).await
+    }
+
+    pub async fn post<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &impl Serialize,
+    ) -> Result<Option<T>> {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(format!("{}{}", self.base, path))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(serde_json::to_vec(body)?)))?;
+        self.send(request).await
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: Request<Full<Bytes>>) -> Result<Option<T>> {
+        let path = request.uri().path().to_string();
+        let response = self
+            .http
+            .request(request)
+            .await
+            .with_context(|| format!("Failed to reach Porta API at {}", self.base))?;
+        let status = response.status();
+        let bytes = response.into_body().collect().await?.to_bytes();
+        let body: ApiResponse<T> = serde_json::from_slice(&bytes)
+            .with_context(|| format!("Unexpected response from {} ({})", path, status))?;
+        if body.code != 0 {
+            bail!("{}", body.message);
+        }
+        Ok(body.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use porta_backend::{models::NodeInfo, BackendConfig};
+
+    #[tokio::test]
+    async fn test_client_against_running_api() {
+        let app = porta_backend::create_app_with(BackendConfig {
+            db_path: ":memory:".into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = ApiClient::new(&format!("http://{}/", addr)).unwrap();
+        let info: Option<NodeInfo> = client.get("/porta/node/info").await.unwrap();
+        assert!(!info.unwrap().node_id.is_empty());
+
+        let err = client
+            .post::<()>(
+                "/porta/community/remove",
+                &serde_json::json!({ "id": "missing" }),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "移除社区失败: 未找到社区");
+    }
+
+    #[test]
+    fn test_rejects_https() {
+        assert!(ApiClient::new("https://example.com").is_err());
+    }
+}
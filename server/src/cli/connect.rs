@@ -0,0 +1,47 @@
+//! `porta connect`: map a published service to a local port without a
+//! persistent node, until interrupted
+
+use anyhow::{Context, Result};
+use serde_json::json;
+use tokio::net::TcpListener;
+
+use crate::{
+    ephemeral::{self, EphemeralNode, JoinOptions},
+    output::print_json,
+};
+
+pub async fn run(
+    options: JoinOptions,
+    service: &str,
+    local_port: Option<u16>,
+    json: bool,
+) -> Result<()> {
+    ephemeral::init_logging();
+    let node = EphemeralNode::join(options).await?;
+    let target = node.resolve(service).await?;
+    // Bound here and handed over, so a picked port cannot be taken in between
+    let listener = TcpListener::bind(("127.0.0.1", local_port.unwrap_or(0)))
+        .await
+        .context("Failed to listen on a local port")?;
+    let local_port = listener.local_addr()?.port();
+    node.state
+        .app
+        .forward_service(&target.uuid, listener)
+        .await?;
+
+    if json {
+        print_json(&json!({
+            "local_port": local_port,
+            "service_uuid": target.uuid,
+            "name": target.name,
+        }))?;
+    } else {
+        println!(
+            "Forwarding localhost:{} -> {} ({}), press Ctrl+C to stop",
+            local_port, target.name, target.uuid
+        );
+    }
+
+    ephemeral::shutdown_signal().await;
+    Ok(())
+}
@@ -0,0 +1,267 @@
+//! Throwaway in-memory node for one-shot commands
+//!
+//! Nothing is written to disk: the store is `:memory:` and, unless a key file
+//! is given, the node gets a fresh identity, so several instances can run side
+//! by side in CI.
+
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Context, Result};
+use porta_backend::{
+    keystore,
+    models::{CommunityAddRequest, DiscoveredService, ServiceQuery},
+    state::AppState,
+    BackendConfig,
+};
+use tracing_subscriber::EnvFilter;
+
+/// Communities a one-shot node joins and the identity it joins them with
+pub struct JoinOptions {
+    pub communities: Vec<CommunityAddRequest>,
+    /// Key file to use instead of a throwaway identity, so communities that
+    /// admit approved members only let the node in
+    pub key: Option<PathBuf>,
+}
+
+pub struct EphemeralNode {
+    pub state: AppState,
+    community_ids: Vec<String>,
+}
+
+impl EphemeralNode {
+    /// Start an in-memory edge node and join the given communities; succeeds
+    /// when at least one of them admits it.
+    pub async fn join(options: JoinOptions) -> Result<Self> {
+        let (key_path, key_passphrase) = match &options.key {
+            Some(path) => unlock_key(path).await?,
+            None => (None, None),
+        };
+        let state = AppState::with_config(BackendConfig {
+            db_path: ":memory:".into(),
+            node_name: Some("porta connect".into()),
+            key_path,
+            key_passphrase,
+            ..Default::default()
+        })
+        .await?;
+        let mut community_ids = Vec::new();
+        let mut last_error = None;
+        for request in options.communities {
+            let community = state.app.add_community(request).await?;
+            match state.app.connect_community(&community.id).await {
+                Ok(()) => community_ids.push(community.id),
+                Err(err) => {
+                    tracing::warn!("Failed to join community {}: {}", community.name, err);
+                    last_error = Some(err);
+                }
+            }
+        }
+        if community_ids.is_empty() {
+            return Err(last_error.unwrap_or_else(|| anyhow!("No community to join")));
+        }
+        Ok(Self {
+            state,
+            community_ids,
+        })
+    }
+
+    /// Look up an online service by UUID, or by name when that is unambiguous.
+    pub async fn resolve(&self, reference: &str) -> Result<DiscoveredService> {
+        let mut services = Vec::new();
+        for id in &self.community_ids {
+            let page = self
+                .state
+                .app
+                .discover_services(Some(id.clone()), ServiceQuery::default())
+                .await?;
+            services.extend(page.items);
+        }
+        pick_service(services, reference)
+    }
+}
+
+/// Check the key file exists (so a typo does not silently create a new
+/// identity) and read the passphrase of an encrypted one from
+/// `PORTA_KEY_PASSPHRASE`.
+async fn unlock_key(path: &std::path::Path) -> Result<(Option<String>, Option<String>)> {
+    if !path.is_file() {
+        bail!("Key file not found: {}", path.display());
+    }
+    let path = path
+        .to_str()
+        .ok_or_else(|| anyhow!("Key file path is not valid UTF-8"))?
+        .to_string();
+    let passphrase = if keystore::is_encrypted(&path).await {
+        Some(
+            std::env::var("PORTA_KEY_PASSPHRASE")
+                .with_context(|| format!("{} is encrypted, set PORTA_KEY_PASSPHRASE", path))?,
+        )
+    } else {
+        None
+    };
+    Ok((Some(path), passphrase))
+}
+
+fn pick_service(services: Vec<DiscoveredService>, reference: &str) -> Result<DiscoveredService> {
+    if let Some(service) = services.iter().find(|s| s.uuid == reference) {
+        return Ok(service.clone());
+    }
+    let mut named: Vec<DiscoveredService> = services
+        .into_iter()
+        .filter(|s| s.name == reference)
+        .collect();
+    match named.len() {
+        0 => Err(anyhow!(
+            "No online service with UUID or name '{}'",
+            reference
+        )),
+        1 => Ok(named.remove(0)),
+        _ => {
+            let uuids: Vec<String> = named.into_iter().map(|s| s.uuid).collect();
+            bail!(
+                "Several services are named '{}', use a UUID instead: {}",
+                reference,
+                uuids.join(", ")
+            )
+        }
+    }
+}
+
+/// Log to stderr so stdout stays usable by scripts; quiet unless `RUST_LOG` is set.
+pub fn init_logging() {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| "warn".into()))
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+/// Resolves on Ctrl+C, or SIGTERM on unix.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(uuid: &str, name: &str) -> DiscoveredService {
+        DiscoveredService {
+            uuid: uuid.into(),
+            name: name.into(),
+            r#type: "TCP".into(),
+            remote_port: 22,
+            provider: "peer".into(),
+            description: String::new(),
+            subscribed: None,
+            community_id: None,
+            provider_addr: None,
+            online: true,
+            tags: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_pick_service_by_uuid_or_unique_name() {
+        let services = vec![
+            service("svc-1", "ssh"),
+            service("svc-2", "web"),
+            service("svc-3", "web"),
+        ];
+        assert_eq!(
+            pick_service(services.clone(), "svc-3").unwrap().uuid,
+            "svc-3"
+        );
+        assert_eq!(pick_service(services.clone(), "ssh").unwrap().uuid, "svc-1");
+        assert!(pick_service(services.clone(), "web").is_err());
+        assert!(pick_service(services, "db").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_join_invite_only_community_with_invite() {
+        use porta_backend::models::{AdmissionMode, InviteCreateRequest};
+
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let community = AppState::with_config(BackendConfig {
+            role: "community".into(),
+            db_path: ":memory:".into(),
+            listen_addrs: vec![format!("/ip4/127.0.0.1/tcp/{}", port)],
+            admission: Some(AdmissionMode::Invite),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let multiaddr = format!(
+            "/ip4/127.0.0.1/tcp/{}/p2p/{}",
+            port,
+            community.p2p.peer_id()
+        );
+        let request = |invite: Option<String>| CommunityAddRequest {
+            id: None,
+            name: "community".into(),
+            description: multiaddr.clone(),
+            multiaddr: Some(multiaddr.clone()),
+            multiaddrs: Vec::new(),
+            peer_id: None,
+            invite,
+        };
+
+        let refused = EphemeralNode::join(JoinOptions {
+            communities: vec![request(None)],
+            key: None,
+        })
+        .await;
+        assert!(refused.is_err());
+
+        let issued = community
+            .app
+            .create_invite(InviteCreateRequest {
+                multiaddrs: vec![format!("/ip4/127.0.0.1/tcp/{}", port)],
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let node = EphemeralNode::join(JoinOptions {
+            communities: vec![request(Some(issued.token))],
+            key: None,
+        })
+        .await
+        .unwrap();
+        assert_eq!(node.community_ids.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_refuse_missing_key_file() {
+        let err = EphemeralNode::join(JoinOptions {
+            communities: Vec::new(),
+            key: Some(PathBuf::from("/nonexistent/porta.key")),
+        })
+        .await
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("Key file not found"));
+    }
+}
@@ -0,0 +1,873 @@
+//! Porta CLI - manage a running Porta node over its HTTP API
+//!
+//! Talks to the same `/porta/*` endpoints as the web UI, so it works against
+//! `porta-server` on headless hosts (locally or through an SSH tunnel).
+
+mod client;
+mod connect;
+mod ephemeral;
+mod output;
+mod stdio;
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Args, Parser, Subcommand};
+use client::ApiClient;
+use output::{print_json, yes_no, Table};
+use porta_backend::models::{
+    BackupImportReport, CommunityAddRequest, CommunitySummary, DiscoveredService, NodeInfo,
+    ProxyStatus, PublishedService, ReloadReport, SecureRoute, ServicePage, SessionInfo,
+    SubscribedService,
+};
+use serde::Serialize;
+use serde_json::json;
+
+/// Porta command-line client
+#[derive(Parser, Debug)]
+#[command(name = "porta")]
+#[command(author = "Porta Team")]
+#[command(version = env!("CARGO_PKG_VERSION"))]
+#[command(about = "Manage a running Porta node from the command line")]
+struct Cli {
+    /// Porta API address
+    #[arg(long, env = "PORTA_API", default_value = "http://127.0.0.1:8090")]
+    api: String,
+
+    /// Print raw JSON instead of tables
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show node information
+    Node,
+    /// Manage communities
+    #[command(subcommand)]
+    Community(CommunityCommand),
+    /// Discover, subscribe to and connect services
+    #[command(subcommand)]
+    Service(ServiceCommand),
+    /// Manage services published by this node
+    #[command(subcommand)]
+    Publish(PublishCommand),
+    /// Manage secure relay routes
+    #[command(subcommand)]
+    Route(RouteCommand),
+    /// Manage the built-in proxy
+    #[command(subcommand)]
+    Proxy(ProxyCommand),
+    /// Reload the server configuration file
+    Reload,
+    /// Back up or restore the node state
+    #[command(subcommand)]
+    Backup(BackupCommand),
+    /// Map a service to a local port from a throwaway in-memory node,
+    /// without a running server; stops on Ctrl+C
+    Connect {
+        #[command(flatten)]
+        join: JoinArgs,
+        /// Service UUID or unique name
+        service: String,
+        /// Local port to listen on (default: a free port)
+        #[arg(long)]
+        local_port: Option<u16>,
+    },
+    /// Bridge a service to stdin/stdout from a throwaway in-memory node,
+    /// e.g. `ssh -o ProxyCommand="porta stdio ssh" host`
+    Stdio {
+        #[command(flatten)]
+        join: JoinArgs,
+        /// Service UUID or unique name
+        service: String,
+    },
+}
+
+/// How `connect` and `stdio` reach a community
+#[derive(Args, Debug)]
+struct JoinArgs {
+    /// Community multiaddr including /p2p/<peerId> (default: the communities
+    /// joined by the node at --api)
+    #[arg(long, env = "PORTA_COMMUNITY")]
+    community: Option<String>,
+    /// Invite token to join with; names the community by itself
+    #[arg(long, env = "PORTA_INVITE")]
+    invite: Option<String>,
+    /// Join with this key file instead of a throwaway identity, e.g. a member
+    /// key the community already approved; an encrypted key is unlocked with
+    /// PORTA_KEY_PASSPHRASE
+    #[arg(long, env = "PORTA_KEY")]
+    key: Option<std::path::PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum CommunityCommand {
+    /// List communities
+    List,
+    /// Add a community
+    Add {
+        #[arg(long)]
+        name: String,
+        #[arg(long)]
+        description: String,
+        /// Community multiaddr including /p2p/<peerId>
+        #[arg(long)]
+        multiaddr: String,
+        #[arg(long)]
+        id: Option<String>,
+    },
+    /// Remove a community
+    Remove { id: String },
+    /// Join a community
+    Connect { id: String },
+}
+
+#[derive(Subcommand, Debug)]
+enum ServiceCommand {
+    /// Discover services, from the local cache unless a community is given
+    Discover(DiscoverArgs),
+    /// Subscribe to a discovered service
+    Subscribe {
+        /// Service UUID
+        uuid: String,
+        /// Community to look the service up in
+        #[arg(long)]
+        community: Option<String>,
+        /// Local port to map the service to (default: the service port)
+        #[arg(long)]
+        local_port: Option<u16>,
+    },
+    /// List subscriptions
+    List,
+    /// Open the local port mapping for a subscription
+    Connect { id: String },
+    /// Close the local port mapping for a subscription
+    Disconnect { id: String },
+    /// List active sessions
+    Sessions,
+}
+
+#[derive(Args, Debug, Serialize)]
+struct DiscoverArgs {
+    #[arg(long)]
+    #[serde(rename = "communityId", skip_serializing_if = "Option::is_none")]
+    community: Option<String>,
+    /// Keyword matched against name and description
+    #[arg(short, long)]
+    #[serde(rename = "q", skip_serializing_if = "Option::is_none")]
+    query: Option<String>,
+    #[arg(long = "type")]
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    service_type: Option<String>,
+    /// Required tag; repeat for several
+    #[arg(long = "tag")]
+    #[serde(serialize_with = "join_tags", skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    /// Provider peer ID
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    provider: Option<String>,
+    /// Include offline services
+    #[arg(long)]
+    #[serde(rename = "onlineOnly", serialize_with = "online_only")]
+    all: bool,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    offset: Option<u32>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<u32>,
+    /// Continue after the page that printed this cursor
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cursor: Option<String>,
+}
+
+fn join_tags<S: serde::Serializer>(tags: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&tags.join(","))
+}
+
+fn online_only<S: serde::Serializer>(all: &bool, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bool(!all)
+}
+
+#[derive(Subcommand, Debug)]
+enum PublishCommand {
+    /// List published services
+    List,
+    /// Publish a local service, or update it when --id is given
+    Add {
+        #[arg(long)]
+        name: String,
+        #[arg(long = "type")]
+        service_type: String,
+        #[arg(long)]
+        port: u16,
+        #[arg(long, default_value = "")]
+        summary: String,
+        /// Tag; repeat for several
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        #[arg(long)]
+        id: Option<String>,
+    },
+    /// Take a published service offline
+    Unpublish { id: String },
+    /// Delete a published service
+    Remove { id: String },
+}
+
+#[derive(Subcommand, Debug)]
+enum RouteCommand {
+    /// List secure routes
+    List,
+    /// Connect a subscription through relay peers
+    Add {
+        subscription_id: String,
+        /// Relay peer ID; repeat for several
+        #[arg(long = "relay", required = true)]
+        relays: Vec<String>,
+        #[arg(long)]
+        local_port: Option<u16>,
+    },
+    /// Close a secure route
+    Remove { id: String },
+}
+
+#[derive(Subcommand, Debug)]
+enum ProxyCommand {
+    /// Show proxy status
+    Status,
+    Enable,
+    Disable,
+}
+
+#[derive(Subcommand, Debug)]
+enum BackupCommand {
+    /// Export identity, communities, subscriptions, published services,
+    /// secure routes and proxy settings to one file
+    Export {
+        /// Output file (default: stdout)
+        #[arg(long, short)]
+        output: Option<std::path::PathBuf>,
+        /// Passphrase to encrypt the backup with; required, as the backup
+        /// holds the node's private key
+        #[arg(long, env = "PORTA_BACKUP_PASSPHRASE")]
+        passphrase: String,
+    },
+    /// Restore a backup file into the node
+    Import {
+        file: std::path::PathBuf,
+        /// Passphrase of an encrypted backup
+        #[arg(long, env = "PORTA_BACKUP_PASSPHRASE")]
+        passphrase: Option<String>,
+        /// What to do with entries that already exist
+        #[arg(long, default_value = "skip", value_parser = ["skip", "replace", "fail"])]
+        on_conflict: String,
+        /// Keep this node's identity instead of restoring the backed-up one
+        #[arg(long)]
+        keep_identity: bool,
+    },
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    if let Err(err) = run(cli).await {
+        eprintln!("Error: {:#}", err);
+        std::process::exit(1);
+    }
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let json = cli.json;
+    let client = ApiClient::new(&cli.api)?;
+    if let Command::Connect {
+        join,
+        service,
+        local_port,
+    } = cli.command
+    {
+        let options = join_options(&client, join).await?;
+        return connect::run(options, &service, local_port, json).await;
+    }
+    if let Command::Stdio { join, service } = cli.command {
+        return stdio::run(join_options(&client, join).await?, &service).await;
+    }
+    match cli.command {
+        Command::Node => {
+            let info: NodeInfo = required(client.get("/porta/node/info").await?)?;
+            if json {
+                return print_json(&info);
+            }
+            let mut table = Table::new(vec!["FIELD", "VALUE"]);
+            table.row(vec!["name".into(), info.name]);
+            table.row(vec!["peer id".into(), info.node_id]);
+            table.row(vec!["uuid".into(), info.uuid]);
+            table.row(vec!["tcp port".into(), info.tcp_listen_port.to_string()]);
+            table.row(vec!["quic port".into(), info.quci_listen_port.to_string()]);
+            table.row(vec!["mdns".into(), yes_no(info.mdns_enable)]);
+            table.row(vec!["dht".into(), yes_no(info.dht_enable)]);
+            table.row(vec!["external".into(), info.external_addr.join(", ")]);
+            print!("{}", table.render());
+        }
+        Command::Community(command) => community(&client, command, json).await?,
+        Command::Service(command) => service(&client, command, json).await?,
+        Command::Publish(command) => publish(&client, command, json).await?,
+        Command::Route(command) => route(&client, command, json).await?,
+        Command::Proxy(command) => proxy(&client, command, json).await?,
+        Command::Backup(command) => backup(&client, command, json).await?,
+        Command::Reload => {
+            let report: ReloadReport =
+                required(client.post("/porta/admin/reload", &json!({})).await?)?;
+            if json {
+                return print_json(&report);
+            }
+            println!("Applied: {}", list_or_none(&report.applied));
+            println!(
+                "Restart required: {}",
+                list_or_none(&report.restart_required)
+            );
+        }
+        Command::Connect { .. } | Command::Stdio { .. } => unreachable!("runs without the API"),
+    }
+    Ok(())
+}
+
+async fn community(client: &ApiClient, command: CommunityCommand, json: bool) -> Result<()> {
+    match command {
+        CommunityCommand::List => {
+            let list: Vec<CommunitySummary> = client
+                .get("/porta/community/list")
+                .await?
+                .unwrap_or_default();
+            if json {
+                return print_json(&list);
+            }
+            let mut table = Table::new(vec![
+                "ID",
+                "NAME",
+                "JOINED",
+                "PEERS",
+                "MANAGED",
+                "MULTIADDR",
+            ]);
+            for item in list {
+                table.row(vec![
+                    item.id,
+                    item.name,
+                    yes_no(item.joined),
+                    item.peers.to_string(),
+                    yes_no(item.managed),
+                    item.multiaddr.unwrap_or_default(),
+                ]);
+            }
+            print!("{}", table.render());
+        }
+        CommunityCommand::Add {
+            name,
+            description,
+            multiaddr,
+            id,
+        } => {
+            let body = json!({ "id": id, "name": name, "description": description, "multiaddr": multiaddr });
+            let saved: CommunitySummary =
+                required(client.post("/porta/community/add", &body).await?)?;
+            if json {
+                return print_json(&saved);
+            }
+            println!("Added community {} ({})", saved.name, saved.id);
+        }
+        CommunityCommand::Remove { id } => {
+            client
+                .post::<()>("/porta/community/remove", &json!({ "id": id }))
+                .await?;
+            done(json, &format!("Removed community {}", id))?;
+        }
+        CommunityCommand::Connect { id } => {
+            client
+                .post::<()>("/porta/community/connect", &json!({ "id": id }))
+                .await?;
+            done(json, &format!("Joined community {}", id))?;
+        }
+    }
+    Ok(())
+}
+
+async fn service(client: &ApiClient, command: ServiceCommand, json: bool) -> Result<()> {
+    match command {
+        ServiceCommand::Discover(args) => {
+            let page = discover(client, &args).await?;
+            if json {
+                return print_json(&page);
+            }
+            let mut table = Table::new(vec!["UUID", "NAME", "TYPE", "PORT", "TAGS", "PROVIDER"]);
+            for item in page.items {
+                table.row(vec![
+                    item.uuid,
+                    item.name,
+                    item.r#type,
+                    item.remote_port.to_string(),
+                    item.tags.join(","),
+                    item.provider,
+                ]);
+            }
+            print!("{}", table.render());
+            if let Some(cursor) = page.next_cursor {
+                println!("More results: --cursor '{}'", cursor);
+            }
+        }
+        ServiceCommand::Subscribe {
+            uuid,
+            community,
+            local_port,
+        } => {
+            let args = DiscoverArgs {
+                community: community.clone(),
+                query: None,
+                service_type: None,
+                tags: Vec::new(),
+                provider: None,
+                all: true,
+                offset: None,
+                limit: None,
+                cursor: None,
+            };
+            let service = discover(client, &args)
+                .await?
+                .items
+                .into_iter()
+                .find(|item| item.uuid == uuid)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Service {} not found; run `porta service discover --community <id>` first",
+                        uuid
+                    )
+                })?;
+            let community_name = match service.community_id.as_deref().or(community.as_deref()) {
+                Some(id) => {
+                    let list: Vec<CommunitySummary> = client
+                        .get("/porta/community/list")
+                        .await?
+                        .unwrap_or_default();
+                    list.into_iter()
+                        .find(|item| item.id == id)
+                        .map(|item| item.name)
+                        .unwrap_or_else(|| id.to_string())
+                }
+                None => String::new(),
+            };
+            let body = json!({
+                "service_uuid": service.uuid,
+                "name": service.name,
+                "type": service.r#type,
+                "community": community_name,
+                "remote_addr": format!("{}:{}", service.provider, service.remote_port),
+                "local_mapping": format!("localhost:{}", local_port.unwrap_or(service.remote_port)),
+            });
+            let saved: SubscribedService =
+                required(client.post("/porta/service/subscribe", &body).await?)?;
+            if json {
+                return print_json(&saved);
+            }
+            println!(
+                "Subscribed to {} as {} (local {})",
+                saved.name, saved.id, saved.local_mapping
+            );
+        }
+        ServiceCommand::List => {
+            let list: Vec<SubscribedService> = client
+                .get("/porta/service/subscriptions")
+                .await?
+                .unwrap_or_default();
+            if json {
+                return print_json(&list);
+            }
+            let mut table = Table::new(vec!["ID", "NAME", "TYPE", "COMMUNITY", "LOCAL", "STATUS"]);
+            for item in list {
+                table.row(vec![
+                    item.id,
+                    item.name,
+                    item.r#type,
+                    item.community,
+                    item.local_mapping,
+                    item.status,
+                ]);
+            }
+            print!("{}", table.render());
+        }
+        ServiceCommand::Connect { id } => {
+            client
+                .post::<()>("/porta/service/connect", &json!({ "id": id }))
+                .await?;
+            done(json, &format!("Connected {}", id))?;
+        }
+        ServiceCommand::Disconnect { id } => {
+            client
+                .post::<()>("/porta/service/disconnect", &json!({ "id": id }))
+                .await?;
+            done(json, &format!("Disconnected {}", id))?;
+        }
+        ServiceCommand::Sessions => {
+            let list: Vec<SessionInfo> = client
+                .get("/porta/service/sessions")
+                .await?
+                .unwrap_or_default();
+            if json {
+                return print_json(&list);
+            }
+            let mut table = Table::new(vec!["SESSION", "SERVICE", "LOCAL PORT", "REMOTE", "STATE"]);
+            for item in list {
+                table.row(vec![
+                    item.session_id,
+                    item.service_id,
+                    item.local_port.to_string(),
+                    item.remote_peer,
+                    item.state,
+                ]);
+            }
+            print!("{}", table.render());
+        }
+    }
+    Ok(())
+}
+
+async fn discover(
+    client: &ApiClient,
+    args: &DiscoverArgs,
+) -> Result<ServicePage<DiscoveredService>> {
+    let query = serde_urlencoded::to_string(args)?;
+    client
+        .get(&format!("/porta/service/discover?{}", query))
+        .await?
+        .ok_or_else(|| anyhow!("Empty discovery response"))
+}
+
+async fn publish(client: &ApiClient, command: PublishCommand, json: bool) -> Result<()> {
+    match command {
+        PublishCommand::List => {
+            let list: Vec<PublishedService> = client
+                .get("/porta/service/published")
+                .await?
+                .unwrap_or_default();
+            if json {
+                return print_json(&list);
+            }
+            let mut table = Table::new(vec![
+                "ID",
+                "NAME",
+                "TYPE",
+                "PORT",
+                "STATUS",
+                "SUBSCRIPTIONS",
+                "MANAGED",
+            ]);
+            for item in list {
+                table.row(vec![
+                    item.id,
+                    item.name,
+                    item.r#type,
+                    item.port.to_string(),
+                    item.status,
+                    item.subscriptions.to_string(),
+                    yes_no(item.managed),
+                ]);
+            }
+            print!("{}", table.render());
+        }
+        PublishCommand::Add {
+            name,
+            service_type,
+            port,
+            summary,
+            tags,
+            id,
+        } => {
+            let body = json!({
+                "id": id,
+                "name": name,
+                "type": service_type,
+                "port": port,
+                "summary": summary,
+                "tags": tags,
+            });
+            let saved: PublishedService =
+                required(client.post("/porta/service/publish", &body).await?)?;
+            if json {
+                return print_json(&saved);
+            }
+            println!("Published {} as {}", saved.name, saved.id);
+        }
+        PublishCommand::Unpublish { id } => {
+            client
+                .post::<()>("/porta/service/unpublish", &json!({ "id": id }))
+                .await?;
+            done(json, &format!("Unpublished {}", id))?;
+        }
+        PublishCommand::Remove { id } => {
+            client
+                .post::<()>("/porta/service/remove", &json!({ "id": id }))
+                .await?;
+            done(json, &format!("Removed {}", id))?;
+        }
+    }
+    Ok(())
+}
+
+async fn route(client: &ApiClient, command: RouteCommand, json: bool) -> Result<()> {
+    match command {
+        RouteCommand::List => {
+            let list: Vec<SecureRoute> = client
+                .get("/porta/service/secure-routes")
+                .await?
+                .unwrap_or_default();
+            if json {
+                return print_json(&list);
+            }
+            let mut table =
+                Table::new(vec!["ID", "SUBSCRIPTION", "LOCAL PORT", "RELAYS", "STATUS"]);
+            for item in list {
+                table.row(vec![
+                    item.id,
+                    item.subscription_id,
+                    item.local_port.to_string(),
+                    item.relay_peers.join(","),
+                    item.status,
+                ]);
+            }
+            print!("{}", table.render());
+        }
+        RouteCommand::Add {
+            subscription_id,
+            relays,
+            local_port,
+        } => {
+            let body = json!({
+                "subscription_id": subscription_id,
+                "relay_peers": relays,
+                "local_port": local_port,
+            });
+            let route: SecureRoute =
+                required(client.post("/porta/service/secure-connect", &body).await?)?;
+            if json {
+                return print_json(&route);
+            }
+            println!(
+                "Secure route {} listening on port {}",
+                route.id, route.local_port
+            );
+        }
+        RouteCommand::Remove { id } => {
+            client
+                .post::<()>("/porta/service/secure-disconnect", &json!({ "id": id }))
+                .await?;
+            done(json, &format!("Closed secure route {}", id))?;
+        }
+    }
+    Ok(())
+}
+
+async fn proxy(client: &ApiClient, command: ProxyCommand, json: bool) -> Result<()> {
+    let enabled = match command {
+        ProxyCommand::Status => {
+            let status: ProxyStatus = required(client.get("/porta/proxy/status").await?)?;
+            if json {
+                return print_json(&status);
+            }
+            let mut table = Table::new(vec!["ENABLED", "PORT", "MANAGED"]);
+            table.row(vec![
+                yes_no(status.enabled),
+                status.listen_port.to_string(),
+                yes_no(status.managed),
+            ]);
+            print!("{}", table.render());
+            return Ok(());
+        }
+        ProxyCommand::Enable => true,
+        ProxyCommand::Disable => false,
+    };
+    let path = if enabled {
+        "/porta/proxy/enable"
+    } else {
+        "/porta/proxy/disable"
+    };
+    client
+        .post::<()>(path, &json!({ "enabled": enabled }))
+        .await?;
+    done(
+        json,
+        if enabled {
+            "Proxy enabled"
+        } else {
+            "Proxy disabled"
+        },
+    )
+}
+
+async fn backup(client: &ApiClient, command: BackupCommand, json: bool) -> Result<()> {
+    match command {
+        BackupCommand::Export { output, passphrase } => {
+            let archive: serde_json::Value = required(
+                client
+                    .post("/porta/backup/export", &json!({ "passphrase": passphrase }))
+                    .await?,
+            )?;
+            let text = serde_json::to_string_pretty(&archive)?;
+            let Some(path) = output else {
+                println!("{}", text);
+                return Ok(());
+            };
+            write_private(&path, &text)?;
+            done(json, &format!("Backup written to {}", path.display()))?;
+        }
+        BackupCommand::Import {
+            file,
+            passphrase,
+            on_conflict,
+            keep_identity,
+        } => {
+            let text = std::fs::read_to_string(&file)
+                .with_context(|| format!("Failed to read {}", file.display()))?;
+            let archive: serde_json::Value = serde_json::from_str(&text)
+                .with_context(|| format!("{} is not a Porta backup", file.display()))?;
+            let report: BackupImportReport = required(
+                client
+                    .post(
+                        "/porta/backup/import",
+                        &json!({
+                            "archive": archive,
+                            "passphrase": passphrase,
+                            "on_conflict": on_conflict,
+                            "keep_identity": keep_identity,
+                        }),
+                    )
+                    .await?,
+            )?;
+            if json {
+                return print_json(&report);
+            }
+            println!("Imported: {}", list_or_none(&report.imported));
+            println!("Skipped: {}", list_or_none(&report.skipped));
+            println!(
+                "Restart required: {}",
+                list_or_none(&report.restart_required)
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Write a file only the current user can read; backups contain the node's private key.
+fn write_private(path: &std::path::Path, contents: &str) -> Result<()> {
+    use std::io::Write;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+/// The communities a one-shot node joins: the one given by `--community` or
+/// `--invite`, else those the local node has joined.
+async fn join_options(client: &ApiClient, join: JoinArgs) -> Result<ephemeral::JoinOptions> {
+    let communities = if join.community.is_some() || join.invite.is_some() {
+        vec![CommunityAddRequest {
+            id: None,
+            name: "community".into(),
+            description: join.community.clone().unwrap_or_else(|| "community".into()),
+            multiaddr: join.community,
+            multiaddrs: Vec::new(),
+            peer_id: None,
+            invite: join.invite,
+        }]
+    } else {
+        let list: Vec<CommunitySummary> = client
+            .get("/porta/community/list")
+            .await
+            .context("No --community or --invite given and the local node is unreachable")?
+            .unwrap_or_default();
+        list.into_iter()
+            .filter(|c| c.joined && (c.multiaddr.is_some() || c.peer_id.is_some()))
+            .map(|c| CommunityAddRequest {
+                id: Some(c.id),
+                name: c.name,
+                description: c.description,
+                multiaddr: c.multiaddr,
+                multiaddrs: c.multiaddrs,
+                peer_id: c.peer_id,
+                invite: None,
+            })
+            .collect()
+    };
+    if communities.is_empty() {
+        bail!("No community to join: pass --community or --invite, or join one on the local node");
+    }
+    Ok(ephemeral::JoinOptions {
+        communities,
+        key: join.key,
+    })
+}
+
+fn required<T>(data: Option<T>) -> Result<T> {
+    data.ok_or_else(|| anyhow!("Empty response from Porta API"))
+}
+
+/// Report a successful action that returns no data
+fn done(json: bool, message: &str) -> Result<()> {
+    if json {
+        print_json(&json!({ "ok": true }))
+    } else {
+        println!("{}", message);
+        Ok(())
+    }
+}
+
+fn list_or_none(items: &[String]) -> String {
+    if items.is_empty() {
+        "(none)".into()
+    } else {
+        items.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discover_query_string() {
+        let cli = Cli::try_parse_from([
+            "porta",
+            "service",
+            "discover",
+            "--community",
+            "dev",
+            "-q",
+            "web ui",
+            "--tag",
+            "a",
+            "--tag",
+            "b",
+        ])
+        .unwrap();
+        let Command::Service(ServiceCommand::Discover(args)) = cli.command else {
+            panic!("expected discover");
+        };
+        assert_eq!(
+            serde_urlencoded::to_string(&args).unwrap(),
+            "communityId=dev&q=web+ui&tags=a%2Cb&onlineOnly=true"
+        );
+    }
+
+    #[test]
+    fn test_json_flag_is_global() {
+        let cli = Cli::try_parse_from(["porta", "community", "list", "--json"]).unwrap();
+        assert!(cli.json);
+    }
+}
@@ -0,0 +1,18 @@
+//! `porta stdio`: bridge a published service to stdin/stdout, for use as an
+//! SSH `ProxyCommand` without a local port
+
+use anyhow::Result;
+use porta_backend::tunnel;
+
+use crate::ephemeral::{self, EphemeralNode, JoinOptions};
+
+pub async fn run(options: JoinOptions, service: &str) -> Result<()> {
+    ephemeral::init_logging();
+    let node = EphemeralNode::join(options).await?;
+    let target = node.resolve(service).await?;
+    let stream = node.state.app.open_service_stream(&target.uuid).await?;
+    tokio::select! {
+        result = tunnel::bridge_stdio(stream) => result,
+        _ = ephemeral::shutdown_signal() => Ok(()),
+    }
+}
@@ -5,6 +5,7 @@
 //! runs the P2P backend with HTTP API.
 
 mod config;
+mod reload;
 
 use axum::{
     body::Body,
@@ -13,12 +14,14 @@ use axum::{
     routing::get,
 };
 use clap::Parser;
-use config::Config;
+use config::{CliOverrides, Config};
 use include_dir::{include_dir, Dir};
-use std::path::PathBuf;
+use porta_backend::{keystore::KeyLocked, state::AppState, BackendConfig};
+use reload::{LogHandle, Reloader};
+use std::{io::IsTerminal, path::PathBuf, sync::Arc};
 use tokio::net::TcpListener;
 use tokio::signal;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
 static WEB_DIR: Dir = include_dir!("$CARGO_MANIFEST_DIR/../frontend/dist");
 
@@ -69,15 +72,12 @@ async fn main() -> anyhow::Result<()> {
     let (mut config, created) = Config::load_or_create_default(&config_path)?;
 
     // Apply command-line overrides
-    if let Some(listen) = args.listen {
-        config.server.listen_addr = listen;
-    }
-    if let Some(port) = args.port {
-        config.server.port = port;
-    }
-    if let Some(level) = args.log_level {
-        config.logging.level = level;
-    }
+    let overrides = CliOverrides {
+        listen_addr: args.listen,
+        port: args.port,
+        log_level: args.log_level,
+    };
+    overrides.apply(&mut config);
 
     // Validate configuration
     config.validate()?;
@@ -104,27 +104,28 @@ async fn main() -> anyhow::Result<()> {
     }
 
     // Initialize logging
-    init_logging(&config)?;
+    let log_handle = init_logging(&config)?;
 
     tracing::info!("Starting Porta server v{}", env!("CARGO_PKG_VERSION"));
     tracing::info!("Config file: {}", config_path.display());
     tracing::info!("Node name: {}", config.node.name);
     tracing::info!("Node role: {}", config.node.role);
-    tracing::info!("Database: {}", config.database.path);
-
-    // Set environment variables for the backend to access configuration
-    // Note: Backend should ideally use config passed directly, but for now we use env vars
-    std::env::set_var("PORTA_ROLE", &config.node.role);
-    std::env::set_var("PORTA_DB", &config.database.path);
-    std::env::set_var("PORTA_P2P_TCP_PORT", config.p2p.tcp_port.to_string());
-    std::env::set_var("PORTA_NODE_NAME", &config.node.name);
-    if let Some(ref key_path) = config.node.key_path {
-        std::env::set_var("PORTA_KEY_PATH", key_path);
-    }
-
-    // Create the application (API + embedded web UI)
-    let app = porta_backend::create_app()
-        .await
+    tracing::info!("Database: {}", config.database.describe());
+
+    // Create the application (API + admin API + embedded web UI)
+    let mut backend_config = config.backend_config();
+    backend_config.key_passphrase = config.key_passphrase()?;
+    let state = start_backend(backend_config).await?;
+    let reloader = Arc::new(Reloader::new(
+        config_path.clone(),
+        overrides,
+        config.clone(),
+        state.clone(),
+        log_handle,
+    ));
+    reload::spawn_sighup_handler(reloader.clone())?;
+    let app = porta_backend::router(state)
+        .merge(reload::router(reloader))
         .fallback(get(serve_embedded));
 
     // Bind to address
@@ -174,16 +175,52 @@ fn file_response(contents: &[u8], path: &str) -> Response {
         .unwrap()
 }
 
-/// Initialize the logging subsystem based on configuration
-fn init_logging(config: &Config) -> anyhow::Result<()> {
+/// Log filter for the configured level; `RUST_LOG` takes precedence
+pub(crate) fn log_filter(config: &Config) -> EnvFilter {
     let log_level = &config.logging.level;
     let filter = format!(
         "porta_backend={},porta_server={},tower_http=debug",
         log_level, log_level
     );
+    EnvFilter::try_from_default_env().unwrap_or_else(|_| filter.into())
+}
+
+/// Start the backend, asking for the key passphrase on the terminal when the
+/// key file is encrypted and no passphrase was configured (or it was wrong).
+async fn start_backend(mut backend_config: BackendConfig) -> anyhow::Result<AppState> {
+    const ATTEMPTS: usize = 3;
+    let mut attempt = 0;
+    loop {
+        match AppState::with_config(backend_config.clone()).await {
+            Ok(state) => return Ok(state),
+            Err(err) => {
+                let Some(locked) = err.downcast_ref::<KeyLocked>() else {
+                    return Err(err);
+                };
+                if attempt == ATTEMPTS || !std::io::stdin().is_terminal() {
+                    anyhow::bail!(
+                        "{}. Set PORTA_KEY_PASSPHRASE or node.key_passphrase_file",
+                        locked
+                    );
+                }
+                attempt += 1;
+                if attempt > 1 {
+                    eprintln!("Wrong passphrase, try again.");
+                }
+                let passphrase = tokio::task::spawn_blocking(|| {
+                    rpassword::prompt_password("Key file passphrase: ")
+                })
+                .await??;
+                backend_config.key_passphrase = Some(passphrase);
+            }
+        }
+    }
+}
 
-    let env_filter =
-        tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| filter.into());
+/// Initialize the logging subsystem based on configuration.
+/// Returns a handle for changing the log level at runtime.
+fn init_logging(config: &Config) -> anyhow::Result<LogHandle> {
+    let (env_filter, handle) = tracing_subscriber::reload::Layer::new(log_filter(config));
 
     match config.logging.format.as_str() {
         "json" => {
@@ -224,7 +261,7 @@ fn init_logging(config: &Config) -> anyhow::Result<()> {
         }
     }
 
-    Ok(())
+    Ok(handle)
 }
 
 /// Wait for shutdown signal (Ctrl+C or SIGTERM)
@@ -0,0 +1,202 @@
+//! Live configuration reload
+//!
+//! Re-reads the TOML file on SIGHUP or `POST /porta/admin/reload`, applies
+//! what can change on a running node and reports the rest as needing a restart.
+
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::Result;
+use axum::{extract::State, routing::post, Router};
+use porta_backend::{models::ReloadReport, resp, state::AppState};
+use tokio::sync::Mutex;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::config::{CliOverrides, Config};
+
+/// Handle for swapping the log filter at runtime
+pub type LogHandle = reload::Handle<EnvFilter, Registry>;
+
+pub struct Reloader {
+    config_path: PathBuf,
+    overrides: CliOverrides,
+    current: Mutex<Config>,
+    state: AppState,
+    log_handle: LogHandle,
+}
+
+impl Reloader {
+    pub fn new(
+        config_path: PathBuf,
+        overrides: CliOverrides,
+        config: Config,
+        state: AppState,
+        log_handle: LogHandle,
+    ) -> Self {
+        Self {
+            config_path,
+            overrides,
+            current: Mutex::new(config),
+            state,
+            log_handle,
+        }
+    }
+
+    /// Reload the config file and apply the differences to the running server.
+    pub async fn reload(&self) -> Result<ReloadReport> {
+        let mut new = Config::load(&self.config_path)?;
+        self.overrides.apply(&mut new);
+        new.validate()?;
+
+        let mut current = self.current.lock().await;
+        let mut report = ReloadReport::default();
+        if new.logging.format != current.logging.format {
+            report.restart_required.push("logging.format".into());
+        }
+        if new.server.listen_addr != current.server.listen_addr {
+            report.restart_required.push("server.listen_addr".into());
+        }
+        if new.server.port != current.server.port {
+            report.restart_required.push("server.port".into());
+        }
+
+        let backend = self.state.reload(new.backend_config()).await?;
+        report.merge(ReloadReport {
+            applied: backend.applied.iter().map(|f| config_key(f)).collect(),
+            restart_required: backend
+                .restart_required
+                .iter()
+                .map(|f| config_key(f))
+                .collect(),
+        });
+        // Last, so a failed backend reload leaves the log level as it was
+        if new.logging.level != current.logging.level {
+            self.log_handle.reload(crate::log_filter(&new))?;
+            report.applied.push("logging.level".into());
+        }
+
+        // Settings that need a restart keep their running values
+        new.server = current.server.clone();
+        new.logging.format = current.logging.format.clone();
+        new.node.role = current.node.role.clone();
+        new.node.key_path = current.node.key_path.clone();
+        new.database = current.database.clone();
+        *current = new;
+
+        tracing::info!(
+            "Configuration reloaded from {}: applied {:?}, restart required {:?}",
+            self.config_path.display(),
+            report.applied,
+            report.restart_required
+        );
+        Ok(report)
+    }
+}
+
+/// Map a backend setting to its key in the config file
+fn config_key(field: &str) -> String {
+    match field {
+        "node_name" => "node.name",
+        "role" => "node.role",
+        "key_path" => "node.key_path",
+        "admission" => "node.admission",
+        "db_path" => "database.path",
+        "database_url" => "database.url",
+        "listen_addrs" => "p2p.tcp_port",
+        "quic_port" => "p2p.quic_port",
+        "mdns_enable" => "p2p.mdns_enable",
+        "dht_enable" => "p2p.dht_enable",
+        "external_addrs" => "p2p.external_addrs",
+        "rendezvous_points" => "p2p.rendezvous",
+        other => other,
+    }
+    .to_string()
+}
+
+pub fn router(reloader: Arc<Reloader>) -> Router {
+    Router::new()
+        .route("/porta/admin/reload", post(reload_config))
+        .with_state(reloader)
+}
+
+async fn reload_config(State(reloader): State<Arc<Reloader>>) -> impl axum::response::IntoResponse {
+    match reloader.reload().await {
+        Ok(report) => resp::ok(Some(report)),
+        Err(err) => resp::err(&format!("重新加载配置失败: {:#}", err)),
+    }
+}
+
+/// Reload whenever the process receives SIGHUP.
+#[cfg(unix)]
+pub fn spawn_sighup_handler(reloader: Arc<Reloader>) -> Result<()> {
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            tracing::info!("Received SIGHUP, reloading configuration...");
+            if let Err(err) = reloader.reload().await {
+                tracing::error!("Configuration reload failed: {:#}", err);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn spawn_sighup_handler(_reloader: Arc<Reloader>) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_key_mapping() {
+        assert_eq!(config_key("node_name"), "node.name");
+        assert_eq!(config_key("listen_addrs"), "p2p.tcp_port");
+        assert_eq!(config_key("logging.level"), "logging.level");
+    }
+
+    #[tokio::test]
+    async fn test_failed_reload_keeps_log_level() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let free_port = || {
+            std::net::TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+                .port()
+        };
+        let mut config = Config::default();
+        config.database.path = ":memory:".into();
+        config.logging.level = "info".into();
+        config.p2p.tcp_port = free_port();
+        config.p2p.quic_port = free_port();
+        let state = AppState::with_config(config.backend_config())
+            .await
+            .unwrap();
+        let (layer, handle) = reload::Layer::new(EnvFilter::new("porta_server=info"));
+        let _subscriber = Registry::default().with(layer);
+
+        // A TCP port that is already taken makes the backend reload fail
+        let taken = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+        let path = std::env::temp_dir().join(format!("porta-reload-{}.toml", std::process::id()));
+        let mut changed = config.clone();
+        changed.logging.level = "debug".into();
+        changed.p2p.tcp_port = taken.local_addr().unwrap().port();
+        std::fs::write(&path, toml::to_string(&changed).unwrap()).unwrap();
+
+        let reloader = Reloader::new(
+            path.clone(),
+            CliOverrides::default(),
+            config,
+            state,
+            handle.clone(),
+        );
+        let result = reloader.reload().await;
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+        let level = handle.with_current(|filter| filter.to_string()).unwrap();
+        assert_eq!(level, "porta_server=info");
+    }
+}
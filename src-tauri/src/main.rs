@@ -55,10 +55,16 @@ fn main() {
 
             // Set database path and role for backend
             let db_path = data_dir.join("porta.db");
-            std::env::set_var("PORTA_DB", db_path.to_string_lossy().to_string());
-            std::env::set_var("PORTA_ROLE", "edge");
-            // Set default P2P TCP port for desktop app
-            std::env::set_var("PORTA_P2P_TCP_PORT", "9000");
+            let config = porta_backend::BackendConfig {
+                role: "edge".into(),
+                db_path: db_path.to_string_lossy().to_string(),
+                // Default P2P TCP port for desktop app
+                listen_addrs: vec![porta_backend::config::tcp_listen_addr(9000)],
+                // The desktop app cannot prompt for it, so an encrypted key
+                // is only unlocked from the environment
+                key_passphrase: std::env::var("PORTA_KEY_PASSPHRASE").ok(),
+                ..Default::default()
+            };
 
             tracing::info!("Database path: {}", db_path.display());
             tracing::info!("Node role: edge");
@@ -66,7 +72,20 @@ fn main() {
             // Start backend server asynchronously
             async_runtime::spawn(async move {
                 tracing::info!("Starting Porta backend server...");
-                let app = porta_backend::create_app().await;
+                let app = match porta_backend::create_app_with(config).await {
+                    Ok(app) => app,
+                    Err(err) => {
+                        if err.is::<porta_backend::keystore::KeyLocked>() {
+                            tracing::error!(
+                                "Failed to start backend: {}. Set PORTA_KEY_PASSPHRASE",
+                                err
+                            );
+                        } else {
+                            tracing::error!("Failed to start backend: {}", err);
+                        }
+                        return;
+                    }
+                };
                 match tokio::net::TcpListener::bind("127.0.0.1:8090").await {
                     Ok(listener) => {
                         tracing::info!("Backend server listening on http://127.0.0.1:8090");